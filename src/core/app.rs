@@ -1,889 +1,4112 @@
-use crate::core::{auth::AuthType, profile::Profile, project::Project};
-use crate::util::git;
-use std::collections::HashMap;
-use std::fs::{self, OpenOptions};
-use std::io::{self, Write};
+use crate::core::connection;
+use crate::core::diff::{self, ProfileDiff};
+use crate::core::doctor::{self, Check};
+use crate::core::dump::{self, ConfigDump};
+use crate::core::exec;
+use crate::core::export;
+use crate::core::forge::Forge;
+use crate::core::hook;
+use crate::core::inspect::detect_auth_type;
+use crate::core::local::{self, LocalProfile};
+use crate::core::plan::{self, ApplyPlan, PlanOperation, Remotes, Stage};
+use crate::core::preflight::{self, Warning};
+use crate::core::signers;
+use crate::core::{
+    auth::AuthType,
+    credential,
+    profile::{Profile, ProfileUsage},
+    project::Project,
+    rules::Rules,
+    settings::{Protocol, Settings},
+    status::{RepoRecord, RepoState},
+    store::ProfileStore,
+};
+use crate::util::{
+    git::{self, GitBackend},
+    keychain,
+    lock::{self, LockOwner, RemovedLock},
+    ssh_config,
+    system::{expand_home, resolve_binary},
+};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 pub struct App {}
 
-// .octopush file format
-// [profile_name]
-// name = test
-// email = test@email.com
-// auth = [none,gh,ssh]
-// gh_hostname = [optional(string), default(github.com)]
-// ssh_key_path = [optional(string)]
-
-trait ProfileManager {
-    const CONFIG_DIR_NAME: &str = if cfg!(test) {
-        "octopush-test"
-    } else {
-        "octopush"
-    };
-    const PROFILES_FILE_NAME: &str = "profiles.toml";
-    const PROJECT_PROFILES_FILE_NAME: &str = "project_profiles.toml";
-
-    fn base_config_dir() -> Result<PathBuf, io::Error> {
-        if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME") {
-            return Ok(PathBuf::from(xdg));
-        }
-
-        let home = std::env::var_os("HOME")
-            .or_else(|| std::env::var_os("USERPROFILE"))
-            .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "HOME/USERPROFILE environment variable not set",
-                )
-            })?;
+pub use crate::core::store::{CONFIG_HOME_ENV, DATA_HOME_ENV, PROFILES_KEY_ENV};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BulkAddSummary {
+    pub created: Vec<String>,
+    // profile name and the reason it was skipped
+    pub skipped: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncSummary {
+    pub synced: Vec<String>,
+    // repository and the reason it was skipped
+    pub skipped: Vec<(String, String)>,
+}
+
+// what sync_all_with reports while it works through the mappings
+#[derive(Debug, PartialEq)]
+pub enum SyncProgress<'a> {
+    // syncing `repo`, one of `total` mapped repositories, begins
+    Repo {
+        repo: &'a str,
+        total: usize,
+    },
+    Stage(Stage),
+    // `repo` is done; the reason when it was skipped
+    Done {
+        repo: &'a str,
+        skipped: Option<&'a str>,
+        changed: Vec<String>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AutoApplySetup {
+    // the global git setting that makes git pick up the hook
+    pub key: &'static str,
+    pub hooks_dir: PathBuf,
+    pub hook: PathBuf,
+    // false when the setting already pointed somewhere and was kept
+    pub configured: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConnectionReport {
+    pub profile_name: String,
+    pub host: String,
+    // None when the host accepted us without naming the account
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GitconfigExport {
+    // the file to include from the global gitconfig
+    pub includes_path: PathBuf,
+    pub profiles: usize,
+    pub sections: usize,
+    // mappings and rules git's includeIf cannot express, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+// how many references `merge_profiles` pointed at the target profile, and
+// the repositories it was re-applied to
+#[derive(Debug, Default, PartialEq)]
+pub struct MergeSummary {
+    pub mappings: usize,
+    pub rules: usize,
+    pub reapplied: Vec<String>,
+    // repositories that keep the source's settings until synced, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+// every local setting a profile may have written, removed by reset-profile
+const RESET_KEYS: [&str; 13] = [
+    "user.name",
+    "user.email",
+    "core.sshCommand",
+    "credential.helper",
+    "credential.useHttpPath",
+    "http.sslCAInfo",
+    "http.sslVerify",
+    "http.proxy",
+    "user.signingKey",
+    "gpg.format",
+    "gpg.program",
+    "commit.gpgSign",
+    "tag.gpgSign",
+];
 
-        Ok(PathBuf::from(home).join(".config"))
+impl App {
+    // the store behind the CLI, located through the environment
+    pub fn store() -> Result<ProfileStore, io::Error> {
+        ProfileStore::from_env()
     }
 
-    fn ensure_app_config_dir() -> Result<PathBuf, io::Error> {
-        let dir = Self::base_config_dir()?.join(Self::CONFIG_DIR_NAME);
-        fs::create_dir_all(&dir)?;
-        Ok(dir)
+    // the backend plans go through: libgit2 when built with the `git2`
+    // feature, the git binary otherwise
+    pub fn git() -> &'static dyn GitBackend {
+        #[cfg(feature = "git2")]
+        return &crate::util::libgit2::Git2Backend;
+        #[cfg(not(feature = "git2"))]
+        return &git::SystemGit;
     }
 
-    fn profiles_config_path() -> Result<PathBuf, io::Error> {
-        let dir = Self::ensure_app_config_dir()?;
-        Ok(dir.join(Self::PROFILES_FILE_NAME))
+    pub fn add_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.add_profile(profile_name, profile)
     }
 
-    fn project_profiles_path() -> Result<PathBuf, io::Error> {
-        let dir = Self::ensure_app_config_dir()?;
-        Ok(dir.join(Self::PROJECT_PROFILES_FILE_NAME))
+    // reads a manifest (profiles.toml layout, or JSON when the file ends in
+    // .json) and adds every valid profile that doesn't exist yet
+    pub fn add_profiles_from_file(path: &Path) -> Result<BulkAddSummary, io::Error> {
+        let store = Self::store()?;
+        let content = fs::read_to_string(path)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        let manifest: BTreeMap<String, Profile> = if is_json {
+            serde_json::from_str(&content).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("JSON parse error: {e}"))
+            })?
+        } else {
+            toml::from_str(&content).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("TOML parse error: {e}"))
+            })?
+        };
+
+        let _lock = store.lock()?;
+        store.add_profiles(manifest)
     }
 
-    fn read_profile(profile_name: String) -> Result<Option<Profile>, io::Error> {
-        let profiles = Self::read_profiles()?;
+    // builds a profile out of the identity already configured in a repository
+    pub fn profile_from_repo(project_path: String) -> Result<Profile, io::Error> {
+        let repo = Path::new(&project_path);
+        git::ensure_repo(repo)?;
 
-        Ok(profiles.get(&profile_name).cloned())
+        let missing = |key: &str| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("repository has no local '{}' to adopt", key),
+            )
+        };
+        let name = git::get_local_config(repo, "user.name")?.ok_or_else(|| missing("user.name"))?;
+        let email =
+            git::get_local_config(repo, "user.email")?.ok_or_else(|| missing("user.email"))?;
+
+        let auth_type = detect_auth_type(repo)?;
+        let (hostname, ssh_key_path) = match auth_type {
+            AuthType::None => (None, None),
+            AuthType::SSH => (
+                None,
+                git::get_local_config(repo, "core.sshCommand")?
+                    .as_deref()
+                    .and_then(git::ssh_key_from_command),
+            ),
+            AuthType::GH | AuthType::GitLab | AuthType::Token => {
+                let host = match git::primary_remote(repo)? {
+                    Some(remote) => git::get_remote_url(repo, &remote)?
+                        .as_deref()
+                        .and_then(git::parse_remote)
+                        .map(|(host, _, _)| host),
+                    None => None,
+                };
+                (
+                    host.or(auth_type.default_hostname().map(str::to_string)),
+                    None,
+                )
+            }
+        };
+
+        Ok(Profile::build(
+            name,
+            email,
+            auth_type,
+            hostname,
+            ssh_key_path,
+        ))
     }
 
-    fn read_profiles() -> Result<HashMap<String, Profile>, io::Error> {
-        let path = Self::profiles_config_path()?;
-        let content = fs::read_to_string(&path).unwrap_or_default();
-        if content.trim().is_empty() {
-            return Ok(HashMap::new());
-        }
-        let profiles: HashMap<String, Profile> = toml::from_str(&content)
-            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
-        Ok(profiles)
+    pub fn update_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.update_profile(profile_name, profile)
     }
 
-    fn write_profiles(profiles: &HashMap<String, Profile>) -> Result<(), io::Error> {
-        let path = Self::profiles_config_path()?;
-        let toml_string = toml::to_string_pretty(profiles)
-            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&path)?;
-        file.write_all(toml_string.as_bytes())?;
-        Ok(())
+    pub fn archive_profile(profile_name: String, archived: bool) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.archive_profile(profile_name, archived)
     }
 
-    fn read_project_profile(repo_name: &str) -> Result<Option<(String, Profile)>, io::Error> {
-        let map = Self::read_project_profiles()?;
-        if let Some(profile_name) = map.get(repo_name) {
-            let profiles = Self::read_profiles()?;
+    pub fn delete_profile(profile_name: String) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.delete_profile(profile_name)
+    }
 
-            Ok(profiles
-                .get(profile_name)
-                .cloned()
-                .map(|profile| (profile_name.clone(), profile)))
-        } else {
-            Ok(None)
+    // stores the access token of a `token` profile in the OS keychain
+    pub fn set_token(profile_name: String, token: String) -> Result<(), io::Error> {
+        let profile = Self::get_profile(profile_name.clone())?;
+        if profile.auth_type != AuthType::Token {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("profile '{}' does not use 'token' auth type", profile_name),
+            ));
+        }
+        if token.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the token cannot be empty",
+            ));
         }
+
+        keychain::set_token(&profile_name, token.trim())
     }
 
-    fn read_project_profiles() -> Result<HashMap<String, String>, io::Error> {
-        let path = Self::project_profiles_path()?;
-        let content = fs::read_to_string(&path).unwrap_or_default();
-        if content.trim().is_empty() {
-            return Ok(HashMap::new());
+    // Serves git's credential helper protocol for `token` profiles. git runs
+    // the helper inside the repository, so the profile is the one rules or
+    // the mapping pick for it. Only `get` answers; tokens are managed with
+    // `set-token`, so `store` and `erase` are ignored.
+    pub fn credential(
+        operation: &str,
+        input: &str,
+        project_path: String,
+    ) -> Result<Option<String>, io::Error> {
+        if operation != "get" && operation != "fill" {
+            return Ok(None);
         }
-        let map: HashMap<String, String> = toml::from_str(&content)
-            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
-        Ok(map)
-    }
 
-    fn write_project_profiles(map: &HashMap<String, String>) -> Result<(), io::Error> {
-        let path = Self::project_profiles_path()?;
-        let toml_string = toml::to_string_pretty(map)
-            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&path)?;
-        file.write_all(toml_string.as_bytes())?;
-        Ok(())
+        // `exec` names its profile, whatever the repository maps to
+        let profile_name = match std::env::var(exec::PROFILE_ENV) {
+            Ok(profile_name) if !profile_name.is_empty() => profile_name,
+            _ => Self::resolve_profile_name(project_path)?,
+        };
+        let profile = Self::get_profile(profile_name.clone())?;
+        let (AuthType::Token, Some(host)) = (profile.auth_type, profile.hostname.as_deref()) else {
+            return Ok(None);
+        };
+        let Some(token) = keychain::get_token(&profile_name)? else {
+            return Ok(None);
+        };
+
+        Ok(credential::fill(
+            &credential::parse_request(input),
+            host,
+            &token,
+        ))
     }
 
-    fn add_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
-        let mut profiles = Self::read_profiles()?;
-        if profiles.contains_key(&profile_name) {
-            return Err(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                format!("profile '{}' already exists", profile_name),
-            ));
+    // folds `from` into `into`: everything that referred to `from` now refers
+    // to `into`, and `from` is deleted
+    pub fn merge_profiles(
+        into: String,
+        from: String,
+        force: bool,
+    ) -> Result<MergeSummary, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let mut repos: Vec<String> = store
+            .read_project_profiles()?
+            .into_iter()
+            .filter(|(key, profile_name)| *profile_name == from && Path::new(key).is_absolute())
+            .map(|(key, _)| key)
+            .collect();
+        repos.sort();
+
+        let mut summary = store.merge_profiles(into, from, force)?;
+        // switch them over now rather than on their next sync
+        for repo in repos {
+            let result = if Path::new(&repo).exists() {
+                Self::sync_repo(repo.clone()).map_err(|e| e.to_string())
+            } else {
+                Err("no longer exists, see `octopush move-repo`".to_string())
+            };
+            match result {
+                Ok(_) => summary.reapplied.push(repo),
+                Err(reason) => summary.skipped.push((repo, reason)),
+            }
         }
-        profiles.insert(profile_name, profile);
-        Self::write_profiles(&profiles)
+        Ok(summary)
     }
 
-    fn update_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
-        let mut profiles = Self::read_profiles()?;
-
-        match profiles.get(&profile_name) {
-            Some(p) => match p.auth_type {
-                AuthType::None => {
-                    if profile.hostname.is_some() || profile.ssh_key_path.is_some() {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "you cannot update 'hostname' or 'ssh_key_path' for 'none' auth type"
-                                .to_string(),
-                        ));
-                    }
-                }
-                AuthType::SSH => {
-                    if profile.hostname.is_some() {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "you cannot update 'hostname' for 'ssh' auth type".to_string(),
-                        ));
-                    }
-                }
-                AuthType::GH => {
-                    if profile.ssh_key_path.is_some() {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "you cannot update 'ssh_key_path' for 'gh' auth type".to_string(),
-                        ));
-                    }
-                }
-            },
-            None => {
-                return Err(io::Error::new(
+    pub fn get_profile(profile_name: String) -> Result<Profile, io::Error> {
+        Self::store()?
+            .read_profile(profile_name.clone())?
+            .ok_or_else(|| {
+                io::Error::new(
                     io::ErrorKind::NotFound,
                     format!("profile '{}' not found", profile_name),
-                ));
-            }
-        }
+                )
+            })
+    }
 
-        profiles.insert(profile_name, profile);
-        Self::write_profiles(&profiles)
+    pub fn diff_profiles(left: String, right: String) -> Result<ProfileDiff, io::Error> {
+        let left_profile = Self::get_profile(left.clone())?;
+        let right_profile = Self::get_profile(right.clone())?;
+
+        Ok(diff::diff_profiles(
+            &left,
+            &left_profile,
+            &right,
+            &right_profile,
+        ))
     }
 
-    fn delete_profile(profile_name: String) -> Result<(), io::Error> {
-        let mut profiles = Self::read_profiles()?;
-        let removed = profiles.remove(&profile_name);
-        if removed.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("profile '{}' not found", profile_name),
-            ));
+    // soft problems that using `profile_name` in the repository, rewriting
+    // `remotes`, would leave behind
+    pub fn preflight(
+        profile_name: String,
+        project_path: String,
+        remotes: &Remotes,
+    ) -> Result<Vec<Warning>, io::Error> {
+        let profile = Self::get_profile(profile_name.clone())?;
+        let mut warnings = preflight::check(Path::new(&project_path), &profile)?;
+        warnings.extend(preflight::ambiguous_remote(
+            Path::new(&project_path),
+            remotes,
+        )?);
+
+        // a broken `.octopush` is reported when resolving, not here
+        if let Some(local) = Project::new(project_path)
+            .and_then(|project| project.get_repo_root())
+            .and_then(|root| local::read(&root))
+            .ok()
+            .flatten()
+            && local.profile_name() != profile_name
+        {
+            warnings.push(Warning::LocalMismatch {
+                expected: local.profile_name().to_string(),
+            });
         }
-        Self::write_profiles(&profiles)
+        Ok(warnings)
     }
 
-    fn apply_profile_to_repo(
-        profile: &Profile,
-        project_path: String,
-    ) -> Result<(), std::io::Error> {
-        let repo = Path::new(&project_path);
-        git::ensure_repo(repo)?;
+    // what `status` flags about the repository's remotes
+    pub fn remote_warnings(project_path: String) -> Result<Vec<Warning>, io::Error> {
+        preflight::remote_warnings(Path::new(&project_path))
+    }
 
-        git::set_local_identity(repo, &profile.name, &profile.email)?;
+    pub fn list_profiles() -> Result<HashMap<String, Profile>, io::Error> {
+        Self::store()?.read_profiles()
+    }
 
-        let remote = git::get_remote_url(repo, "origin")?;
+    // repository key -> profile name, in key order
+    pub fn list_projects() -> Result<BTreeMap<String, String>, io::Error> {
+        Ok(Self::store()?
+            .read_project_profiles()?
+            .into_iter()
+            .collect())
+    }
 
-        match profile.auth_type {
-            AuthType::SSH => {
-                if let Some(key) = &profile.ssh_key_path {
-                    git::ensure_ssh_command(repo, key)?;
-                }
-                if let Some(url) = remote
-                    && let Some((host, owner, repo_name)) = git::parse_remote(&url)
-                    && url.starts_with("https://")
-                {
-                    let ssh_url = git::to_ssh(&host, &owner, &repo_name);
-                    git::set_remote_url(repo, "origin", &ssh_url)?;
-                }
-                git::clear_gh_credential_helper(repo)?;
-            }
-            AuthType::GH => {
-                if let Some(url) = remote
-                    && let Some((host, owner, repo_name)) = git::parse_remote(&url)
-                {
-                    if url.starts_with("git@") || url.starts_with("ssh://") {
-                        let https_url = git::to_https(&host, &owner, &repo_name);
-                        git::set_remote_url(repo, "origin", &https_url)?;
-                    }
-                    let _gh_ok = git::is_gh_authenticated(&host);
-                }
-                git::set_gh_credential_helper(repo)?;
-                git::clear_ssh_command(repo)?;
-            }
-            AuthType::None => {
-                git::clear_ssh_command(repo)?;
-                git::clear_gh_credential_helper(repo)?;
-            }
-        }
+    // fuzzy-matches the query against profile id, name, email and tags,
+    // best matches first
+    pub fn search_profiles(query: &str) -> Result<Vec<(String, Profile)>, io::Error> {
+        let matcher = SkimMatcherV2::default().ignore_case();
+
+        let mut matches: Vec<(i64, String, Profile)> = Self::store()?
+            .read_profiles()?
+            .into_iter()
+            .filter_map(|(profile_name, profile)| {
+                let score = [&profile_name, &profile.name, &profile.email]
+                    .into_iter()
+                    .chain(profile.tags.iter())
+                    .filter_map(|field| matcher.fuzzy_match(field, query))
+                    .max()?;
+
+                Some((score, profile_name, profile))
+            })
+            .collect();
+
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        Ok(matches
+            .into_iter()
+            .map(|(_, profile_name, profile)| (profile_name, profile))
+            .collect())
+    }
 
-        Ok(())
+    pub fn use_profile(profile_name: String, project_path: String) -> Result<(), io::Error> {
+        Self::use_profile_with_remotes(profile_name, project_path, &Remotes::Primary)
     }
-}
 
-impl ProfileManager for App {}
+    pub fn use_profile_with_remotes(
+        profile_name: String,
+        project_path: String,
+        remotes: &Remotes,
+    ) -> Result<(), io::Error> {
+        Self::use_profile_in_stages(profile_name, project_path, remotes, &mut |_| {}).map(|_| ())
+    }
 
-impl App {
-    pub fn add_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
-        <Self as ProfileManager>::add_profile(profile_name, profile)
+    // use_profile_with_remotes, telling `on_stage` as each step of the apply
+    // begins; returns the plan it applied
+    pub fn use_profile_in_stages(
+        profile_name: String,
+        project_path: String,
+        remotes: &Remotes,
+        on_stage: &mut dyn FnMut(Stage),
+    ) -> Result<ApplyPlan, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.migrate_project_mapping(&Project::new(&project_path)?)?;
+        let plan = Self::plan_use_profile_with_remotes(profile_name, project_path, remotes)?;
+
+        store.apply_plan_in_stages(Self::git(), &plan, on_stage)?;
+        store.record_usage(&plan.profile_name)?;
+        Ok(plan)
     }
 
-    // NOTE: it's not being used in the cli for now
-    pub fn update_profile(profile_name: String, profile: Profile) -> Result<(), io::Error> {
-        <Self as ProfileManager>::update_profile(profile_name, profile)
+    pub fn list_usage() -> Result<HashMap<String, ProfileUsage>, io::Error> {
+        Self::store()?.read_usage()
     }
 
-    pub fn delete_profile(profile_name: String) -> Result<(), io::Error> {
-        <Self as ProfileManager>::delete_profile(profile_name)
+    pub fn settings() -> Result<Settings, io::Error> {
+        Self::store()?.read_settings()
     }
 
-    pub fn list_profiles() -> Result<HashMap<String, Profile>, io::Error> {
-        <Self as ProfileManager>::read_profiles()
+    pub fn save_settings(settings: &Settings) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.write_settings(settings)
     }
 
-    pub fn use_profile(profile_name: String, project_path: String) -> Result<(), io::Error> {
-        let profile = <Self as ProfileManager>::read_profile(profile_name.clone())?;
-        if profile.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("profile '{}' not found", profile_name),
-            ));
+    // `octopush config set`: changes one setting, validated before saving
+    pub fn set_setting(key: &str, value: &str) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let previous = store.read_settings()?;
+        let mut settings = previous.clone();
+        settings.set(key, value)?;
+        if settings.encrypt_profiles == previous.encrypt_profiles {
+            return store.write_settings(&settings);
         }
-        let profile = profile.unwrap();
 
-        let project = Project::new(project_path.clone())?;
-        let repo_name = project.get_repo_name()?;
+        // rewrite profiles.toml right away, in the clear or encrypted
+        let profiles = store.read_profiles()?;
+        store.write_settings(&settings)?;
+        if let Err(e) = store.write_profiles(&profiles) {
+            store.write_settings(&previous)?;
+            return Err(e);
+        }
+        Ok(())
+    }
 
-        let mut map = <Self as ProfileManager>::read_project_profiles()?;
-        map.insert(repo_name, profile_name);
-        <Self as ProfileManager>::write_project_profiles(&map)?;
+    pub fn config_dump() -> Result<ConfigDump, io::Error> {
+        Ok(dump::config_dump(&Self::store()?))
+    }
 
-        <Self as ProfileManager>::apply_profile_to_repo(&profile, project_path)?;
+    pub fn rules() -> Result<Rules, io::Error> {
+        Self::store()?.read_rules()
+    }
 
-        Ok(())
+    pub fn save_rules(rules: &Rules) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.write_rules(rules)
     }
 
-    pub fn get_project_profile(
-        project_path: String,
-    ) -> Result<(String, Profile, String) /* profile_name, profile, and repo_name */, io::Error>
-    {
-        let project = Project::new(project_path)?;
-        let repo_name = project.get_repo_name()?;
+    // profile names ordered for completing `use-profile` in a repository:
+    // the mapped profile, then the ones it used before, then the rest
+    pub fn ranked_profile_names(project_path: String) -> Result<Vec<String>, io::Error> {
+        let mut ranked = Vec::new();
+        if let Ok((profile_name, _, _)) = Self::get_project_profile(project_path.clone()) {
+            ranked.push(profile_name);
+        }
+        if let Ok(Some(record)) = Self::repo_record(project_path) {
+            ranked.extend(record.previous_profiles);
+        }
 
-        match App::read_project_profile(&repo_name)? {
-            Some(profile) => Ok((profile.0, profile.1, repo_name)),
-            None => Err(io::Error::new(
+        let profiles = Self::store()?.read_profiles()?;
+        let mut rest: Vec<String> = profiles
+            .iter()
+            .filter(|(_, profile)| !profile.archived)
+            .map(|(name, _)| name.clone())
+            .collect();
+        rest.sort();
+
+        let mut names = Vec::new();
+        for name in ranked.into_iter().chain(rest) {
+            if profiles.contains_key(&name) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    // the repository's page on its forge; with `account`, a page that first
+    // switches to the account of the repository's profile where supported
+    pub fn web_url(project_path: String, account: bool) -> Result<String, io::Error> {
+        let project = Project::new(project_path.clone())?;
+        let identity = project.get_remote_identity()?.ok_or_else(|| {
+            io::Error::new(
                 io::ErrorKind::NotFound,
-                format!("profile not found for '{}'", repo_name),
-            )),
+                "the repository has no remote to open",
+            )
+        })?;
+        let (host, owner) = split_identity(&identity);
+        let repo = identity.rsplit('/').next().unwrap_or_default();
+        let forge = Forge::detect(host);
+
+        if account
+            && let Ok((_, profile, _)) = Self::get_project_profile(project_path)
+            && profile.auth_type == AuthType::GH
+            && let Some(login) = git::gh_user(profile.hostname.as_deref().unwrap_or(host))
+            && let Some(url) = forge.account_url(host, owner, repo, &login)
+        {
+            return Ok(url);
         }
+
+        Ok(forge.repo_url(host, owner, repo))
     }
 
-    pub fn reset_profile_for_project(project_path: String) -> Result<(), io::Error> {
+    // picks the profile for a repository when none is named: the one its
+    // `.octopush` file asks for, then a matching rule, then the profile it
+    // is already mapped to, then the `default_profile` setting
+    pub fn resolve_profile_name(project_path: String) -> Result<String, io::Error> {
+        let store = Self::store()?;
         let project = Project::new(project_path.clone())?;
-        let repo_name = project.get_repo_name()?;
+        let root = project.get_repo_root()?.canonicalize()?;
 
-        let mut map = <Self as ProfileManager>::read_project_profiles()?;
-        map.remove(&repo_name);
-        <Self as ProfileManager>::write_project_profiles(&map)?;
+        if let Some(local) = local::read(&root)? {
+            let profile_name = Self::adopt_local_profile(local)?;
+            log::info!("{} picks profile '{}'", local::FILE_NAME, profile_name);
+            return Ok(profile_name);
+        }
 
-        let repo = std::path::Path::new(&project_path);
-        git::ensure_repo(repo)?;
-        git::unset_local(repo, "user.name")?;
-        git::unset_local(repo, "user.email")?;
-        git::clear_ssh_command(repo)?;
-        git::clear_gh_credential_helper(repo)?;
-        Ok(())
+        let remote = project.get_remote_identity()?;
+        if let Some(rule) = store.read_rules()?.resolve(&root, remote.as_deref()) {
+            log::info!("rules.toml picks profile '{}'", rule.profile);
+            return Ok(rule.profile.clone());
+        }
+        log::info!("no rule matches, falling back to the repository's mapping");
+
+        match Self::get_project_profile(project_path) {
+            Ok((profile_name, _, _)) => Ok(profile_name),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if let Some(profile_name) = store.read_settings()?.default_profile {
+                    log::info!("the repository is not mapped, using the default profile");
+                    return Ok(profile_name);
+                }
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no rule matches this repository and it is not mapped to a profile",
+                ))
+            }
+            Err(e) => Err(e),
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::test_helpers::TempConfig;
+    // the profile a `.octopush` file asks for; an inline one is added to
+    // profiles.toml the first time, and must not contradict a profile of the
+    // same name there
+    fn adopt_local_profile(local: LocalProfile) -> Result<String, io::Error> {
+        let store = Self::store()?;
+        let LocalProfile::Inline(profile_name, profile) = local else {
+            return Ok(local.profile_name().to_string());
+        };
+
+        let _lock = store.lock()?;
+        match store.read_profile(profile_name.clone())? {
+            Some(existing) if existing.name == profile.name && existing.email == profile.email => {}
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} defines profile '{}' with another name or email than profiles.toml",
+                        local::FILE_NAME,
+                        profile_name
+                    ),
+                ));
+            }
+            None => {
+                log::info!(
+                    "adding profile '{}' from {}",
+                    profile_name,
+                    local::FILE_NAME
+                );
+                store.add_profile(profile_name.clone(), *profile)?;
+            }
+        }
+        Ok(profile_name)
+    }
 
-    static CONFIG_DIR_NAME: &str = "octopush-test";
-    static PROFILES_FILE_NAME: &str = "profiles.toml";
-    static PROJECT_PROFILES_FILE_NAME: &str = "project_profiles.toml";
-    static PROFILE_1_PROFILE_NAME: &str = "profile_1_profile_name";
-    static PROFILE_2_PROFILE_NAME: &str = "profile_2_profile_name";
-    static PROFILE_1_NAME: &str = "profile_1_name";
-    static PROFILE_2_NAME: &str = "profile_2_name";
-    static PROFILE_1_EMAIL: &str = "profile-1@email.com";
-    static PROFILE_2_EMAIL: &str = "profile-2@email.com";
-    static PROFILE_1_AUTH_TYPE: AuthType = AuthType::SSH;
-    static PROFILE_2_AUTH_TYPE: AuthType = AuthType::GH;
-    static SSH_KEY_PATH: &str = "~/.ssh/id_rsa";
-    static HOSTNAME: &str = "mycompany.github.com";
-    static REPO_1_NAME: &str = "repo_1";
-    static REPO_2_NAME: &str = "repo_2";
+    // Guesses the profile for a repository from its primary remote: profiles
+    // already mapped to repositories of the same owner score highest, then
+    // those mapped to the same host or whose hostname matches it.
+    pub fn suggest_profile(project_path: String) -> Result<Option<String>, io::Error> {
+        let store = Self::store()?;
+        let project = Project::new(project_path)?;
+        let Some(identity) = project.get_remote_identity()? else {
+            return Ok(None);
+        };
+        let (host, owner) = split_identity(&identity);
 
-    struct TestPM {}
+        let profiles = store.read_profiles()?;
+        let mut scores: HashMap<&str, u32> = HashMap::new();
 
-    impl ProfileManager for TestPM {}
+        for (profile_name, profile) in &profiles {
+            if !profile.archived && profile.hostname.as_deref() == Some(host) {
+                *scores.entry(profile_name).or_default() += 2;
+            }
+        }
 
-    // ProfileManager tests
+        for (key, profile_name) in store.read_project_profiles()? {
+            let Some((profile_name, profile)) = profiles.get_key_value(&profile_name) else {
+                continue;
+            };
+            if profile.archived {
+                continue;
+            }
 
-    #[test]
-    fn gets_config_dir_returns_path_when_exists() -> Result<(), std::io::Error> {
-        let cfg = TempConfig::new()?;
+            // path keys need the mapped repository's remote, remote keys are it
+            let mapped = if Path::new(&key).is_absolute() {
+                Project::new(&key)
+                    .and_then(|mapped| mapped.get_remote_identity())
+                    .ok()
+                    .flatten()
+            } else {
+                Some(key)
+            };
+            let Some(mapped) = mapped else {
+                continue;
+            };
+
+            let (mapped_host, mapped_owner) = split_identity(&mapped);
+            if mapped_host == host {
+                *scores.entry(profile_name).or_default() +=
+                    if mapped_owner == owner { 3 } else { 1 };
+            }
+        }
 
-        let config_dir = TestPM::base_config_dir()?;
+        Ok(scores
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(profile_name, _)| profile_name.to_string()))
+    }
 
-        assert_eq!(config_dir, cfg.base);
+    // pins the gh binary configured for the profile (or in settings) to its
+    // resolved path; only done for profiles that actually run gh
+    fn resolve_tools(mut profile: Profile) -> Result<Profile, io::Error> {
+        if profile.auth_type == AuthType::GH {
+            let configured = match profile.gh_path.take() {
+                Some(gh_path) => Some(gh_path),
+                None => Self::store()?.read_settings()?.gh_path,
+            };
+            if let Some(configured) = configured {
+                let resolved = resolve_binary(&configured)?;
+                profile.gh_path = Some(resolved.to_string_lossy().into_owned());
+            }
+        }
+        Ok(profile)
+    }
 
-        Ok(())
+    pub fn lock_owner() -> Result<Option<LockOwner>, io::Error> {
+        Ok(lock::owner(&Self::store()?.lock_path()))
     }
 
-    #[test]
-    fn gets_app_config_dir_returns_path() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    // manual escape hatch for a lock that automatic recovery cannot clear;
+    // a lock whose owner is still running is only removed with `force`
+    pub fn unlock(force: bool) -> Result<Option<RemovedLock>, io::Error> {
+        let path = Self::store()?.lock_path();
+        let owner = lock::owner(&path);
 
-        let path_exists = TestPM::ensure_app_config_dir()?.exists();
+        if let Some(owner) = owner
+            && owner.alive
+            && !force
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!(
+                    "process {} holding the lock is still running, pass --force to remove the lock anyway",
+                    owner.pid
+                ),
+            ));
+        }
 
-        assert!(path_exists);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(Some(match owner {
+                Some(owner) => RemovedLock::Owned(owner),
+                None => RemovedLock::Unreadable,
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 
-        Ok(())
+    // a profile that exists and is not archived
+    fn usable_profile(profile_name: &str) -> Result<Profile, io::Error> {
+        let profile = Self::get_profile(profile_name.to_string())?;
+        if profile.archived {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "profile '{}' is archived, restore it with `octopush archive-profile {} --unarchive`",
+                    profile_name, profile_name
+                ),
+            ));
+        }
+        Ok(profile)
     }
 
-    #[test]
-    fn gets_profiles_config_path_returns_path() -> Result<(), std::io::Error> {
-        let cfg = TempConfig::new()?;
+    pub fn install_hook(project_path: String, force: bool) -> Result<PathBuf, io::Error> {
+        let repo = Project::new(project_path)?.get_repo_root()?;
+        hook::install_pre_commit(&git::hooks_dir(&repo)?, force)
+    }
 
-        let path = TestPM::profiles_config_path()?;
+    // Installs the post-checkout hook that applies rules to fresh clones.
+    // By default it goes into git's init.templateDir, which only new clones
+    // copy from; `hooks_path` uses core.hooksPath instead, which also covers
+    // existing repositories but replaces their own .git/hooks. A template dir
+    // or hooks path the user already configured is reused, not replaced.
+    pub fn setup_auto_apply(hooks_path: bool, force: bool) -> Result<AutoApplySetup, io::Error> {
+        let key = if hooks_path {
+            "core.hooksPath"
+        } else {
+            "init.templateDir"
+        };
+
+        let (root, configured) = match git::get_global_config(key)? {
+            Some(existing) if !existing.is_empty() => (expand_home(&existing), false),
+            _ => {
+                let dir = Self::store()?.ensure_app_config_dir()?;
+                let root = if hooks_path {
+                    dir.join(ProfileStore::HOOKS_DIR_NAME)
+                } else {
+                    dir.join(ProfileStore::TEMPLATE_DIR_NAME)
+                };
+                (root, true)
+            }
+        };
 
-        let expected_path = cfg.base.join(CONFIG_DIR_NAME).join(PROFILES_FILE_NAME);
+        // a template dir keeps its hooks in a `hooks` subdirectory
+        let hooks_dir = if hooks_path {
+            root.clone()
+        } else {
+            root.join("hooks")
+        };
+        let hook = hook::install_post_checkout(&hooks_dir, force)?;
 
-        assert_eq!(path, expected_path);
+        if configured {
+            git::set_global_config(key, &root.to_string_lossy())?;
+        }
 
-        Ok(())
+        Ok(AutoApplySetup {
+            key,
+            hooks_dir,
+            hook,
+            configured,
+        })
     }
 
-    #[test]
-    fn gets_project_profiles_path() -> Result<(), std::io::Error> {
-        let cfg = TempConfig::new()?;
+    // what the post-checkout hook runs: applies the rule-resolved profile and
+    // returns None when nothing decides for the repository
+    pub fn auto_apply(project_path: String) -> Result<Option<String>, io::Error> {
+        let profile_name = match Self::resolve_profile_name(project_path.clone()) {
+            Ok(profile_name) => profile_name,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Self::use_profile(profile_name.clone(), project_path)?;
+        Ok(Some(profile_name))
+    }
 
-        let path = TestPM::project_profiles_path()?;
+    // asks the host which account the profile authenticates as; the profile
+    // and host default to the ones the repository at `project_path` uses
+    pub fn test_connection(
+        profile_name: Option<String>,
+        hostname: Option<String>,
+        project_path: String,
+    ) -> Result<ConnectionReport, io::Error> {
+        let profile_name = match profile_name {
+            Some(profile_name) => profile_name,
+            None => Self::resolve_profile_name(project_path.clone())?,
+        };
+        let profile = Self::get_profile(profile_name.clone())?;
+
+        let remote_host = || {
+            Project::new(project_path.clone())
+                .and_then(|project| project.get_remote_identity())
+                .ok()
+                .flatten()
+                .and_then(|identity| identity.split('/').next().map(str::to_string))
+        };
+        let host = hostname
+            .or_else(|| profile.hostname.clone())
+            .or_else(remote_host)
+            .or_else(|| profile.auth_type.default_hostname().map(str::to_string))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "profile '{}' has no host to test, pass --hostname",
+                        profile_name
+                    ),
+                )
+            })?;
 
-        let expected_path = cfg
-            .base
-            .join(CONFIG_DIR_NAME)
-            .join(PROJECT_PROFILES_FILE_NAME);
+        let gh_path = Self::gh_program(&profile)?;
+        let account = connection::test(&profile, &host, &gh_path)?;
+        Ok(ConnectionReport {
+            profile_name,
+            host,
+            account,
+        })
+    }
 
-        assert_eq!(path, expected_path);
+    // logs the profile's host in through gh or glab, interactively
+    pub fn login(profile_name: String) -> Result<(), io::Error> {
+        let profile = Self::get_profile(profile_name.clone())?;
+        let program = match profile.auth_type {
+            AuthType::GH => Self::gh_program(&profile)?,
+            AuthType::GitLab => "glab".to_string(),
+            auth_type => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "profile '{}' uses {} auth, which has no cli login",
+                        profile_name,
+                        <&str>::from(auth_type)
+                    ),
+                ));
+            }
+        };
+        let host = profile
+            .hostname
+            .as_deref()
+            .or(profile.auth_type.default_hostname())
+            .unwrap_or_default();
+
+        git::auth_login(&program, host)
+    }
 
-        Ok(())
+    // the gh binary a profile uses: its own, the configured one, or PATH's
+    fn gh_program(profile: &Profile) -> Result<String, io::Error> {
+        match profile.gh_path.clone() {
+            Some(gh_path) => Ok(gh_path),
+            None => Ok(Self::store()?
+                .read_settings()?
+                .gh_path
+                .unwrap_or_else(|| "gh".to_string())),
+        }
     }
 
-    #[test]
-    fn reads_profile_when_no_profiles_exist_returns_none() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    // fails unless a commit made now would carry the mapped profile's email;
+    // GIT_AUTHOR_EMAIL wins over git config, as it does for git itself
+    pub fn check_identity(project_path: String) -> Result<String, io::Error> {
+        let (profile_name, profile, _) = match Self::get_project_profile(project_path.clone()) {
+            Ok(found) => found,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no profile is mapped to this repository, run `octopush use-profile` first",
+                ));
+            }
+            Err(e) => return Err(e),
+        };
 
-        let result = TestPM::read_profile(PROFILE_1_NAME.to_owned())?;
+        let repo = Project::new(project_path)?.get_repo_root()?;
+        let email = match std::env::var("GIT_AUTHOR_EMAIL") {
+            Ok(email) => Some(email),
+            Err(_) => git::get_config(&repo, "user.email")?,
+        };
 
-        assert!(result.is_none());
+        if email.as_deref() != Some(profile.email.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "committing as '{}' but profile '{}' uses '{}'",
+                    email.as_deref().unwrap_or("(unset)"),
+                    profile_name,
+                    profile.email
+                ),
+            ));
+        }
 
-        Ok(())
+        Ok(profile_name)
     }
 
-    #[test]
-    fn reads_profiles_when_no_profiles_exist_returns_empty_hashmap() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-        let result = TestPM::read_profiles()?;
+    // creates a repository with the profile's new-repo defaults and maps it
+    pub fn init_repo(profile_name: String, path: String) -> Result<(), io::Error> {
+        let profile = Self::usable_profile(&profile_name)?;
 
-        assert!(result.is_empty());
+        fs::create_dir_all(&path)?;
+        git::init_repo(
+            Path::new(&path),
+            profile.default_branch.as_deref(),
+            profile.template_dir.as_deref().map(expand_home).as_deref(),
+        )?;
 
-        Ok(())
+        Self::use_profile(profile_name, path)
     }
 
-    #[test]
-    fn reads_profile_when_profiles_exist_returns_profile() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-        let ((profile_name, profile), _) = get_profiles();
-
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(profile_name.to_string(), profile.clone())]);
-
-        TestPM::write_profiles(&profiles)?;
+    // clones as the profile (using its ssh key, if any) and maps the clone
+    pub fn clone_repo(profile_name: String, url: String, dest: String) -> Result<(), io::Error> {
+        let profile = Self::usable_profile(&profile_name)?;
+        let url = Self::clone_url(&profile_name, &profile, url)?;
 
-        let mut found_profile = TestPM::read_profile(profile_name.to_string())?;
+        let envs: Vec<(String, String)> = exec::ssh_env(&profile).into_iter().collect();
+        git::clone_repo(
+            &url,
+            Path::new(&dest),
+            profile.template_dir.as_deref().map(expand_home).as_deref(),
+            &envs,
+        )?;
 
-        assert!(found_profile.is_some());
-        assert_eq!(found_profile.take(), Some(profile.clone()));
-
-        Ok(())
+        Self::use_profile(profile_name, dest)
+    }
+
+    // expands an `owner/repo` shorthand into a url on the profile's host, over
+    // the `protocol` setting or else the scheme its auth type uses
+    fn clone_url(profile_name: &str, profile: &Profile, url: String) -> Result<String, io::Error> {
+        let is_shorthand = !url.contains(':')
+            && !url.starts_with(['.', '/', '~'])
+            && url.trim_end_matches('/').contains('/')
+            && !Path::new(&url).exists();
+        if !is_shorthand {
+            return Ok(url);
+        }
+
+        let host = profile
+            .hostname
+            .as_deref()
+            .or(profile.auth_type.default_hostname())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "profile '{}' has no hostname to expand '{}' with, give the full url",
+                        profile_name, url
+                    ),
+                )
+            })?;
+        let (owner, repo) = url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit_once('/')
+            .unwrap_or_default();
+
+        let protocol =
+            Self::store()?
+                .read_settings()?
+                .protocol
+                .unwrap_or(match profile.auth_type {
+                    AuthType::SSH => Protocol::Ssh,
+                    _ => Protocol::Https,
+                });
+        Ok(match protocol {
+            Protocol::Ssh => git::to_ssh(host, owner, repo),
+            Protocol::Https => git::to_https(host, owner, repo),
+        })
+    }
+
+    // Writes the profile's identity and auth settings to an octopush-owned
+    // file included from the global git config, for machines where every
+    // repository shares one identity. Returns the file.
+    pub fn use_profile_globally(profile_name: String) -> Result<PathBuf, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let profile = Self::resolve_tools(Self::usable_profile(&profile_name)?)?;
+
+        let path = store
+            .ensure_app_config_dir()?
+            .join(ProfileStore::GLOBAL_GITCONFIG_FILE_NAME);
+        fs::write(
+            &path,
+            format!(
+                "# managed by octopush for profile '{}', remove with `octopush reset-profile --global`\n{}",
+                profile_name,
+                export::profile_snippet(&profile)
+            ),
+        )?;
+        git::add_global_include(&path.to_string_lossy())?;
+
+        Ok(path)
+    }
+
+    // undoes `use_profile_globally`; false when no profile was applied globally
+    pub fn reset_global_profile() -> Result<bool, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let path = store
+            .config_dir()
+            .join(ProfileStore::GLOBAL_GITCONFIG_FILE_NAME);
+
+        let included = git::remove_global_include(&path.to_string_lossy())?;
+        let existed = match fs::remove_file(&path) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+
+        Ok(included || existed)
+    }
+
+    pub fn plan_use_profile(
+        profile_name: String,
+        project_path: String,
+    ) -> Result<ApplyPlan, io::Error> {
+        Self::plan_use_profile_with_remotes(profile_name, project_path, &Remotes::Primary)
+    }
+
+    pub fn plan_use_profile_with_remotes(
+        profile_name: String,
+        project_path: String,
+        remotes: &Remotes,
+    ) -> Result<ApplyPlan, io::Error> {
+        let store = Self::store()?;
+        let profile = Self::usable_profile(&profile_name)?;
+
+        let project = Project::new(project_path.clone())?;
+
+        // a credential helper would hand tokens to every remote of the repo
+        if profile.uses_credential_helper()
+            && !store.read_settings()?.allow_insecure_remotes
+            && let Some((remote, url)) = git::insecure_remotes(Path::new(&project_path))?
+                .into_iter()
+                .next()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "refusing to store credentials for insecure remote '{}' ({}), set allow_insecure_remotes = true in settings.toml to override",
+                    remote, url
+                ),
+            ));
+        }
+
+        let profile = Self::resolve_tools(profile)?;
+        let mut plan = ApplyPlan::build_with_remotes(
+            Self::git(),
+            Path::new(&project_path),
+            &profile_name,
+            &profile,
+            remotes,
+        )?;
+
+        let keep: Vec<String> = plan::rewrite_config(&profile)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        plan.operations.extend(plan::rewrite_removals(
+            Self::git(),
+            Path::new(&project_path),
+            &keep,
+            store.read_profiles()?.values(),
+        )?);
+
+        if let Some(operation) =
+            Self::allowed_signers_operation(Path::new(&project_path), &profile)?
+        {
+            plan.operations.push(operation);
+        }
+
+        let (repo_key, map) = store.read_project_mapping(&project)?;
+        let before = map.get(&repo_key).cloned();
+        if before.as_deref() != Some(profile_name.as_str()) {
+            plan.operations.push(PlanOperation::Mapping {
+                key: repo_key,
+                before,
+                after: Some(profile_name),
+            });
+        }
+
+        Ok(plan)
+    }
+
+    // Points repositories signing with ssh keys at the allowed_signers file
+    // so signatures verify locally. An allowedSignersFile the user set up
+    // themselves is left alone.
+    fn allowed_signers_operation(
+        repo: &Path,
+        profile: &Profile,
+    ) -> Result<Option<PlanOperation>, io::Error> {
+        // profiles without a signing key leave signing to the global config
+        let format = match profile.signing_format() {
+            Some(format) => Some(format.to_string()),
+            None => git::get_global_config("gpg.format")?,
+        };
+        if format.as_deref() != Some("ssh") {
+            return Ok(None);
+        }
+
+        let path = Self::store()?
+            .allowed_signers_path()
+            .to_string_lossy()
+            .to_string();
+        let before = git::get_local_config(repo, signers::CONFIG_KEY)?;
+        let current = git::get_config(repo, signers::CONFIG_KEY)?;
+        if current.as_ref().is_some_and(|current| *current != path) || before == Some(path.clone())
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(PlanOperation::GitConfig {
+            key: signers::CONFIG_KEY.to_string(),
+            before,
+            after: Some(path),
+        }))
+    }
+
+    // re-applies the repository's mapped profile, pushing edits made to the
+    // profile since it was applied down to the repository
+    pub fn sync_repo(project_path: String) -> Result<String, io::Error> {
+        Self::sync_repo_in_stages(project_path, &mut |_| {}).map(|plan| plan.profile_name)
+    }
+
+    pub fn sync_repo_in_stages(
+        project_path: String,
+        on_stage: &mut dyn FnMut(Stage),
+    ) -> Result<ApplyPlan, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.migrate_project_mapping(&Project::new(&project_path)?)?;
+        let plan = Self::plan_sync(project_path)?;
+
+        store.apply_plan_in_stages(Self::git(), &plan, on_stage)?;
+        Ok(plan)
+    }
+
+    // what sync_repo would apply
+    pub fn plan_sync(project_path: String) -> Result<ApplyPlan, io::Error> {
+        let (profile_name, _, _) = Self::get_project_profile(project_path.clone())?;
+        Self::plan_use_profile(profile_name, project_path)
+    }
+
+    // mapped repositories by path, in order; remote-keyed mappings are
+    // shared by all clones and have no single location
+    pub fn mapped_repo_paths() -> Result<Vec<String>, io::Error> {
+        let mut repos: Vec<String> = Self::store()?
+            .read_project_profiles()?
+            .into_keys()
+            .filter(|key| Path::new(key).is_absolute())
+            .collect();
+        repos.sort();
+        Ok(repos)
+    }
+
+    // syncs every mapped repository that can be found on disk
+    pub fn sync_all() -> Result<SyncSummary, io::Error> {
+        Self::sync_all_with(&mut |_| {})
+    }
+
+    pub fn sync_all_with(
+        on_progress: &mut dyn FnMut(SyncProgress),
+    ) -> Result<SyncSummary, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+
+        let repos = Self::mapped_repo_paths()?;
+        let total = repos.len();
+        let mut summary = SyncSummary::default();
+        for repo in repos {
+            on_progress(SyncProgress::Repo { repo: &repo, total });
+            let result = if Path::new(&repo).exists() {
+                Self::sync_repo_in_stages(repo.clone(), &mut |stage| {
+                    on_progress(SyncProgress::Stage(stage))
+                })
+                .map_err(|e| e.to_string())
+            } else {
+                Err("no longer exists, see `octopush move-repo`".to_string())
+            };
+
+            on_progress(SyncProgress::Done {
+                repo: &repo,
+                skipped: result.as_ref().err().map(String::as_str),
+                changed: result.as_ref().map(ApplyPlan::changed).unwrap_or_default(),
+            });
+            match result {
+                Ok(_) => summary.synced.push(repo),
+                Err(reason) => summary.skipped.push((repo, reason)),
+            }
+        }
+
+        // mappings without a location of their own are counted, not lost
+        let mut unlocated: Vec<String> = store
+            .read_project_profiles()?
+            .into_keys()
+            .filter(|key| !Path::new(key).is_absolute())
+            .collect();
+        unlocated.sort();
+        for key in unlocated {
+            let reason = if key.contains('/') {
+                "mapped by remote, which every clone shares; run `octopush sync` inside one"
+            } else {
+                "mapped by directory name by an older octopush; run `octopush sync` inside it"
+            };
+            summary.skipped.push((key, reason.to_string()));
+        }
+
+        Ok(summary)
+    }
+
+    // applies a previously created plan verbatim, refusing if the repository
+    // or mapping has changed since the plan was generated
+    pub fn execute_plan(plan: &ApplyPlan) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        store.verify_plan(Self::git(), plan)?;
+        store.apply_plan(Self::git(), plan)?;
+        store.record_usage(&plan.profile_name)
+    }
+
+    pub fn get_project_profile(
+        project_path: String,
+    ) -> Result<(String, Profile, String) /* profile_name, profile, and repo_name */, io::Error>
+    {
+        let store = Self::store()?;
+        let project = Project::new(project_path)?;
+        let repo_name = project.get_repo_name()?;
+        let (repo_key, _) = store.read_project_mapping(&project)?;
+
+        match store.read_project_profile(&repo_key)? {
+            Some(profile) => Ok((profile.0, profile.1, repo_name)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("profile not found for '{}'", repo_name),
+            )),
+        }
+    }
+
+    // The profile mapped to the repository holding `path`, for `current`.
+    // Prompts call it on every keystroke, so it spawns no git and reads only
+    // the mappings file, plus the repository's config when a mapping is
+    // keyed by remote. Repositories that are only matched by rules report
+    // nothing.
+    pub fn current_profile_name(path: &Path) -> Option<String> {
+        let (root, git_dir) = Project::locate(path)?;
+        let map = Self::store().ok()?.read_project_profiles().ok()?;
+        if map.is_empty() {
+            return None;
+        }
+
+        // remote keys (`host/owner/repo`) win over path keys, as in
+        // read_project_mapping
+        if map
+            .keys()
+            .any(|key| !Path::new(key).is_absolute() && key.contains('/'))
+            && let Some((host, owner, repo)) = Project::config_remote_url(&git_dir)
+                .as_deref()
+                .and_then(git::parse_remote)
+            && let Some(profile_name) = map.get(&format!(
+                "{}/{}/{}",
+                ssh_config::real_host(&host),
+                owner,
+                repo
+            ))
+        {
+            return Some(profile_name.clone());
+        }
+
+        let root = root.canonicalize().ok()?;
+        map.get(root.to_string_lossy().as_ref())
+            .or_else(|| map.get(root.file_name()?.to_str()?))
+            .cloned()
+    }
+
+    // Writes every profile as a gitconfig snippet plus an includes file with
+    // an `[includeIf]` section per mapping and rule, so identities also apply
+    // on machines where octopush never configures the repositories. Later
+    // sections win in git, so they follow octopush's own precedence: mappings,
+    // then gitdir rules, then remote rules.
+    pub fn export_gitconfig() -> Result<GitconfigExport, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let dir = store
+            .ensure_app_config_dir()?
+            .join(ProfileStore::GITCONFIG_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+
+        // snippets of deleted profiles must not linger
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "gitconfig") {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let profiles = store.read_profiles()?;
+        let mut summary = GitconfigExport::default();
+        let mut snippets = HashMap::new();
+        for (profile_name, profile) in profiles.iter().filter(|(_, p)| !p.archived) {
+            let path = dir.join(format!("{}.gitconfig", profile_name));
+            fs::write(&path, export::profile_snippet(profile))?;
+            snippets.insert(profile_name.clone(), path);
+            summary.profiles += 1;
+        }
+
+        // (condition, profile, source) in the order git should evaluate them
+        let mut conditions = Vec::new();
+
+        let mut map: Vec<(String, String)> = store.read_project_profiles()?.into_iter().collect();
+        map.sort();
+        for (key, profile_name) in map {
+            if Path::new(&key).is_absolute() {
+                conditions.push((export::gitdir_condition(&key), profile_name, key));
+            } else {
+                summary.skipped.push((
+                    key,
+                    "mapped by remote, which has no location on disk".to_string(),
+                ));
+            }
+        }
+
+        let rules = store.read_rules()?.rules;
+        for rule in &rules {
+            if let (Some(gitdir), None) = (&rule.gitdir, &rule.remote) {
+                conditions.push((
+                    format!("gitdir:{}", gitdir),
+                    rule.profile.clone(),
+                    gitdir.clone(),
+                ));
+            }
+        }
+        for rule in &rules {
+            match (&rule.gitdir, &rule.remote) {
+                (None, Some(remote)) => {
+                    for condition in export::remote_conditions(remote) {
+                        conditions.push((condition, rule.profile.clone(), remote.clone()));
+                    }
+                }
+                (Some(gitdir), Some(remote)) => summary.skipped.push((
+                    format!("{} + {}", gitdir, remote),
+                    "includeIf cannot combine a gitdir and a remote condition".to_string(),
+                )),
+                _ => {}
+            }
+        }
+
+        let mut sections = Vec::new();
+        for (condition, profile_name, source) in conditions {
+            match snippets.get(&profile_name) {
+                Some(snippet) => sections.push(export::include_section(&condition, snippet)),
+                None => summary.skipped.push((
+                    source,
+                    format!("profile '{}' is missing or archived", profile_name),
+                )),
+            }
+        }
+
+        summary.sections = sections.len();
+        summary.includes_path = dir.join(ProfileStore::INCLUDES_FILE_NAME);
+        let content = format!(
+            "# generated by `octopush export-gitconfig`, changes are overwritten\n{}",
+            sections.join("")
+        );
+        fs::write(&summary.includes_path, content)?;
+
+        Ok(summary)
+    }
+
+    // environment diagnostics: tools, credentials, config files and mappings
+    pub fn doctor() -> Vec<Check> {
+        let mut checks = vec![doctor::check_git()];
+
+        fn parsed<T>(
+            checks: &mut Vec<Check>,
+            file: &str,
+            result: Result<T, io::Error>,
+        ) -> Option<T> {
+            let name = format!("{} is readable", file);
+            match result {
+                Ok(value) => {
+                    checks.push(Check::pass(name));
+                    Some(value)
+                }
+                Err(e) => {
+                    checks.push(Check::fail(name, e.to_string()));
+                    None
+                }
+            }
+        }
+        let profiles = parsed(
+            &mut checks,
+            ProfileStore::PROFILES_FILE_NAME,
+            Self::store().and_then(|store| store.read_profiles()),
+        );
+        let map = parsed(
+            &mut checks,
+            ProfileStore::PROJECT_PROFILES_FILE_NAME,
+            Self::store().and_then(|store| store.read_project_profiles()),
+        );
+        let settings = parsed(
+            &mut checks,
+            ProfileStore::SETTINGS_FILE_NAME,
+            Self::store().and_then(|store| store.read_settings()),
+        );
+        let rules = parsed(
+            &mut checks,
+            ProfileStore::RULES_FILE_NAME,
+            Self::store().and_then(|store| store.read_rules()),
+        );
+        parsed(
+            &mut checks,
+            ProfileStore::USAGE_FILE_NAME,
+            Self::store().and_then(|store| store.read_usage()),
+        );
+        let repos = parsed(
+            &mut checks,
+            ProfileStore::REPOS_FILE_NAME,
+            Self::store().and_then(|store| store.read_repos()),
+        );
+
+        let Some(profiles) = profiles else {
+            return checks;
+        };
+        let mut active: Vec<(&String, &Profile)> =
+            profiles.iter().filter(|(_, p)| !p.archived).collect();
+        active.sort_by(|a, b| a.0.cmp(b.0));
+
+        let default_gh = settings
+            .and_then(|settings| settings.gh_path)
+            .unwrap_or_else(|| "gh".to_string());
+        let mut gh_paths = Vec::new();
+        let mut gh_hosts = Vec::new();
+        let mut glab_hosts = Vec::new();
+        for (profile_name, profile) in &active {
+            if let Some(path) = &profile.ssl_ca_info {
+                checks.push(doctor::check_ca_info(profile_name, path));
+            }
+            // a profile's own credential helper replaces gh, glab and the keychain token
+            if profile.credential_helper.is_some() {
+                continue;
+            }
+            match profile.auth_type {
+                AuthType::SSH => {
+                    if let Some(key) = &profile.ssh_key_path {
+                        checks.push(doctor::check_ssh_key(profile_name, key));
+                    }
+                }
+                AuthType::GH => {
+                    let gh_path = profile.gh_path.clone().unwrap_or(default_gh.clone());
+                    if !gh_paths.contains(&gh_path) {
+                        gh_paths.push(gh_path);
+                    }
+                    let host = profile
+                        .hostname
+                        .clone()
+                        .unwrap_or_else(|| "github.com".to_string());
+                    if !gh_hosts.contains(&host) {
+                        gh_hosts.push(host);
+                    }
+                }
+                AuthType::GitLab => {
+                    let host = profile
+                        .hostname
+                        .clone()
+                        .unwrap_or_else(|| "gitlab.com".to_string());
+                    if !glab_hosts.contains(&host) {
+                        glab_hosts.push(host);
+                    }
+                }
+                AuthType::Token => checks.push(doctor::check_token(profile_name)),
+                AuthType::None => {}
+            }
+        }
+        checks.extend(gh_paths.iter().map(|gh_path| doctor::check_gh(gh_path)));
+        checks.extend(gh_hosts.iter().map(|host| doctor::check_gh_auth(host)));
+        checks.extend(glab_hosts.iter().map(|host| doctor::check_glab_auth(host)));
+
+        if let Some(map) = &map {
+            let mut orphaned: Vec<String> = map
+                .iter()
+                .filter(|(_, profile_name)| !profiles.contains_key(*profile_name))
+                .map(|(key, profile_name)| format!("{} -> {}", key, profile_name))
+                .collect();
+            orphaned.sort();
+            checks.push(if orphaned.is_empty() {
+                Check::pass("every mapping points at an existing profile")
+            } else {
+                Check::fail(
+                    "every mapping points at an existing profile",
+                    orphaned.join(", "),
+                )
+            });
+
+            let mut missing: Vec<&String> = map
+                .keys()
+                .filter(|key| Path::new(key).is_absolute() && !Path::new(key).exists())
+                .collect();
+            missing.sort();
+            if !missing.is_empty() {
+                checks.push(Check::warn(
+                    "every mapped repository exists",
+                    format!(
+                        "{} (moved? see `octopush move-repo`)",
+                        missing
+                            .iter()
+                            .map(|key| key.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(rules) = rules {
+            let orphaned: Vec<&str> = rules
+                .rules
+                .iter()
+                .filter(|rule| !profiles.contains_key(&rule.profile))
+                .map(|rule| rule.profile.as_str())
+                .collect();
+            checks.push(if orphaned.is_empty() {
+                Check::pass("every rule points at an existing profile")
+            } else {
+                Check::fail(
+                    "every rule points at an existing profile",
+                    orphaned.join(", "),
+                )
+            });
+        }
+
+        if let (Some(repos), Some(map)) = (repos, &map) {
+            // mappings of repositories that are still around, without a
+            // record or with one from an older layout
+            let outdated = map
+                .keys()
+                .filter(|key| !Path::new(key).is_absolute() || Path::new(key).exists())
+                .filter(|key| repos.get(*key).is_none_or(RepoRecord::is_outdated))
+                .count();
+            if outdated > 0 {
+                checks.push(Check::warn(
+                    "repositories use the current managed config layout",
+                    format!(
+                        "{} configured by an older octopush, run `octopush sync --all`",
+                        outdated
+                    ),
+                ));
+            }
+        }
+
+        checks
+    }
+
+    // what octopush recorded the last time it configured the repository
+    pub fn repo_record(project_path: String) -> Result<Option<RepoRecord>, io::Error> {
+        let store = Self::store()?;
+        let project = Project::new(project_path)?;
+        let (repo_key, _) = store.read_project_mapping(&project)?;
+        Ok(store.read_repos()?.remove(&repo_key))
+    }
+
+    // Warning::Outdated when the repository's mapping was applied with an
+    // older managed config layout, or before octopush kept records at all
+    pub fn outdated_warning(project_path: String) -> Result<Option<Warning>, io::Error> {
+        let store = Self::store()?;
+        let project = Project::new(project_path)?;
+        let (repo_key, map) = store.read_project_mapping(&project)?;
+        if !map.contains_key(&repo_key) {
+            return Ok(None);
+        }
+
+        Ok(match store.read_repos()?.remove(&repo_key) {
+            Some(record) if !record.is_outdated() => None,
+            record => Some(Warning::Outdated {
+                version: record.map(|record| record.octopush_version),
+            }),
+        })
+    }
+
+    // compares the repository against its mapped profile without changing anything
+    pub fn repo_state(project_path: String) -> Result<RepoState, io::Error> {
+        let (profile_name, profile, _) = match Self::get_project_profile(project_path.clone()) {
+            Ok(found) => found,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RepoState::Unmapped),
+            Err(e) => return Err(e),
+        };
+
+        let profile = Self::resolve_tools(profile)?;
+        let plan = ApplyPlan::build(
+            Self::git(),
+            Path::new(&project_path),
+            &profile_name,
+            &profile,
+        )?;
+        if plan.is_empty() {
+            Ok(RepoState::InSync { profile_name })
+        } else {
+            Ok(RepoState::Drifted { profile_name, plan })
+        }
+    }
+
+    // A mapped repository whose path is gone but whose origin matches the one
+    // at `project_path`, likely the same checkout moved on disk: its old path
+    // and profile, for `status` to suggest `move-repo`.
+    pub fn moved_from(project_path: String) -> Result<Option<(String, String)>, io::Error> {
+        let store = Self::store()?;
+        let Some(remote) = Project::new(project_path)?.get_remote_identity()? else {
+            return Ok(None);
+        };
+
+        let map = store.read_project_profiles()?;
+        let mut candidates: Vec<(String, String)> = store
+            .read_repos()?
+            .into_iter()
+            .filter(|(key, record)| {
+                record.remote.as_deref() == Some(remote.as_str())
+                    && Path::new(key).is_absolute()
+                    && !Path::new(key).exists()
+            })
+            .filter_map(|(key, _)| Some((key.clone(), map.get(&key)?.clone())))
+            .collect();
+        candidates.sort();
+        Ok(candidates.into_iter().next())
+    }
+
+    // re-keys the mapping of a repository that was moved or renamed on disk;
+    // `from` usually no longer exists, so it is matched lexically as well
+    pub fn move_repo(from: &Path, project_path: String) -> Result<String, io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let project = Project::new(project_path)?;
+        store.migrate_project_mapping(&project)?;
+        let (to_key, mut map) = store.read_project_mapping(&project)?;
+
+        let mut candidates = vec![std::path::absolute(from)?.to_string_lossy().to_string()];
+        if let Ok(canonical) = from.canonicalize() {
+            candidates.push(canonical.to_string_lossy().to_string());
+        }
+        candidates.push(from.to_string_lossy().to_string());
+
+        let Some(from_key) = candidates.into_iter().find(|key| map.contains_key(key)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no mapping found for '{}'", from.display()),
+            ));
+        };
+        if from_key == to_key {
+            return Ok(map[&from_key].clone());
+        }
+        if let Some(existing) = map.get(&to_key) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' is already mapped to profile '{}'", to_key, existing),
+            ));
+        }
+
+        let profile_name = map.remove(&from_key).unwrap_or_default();
+        map.insert(to_key.clone(), profile_name.clone());
+        store.write_project_profiles(&map)?;
+
+        let mut repos = store.read_repos()?;
+        if let Some(record) = repos.remove(&from_key) {
+            repos.insert(to_key, record);
+            store.write_repos(&repos)?;
+        }
+
+        Ok(profile_name)
+    }
+
+    // what reset-profile removes, with the values it removes: the mapping,
+    // url rewrites and every setting a profile may have written
+    pub fn plan_reset(project_path: String) -> Result<Vec<PlanOperation>, io::Error> {
+        let store = Self::store()?;
+        let project = Project::new(project_path.clone())?;
+        let repo = Path::new(&project_path);
+        git::ensure_repo(repo)?;
+
+        let mut operations = Vec::new();
+        let (repo_key, map) = store.read_project_mapping(&project)?;
+        if let Some(profile_name) = map.get(&repo_key) {
+            operations.push(PlanOperation::Mapping {
+                key: repo_key.clone(),
+                before: Some(profile_name.clone()),
+                after: None,
+            });
+        }
+
+        let profiles = store.read_profiles()?;
+        operations.extend(plan::rewrite_removals(
+            Self::git(),
+            repo,
+            &[],
+            profiles.values(),
+        )?);
+
+        let signers_path = store.allowed_signers_path();
+        for key in RESET_KEYS.into_iter().chain([signers::CONFIG_KEY]) {
+            let Some(before) = git::get_local_config(repo, key)? else {
+                continue;
+            };
+            // an allowedSignersFile the user set up is theirs to keep
+            if key == signers::CONFIG_KEY && before != signers_path.to_string_lossy() {
+                continue;
+            }
+            operations.push(PlanOperation::GitConfig {
+                key: key.to_string(),
+                before: Some(before),
+                after: None,
+            });
+        }
+
+        Ok(operations)
+    }
+
+    pub fn reset_profile_for_project(project_path: String) -> Result<(), io::Error> {
+        let store = Self::store()?;
+        let _lock = store.lock()?;
+        let repo = Path::new(&project_path);
+        store.migrate_project_mapping(&Project::new(&project_path)?)?;
+
+        for operation in Self::plan_reset(project_path.clone())? {
+            match operation {
+                PlanOperation::Mapping { key, .. } => {
+                    let mut map = store.read_project_profiles()?;
+                    map.remove(&key);
+                    store.write_project_profiles(&map)?;
+
+                    let mut repos = store.read_repos()?;
+                    if repos.remove(&key).is_some() {
+                        store.write_repos(&repos)?;
+                    }
+                }
+                operation => plan::apply_operation(Self::git(), repo, &operation)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// `host/owner/repo` -> (host, owner)
+fn split_identity(identity: &str) -> (&str, &str) {
+    let (host, path) = identity.split_once('/').unwrap_or((identity, ""));
+    // GitLab subgroups make the owner `group/subgroup`
+    let owner = path.rsplit_once('/').map_or(path, |(owner, _)| owner);
+    (host, owner)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::settings::MappingKey;
+    use crate::test_helpers::TempConfig;
+    use crate::util::encryption;
+
+    static CONFIG_DIR_NAME: &str = "octopush-test";
+    static PROFILES_FILE_NAME: &str = "profiles.toml";
+    static PROJECT_PROFILES_FILE_NAME: &str = "project_profiles.toml";
+    static PROFILE_1_PROFILE_NAME: &str = "profile_1_profile_name";
+    static PROFILE_2_PROFILE_NAME: &str = "profile_2_profile_name";
+    static PROFILE_1_NAME: &str = "profile_1_name";
+    static PROFILE_2_NAME: &str = "profile_2_name";
+    static PROFILE_1_EMAIL: &str = "profile-1@email.com";
+    static PROFILE_2_EMAIL: &str = "profile-2@email.com";
+    static PROFILE_1_AUTH_TYPE: AuthType = AuthType::SSH;
+    static PROFILE_2_AUTH_TYPE: AuthType = AuthType::GH;
+    static SSH_KEY_PATH: &str = "~/.ssh/id_rsa";
+    static HOSTNAME: &str = "mycompany.github.com";
+    static REPO_1_NAME: &str = "repo_1";
+    static REPO_2_NAME: &str = "repo_2";
+
+    fn store() -> ProfileStore {
+        ProfileStore::from_env().unwrap()
+    }
+
+    // ProfileStore tests
+
+    #[test]
+    fn gets_config_dir_returns_path_when_exists() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+
+        let config_dir = crate::core::store::base_config_dir()?;
+
+        assert_eq!(config_dir, cfg.base);
+
+        Ok(())
+    }
+
+    #[test]
+    fn gets_app_config_dir_returns_path() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let path_exists = store().ensure_app_config_dir()?.exists();
+
+        assert!(path_exists);
+
+        Ok(())
+    }
+
+    #[test]
+    fn gets_profiles_config_path_returns_path() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+
+        let path = store().profiles_config_path();
+
+        let expected_path = cfg.base.join(CONFIG_DIR_NAME).join(PROFILES_FILE_NAME);
+
+        assert_eq!(path, expected_path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn gets_project_profiles_path() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+
+        let path = store().project_profiles_path()?;
+
+        let expected_path = cfg
+            .base
+            .join("share")
+            .join(CONFIG_DIR_NAME)
+            .join(PROJECT_PROFILES_FILE_NAME);
+
+        assert_eq!(path, expected_path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn moves_state_out_of_the_config_dir() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+        let legacy = store()
+            .ensure_app_config_dir()?
+            .join(PROJECT_PROFILES_FILE_NAME);
+        fs::write(&legacy, "\"/code/app\" = \"work\"\n")?;
+
+        assert_eq!(
+            store().read_project_profiles()?,
+            HashMap::from([("/code/app".to_string(), "work".to_string())])
+        );
+        assert!(!legacy.exists());
+        assert!(
+            cfg.base
+                .join("share")
+                .join(CONFIG_DIR_NAME)
+                .join(PROJECT_PROFILES_FILE_NAME)
+                .exists()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_profile_when_no_profiles_exist_returns_none() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let result = store().read_profile(PROFILE_1_NAME.to_owned())?;
+
+        assert!(result.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_profiles_when_no_profiles_exist_returns_empty_hashmap() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+        let result = store().read_profiles()?;
+
+        assert!(result.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_profile_when_profiles_exist_returns_profile() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+        let ((profile_name, profile), _) = get_profiles();
+
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(profile_name.to_string(), profile.clone())]);
+
+        store().write_profiles(&profiles)?;
+
+        let mut found_profile = store().read_profile(profile_name.to_string())?;
+
+        assert!(found_profile.is_some());
+        assert_eq!(found_profile.take(), Some(profile.clone()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_profiles_when_profiles_exist_returns_profile() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+        let ((profile_1_name, profile_1), (profile_2_name, profile_2)) = get_profiles();
+
+        let profiles: HashMap<String, Profile> = HashMap::from([
+            (profile_1_name.to_string(), profile_1.clone()),
+            (profile_2_name.to_string(), profile_2.clone()),
+        ]);
+
+        store().write_profiles(&profiles)?;
+
+        let found_profiles = store().read_profiles()?;
+
+        assert!(!found_profiles.is_empty());
+        assert_eq!(
+            found_profiles.get(profile_1_name).unwrap().to_owned(),
+            profile_1
+        );
+        assert_eq!(
+            found_profiles.get(profile_2_name).unwrap().to_owned(),
+            profile_2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_project_profile_when_no_profiles_exist_returns_none() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let result = store().read_project_profile(REPO_1_NAME)?;
+
+        assert!(result.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_project_profiles_when_no_profiles_exist_returns_empty_hashmap()
+    -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+        let result = store().read_project_profiles()?;
+
+        assert!(result.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_project_profile_when_profile_exists_returns_profile() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(profile_1_name.to_string(), profile_1.clone())]);
+
+        store().write_profiles(&profiles)?;
+
+        let project_profiles: HashMap<String, String> =
+            HashMap::from([(REPO_1_NAME.to_string(), profile_1_name.to_string())]);
+
+        store().write_project_profiles(&project_profiles)?;
+
+        let result = store().read_project_profile(REPO_1_NAME)?;
+
+        assert_eq!(profile_1, result.unwrap().1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_project_profiles_when_profiles_exist_returns_hashmap() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), (profile_2_name, profile_2)) = get_profiles();
+
+        let profiles: HashMap<String, Profile> = HashMap::from([
+            (profile_1_name.to_string(), profile_1),
+            (profile_2_name.to_string(), profile_2),
+        ]);
+
+        store().write_profiles(&profiles)?;
+
+        let project_profiles: HashMap<String, String> = HashMap::from([
+            (REPO_1_NAME.to_string(), profile_1_name.to_string()),
+            (REPO_2_NAME.to_string(), profile_2_name.to_string()),
+        ]);
+
+        store().write_project_profiles(&project_profiles)?;
+
+        let result = store().read_project_profiles()?;
+
+        assert_eq!(project_profiles, result);
+
+        Ok(())
+    }
+
+    #[test]
+    fn adds_profile_successfully() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+
+        store().add_profile(profile_1_name.to_string(), profile_1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn builds_only_valid_profiles() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let builder = |auth_type| {
+            Profile::builder("John".to_string(), "john@acme.com".to_string(), auth_type)
+        };
+        let ssh = builder(AuthType::SSH)
+            .ssh_key_path("~/.ssh/id_acme".to_string())
+            .ssh_host_alias(true)
+            .build()?;
+        assert_eq!(ssh.ssh_key_path.as_deref(), Some("~/.ssh/id_acme"));
+        assert!(ssh.ssh_host_alias);
+
+        let none_with_host = builder(AuthType::None).hostname("github.com".to_string());
+        assert_eq!(
+            none_with_host.build().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(builder(AuthType::Token).build().is_err());
+        assert!(
+            builder(AuthType::GH)
+                .ssh_key_path("~/.ssh/id_acme".to_string())
+                .build()
+                .is_err()
+        );
+
+        // fields are public, so the store checks again
+        let mut invalid = ssh.clone();
+        invalid.email = "john".to_string();
+        assert!(store().add_profile("acme".to_string(), invalid).is_err());
+        assert!(store().read_profiles()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_on_duplicate_profile() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+
+        store().add_profile(profile_1_name.to_string(), profile_1.clone())?;
+        let result = store().add_profile(profile_1_name.to_string(), profile_1);
+
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            err.to_string(),
+            format!("profile '{}' already exists", profile_1_name)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn adds_profiles_from_manifest_with_summary() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+        store().add_profile(profile_1_name.to_string(), profile_1)?;
+
+        let manifest = cfg.base.join("manifest.toml");
+        fs::write(
+            &manifest,
+            format!(
+                r#"
+[{profile_1_name}]
+name = "dup"
+email = "dup@example.com"
+auth_type = "None"
+
+[new_one]
+name = "New One"
+email = "new@example.com"
+auth_type = "SSH"
+ssh_key_path = "~/.ssh/new"
+
+[bad_one]
+name = "Bad"
+email = "bad@example.com"
+auth_type = "GH"
+ssh_key_path = "~/.ssh/bad"
+"#
+            ),
+        )?;
+
+        let summary = App::add_profiles_from_file(&manifest)?;
+
+        assert_eq!(summary.created, vec!["new_one".to_string()]);
+        assert_eq!(
+            summary.skipped,
+            vec![
+                (
+                    "bad_one".to_string(),
+                    "'ssh_key_path' is not allowed for 'gh' auth type".to_string()
+                ),
+                (
+                    profile_1_name.to_string(),
+                    "profile already exists".to_string()
+                ),
+            ]
+        );
+        assert!(store().read_profile("new_one".to_string())?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn adds_profiles_from_json_manifest() -> Result<(), std::io::Error> {
+        let cfg = TempConfig::new()?;
+
+        let manifest = cfg.base.join("manifest.json");
+        fs::write(
+            &manifest,
+            r#"{"oss": {"name": "Oss", "email": "oss@example.com", "auth_type": "GH", "hostname": "github.com"}}"#,
+        )?;
+
+        let summary = App::add_profiles_from_file(&manifest)?;
+        assert_eq!(summary.created, vec!["oss".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn updates_profile_successfully() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, mut profile_1), _) = get_profiles();
+
+        store().add_profile(profile_1_name.to_string(), profile_1.clone())?;
+        store().update_profile(profile_1_name.to_string(), profile_1.clone())?;
+
+        profile_1.name = "isim".to_string();
+
+        store().update_profile(profile_1_name.to_string(), profile_1.clone())?;
+        let updated_profile = store().read_profile(profile_1_name.to_string())?;
+
+        assert!(updated_profile.is_some());
+        assert_eq!(updated_profile.unwrap(), profile_1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_on_nonexistent_profile() {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+
+        let result = store().update_profile(profile_1_name.to_string(), profile_1);
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            format!("profile '{}' not found", profile_1_name)
+        );
+    }
+
+    #[test]
+    fn errors_when_updating_wrong_profile_fields() {
+        let _cfg = TempConfig::new();
+
+        // profile auth types in order: 1 ssh 2 gh 3 none
+        let ((profile_1_name, mut profile_1), (profile_2_name, mut profile_2)) = get_profiles();
+        let profile_3_name = "profile_3_name";
+        let mut profile_3 = Profile::build(
+            "profile_3".to_string(),
+            "profile_3@mail.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+
+        let profiles: HashMap<String, Profile> = HashMap::from([
+            (profile_1_name.to_string(), profile_1.clone()),
+            (profile_2_name.to_string(), profile_2.clone()),
+            (profile_3_name.to_string(), profile_3.clone()),
+        ]);
+        let _ = store().write_profiles(&profiles);
+
+        // update wrong fields
+        profile_1.hostname = Some("hostname.github.com".to_owned());
+        profile_2.ssh_key_path = Some("some_path/to_key".to_owned());
+        profile_3.hostname = Some("hostname.github.com".to_owned());
+
+        let result = store().update_profile(profile_1_name.to_string(), profile_1);
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!("you cannot update 'hostname' for 'ssh' auth type"),
+        );
+
+        let result = store().update_profile(profile_2_name.to_string(), profile_2);
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!("you cannot update 'ssh_key_path' for 'gh' auth type"),
+        );
+
+        let result = store().update_profile(profile_3_name.to_string(), profile_3);
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!("you cannot update 'hostname' or 'ssh_key_path' for 'none' auth type"),
+        );
+    }
+
+    #[test]
+    fn updates_profile_auth_type_with_matching_fields() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, mut profile_1), _) = get_profiles();
+
+        store().add_profile(profile_1_name.to_string(), profile_1.clone())?;
+
+        // switch from ssh to gh, dropping the ssh key for a hostname
+        profile_1.auth_type = AuthType::GH;
+        profile_1.ssh_key_path = None;
+        profile_1.hostname = Some(HOSTNAME.to_string());
+
+        store().update_profile(profile_1_name.to_string(), profile_1.clone())?;
+        let updated_profile = store().read_profile(profile_1_name.to_string())?;
+
+        assert_eq!(updated_profile, Some(profile_1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn searches_profiles_by_fields_and_tags() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), (profile_2_name, mut profile_2)) = get_profiles();
+        profile_2.tags = vec!["acme-client".to_string()];
+
+        store().add_profile(profile_1_name.to_string(), profile_1)?;
+        store().add_profile(profile_2_name.to_string(), profile_2)?;
+
+        let found = App::search_profiles("acme")?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, profile_2_name);
+
+        // email domain matches as a subsequence
+        let found = App::search_profiles("prfl1email")?;
+        assert_eq!(found[0].0, profile_1_name);
+
+        assert!(App::search_profiles("zzzz")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_profile_successfully() -> Result<(), std::io::Error> {
+        let _cfg = TempConfig::new();
+
+        let ((profile_1_name, profile_1), _) = get_profiles();
+
+        store().add_profile(profile_1_name.to_string(), profile_1)?;
+
+        let profile = store().read_profile(profile_1_name.to_string())?;
+        assert!(profile.is_some());
+
+        store().delete_profile(profile_1_name.to_string())?;
+
+        let profile = store().read_profile(profile_1_name.to_string())?;
+        assert!(profile.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_when_deleting_nonexistent_profile() {
+        let _cfg = TempConfig::new();
+
+        let profile_name = "profile_name";
+        let result = store().delete_profile(profile_name.to_owned());
+        let err = result.unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            format!("profile '{}' not found", profile_name)
+        );
+    }
+
+    // App tests
+    #[test]
+    fn use_profile_errors_when_missing_profile() {
+        let cfg = TempConfig::new().unwrap();
+
+        let err = App::use_profile("nope".to_string(), cfg.repo.to_string_lossy().to_string())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn use_profile_applies_ssh_and_records_mapping() {
+        let cfg = TempConfig::new().unwrap();
+
+        // Arrange: write SSH profile and set an https remote to verify rewrite
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
+        store().write_profiles(&profiles).unwrap();
+
+        // set remote to https so it should be converted to ssh
+        let _ = git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "https://github.com/acme/app.git"],
+        )
+        .unwrap();
+
+        // Act
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        // Assert: mapping exists for this repo
+        let repo_key = Project::new(&cfg.repo).unwrap().get_repo_key().unwrap();
+        let mapping = store().read_project_profiles().unwrap();
+        assert_eq!(mapping.get(&repo_key), Some(&ssh_profile_name.to_string()));
+
+        // Assert: identity set
+        let g1 = git::run_git(&cfg.repo, ["config", "--local", "user.name"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&g1.stdout).trim(), ssh_profile.name);
+        let g2 = git::run_git(&cfg.repo, ["config", "--local", "user.email"]).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&g2.stdout).trim(),
+            ssh_profile.email
+        );
+
+        // Assert: sshCommand set (skip strict check on Windows)
+        let ssh_cmd = git::run_git(&cfg.repo, ["config", "--local", "core.sshCommand"]).unwrap();
+        if cfg!(windows) {
+            assert!(ssh_cmd.status.success());
+        } else {
+            let out = String::from_utf8_lossy(&ssh_cmd.stdout);
+            assert!(out.contains(SSH_KEY_PATH));
+        }
+
+        // Assert: remote rewritten to ssh
+        let url = git::get_remote_url(&cfg.repo, "origin").unwrap();
+        assert_eq!(url.as_deref(), Some("git@github.com:acme/app.git"));
+
+        // Assert: gh credential helper cleared
+        let gh = git::run_git(
+            &cfg.repo,
+            ["config", "--local", "--get", "credential.helper"],
+        )
+        .unwrap();
+        assert!(!gh.status.success());
+    }
+
+    #[test]
+    fn global_profile_is_included_and_reset() {
+        let cfg = TempConfig::new().unwrap();
+        let prev_global = std::env::var_os("GIT_CONFIG_GLOBAL");
+        let global = cfg.base.join("global-gitconfig");
+        unsafe {
+            std::env::set_var("GIT_CONFIG_GLOBAL", &global);
+        }
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile.clone())
+            .unwrap();
+
+        let path = App::use_profile_globally(ssh_profile_name.to_string()).unwrap();
+        // applying twice must not include the file twice
+        App::use_profile_globally(ssh_profile_name.to_string()).unwrap();
+        let email = git::run_git(&cfg.repo, ["config", "user.email"]).unwrap();
+        let includes = fs::read_to_string(&global).unwrap();
+        let reset = App::reset_global_profile().unwrap();
+        let reset_again = App::reset_global_profile().unwrap();
+        let email_after = git::run_git(&cfg.repo, ["config", "user.email"]).unwrap();
+
+        unsafe {
+            match prev_global {
+                Some(v) => std::env::set_var("GIT_CONFIG_GLOBAL", v),
+                None => std::env::remove_var("GIT_CONFIG_GLOBAL"),
+            }
+        }
+
+        assert_eq!(
+            String::from_utf8_lossy(&email.stdout).trim(),
+            ssh_profile.email
+        );
+        assert_eq!(includes.matches(&*path.to_string_lossy()).count(), 1);
+        assert!(reset);
+        assert!(!reset_again);
+        assert!(!path.exists());
+        assert!(!email_after.status.success());
+    }
+
+    #[test]
+    fn url_rewrites_follow_the_applied_profile() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        let key = "url.git@github.com-work:acme/.insteadOf";
+
+        let ((ssh_profile_name, mut ssh_profile), (gh_profile_name, gh_profile)) = get_profiles();
+        ssh_profile.url_rewrites.insert(
+            "https://github.com/acme/".to_string(),
+            "git@github.com-work:acme/".to_string(),
+        );
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
+        git::set_local_config(
+            &cfg.repo,
+            "url.git@example.com:.insteadOf",
+            "https://example.com/",
+        )
+        .unwrap();
+
+        App::use_profile(ssh_profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, key).unwrap().as_deref(),
+            Some("https://github.com/acme/")
+        );
+
+        App::use_profile(gh_profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(git::get_local_config(&cfg.repo, key).unwrap(), None);
+        // rewrites octopush did not write are left alone
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "url.git@example.com:.insteadOf")
+                .unwrap()
+                .as_deref(),
+            Some("https://example.com/")
+        );
+
+        App::use_profile(ssh_profile_name.to_string(), repo.clone()).unwrap();
+        App::reset_profile_for_project(repo).unwrap();
+        assert_eq!(git::get_local_config(&cfg.repo, key).unwrap(), None);
+    }
+
+    #[test]
+    fn http_settings_are_applied_and_reset() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let (_, (gh_profile_name, mut gh_profile)) = get_profiles();
+        gh_profile.ssl_ca_info = Some("~/certs/corp.pem".to_string());
+        gh_profile.ssl_verify = Some(true);
+        gh_profile.http_proxy = Some("http://proxy.corp.com:3128".to_string());
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
+
+        App::use_profile(gh_profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.sslCAInfo")
+                .unwrap()
+                .as_deref(),
+            Some("~/certs/corp.pem")
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.sslVerify")
+                .unwrap()
+                .as_deref(),
+            Some("true")
+        );
+
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.proxy")
+                .unwrap()
+                .as_deref(),
+            Some("http://proxy.corp.com:3128")
+        );
+
+        App::reset_profile_for_project(repo).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.sslCAInfo").unwrap(),
+            None
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.proxy").unwrap(),
+            None
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "http.sslVerify").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn signing_settings_are_applied_and_reset() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let key_path = cfg.base.join("id_work");
+        fs::write(
+            cfg.base.join("id_work.pub"),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIwork john@laptop\n",
+        )
+        .unwrap();
+        let ((ssh_profile_name, mut ssh_profile), _) = get_profiles();
+        ssh_profile.ssh_key_path = Some(key_path.to_string_lossy().to_string());
+        ssh_profile.signing_key = Some(format!("{}.pub", key_path.display()));
+        ssh_profile.gpg_program = Some("gpg2".to_string());
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile.clone())
+            .unwrap();
+
+        App::use_profile(ssh_profile_name.to_string(), repo.clone()).unwrap();
+        let signers_path = store().allowed_signers_path();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, signers::CONFIG_KEY)
+                .unwrap()
+                .as_deref(),
+            Some(&*signers_path.to_string_lossy())
+        );
+        assert!(
+            fs::read_to_string(&signers_path)
+                .unwrap()
+                .contains(&format!(
+                    "{} namespaces=\"git\" ssh-ed25519 ",
+                    ssh_profile.email
+                ))
+        );
+
+        // edits to the profile reach the file without re-applying it
+        ssh_profile.email = "john@new.com".to_string();
+        App::update_profile(ssh_profile_name.to_string(), ssh_profile).unwrap();
+        assert!(
+            fs::read_to_string(&signers_path)
+                .unwrap()
+                .contains("john@new.com namespaces=\"git\" ")
+        );
+
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "gpg.program")
+                .unwrap()
+                .as_deref(),
+            Some("gpg2")
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "gpg.format")
+                .unwrap()
+                .as_deref(),
+            Some("ssh")
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "commit.gpgSign")
+                .unwrap()
+                .as_deref(),
+            Some("true")
+        );
+
+        App::reset_profile_for_project(repo).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, signers::CONFIG_KEY).unwrap(),
+            None
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "gpg.program").unwrap(),
+            None
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "user.signingKey").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_credential_helper_is_applied_and_reset() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let (_, (gh_profile_name, mut gh_profile)) = get_profiles();
+        gh_profile.credential_helper = Some("osxkeychain".to_string());
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
+
+        App::use_profile(gh_profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "credential.helper")
+                .unwrap()
+                .as_deref(),
+            Some("osxkeychain")
+        );
+        assert!(matches!(
+            App::repo_state(repo.clone()).unwrap(),
+            RepoState::InSync { .. }
+        ));
+
+        App::reset_profile_for_project(repo).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "credential.helper").unwrap(),
+            None
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "credential.useHttpPath").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn applies_the_credential_cache_timeout() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let (_, (gh_profile_name, mut gh_profile)) = get_profiles();
+        gh_profile.credential_cache_timeout = Some(3_600);
+        assert!(gh_profile.validate().is_err());
+        gh_profile.credential_helper = Some("cache --timeout=60".to_string());
+        assert!(gh_profile.validate().is_err());
+
+        gh_profile.credential_helper = Some("cache".to_string());
+        assert_eq!(gh_profile.credential_cache_lifetime(), Some(3_600));
+        App::add_profile(gh_profile_name.to_string(), gh_profile.clone()).unwrap();
+        App::use_profile(gh_profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "credential.helper")
+                .unwrap()
+                .as_deref(),
+            Some("cache --timeout=3600")
+        );
+        assert!(matches!(
+            App::repo_state(repo).unwrap(),
+            RepoState::InSync { .. }
+        ));
+
+        gh_profile.credential_cache_timeout = None;
+        assert_eq!(gh_profile.credential_cache_lifetime(), Some(900));
+        gh_profile.credential_helper = Some("osxkeychain".to_string());
+        assert_eq!(gh_profile.credential_cache_lifetime(), None);
+    }
+
+    #[test]
+    fn use_profile_applies_gh_and_records_mapping() {
+        let cfg = TempConfig::new().unwrap();
+
+        // Arrange: write GH profile and set an ssh remote to verify rewrite
+        let (_, gh_pair) = get_profiles();
+        let (gh_profile_name, gh_profile) = gh_pair;
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(gh_profile_name.to_string(), gh_profile.clone())]);
+        store().write_profiles(&profiles).unwrap();
+
+        // set remote to ssh so it should be converted to https
+        let _ = git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "git@github.com:acme/app.git"],
+        )
+        .unwrap();
+
+        // Act
+        App::use_profile(
+            gh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        // Assert: mapping exists for this repo
+        let repo_key = Project::new(&cfg.repo).unwrap().get_repo_key().unwrap();
+        let mapping = store().read_project_profiles().unwrap();
+        assert_eq!(mapping.get(&repo_key), Some(&gh_profile_name.to_string()));
+
+        // Assert: identity set
+        let g1 = git::run_git(&cfg.repo, ["config", "--local", "user.name"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&g1.stdout).trim(), gh_profile.name);
+        let g2 = git::run_git(&cfg.repo, ["config", "--local", "user.email"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&g2.stdout).trim(), gh_profile.email);
+
+        // Assert: sshCommand cleared
+        let ssh =
+            git::run_git(&cfg.repo, ["config", "--local", "--get", "core.sshCommand"]).unwrap();
+        assert!(!ssh.status.success());
+
+        // Assert: remote rewritten to https
+        let url = git::get_remote_url(&cfg.repo, "origin").unwrap();
+        assert_eq!(url.as_deref(), Some("https://github.com/acme/app.git"));
+
+        // Assert: gh credential helper set
+        let gh = git::run_git(&cfg.repo, ["config", "--local", "credential.helper"]).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&gh.stdout).trim(),
+            "!gh auth git-credential"
+        );
+        let gh2 = git::run_git(&cfg.repo, ["config", "--local", "credential.useHttpPath"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&gh2.stdout).trim(), "true");
+    }
+
+    #[test]
+    fn use_profile_applies_token_credential_helper() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let mut profile = Profile::build(
+            "Token User".to_string(),
+            "token@example.com".to_string(),
+            AuthType::Token,
+            None,
+            None,
+        );
+        // the token is only handed out for the profile's host
+        assert!(profile.validate().is_err());
+
+        profile.hostname = Some("git.corp.com".to_string());
+        App::add_profile("corp".to_string(), profile).unwrap();
+        App::use_profile("corp".to_string(), repo.clone()).unwrap();
+
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "credential.helper")
+                .unwrap()
+                .as_deref(),
+            Some(git::TOKEN_CREDENTIAL_HELPER)
+        );
+        assert_eq!(detect_auth_type(&cfg.repo).unwrap(), AuthType::Token);
+        // git only ever asks the helper to `get`; the others are no-ops
+        assert_eq!(
+            App::credential("store", "protocol=https\nhost=git.corp.com\n", repo).unwrap(),
+            None
+        );
+
+        let ((ssh_name, ssh_profile), _) = get_profiles();
+        App::add_profile(ssh_name.to_string(), ssh_profile).unwrap();
+        let err = App::set_token(ssh_name.to_string(), "s3cret".to_string()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn use_profile_applies_none_clears_auth_helpers() {
+        let cfg = TempConfig::new().unwrap();
+
+        // Arrange: create a NONE profile and write it
+        let profile_name = "none_profile";
+        let none_profile = Profile::build(
+            "None User".to_string(),
+            "none@example.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(profile_name.to_string(), none_profile.clone())]);
+        store().write_profiles(&profiles).unwrap();
+
+        // Pre-set ssh and gh helpers to verify they get cleared
+        git::ensure_ssh_command(&cfg.repo, "/tmp/fake_key").unwrap_or(());
+        let _ = git::run_git(
+            &cfg.repo,
+            [
+                "config",
+                "--local",
+                "credential.helper",
+                "!gh auth git-credential",
+            ],
+        )
+        .unwrap();
+
+        // Act
+        App::use_profile(
+            profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        // Assert: ssh cleared
+        let ssh =
+            git::run_git(&cfg.repo, ["config", "--local", "--get", "core.sshCommand"]).unwrap();
+        assert!(!ssh.status.success());
+        // Assert: gh helper cleared
+        let gh = git::run_git(
+            &cfg.repo,
+            ["config", "--local", "--get", "credential.helper"],
+        )
+        .unwrap();
+        assert!(!gh.status.success());
+    }
+
+    #[test]
+    fn plan_use_profile_does_not_touch_repo_or_mapping() {
+        let cfg = TempConfig::new().unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
+        store().write_profiles(&profiles).unwrap();
+
+        let plan = App::plan_use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        assert!(plan.operations.iter().any(|op| matches!(
+            op,
+            PlanOperation::Mapping { after: Some(name), .. } if name == ssh_profile_name
+        )));
+        assert!(store().read_project_profiles().unwrap().is_empty());
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "user.email").unwrap(),
+            None
+        );
+
+        App::execute_plan(&plan).unwrap();
+
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "user.email").unwrap(),
+            Some(ssh_profile.email)
+        );
+        assert_eq!(store().read_project_profiles().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn execute_plan_refuses_stale_plan() {
+        let cfg = TempConfig::new().unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(ssh_profile_name.to_string(), ssh_profile)]);
+        store().write_profiles(&profiles).unwrap();
+
+        let plan = App::plan_use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        // the repository changes between planning and execution
+        git::set_local_config(&cfg.repo, "user.name", "someone else").unwrap();
+
+        let err = App::execute_plan(&plan).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "plan is stale: 'git config user.name' changed since the plan was created"
+        );
+    }
+
+    #[test]
+    fn profile_from_repo_adopts_local_identity() {
+        let cfg = TempConfig::new().unwrap();
+
+        let err = App::profile_from_repo(cfg.repo.to_string_lossy().to_string()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+        git::set_local_identity(&cfg.repo, "Repo User", "repo@example.com").unwrap();
+        git::ensure_ssh_command(&cfg.repo, "/tmp/repo_key").unwrap();
+
+        let profile = App::profile_from_repo(cfg.repo.to_string_lossy().to_string()).unwrap();
+
+        assert_eq!(
+            profile,
+            Profile::build(
+                "Repo User".to_string(),
+                "repo@example.com".to_string(),
+                AuthType::SSH,
+                None,
+                Some("/tmp/repo_key".to_string()),
+            )
+        );
+    }
+
+    #[test]
+    fn use_profile_records_usage() {
+        let cfg = TempConfig::new().unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+
+        for _ in 0..2 {
+            App::use_profile(
+                ssh_profile_name.to_string(),
+                cfg.repo.to_string_lossy().to_string(),
+            )
+            .unwrap();
+        }
+
+        let usage = App::list_usage().unwrap();
+        let entry = usage.get(ssh_profile_name).unwrap();
+        assert_eq!(entry.use_count, 2);
+        assert!(entry.last_used > 0);
+
+        store()
+            .delete_profile(ssh_profile_name.to_string())
+            .unwrap();
+        assert!(App::list_usage().unwrap().is_empty());
+    }
+
+    #[test]
+    fn use_profile_refuses_archived_profile() {
+        let cfg = TempConfig::new().unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        App::archive_profile(ssh_profile_name.to_string(), true).unwrap();
+
+        let err = App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(store().read_project_profiles().unwrap().is_empty());
+
+        App::archive_profile(ssh_profile_name.to_string(), false).unwrap();
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn current_profile_name_reads_mappings_without_git() {
+        let cfg = TempConfig::new().unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        assert_eq!(App::current_profile_name(&cfg.repo), None);
+
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+        let nested = cfg.repo.join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(
+            App::current_profile_name(&nested).as_deref(),
+            Some(ssh_profile_name)
+        );
+        assert_eq!(App::current_profile_name(&cfg.base), None);
+
+        // a mapping keyed by the remote wins over the path
+        git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "git@github.com:acme/app.git"],
+        )
+        .unwrap();
+        let mut map = store().read_project_profiles().unwrap();
+        map.insert("github.com/acme/app".to_string(), "by-remote".to_string());
+        store().write_project_profiles(&map).unwrap();
+        assert_eq!(
+            App::current_profile_name(&cfg.repo).as_deref(),
+            Some("by-remote")
+        );
+    }
+
+    #[test]
+    fn get_project_profile_returns_selected_profile() {
+        let cfg = TempConfig::new().unwrap();
+
+        // Arrange: write a profile and use it
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        let profiles: HashMap<String, Profile> =
+            HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
+        store().write_profiles(&profiles).unwrap();
+
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        let (_profile_name, found_profile, _repo_name) =
+            App::get_project_profile(cfg.repo.to_string_lossy().to_string()).unwrap();
+
+        // Assert
+        assert_eq!(found_profile, ssh_profile);
     }
 
     #[test]
-    fn reads_profiles_when_profiles_exist_returns_profile() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-        let ((profile_1_name, profile_1), (profile_2_name, profile_2)) = get_profiles();
+    fn refuses_credentials_for_insecure_remotes_unless_allowed() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
 
-        let profiles: HashMap<String, Profile> = HashMap::from([
-            (profile_1_name.to_string(), profile_1.clone()),
-            (profile_2_name.to_string(), profile_2.clone()),
-        ]);
+        git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "http://example.com/acme/app.git"],
+        )
+        .unwrap();
 
-        TestPM::write_profiles(&profiles)?;
+        let (_, (gh_profile_name, gh_profile)) = get_profiles();
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
 
-        let found_profiles = TestPM::read_profiles()?;
+        let err = App::plan_use_profile(gh_profile_name.to_string(), repo.clone()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
 
-        assert!(!found_profiles.is_empty());
+        App::save_settings(&Settings {
+            allow_insecure_remotes: true,
+            ..Settings::default()
+        })
+        .unwrap();
+        assert!(App::plan_use_profile(gh_profile_name.to_string(), repo).is_ok());
+    }
+
+    #[test]
+    fn warns_about_insecure_remotes_of_unmapped_repos() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        assert!(App::remote_warnings(repo.clone()).unwrap().is_empty());
+
+        git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "git://example.com/acme/app.git"],
+        )
+        .unwrap();
         assert_eq!(
-            found_profiles.get(profile_1_name).unwrap().to_owned(),
-            profile_1
+            App::remote_warnings(repo).unwrap(),
+            vec![Warning::InsecureRemote {
+                remote: "origin".to_string(),
+                url: "git://example.com/acme/app.git".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn recognises_a_moved_checkout_by_its_origin() {
+        let cfg = TempConfig::new().unwrap();
+        let old = cfg.base.join("old").join("app");
+        let new = cfg.base.join("new").join("app");
+        TempConfig::init_repo_at(&old);
+        git::run_git(
+            &old,
+            ["remote", "add", "origin", "git@github.com:acme/app.git"],
+        )
+        .unwrap();
+
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            old.to_string_lossy().to_string(),
+        )
+        .unwrap();
+        let old_key = old.canonicalize().unwrap().to_string_lossy().to_string();
+
+        let new_path = new.to_string_lossy().to_string();
+        fs::create_dir_all(new.parent().unwrap()).unwrap();
+        fs::rename(&old, &new).unwrap();
+        assert_eq!(
+            App::repo_state(new_path.clone()).unwrap(),
+            RepoState::Unmapped
         );
         assert_eq!(
-            found_profiles.get(profile_2_name).unwrap().to_owned(),
-            profile_2
+            App::moved_from(new_path.clone()).unwrap(),
+            Some((old_key.clone(), ssh_profile_name.to_string()))
         );
 
-        Ok(())
+        App::move_repo(Path::new(&old_key), new_path.clone()).unwrap();
+        assert_eq!(App::moved_from(new_path).unwrap(), None);
     }
 
     #[test]
-    fn reads_project_profile_when_no_profiles_exist_returns_none() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    fn clones_with_the_same_name_keep_separate_mappings() {
+        let cfg = TempConfig::new().unwrap();
 
-        let result = TestPM::read_project_profile(REPO_1_NAME)?;
+        let first = cfg.base.join("a").join("app");
+        let second = cfg.base.join("b").join("app");
+        TempConfig::init_repo_at(&first);
+        TempConfig::init_repo_at(&second);
 
-        assert!(result.is_none());
+        let ((ssh_profile_name, ssh_profile), (gh_profile_name, gh_profile)) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
 
-        Ok(())
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            first.to_string_lossy().to_string(),
+        )
+        .unwrap();
+        App::use_profile(
+            gh_profile_name.to_string(),
+            second.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        let (first_profile, _, _) =
+            App::get_project_profile(first.to_string_lossy().to_string()).unwrap();
+        let (second_profile, _, _) =
+            App::get_project_profile(second.to_string_lossy().to_string()).unwrap();
+
+        assert_eq!(first_profile, ssh_profile_name);
+        assert_eq!(second_profile, gh_profile_name);
     }
 
     #[test]
-    fn reads_project_profiles_when_no_profiles_exist_returns_empty_hashmap()
-    -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-        let result = TestPM::read_project_profiles()?;
+    fn remote_keyed_mapping_follows_fresh_clones() {
+        let cfg = TempConfig::new().unwrap();
 
-        assert!(result.is_empty());
+        let first = cfg.base.join("first").join("app");
+        let second = cfg.base.join("second").join("app-copy");
+        for repo in [&first, &second] {
+            TempConfig::init_repo_at(repo);
+            git::run_git(
+                repo,
+                ["remote", "add", "origin", "git@github.com:acme/app.git"],
+            )
+            .unwrap();
+        }
 
-        Ok(())
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        App::save_settings(&Settings {
+            mapping_key: MappingKey::Remote,
+            ..Settings::default()
+        })
+        .unwrap();
+
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            first.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        let mapping = store().read_project_profiles().unwrap();
+        assert_eq!(
+            mapping.get("github.com/acme/app"),
+            Some(&ssh_profile_name.to_string())
+        );
+
+        let (profile_name, _, _) =
+            App::get_project_profile(second.to_string_lossy().to_string()).unwrap();
+        assert_eq!(profile_name, ssh_profile_name);
     }
 
     #[test]
-    fn reads_project_profile_when_profile_exists_returns_profile() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    fn move_repo_transfers_mapping_to_new_location() {
+        let cfg = TempConfig::new().unwrap();
 
-        let ((profile_1_name, profile_1), _) = get_profiles();
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        App::use_profile(
+            ssh_profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
+        )
+        .unwrap();
 
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(profile_1_name.to_string(), profile_1.clone())]);
+        let old_path = cfg.repo.canonicalize().unwrap();
+        let new_path = cfg.base.join("moved");
+        fs::rename(&cfg.repo, &new_path).unwrap();
 
-        TestPM::write_profiles(&profiles)?;
+        let err = App::get_project_profile(new_path.to_string_lossy().to_string()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
 
-        let project_profiles: HashMap<String, String> =
-            HashMap::from([(REPO_1_NAME.to_string(), profile_1_name.to_string())]);
+        let moved = App::move_repo(&old_path, new_path.to_string_lossy().to_string()).unwrap();
+        assert_eq!(moved, ssh_profile_name);
+
+        let (profile_name, _, _) =
+            App::get_project_profile(new_path.to_string_lossy().to_string()).unwrap();
+        assert_eq!(profile_name, ssh_profile_name);
+        assert!(
+            !store()
+                .read_project_profiles()
+                .unwrap()
+                .contains_key(&old_path.to_string_lossy().to_string())
+        );
+    }
 
-        TestPM::write_project_profiles(&project_profiles)?;
+    #[test]
+    fn init_repo_and_clone_apply_new_repo_defaults() {
+        let cfg = TempConfig::new().unwrap();
 
-        let result = TestPM::read_project_profile(REPO_1_NAME)?;
+        let mut profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+        profile.default_branch = Some("trunk".to_string());
+        store().add_profile("org".to_string(), profile).unwrap();
 
-        assert_eq!(profile_1, result.unwrap().1);
+        let created = cfg.base.join("created");
+        App::init_repo("org".to_string(), created.to_string_lossy().to_string()).unwrap();
 
-        Ok(())
+        let head = git::run_git(&created, ["symbolic-ref", "HEAD"]).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&head.stdout).trim(),
+            "refs/heads/trunk"
+        );
+        let (profile_name, _, _) =
+            App::get_project_profile(created.to_string_lossy().to_string()).unwrap();
+        assert_eq!(profile_name, "org");
+
+        let cloned = cfg.base.join("cloned");
+        App::clone_repo(
+            "org".to_string(),
+            created.to_string_lossy().to_string(),
+            cloned.to_string_lossy().to_string(),
+        )
+        .unwrap();
+
+        let email = git::get_local_config(&cloned, "user.email").unwrap();
+        assert_eq!(email.as_deref(), Some("name@example.com"));
     }
 
     #[test]
-    fn reads_project_profiles_when_profiles_exist_returns_hashmap() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    fn resolves_profile_from_gitdir_rules() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
 
-        let ((profile_1_name, profile_1), (profile_2_name, profile_2)) = get_profiles();
+        let err = App::resolve_profile_name(repo.clone()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
 
-        let profiles: HashMap<String, Profile> = HashMap::from([
-            (profile_1_name.to_string(), profile_1),
-            (profile_2_name.to_string(), profile_2),
-        ]);
+        let base = cfg.base.canonicalize().unwrap();
+        App::save_rules(&Rules {
+            rules: vec![crate::core::rules::Rule {
+                gitdir: Some(format!("{}/", base.to_string_lossy())),
+                remote: None,
+                profile: "work".to_string(),
+            }],
+        })
+        .unwrap();
 
-        TestPM::write_profiles(&profiles)?;
+        assert_eq!(App::resolve_profile_name(repo.clone()).unwrap(), "work");
 
-        let project_profiles: HashMap<String, String> = HashMap::from([
-            (REPO_1_NAME.to_string(), profile_1_name.to_string()),
-            (REPO_2_NAME.to_string(), profile_2_name.to_string()),
-        ]);
+        git::run_git(
+            &cfg.repo,
+            [
+                "remote",
+                "add",
+                "origin",
+                "git@gitlab.mycorp.com:team/app.git",
+            ],
+        )
+        .unwrap();
+        let mut rules = App::rules().unwrap();
+        rules.rules.insert(
+            0,
+            crate::core::rules::Rule {
+                gitdir: None,
+                remote: Some("gitlab.mycorp.com/**".to_string()),
+                profile: "corp".to_string(),
+            },
+        );
+        App::save_rules(&rules).unwrap();
 
-        TestPM::write_project_profiles(&project_profiles)?;
+        assert_eq!(App::resolve_profile_name(repo).unwrap(), "corp");
+    }
 
-        let result = TestPM::read_project_profiles()?;
+    #[test]
+    fn falls_back_to_default_profile_and_expands_clone_shorthand() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        App::set_setting("default_profile", "work").unwrap();
+        assert_eq!(App::resolve_profile_name(repo).unwrap(), "work");
+
+        let gh = Profile::build(
+            "John".to_string(),
+            "john@acme.com".to_string(),
+            AuthType::GH,
+            None,
+            None,
+        );
+        let clone_url = |url: &str| App::clone_url("work", &gh, url.to_string()).unwrap();
+        assert_eq!(clone_url("acme/app"), "https://github.com/acme/app.git");
+        assert_eq!(clone_url("git@host:acme/app.git"), "git@host:acme/app.git");
 
-        assert_eq!(project_profiles, result);
+        App::set_setting("protocol", "ssh").unwrap();
+        assert_eq!(clone_url("acme/app"), "git@github.com:acme/app.git");
+    }
 
-        Ok(())
+    #[test]
+    fn encrypts_profiles_at_rest_when_enabled() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        store()
+            .write_profiles(&HashMap::from([(
+                profile_name.to_string(),
+                profile.clone(),
+            )]))
+            .unwrap();
+        let path = store().profiles_config_path();
+        cfg.set_profiles_key(&encryption::Key::generate().unwrap().to_hex());
+
+        App::set_setting("encrypt_profiles", "true").unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(encryption::is_encrypted(&content));
+        assert!(!content.contains(&profile.email));
+        assert_eq!(App::get_profile(profile_name.to_string()).unwrap(), profile);
+
+        App::set_setting("encrypt_profiles", "false").unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains(&profile.email));
+
+        // a key that does not match leaves the setting as it was
+        App::set_setting("encrypt_profiles", "true").unwrap();
+        cfg.set_profiles_key(&encryption::Key::generate().unwrap().to_hex());
+        assert!(App::get_profile(profile_name.to_string()).is_err());
+        assert!(App::set_setting("encrypt_profiles", "false").is_err());
+        assert!(App::settings().unwrap().encrypt_profiles);
     }
 
     #[test]
-    fn adds_profile_successfully() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    fn resolves_profile_from_local_file_first() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        App::save_rules(&Rules {
+            rules: vec![crate::core::rules::Rule {
+                gitdir: Some(format!("{}/", cfg.base.canonicalize().unwrap().display())),
+                remote: None,
+                profile: "work".to_string(),
+            }],
+        })
+        .unwrap();
 
-        let ((profile_1_name, profile_1), _) = get_profiles();
+        fs::write(
+            cfg.repo.join(local::FILE_NAME),
+            "[acme]\nname = \"John\"\nemail = \"john@acme.com\"\nauth_type = \"None\"\n",
+        )
+        .unwrap();
+        assert_eq!(App::resolve_profile_name(repo.clone()).unwrap(), "acme");
+        assert_eq!(
+            App::get_profile("acme".to_string()).unwrap().email,
+            "john@acme.com"
+        );
+
+        let other = Profile::build(
+            "John".to_string(),
+            "john@home.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+        App::add_profile("home".to_string(), other.clone()).unwrap();
+        assert_eq!(
+            App::preflight("home".to_string(), repo.clone(), &Remotes::Primary).unwrap(),
+            [Warning::LocalMismatch {
+                expected: "acme".to_string()
+            }]
+        );
 
-        TestPM::add_profile(profile_1_name.to_string(), profile_1)?;
+        // an inline profile never overrides the user's own of the same name
+        fs::write(
+            cfg.repo.join(local::FILE_NAME),
+            "[home]\nname = \"John\"\nemail = \"john@acme.com\"\nauth_type = \"None\"\n",
+        )
+        .unwrap();
+        let err = App::resolve_profile_name(repo.clone()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(App::get_profile("home".to_string()).unwrap(), other);
 
-        Ok(())
+        fs::write(cfg.repo.join(local::FILE_NAME), "profile = \"home\"\n").unwrap();
+        assert_eq!(App::resolve_profile_name(repo).unwrap(), "home");
     }
 
     #[test]
-    fn errors_on_duplicate_profile() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-
-        let ((profile_1_name, profile_1), _) = get_profiles();
+    fn login_runs_configured_gh_for_profile_host() {
+        let cfg = TempConfig::new().unwrap();
 
-        TestPM::add_profile(profile_1_name.to_string(), profile_1.clone())?;
-        let result = TestPM::add_profile(profile_1_name.to_string(), profile_1);
+        let args = cfg.base.join("gh-args");
+        let gh = cfg.base.join("bin").join("gh");
+        fs::create_dir_all(gh.parent().unwrap()).unwrap();
+        fs::write(
+            &gh,
+            format!("#!/bin/sh\necho \"$@\" > {}\n", args.display()),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&gh, fs::Permissions::from_mode(0o755)).unwrap();
+        }
 
-        let err = result.unwrap_err();
+        let (_, (gh_profile_name, gh_profile)) = get_profiles();
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
+        App::save_settings(&Settings {
+            gh_path: Some(gh.to_string_lossy().to_string()),
+            ..Settings::default()
+        })
+        .unwrap();
 
-        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        App::login(gh_profile_name.to_string()).unwrap();
         assert_eq!(
-            err.to_string(),
-            format!("profile '{}' already exists", profile_1_name)
+            fs::read_to_string(&args).unwrap(),
+            format!("auth login --hostname {}\n", HOSTNAME)
         );
-
-        Ok(())
     }
 
     #[test]
-    fn updates_profile_successfully() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
+    fn gh_credential_helper_uses_configured_binary() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let gh = cfg.base.join("bin").join("gh");
+        fs::create_dir_all(gh.parent().unwrap()).unwrap();
+        fs::write(&gh, "#!/bin/sh\n").unwrap();
+
+        let (_, (gh_profile_name, gh_profile)) = get_profiles();
+        store()
+            .add_profile(gh_profile_name.to_string(), gh_profile)
+            .unwrap();
+        App::save_settings(&Settings {
+            gh_path: Some(gh.to_string_lossy().to_string()),
+            ..Settings::default()
+        })
+        .unwrap();
 
-        let ((profile_1_name, mut profile_1), _) = get_profiles();
+        App::use_profile(gh_profile_name.to_string(), repo.clone()).unwrap();
+
+        let helper = git::get_local_config(&cfg.repo, "credential.helper").unwrap();
+        assert_eq!(
+            helper,
+            Some(git::gh_credential_helper(&gh.to_string_lossy()))
+        );
+        assert_eq!(detect_auth_type(&cfg.repo).unwrap(), AuthType::GH);
+        assert!(matches!(
+            App::repo_state(repo).unwrap(),
+            RepoState::InSync { .. }
+        ));
+    }
 
-        TestPM::add_profile(profile_1_name.to_string(), profile_1.clone())?;
-        TestPM::update_profile(profile_1_name.to_string(), profile_1.clone())?;
+    #[test]
+    fn recovers_stale_lock_and_unlocks_on_request() {
+        let cfg = TempConfig::new().unwrap();
+        let lock_path = cfg.base.join(CONFIG_DIR_NAME).join("octopush.lock");
 
-        profile_1.name = "isim".to_string();
+        // left behind by a crashed process
+        fs::create_dir_all(lock_path.parent().unwrap()).unwrap();
+        fs::write(&lock_path, u32::MAX.to_string()).unwrap();
 
-        TestPM::update_profile(profile_1_name.to_string(), profile_1.clone())?;
-        let updated_profile = TestPM::read_profile(profile_1_name.to_string())?;
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile).unwrap();
+        assert!(!lock_path.exists());
 
-        assert!(updated_profile.is_some());
-        assert_eq!(updated_profile.unwrap(), profile_1);
+        // a live owner is kept unless forced
+        fs::write(&lock_path, std::process::id().to_string()).unwrap();
+        let err = App::unlock(false).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
 
-        Ok(())
+        let removed = App::unlock(true).unwrap();
+        assert_eq!(
+            removed,
+            Some(RemovedLock::Owned(LockOwner {
+                pid: std::process::id(),
+                alive: true
+            }))
+        );
+        assert!(!lock_path.exists());
+
+        fs::write(&lock_path, "").unwrap();
+        assert_eq!(App::unlock(false).unwrap(), Some(RemovedLock::Unreadable));
     }
 
     #[test]
-    fn errors_on_nonexistent_profile() {
-        let _cfg = TempConfig::new();
+    fn concurrent_writers_do_not_lose_updates() {
+        let cfg = TempConfig::new().unwrap();
+        let ((_, profile), _) = get_profiles();
 
-        let ((profile_1_name, profile_1), _) = get_profiles();
+        let results = cfg.run_concurrently(8, |i| {
+            App::add_profile(format!("concurrent_{}", i), profile.clone())
+        });
+        assert!(results.iter().all(Result::is_ok));
 
-        let result = TestPM::update_profile(profile_1_name.to_string(), profile_1);
-        let err = result.unwrap_err();
+        let profiles = App::list_profiles().unwrap();
+        for i in 0..8 {
+            assert!(profiles.contains_key(&format!("concurrent_{}", i)));
+        }
 
-        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        // mapping writes go through the same lock
+        let repos: Vec<PathBuf> = (0..8)
+            .map(|i| cfg.base.join(format!("repo_{}", i)))
+            .collect();
+        repos.iter().for_each(|repo| TempConfig::init_repo_at(repo));
+
+        let results = cfg.run_concurrently(8, |i| {
+            App::use_profile(
+                format!("concurrent_{}", i),
+                repos[i].to_string_lossy().to_string(),
+            )
+        });
+        assert!(results.iter().all(Result::is_ok));
+
+        for (i, repo) in repos.iter().enumerate() {
+            assert_eq!(
+                App::get_project_profile(repo.to_string_lossy().to_string())
+                    .unwrap()
+                    .0,
+                format!("concurrent_{}", i)
+            );
+        }
+    }
+
+    #[test]
+    fn merge_profiles_rewrites_mappings_rules_and_usage() {
+        let cfg = TempConfig::new().unwrap();
+        let ((ssh_name, ssh_profile), (gh_name, gh_profile)) = get_profiles();
+        App::add_profile(ssh_name.to_string(), ssh_profile.clone()).unwrap();
+        App::add_profile("ssh_old".to_string(), ssh_profile).unwrap();
+        App::add_profile(gh_name.to_string(), gh_profile).unwrap();
+
+        let repo = cfg.repo.to_string_lossy().to_string();
+        App::use_profile("ssh_old".to_string(), repo.clone()).unwrap();
+        App::use_profile(ssh_name.to_string(), repo.clone()).unwrap();
+        App::use_profile("ssh_old".to_string(), repo.clone()).unwrap();
+        App::save_rules(&Rules {
+            rules: vec![crate::core::rules::Rule {
+                gitdir: Some("~/old/".to_string()),
+                remote: None,
+                profile: "ssh_old".to_string(),
+            }],
+        })
+        .unwrap();
+
+        let err =
+            App::merge_profiles(gh_name.to_string(), "ssh_old".to_string(), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let summary =
+            App::merge_profiles(ssh_name.to_string(), "ssh_old".to_string(), false).unwrap();
         assert_eq!(
-            err.to_string(),
-            format!("profile '{}' not found", profile_1_name)
+            summary,
+            MergeSummary {
+                mappings: 1,
+                rules: 1,
+                reapplied: vec![Project::new(&repo).unwrap().get_repo_key().unwrap()],
+                skipped: Vec::new(),
+            }
         );
+
+        assert!(!App::list_profiles().unwrap().contains_key("ssh_old"));
+        assert_eq!(App::get_project_profile(repo).unwrap().0, ssh_name);
+        assert_eq!(App::rules().unwrap().rules[0].profile, ssh_name);
+
+        let usage = App::list_usage().unwrap();
+        assert!(!usage.contains_key("ssh_old"));
+        assert_eq!(usage[ssh_name].use_count, 3);
     }
 
     #[test]
-    fn errors_when_updating_wrong_profile_fields() {
-        let _cfg = TempConfig::new();
-
-        // profile auth types in order: 1 ssh 2 gh 3 none
-        let ((profile_1_name, mut profile_1), (profile_2_name, mut profile_2)) = get_profiles();
-        let profile_3_name = "profile_3_name";
-        let mut profile_3 = Profile::build(
-            "profile_3".to_string(),
-            "profile_3@mail.com".to_string(),
-            AuthType::None,
-            None,
-            None,
+    fn merging_profiles_with_other_keys_needs_force() {
+        let cfg = TempConfig::new().unwrap();
+        let ((ssh_name, ssh_profile), _) = get_profiles();
+        let other_key = Profile {
+            ssh_key_path: Some("~/.ssh/other".to_string()),
+            ..ssh_profile.clone()
+        };
+        App::add_profile(ssh_name.to_string(), ssh_profile).unwrap();
+        App::add_profile("ssh_other".to_string(), other_key).unwrap();
+
+        let repo = cfg.repo.to_string_lossy().to_string();
+        App::use_profile("ssh_other".to_string(), repo.clone()).unwrap();
+
+        let err =
+            App::merge_profiles(ssh_name.to_string(), "ssh_other".to_string(), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("ssh_key_path"));
+
+        let summary =
+            App::merge_profiles(ssh_name.to_string(), "ssh_other".to_string(), true).unwrap();
+        assert_eq!(summary.reapplied.len(), 1);
+        assert_eq!(
+            App::repo_state(repo).unwrap(),
+            RepoState::InSync {
+                profile_name: ssh_name.to_string()
+            }
         );
+    }
 
-        let profiles: HashMap<String, Profile> = HashMap::from([
-            (profile_1_name.to_string(), profile_1.clone()),
-            (profile_2_name.to_string(), profile_2.clone()),
-            (profile_3_name.to_string(), profile_3.clone()),
-        ]);
-        let _ = TestPM::write_profiles(&profiles);
+    #[test]
+    fn use_profile_in_linked_worktree_writes_shared_config() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile.clone()).unwrap();
 
-        // update wrong fields
-        profile_1.hostname = Some("hostname.github.com".to_owned());
-        profile_2.ssh_key_path = Some("some_path/to_key".to_owned());
-        profile_3.hostname = Some("hostname.github.com".to_owned());
+        let worktree = cfg.base.join("worktree");
+        git::run_git(
+            &cfg.repo,
+            [
+                "-c",
+                "user.name=x",
+                "-c",
+                "user.email=x@example.com",
+                "commit",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        )
+        .unwrap();
+        let o = git::run_git(
+            &cfg.repo,
+            [
+                "worktree",
+                "add",
+                "-b",
+                "feature",
+                &worktree.to_string_lossy(),
+            ],
+        )
+        .unwrap();
+        assert!(o.status.success(), "git worktree add failed: {:?}", o);
 
-        let result = TestPM::update_profile(profile_1_name.to_string(), profile_1);
-        let err = result.unwrap_err();
+        App::use_profile(
+            profile_name.to_string(),
+            worktree.to_string_lossy().to_string(),
+        )
+        .unwrap();
 
-        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // identity lands in the main repository's config, seen by every worktree
         assert_eq!(
-            err.to_string(),
-            format!("you cannot update 'hostname' for 'ssh' auth type"),
+            git::get_local_config(&cfg.repo, "user.email").unwrap(),
+            Some(profile.email)
         );
 
-        let result = TestPM::update_profile(profile_2_name.to_string(), profile_2);
-        let err = result.unwrap_err();
+        let (mapped, _, repo_name) =
+            App::get_project_profile(cfg.repo.to_string_lossy().to_string()).unwrap();
+        assert_eq!(mapped, profile_name);
+        assert_eq!(repo_name, "repo");
+        assert!(matches!(
+            App::repo_state(worktree.to_string_lossy().to_string()).unwrap(),
+            RepoState::InSync { .. }
+        ));
+    }
 
-        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    #[test]
+    fn web_url_links_repo_and_profile_account() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        let err = App::web_url(repo.clone(), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        git::run_git(
+            &cfg.repo,
+            ["remote", "add", "origin", "git@github.com:acme/app.git"],
+        )
+        .unwrap();
         assert_eq!(
-            err.to_string(),
-            format!("you cannot update 'ssh_key_path' for 'gh' auth type"),
+            App::web_url(repo.clone(), true).unwrap(),
+            "https://github.com/acme/app"
         );
 
-        let result = TestPM::update_profile(profile_3_name.to_string(), profile_3);
-        let err = result.unwrap_err();
+        let gh = Profile::build(
+            "Jane".to_string(),
+            "jane@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+        App::add_profile("oss".to_string(), gh).unwrap();
+        App::use_profile("oss".to_string(), repo.clone()).unwrap();
 
-        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
         assert_eq!(
-            err.to_string(),
-            format!("you cannot update 'hostname' or 'ssh_key_path' for 'none' auth type"),
+            App::web_url(repo, true).unwrap(),
+            "https://github.com/login?login=someone&return_to=%2Facme%2Fapp"
         );
     }
 
     #[test]
-    fn delete_profile_successfully() -> Result<(), std::io::Error> {
-        let _cfg = TempConfig::new();
-
-        let ((profile_1_name, profile_1), _) = get_profiles();
-
-        TestPM::add_profile(profile_1_name.to_string(), profile_1)?;
-
-        let profile = TestPM::read_profile(profile_1_name.to_string())?;
-        assert!(profile.is_some());
+    fn records_octopush_version_of_configured_repos() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile).unwrap();
 
-        TestPM::delete_profile(profile_1_name.to_string())?;
+        let repo = cfg.repo.to_string_lossy().to_string();
+        assert_eq!(App::repo_record(repo.clone()).unwrap(), None);
 
-        let profile = TestPM::read_profile(profile_1_name.to_string())?;
-        assert!(profile.is_none());
+        App::use_profile(profile_name.to_string(), repo.clone()).unwrap();
+        let record = App::repo_record(repo.clone()).unwrap().unwrap();
+        assert_eq!(record.octopush_version, env!("CARGO_PKG_VERSION"));
+        assert!(!record.is_outdated());
 
-        Ok(())
+        App::reset_profile_for_project(repo.clone()).unwrap();
+        assert_eq!(App::repo_record(repo).unwrap(), None);
     }
 
     #[test]
-    fn errors_when_deleting_nonexistent_profile() {
-        let _cfg = TempConfig::new();
+    fn flags_repos_configured_with_an_older_layout() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile).unwrap();
 
-        let profile_name = "profile_name";
-        let result = TestPM::delete_profile(profile_name.to_owned());
-        let err = result.unwrap_err();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        assert_eq!(App::outdated_warning(repo.clone()).unwrap(), None);
 
-        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        App::use_profile(profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(App::outdated_warning(repo.clone()).unwrap(), None);
+
+        // mapped by an octopush that kept no records
+        let key = Project::new(&repo).unwrap().get_repo_key().unwrap();
+        store().write_repos(&HashMap::new()).unwrap();
         assert_eq!(
-            err.to_string(),
-            format!("profile '{}' not found", profile_name)
+            App::outdated_warning(repo.clone()).unwrap(),
+            Some(Warning::Outdated { version: None })
+        );
+
+        // recorded before the layout marker existed
+        fs::write(
+            store().repos_path().unwrap(),
+            format!(
+                "[\"{}\"]\noctopush_version = \"0.1.0\"\nconfigured_at = 0\n",
+                key
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            App::outdated_warning(repo.clone()).unwrap(),
+            Some(Warning::Outdated {
+                version: Some("0.1.0".to_string())
+            })
         );
+
+        App::sync_repo(repo.clone()).unwrap();
+        assert_eq!(App::outdated_warning(repo).unwrap(), None);
     }
 
-    // App tests
     #[test]
-    fn use_profile_errors_when_missing_profile() {
+    fn ranks_current_and_previous_profiles_first() {
         let cfg = TempConfig::new().unwrap();
+        let ((ssh_name, ssh_profile), _) = get_profiles();
+        for name in ["a_profile", "b_profile", ssh_name] {
+            App::add_profile(name.to_string(), ssh_profile.clone()).unwrap();
+        }
 
-        let err = App::use_profile("nope".to_string(), cfg.repo.to_string_lossy().to_string())
-            .unwrap_err();
-        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        let repo = cfg.repo.to_string_lossy().to_string();
+        assert_eq!(
+            App::ranked_profile_names(repo.clone()).unwrap(),
+            ["a_profile", "b_profile", ssh_name]
+        );
+
+        App::use_profile(ssh_name.to_string(), repo.clone()).unwrap();
+        App::use_profile("b_profile".to_string(), repo.clone()).unwrap();
+
+        assert_eq!(
+            App::ranked_profile_names(repo).unwrap(),
+            ["b_profile", ssh_name, "a_profile"]
+        );
     }
 
     #[test]
-    fn use_profile_applies_ssh_and_records_mapping() {
+    fn sync_pushes_profile_edits_to_mapped_repos() {
         let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile.clone()).unwrap();
 
-        // Arrange: write SSH profile and set an https remote to verify rewrite
-        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
-        TestPM::write_profiles(&profiles).unwrap();
+        let other = cfg.base.join("other");
+        let gone = cfg.base.join("gone");
+        for repo in [&cfg.repo, &other, &gone] {
+            TempConfig::init_repo_at(repo);
+            App::use_profile(profile_name.to_string(), repo.to_string_lossy().to_string()).unwrap();
+        }
+        fs::remove_dir_all(&gone).unwrap();
+
+        let mut edited = profile;
+        edited.email = "edited@email.com".to_string();
+        App::update_profile(profile_name.to_string(), edited).unwrap();
+
+        // a dry run only reports the change
+        let plan = App::plan_sync(cfg.repo.to_string_lossy().to_string()).unwrap();
+        assert!(plan.operations.contains(&PlanOperation::GitConfig {
+            key: "user.email".to_string(),
+            before: Some(PROFILE_1_EMAIL.to_string()),
+            after: Some("edited@email.com".to_string()),
+        }));
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "user.email").unwrap(),
+            Some(PROFILE_1_EMAIL.to_string())
+        );
 
-        // set remote to https so it should be converted to ssh
-        let _ = git::run_git(
-            &cfg.repo,
-            ["remote", "add", "origin", "https://github.com/acme/app.git"],
-        )
+        assert_eq!(
+            App::sync_repo(cfg.repo.to_string_lossy().to_string()).unwrap(),
+            profile_name
+        );
+        assert_eq!(
+            git::get_local_config(&cfg.repo, "user.email").unwrap(),
+            Some("edited@email.com".to_string())
+        );
+
+        // only the other repository still has something to apply
+        let mut stages = Vec::new();
+        let mut skipped = Vec::new();
+        let mut changed = Vec::new();
+        let summary = App::sync_all_with(&mut |event| match event {
+            SyncProgress::Repo { total, .. } => assert_eq!(total, 3),
+            SyncProgress::Stage(stage) => stages.push(stage),
+            SyncProgress::Done {
+                repo,
+                skipped: Some(_),
+                ..
+            } => skipped.push(repo.to_string()),
+            SyncProgress::Done { changed: c, .. } => changed.extend(c),
+        })
         .unwrap();
+        assert_eq!(stages, vec![Stage::Identity]);
+        assert_eq!(changed, vec!["git config user.email"]);
+        assert_eq!(summary.synced.len(), 2);
+        assert_eq!(summary.skipped.len(), 1);
+        assert!(summary.skipped[0].0.ends_with("gone"));
+        assert_eq!(skipped, vec![summary.skipped[0].0.clone()]);
+        assert_eq!(
+            git::get_local_config(&other, "user.email").unwrap(),
+            Some("edited@email.com".to_string())
+        );
+    }
 
-        // Act
+    #[test]
+    fn sync_all_counts_mappings_without_a_path() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile).unwrap();
         App::use_profile(
-            ssh_profile_name.to_string(),
+            profile_name.to_string(),
             cfg.repo.to_string_lossy().to_string(),
         )
         .unwrap();
 
-        // Assert: mapping exists for this repo
-        let repo_name = Project::new(&cfg.repo).unwrap().get_repo_name().unwrap();
-        let mapping = TestPM::read_project_profiles().unwrap();
-        assert_eq!(mapping.get(&repo_name), Some(&ssh_profile_name.to_string()));
+        let mut map = store().read_project_profiles().unwrap();
+        map.insert("github.com/acme/app".to_string(), profile_name.to_string());
+        store().write_project_profiles(&map).unwrap();
 
-        // Assert: identity set
-        let g1 = git::run_git(&cfg.repo, ["config", "--local", "user.name"]).unwrap();
-        assert_eq!(String::from_utf8_lossy(&g1.stdout).trim(), ssh_profile.name);
-        let g2 = git::run_git(&cfg.repo, ["config", "--local", "user.email"]).unwrap();
-        assert_eq!(
-            String::from_utf8_lossy(&g2.stdout).trim(),
-            ssh_profile.email
-        );
+        let summary = App::sync_all().unwrap();
+        assert_eq!(summary.synced.len(), 1);
+        assert_eq!(summary.skipped.len(), 1);
+        assert_eq!(summary.skipped[0].0, "github.com/acme/app");
+        assert!(summary.skipped[0].1.contains("mapped by remote"));
+    }
 
-        // Assert: sshCommand set (skip strict check on Windows)
-        let ssh_cmd = git::run_git(&cfg.repo, ["config", "--local", "core.sshCommand"]).unwrap();
-        if cfg!(windows) {
-            assert!(ssh_cmd.status.success());
-        } else {
-            let out = String::from_utf8_lossy(&ssh_cmd.stdout);
-            assert!(out.contains(SSH_KEY_PATH));
-        }
+    #[test]
+    fn plan_reset_lists_what_reset_removes() {
+        let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile.clone()).unwrap();
+        App::use_profile(profile_name.to_string(), repo.clone()).unwrap();
+
+        let operations = App::plan_reset(repo.clone()).unwrap();
+        assert!(matches!(
+            &operations[0],
+            PlanOperation::Mapping { before: Some(name), after: None, .. } if name == profile_name
+        ));
+        assert!(operations.contains(&PlanOperation::GitConfig {
+            key: "user.email".to_string(),
+            before: Some(profile.email.clone()),
+            after: None,
+        }));
+        // planning changed nothing
+        assert!(App::get_project_profile(repo.clone()).is_ok());
+
+        App::reset_profile_for_project(repo.clone()).unwrap();
+        assert!(App::get_project_profile(repo.clone()).is_err());
+        assert_eq!(App::plan_reset(repo).unwrap(), []);
+    }
 
-        // Assert: remote rewritten to ssh
-        let url = git::get_remote_url(&cfg.repo, "origin").unwrap();
-        assert_eq!(url.as_deref(), Some("git@github.com:acme/app.git"));
+    #[test]
+    fn doctor_fails_on_orphaned_mappings_and_missing_keys() {
+        let cfg = TempConfig::new().unwrap();
+        let ((profile_name, mut profile), _) = get_profiles();
+        let key = cfg.base.join("id_doctor");
+        fs::write(&key, "key").unwrap();
+        profile.ssh_key_path = Some(key.to_string_lossy().to_string());
+        App::add_profile(profile_name.to_string(), profile.clone()).unwrap();
+
+        let failures = |checks: Vec<Check>| {
+            checks
+                .into_iter()
+                .filter(|check| check.status == doctor::CheckStatus::Fail)
+                .map(|check| check.name)
+                .collect::<Vec<_>>()
+        };
+        assert!(failures(App::doctor()).is_empty());
 
-        // Assert: gh credential helper cleared
-        let gh = git::run_git(
-            &cfg.repo,
-            ["config", "--local", "--get", "credential.helper"],
+        App::use_profile(
+            profile_name.to_string(),
+            cfg.repo.to_string_lossy().to_string(),
         )
         .unwrap();
-        assert!(!gh.status.success());
+        App::add_profile("keyless".to_string(), {
+            let mut keyless = profile;
+            keyless.ssh_key_path = Some("/nonexistent/octopush/key".to_string());
+            keyless
+        })
+        .unwrap();
+        let mut profiles = store().read_profiles().unwrap();
+        profiles.remove(profile_name);
+        store().write_profiles(&profiles).unwrap();
+
+        assert_eq!(
+            failures(App::doctor()),
+            [
+                "ssh key of 'keyless' is readable",
+                "every mapping points at an existing profile"
+            ]
+        );
     }
 
     #[test]
-    fn use_profile_applies_gh_and_records_mapping() {
+    fn pre_commit_hook_guards_the_mapped_identity() {
         let cfg = TempConfig::new().unwrap();
+        let ((profile_name, profile), _) = get_profiles();
+        App::add_profile(profile_name.to_string(), profile.clone()).unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
 
-        // Arrange: write GH profile and set an ssh remote to verify rewrite
-        let (_, gh_pair) = get_profiles();
-        let (gh_profile_name, gh_profile) = gh_pair;
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(gh_profile_name.to_string(), gh_profile.clone())]);
-        TestPM::write_profiles(&profiles).unwrap();
+        let err = App::check_identity(repo.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
 
-        // set remote to ssh so it should be converted to https
-        let _ = git::run_git(
-            &cfg.repo,
-            ["remote", "add", "origin", "git@github.com:acme/app.git"],
-        )
-        .unwrap();
+        App::use_profile(profile_name.to_string(), repo.clone()).unwrap();
+        assert_eq!(App::check_identity(repo.clone()).unwrap(), profile_name);
 
-        // Act
-        App::use_profile(
-            gh_profile_name.to_string(),
-            cfg.repo.to_string_lossy().to_string(),
-        )
+        git::set_local_config(&cfg.repo, "user.email", "other@email.com").unwrap();
+        let err = App::check_identity(repo.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        // honours core.hooksPath
+        git::set_local_config(&cfg.repo, "core.hooksPath", ".githooks").unwrap();
+        let hook = App::install_hook(repo, false).unwrap();
+        assert_eq!(hook, cfg.repo.join(".githooks").join("pre-commit"));
+        assert!(hook.exists());
+    }
+
+    #[test]
+    fn exports_mappings_and_rules_as_include_if() {
+        let cfg = TempConfig::new().unwrap();
+        let ((ssh_name, ssh_profile), (gh_name, gh_profile)) = get_profiles();
+        App::add_profile(ssh_name.to_string(), ssh_profile).unwrap();
+        App::add_profile(gh_name.to_string(), gh_profile).unwrap();
+
+        App::use_profile(ssh_name.to_string(), cfg.repo.to_string_lossy().to_string()).unwrap();
+        App::save_rules(&Rules {
+            rules: vec![
+                crate::core::rules::Rule {
+                    gitdir: Some("~/oss/".to_string()),
+                    remote: None,
+                    profile: gh_name.to_string(),
+                },
+                crate::core::rules::Rule {
+                    gitdir: Some("~/oss/".to_string()),
+                    remote: Some("github.com/**".to_string()),
+                    profile: gh_name.to_string(),
+                },
+            ],
+        })
         .unwrap();
 
-        // Assert: mapping exists for this repo
-        let repo_name = Project::new(&cfg.repo).unwrap().get_repo_name().unwrap();
-        let mapping = TestPM::read_project_profiles().unwrap();
-        assert_eq!(mapping.get(&repo_name), Some(&gh_profile_name.to_string()));
+        let summary = App::export_gitconfig().unwrap();
+        assert_eq!(summary.profiles, 2);
+        assert_eq!(summary.sections, 2);
+        assert_eq!(summary.skipped.len(), 1);
+
+        let includes = fs::read_to_string(&summary.includes_path).unwrap();
+        let repo_key = Project::new(&cfg.repo).unwrap().get_repo_key().unwrap();
+        let mapping = includes.find(&format!("gitdir:{}/", repo_key)).unwrap();
+        let rule = includes.find("gitdir:~/oss/").unwrap();
+        assert!(mapping < rule);
+
+        let snippet = summary
+            .includes_path
+            .with_file_name(format!("{}.gitconfig", ssh_name));
+        assert!(fs::read_to_string(snippet).unwrap().contains(&format!(
+            "sshCommand = \"ssh -i ~/.ssh/id_rsa -F {}\"",
+            git::NULL_DEVICE
+        )));
+    }
 
-        // Assert: identity set
-        let g1 = git::run_git(&cfg.repo, ["config", "--local", "user.name"]).unwrap();
-        assert_eq!(String::from_utf8_lossy(&g1.stdout).trim(), gh_profile.name);
-        let g2 = git::run_git(&cfg.repo, ["config", "--local", "user.email"]).unwrap();
-        assert_eq!(String::from_utf8_lossy(&g2.stdout).trim(), gh_profile.email);
+    #[test]
+    fn suggests_profile_mapped_to_the_same_owner() {
+        let cfg = TempConfig::new().unwrap();
 
-        // Assert: sshCommand cleared
-        let ssh =
-            git::run_git(&cfg.repo, ["config", "--local", "--get", "core.sshCommand"]).unwrap();
-        assert!(!ssh.status.success());
+        let mapped = cfg.base.join("mapped");
+        let fresh = cfg.base.join("fresh");
+        for (repo, url) in [
+            (&mapped, "git@mycompany.github.com:acme/app.git"),
+            (&fresh, "git@mycompany.github.com:acme/other.git"),
+        ] {
+            TempConfig::init_repo_at(repo);
+            git::run_git(repo, ["remote", "add", "origin", url]).unwrap();
+        }
 
-        // Assert: remote rewritten to https
-        let url = git::get_remote_url(&cfg.repo, "origin").unwrap();
-        assert_eq!(url.as_deref(), Some("https://github.com/acme/app.git"));
+        let ((ssh_name, ssh_profile), (gh_name, gh_profile)) = get_profiles();
+        store()
+            .add_profile(ssh_name.to_string(), ssh_profile)
+            .unwrap();
+        store()
+            .add_profile(gh_name.to_string(), gh_profile)
+            .unwrap();
 
-        // Assert: gh credential helper set
-        let gh = git::run_git(&cfg.repo, ["config", "--local", "credential.helper"]).unwrap();
+        // only the gh profile's hostname matches so far
         assert_eq!(
-            String::from_utf8_lossy(&gh.stdout).trim(),
-            "!gh auth git-credential"
+            App::suggest_profile(fresh.to_string_lossy().to_string()).unwrap(),
+            Some(gh_name.to_string())
+        );
+
+        App::use_profile(ssh_name.to_string(), mapped.to_string_lossy().to_string()).unwrap();
+
+        assert_eq!(
+            App::suggest_profile(fresh.to_string_lossy().to_string()).unwrap(),
+            Some(ssh_name.to_string())
         );
-        let gh2 = git::run_git(&cfg.repo, ["config", "--local", "credential.useHttpPath"]).unwrap();
-        assert_eq!(String::from_utf8_lossy(&gh2.stdout).trim(), "true");
     }
 
     #[test]
-    fn use_profile_applies_none_clears_auth_helpers() {
+    fn migrates_name_keyed_mapping_on_first_write() {
         let cfg = TempConfig::new().unwrap();
 
-        // Arrange: create a NONE profile and write it
-        let profile_name = "none_profile";
-        let none_profile = Profile::build(
-            "None User".to_string(),
-            "none@example.com".to_string(),
-            AuthType::None,
-            None,
-            None,
+        let ((ssh_profile_name, ssh_profile), _) = get_profiles();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+
+        let project = Project::new(&cfg.repo).unwrap();
+        let repo_name = project.get_repo_name().unwrap();
+        store()
+            .write_project_profiles(&HashMap::from([(
+                repo_name.clone(),
+                ssh_profile_name.to_string(),
+            )]))
+            .unwrap();
+
+        let (profile_name, _, _) =
+            App::get_project_profile(cfg.repo.to_string_lossy().to_string()).unwrap();
+        assert_eq!(profile_name, ssh_profile_name);
+        // lookups leave the file alone
+        assert!(
+            store()
+                .read_project_profiles()
+                .unwrap()
+                .contains_key(&repo_name)
         );
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(profile_name.to_string(), none_profile.clone())]);
-        TestPM::write_profiles(&profiles).unwrap();
-
-        // Pre-set ssh and gh helpers to verify they get cleared
-        git::ensure_ssh_command(&cfg.repo, "/tmp/fake_key").unwrap_or(());
-        let _ = git::run_git(
-            &cfg.repo,
-            [
-                "config",
-                "--local",
-                "credential.helper",
-                "!gh auth git-credential",
-            ],
-        )
-        .unwrap();
-
-        // Act
-        App::use_profile(
-            profile_name.to_string(),
-            cfg.repo.to_string_lossy().to_string(),
-        )
-        .unwrap();
 
-        // Assert: ssh cleared
-        let ssh =
-            git::run_git(&cfg.repo, ["config", "--local", "--get", "core.sshCommand"]).unwrap();
-        assert!(!ssh.status.success());
-        // Assert: gh helper cleared
-        let gh = git::run_git(
-            &cfg.repo,
-            ["config", "--local", "--get", "credential.helper"],
-        )
-        .unwrap();
-        assert!(!gh.status.success());
+        App::sync_repo(cfg.repo.to_string_lossy().to_string()).unwrap();
+        let mapping = store().read_project_profiles().unwrap();
+        assert!(!mapping.contains_key(&repo_name));
+        assert_eq!(
+            mapping.get(&project.get_repo_key().unwrap()),
+            Some(&ssh_profile_name.to_string())
+        );
     }
 
     #[test]
-    fn get_project_profile_returns_selected_profile() {
+    fn repo_state_reports_unmapped_in_sync_and_drift() {
         let cfg = TempConfig::new().unwrap();
+        let repo = cfg.repo.to_string_lossy().to_string();
+
+        assert_eq!(App::repo_state(repo.clone()).unwrap(), RepoState::Unmapped);
 
-        // Arrange: write a profile and use it
         let ((ssh_profile_name, ssh_profile), _) = get_profiles();
-        let profiles: HashMap<String, Profile> =
-            HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
-        TestPM::write_profiles(&profiles).unwrap();
+        store()
+            .add_profile(ssh_profile_name.to_string(), ssh_profile)
+            .unwrap();
+        App::use_profile(ssh_profile_name.to_string(), repo.clone()).unwrap();
 
-        App::use_profile(
-            ssh_profile_name.to_string(),
-            cfg.repo.to_string_lossy().to_string(),
-        )
-        .unwrap();
+        assert_eq!(
+            App::repo_state(repo.clone()).unwrap(),
+            RepoState::InSync {
+                profile_name: ssh_profile_name.to_string()
+            }
+        );
 
-        let (_profile_name, found_profile, _repo_name) =
-            App::get_project_profile(cfg.repo.to_string_lossy().to_string()).unwrap();
+        git::set_local_config(&cfg.repo, "user.email", "manual@edit.com").unwrap();
 
-        // Assert
-        assert_eq!(found_profile, ssh_profile);
+        match App::repo_state(repo).unwrap() {
+            RepoState::Drifted { profile_name, plan } => {
+                assert_eq!(profile_name, ssh_profile_name);
+                assert_eq!(plan.operations.len(), 1);
+            }
+            state => panic!("expected drift, got {:?}", state),
+        }
     }
 
     #[test]
@@ -894,7 +4117,7 @@ mod test {
         let ((ssh_profile_name, ssh_profile), _) = get_profiles();
         let profiles: HashMap<String, Profile> =
             HashMap::from([(ssh_profile_name.to_string(), ssh_profile.clone())]);
-        TestPM::write_profiles(&profiles).unwrap();
+        store().write_profiles(&profiles).unwrap();
 
         App::use_profile(
             ssh_profile_name.to_string(),
@@ -903,16 +4126,16 @@ mod test {
         .unwrap();
 
         // Pre-verify mapping exists
-        let repo_name = Project::new(&cfg.repo).unwrap().get_repo_name().unwrap();
-        let mapping = TestPM::read_project_profiles().unwrap();
-        assert!(mapping.contains_key(&repo_name));
+        let repo_key = Project::new(&cfg.repo).unwrap().get_repo_key().unwrap();
+        let mapping = store().read_project_profiles().unwrap();
+        assert!(mapping.contains_key(&repo_key));
 
         // Act
         App::reset_profile_for_project(cfg.repo.to_string_lossy().to_string()).unwrap();
 
         // Assert: mapping removed
-        let mapping_after = TestPM::read_project_profiles().unwrap();
-        assert!(!mapping_after.contains_key(&repo_name));
+        let mapping_after = store().read_project_profiles().unwrap();
+        assert!(!mapping_after.contains_key(&repo_key));
 
         // Assert: git identity cleared
         let g1 = git::run_git(&cfg.repo, ["config", "--local", "--get", "user.name"]).unwrap();
@@ -8,6 +8,26 @@ pub enum AuthType {
     None,
     SSH,
     GH,
+    #[value(name = "gitlab")]
+    GitLab,
+    Token,
+}
+
+impl AuthType {
+    // auth types that hand https credentials to git through a forge CLI's
+    // credential helper
+    pub fn uses_credential_helper(self) -> bool {
+        matches!(self, AuthType::GH | AuthType::GitLab | AuthType::Token)
+    }
+
+    // host assumed when a credential helper profile has no hostname
+    pub fn default_hostname(self) -> Option<&'static str> {
+        match self {
+            AuthType::GH => Some("github.com"),
+            AuthType::GitLab => Some("gitlab.com"),
+            AuthType::None | AuthType::SSH | AuthType::Token => None,
+        }
+    }
 }
 
 impl FromStr for AuthType {
@@ -18,6 +38,8 @@ impl FromStr for AuthType {
             "none" => Ok(AuthType::None),
             "ssh" => Ok(AuthType::SSH),
             "gh" => Ok(AuthType::GH),
+            "gitlab" => Ok(AuthType::GitLab),
+            "token" => Ok(AuthType::Token),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "invalid auth type",
@@ -32,6 +54,8 @@ impl From<AuthType> for String {
             AuthType::None => "none".to_string(),
             AuthType::SSH => "ssh".to_string(),
             AuthType::GH => "gh".to_string(),
+            AuthType::GitLab => "gitlab".to_string(),
+            AuthType::Token => "token".to_string(),
         }
     }
 }
@@ -42,6 +66,8 @@ impl From<AuthType> for &str {
             AuthType::None => "none",
             AuthType::SSH => "ssh",
             AuthType::GH => "gh",
+            AuthType::GitLab => "gitlab",
+            AuthType::Token => "token",
         }
     }
 }
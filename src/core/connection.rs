@@ -0,0 +1,155 @@
+use crate::core::{auth::AuthType, profile::Profile};
+use crate::util::{git, system::expand_home};
+use std::io;
+use std::process::{Command, Output};
+
+// connects to `host` the way git would for this profile and returns the
+// account the host says it authenticated, when it says so
+pub fn test(profile: &Profile, host: &str, gh_path: &str) -> Result<Option<String>, io::Error> {
+    match profile.auth_type {
+        AuthType::SSH | AuthType::None => test_ssh(
+            profile.ssh_key_path.as_deref(),
+            profile.ssh_identity_agent.as_deref(),
+            host,
+        ),
+        AuthType::GH => test_cli(gh_path, host),
+        AuthType::GitLab => test_cli("glab", host),
+        AuthType::Token => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "token profiles cannot be tested, the host only sees the token when git pushes or fetches",
+        )),
+    }
+}
+
+fn test_ssh(
+    key_path: Option<&str>,
+    agent: Option<&str>,
+    host: &str,
+) -> Result<Option<String>, io::Error> {
+    let mut command = Command::new("ssh");
+    // never stop to ask for a passphrase or to trust a new host key
+    command.args(["-T", "-o", "BatchMode=yes"]);
+    if let Some(key_path) = key_path {
+        command
+            .args(["-o", "IdentitiesOnly=yes", "-F", "/dev/null", "-i"])
+            .arg(expand_home(key_path));
+    }
+    if let Some(agent) = agent {
+        command
+            .arg("-o")
+            .arg(format!("IdentityAgent={}", expand_home(agent).display()));
+    }
+    command.arg(format!("git@{}", host));
+
+    let out = git::output(&mut command)?;
+    let text = output_text(&out);
+
+    // GitHub closes the session with status 1 even after authenticating
+    match ssh_account(&text) {
+        Some(account) => Ok(Some(account)),
+        None if out.status.success() => Ok(None),
+        None => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            last_line(&text).unwrap_or("ssh failed without any output"),
+        )),
+    }
+}
+
+fn test_cli(program: &str, host: &str) -> Result<Option<String>, io::Error> {
+    let out = git::output(Command::new(program).args(["auth", "status", "--hostname", host]))?;
+    let text = output_text(&out);
+
+    if !out.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            last_line(&text).unwrap_or("not logged in"),
+        ));
+    }
+
+    Ok(cli_account(&text))
+}
+
+// ssh greetings and `auth status` go to either stream depending on the
+// server or the cli version
+fn output_text(out: &Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    )
+}
+
+fn last_line(text: &str) -> Option<&str> {
+    text.lines().map(str::trim).rfind(|line| !line.is_empty())
+}
+
+// `Hi octocat! You've successfully authenticated...` (GitHub),
+// `Hi there, octocat! ...` (Gitea), `Welcome to GitLab, @octocat!` and
+// `... logged in as octocat.` (Bitbucket)
+pub fn ssh_account(text: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Hi ") {
+            let rest = rest.strip_prefix("there, ").unwrap_or(rest);
+            return rest.split_once('!').map(|(account, _)| account.to_string());
+        }
+        if let Some((_, rest)) = line.split_once("Welcome to GitLab, @") {
+            return rest.split_once('!').map(|(account, _)| account.to_string());
+        }
+        if let Some((_, rest)) = line.split_once("logged in as ") {
+            return Some(rest.trim_end_matches('.').to_string());
+        }
+        None
+    })
+}
+
+// `Logged in to github.com account octocat (keyring)` from current gh,
+// `Logged in to github.com as octocat (...)` from older gh and from glab
+pub fn cli_account(text: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let (_, rest) = line.split_once("Logged in to ")?;
+        let (_, rest) = rest
+            .split_once(" account ")
+            .or_else(|| rest.split_once(" as "))?;
+        rest.split_whitespace().next().map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_account_from_host_greetings() {
+        assert_eq!(
+            ssh_account(
+                "Hi octocat! You've successfully authenticated, but GitHub does not provide shell access.\n"
+            )
+            .as_deref(),
+            Some("octocat")
+        );
+        assert_eq!(
+            ssh_account("Welcome to GitLab, @octocat!\n").as_deref(),
+            Some("octocat")
+        );
+        assert_eq!(
+            ssh_account("authenticated via ssh key.\n\nYou can use git to connect to Bitbucket. Shell access is disabled\nlogged in as octocat.\n").as_deref(),
+            Some("octocat")
+        );
+        assert_eq!(
+            ssh_account("git@github.com: Permission denied (publickey).\n"),
+            None
+        );
+
+        assert_eq!(
+            cli_account("github.com\n  ✓ Logged in to github.com account octocat (keyring)\n")
+                .as_deref(),
+            Some("octocat")
+        );
+        assert_eq!(
+            cli_account("gitlab.com\n  ✓ Logged in to gitlab.com as octocat (/home/me/.config/glab-cli/config.yml)\n")
+                .as_deref(),
+            Some("octocat")
+        );
+    }
+}
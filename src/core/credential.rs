@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+// username sent along with the token when git did not ask for a specific
+// one; forges that authenticate by token ignore it
+const TOKEN_USERNAME: &str = "token";
+
+// parses the `key=value` lines git writes to a credential helper's stdin
+pub fn parse_request(input: &str) -> HashMap<String, String> {
+    input
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+// Answers a `get` request with the profile's token, but only for https
+// requests to the profile's own host so the token never leaks to another
+// remote of the repository.
+pub fn fill(request: &HashMap<String, String>, host: &str, token: &str) -> Option<String> {
+    if request.get("protocol").map(String::as_str) != Some("https")
+        || request.get("host").map(String::as_str) != Some(host)
+    {
+        return None;
+    }
+
+    let username = request
+        .get("username")
+        .map(String::as_str)
+        .unwrap_or(TOKEN_USERNAME);
+    Some(format!("username={}\npassword={}\n", username, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_only_https_requests_for_the_profile_host() {
+        let request = parse_request("protocol=https\nhost=git.corp.com\npath=team/app.git\n\n");
+        assert_eq!(
+            fill(&request, "git.corp.com", "s3cret").as_deref(),
+            Some("username=token\npassword=s3cret\n")
+        );
+        assert_eq!(fill(&request, "github.com", "s3cret"), None);
+
+        let request = parse_request("protocol=http\nhost=git.corp.com\n");
+        assert_eq!(fill(&request, "git.corp.com", "s3cret"), None);
+
+        let request = parse_request("protocol=https\nhost=git.corp.com\nusername=jane\n");
+        assert_eq!(
+            fill(&request, "git.corp.com", "s3cret").as_deref(),
+            Some("username=jane\npassword=s3cret\n")
+        );
+    }
+}
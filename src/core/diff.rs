@@ -0,0 +1,248 @@
+use crate::core::{plan::desired_config, profile::Profile};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    // taking the right value alone would change the git config `use-profile` writes
+    pub changes_git_config: bool,
+}
+
+impl FieldDiff {
+    pub fn is_equal(&self) -> bool {
+        self.left == self.right
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigDiff {
+    pub key: &'static str,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ProfileDiff {
+    pub left: String,
+    pub right: String,
+    pub fields: Vec<FieldDiff>,
+    pub git_config: Vec<ConfigDiff>,
+}
+
+impl ProfileDiff {
+    pub fn is_identical(&self) -> bool {
+        self.fields.iter().all(FieldDiff::is_equal)
+    }
+}
+
+type Getter = fn(&Profile) -> Option<String>;
+type Setter = fn(&mut Profile, &Profile);
+
+const FIELDS: [(&str, Getter, Setter); 20] = [
+    (
+        "name",
+        |p| Some(p.name.clone()),
+        |p, o| p.name = o.name.clone(),
+    ),
+    (
+        "email",
+        |p| Some(p.email.clone()),
+        |p, o| p.email = o.email.clone(),
+    ),
+    (
+        "auth_type",
+        |p| Some(String::from(p.auth_type)),
+        |p, o| p.auth_type = o.auth_type,
+    ),
+    (
+        "hostname",
+        |p| p.hostname.clone(),
+        |p, o| p.hostname = o.hostname.clone(),
+    ),
+    (
+        "ssh_key_path",
+        |p| p.ssh_key_path.clone(),
+        |p, o| p.ssh_key_path = o.ssh_key_path.clone(),
+    ),
+    (
+        "ssh_host_alias",
+        |p| Some(p.ssh_host_alias.to_string()),
+        |p, o| p.ssh_host_alias = o.ssh_host_alias,
+    ),
+    (
+        "ssh_identity_agent",
+        |p| p.ssh_identity_agent.clone(),
+        |p, o| p.ssh_identity_agent = o.ssh_identity_agent.clone(),
+    ),
+    (
+        "gh_path",
+        |p| p.gh_path.clone(),
+        |p, o| p.gh_path = o.gh_path.clone(),
+    ),
+    (
+        "credential_helper",
+        |p| p.credential_helper.clone(),
+        |p, o| p.credential_helper = o.credential_helper.clone(),
+    ),
+    (
+        "credential_cache_timeout",
+        |p| p.credential_cache_timeout.map(|v| v.to_string()),
+        |p, o| p.credential_cache_timeout = o.credential_cache_timeout,
+    ),
+    (
+        "ssl_ca_info",
+        |p| p.ssl_ca_info.clone(),
+        |p, o| p.ssl_ca_info = o.ssl_ca_info.clone(),
+    ),
+    (
+        "ssl_verify",
+        |p| p.ssl_verify.map(|v| v.to_string()),
+        |p, o| p.ssl_verify = o.ssl_verify,
+    ),
+    (
+        "http_proxy",
+        |p| p.http_proxy.clone(),
+        |p, o| p.http_proxy = o.http_proxy.clone(),
+    ),
+    (
+        "signing_key",
+        |p| p.signing_key.clone(),
+        |p, o| p.signing_key = o.signing_key.clone(),
+    ),
+    (
+        "gpg_program",
+        |p| p.gpg_program.clone(),
+        |p, o| p.gpg_program = o.gpg_program.clone(),
+    ),
+    (
+        "url_rewrites",
+        |p| {
+            (!p.url_rewrites.is_empty()).then(|| {
+                p.url_rewrites
+                    .iter()
+                    .map(|(from, to)| format!("{} -> {}", from, to))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+        },
+        |p, o| p.url_rewrites = o.url_rewrites.clone(),
+    ),
+    (
+        "tags",
+        |p| (!p.tags.is_empty()).then(|| p.tags.join(",")),
+        |p, o| p.tags = o.tags.clone(),
+    ),
+    (
+        "archived",
+        |p| Some(p.archived.to_string()),
+        |p, o| p.archived = o.archived,
+    ),
+    (
+        "default_branch",
+        |p| p.default_branch.clone(),
+        |p, o| p.default_branch = o.default_branch.clone(),
+    ),
+    (
+        "template_dir",
+        |p| p.template_dir.clone(),
+        |p, o| p.template_dir = o.template_dir.clone(),
+    ),
+];
+
+pub fn diff_profiles(
+    left_name: &str,
+    left: &Profile,
+    right_name: &str,
+    right: &Profile,
+) -> ProfileDiff {
+    let left_config = desired_config(left);
+    let right_config = desired_config(right);
+
+    let fields = FIELDS
+        .iter()
+        .map(|(field, get, set)| {
+            let (l, r) = (get(left), get(right));
+
+            let changes_git_config = l != r && {
+                let mut swapped = left.clone();
+                set(&mut swapped, right);
+                desired_config(&swapped) != left_config || swapped.url_rewrites != left.url_rewrites
+            };
+
+            FieldDiff {
+                field,
+                left: l,
+                right: r,
+                changes_git_config,
+            }
+        })
+        .collect();
+
+    // a key only one side sets, such as an ssh profile's missing key
+    let right_only: Vec<_> = right_config
+        .iter()
+        .filter(|(key, _)| !left_config.iter().any(|(k, _)| k == key))
+        .map(|(key, _)| (*key, None))
+        .collect();
+    let git_config = left_config
+        .iter()
+        .cloned()
+        .chain(right_only)
+        .filter_map(|(key, l)| {
+            let r = right_config
+                .iter()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v.clone());
+            (l != r).then_some(ConfigDiff {
+                key,
+                left: l,
+                right: r,
+            })
+        })
+        .collect();
+
+    ProfileDiff {
+        left: left_name.to_string(),
+        right: right_name.to_string(),
+        fields,
+        git_config,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+
+    #[test]
+    fn flags_only_differences_that_reach_git_config() {
+        let left = Profile::build(
+            "Jane".to_string(),
+            "jane@work.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("~/.ssh/work".to_string()),
+        );
+        let mut right = left.clone();
+        right.ssh_key_path = Some("~/.ssh/personal".to_string());
+        right.tags = vec!["personal".to_string()];
+
+        let diff = diff_profiles("work", &left, "personal", &right);
+        assert!(!diff.is_identical());
+
+        let changed: Vec<_> = diff
+            .fields
+            .iter()
+            .filter(|f| !f.is_equal())
+            .map(|f| (f.field, f.changes_git_config))
+            .collect();
+        assert_eq!(changed, [("ssh_key_path", true), ("tags", false)]);
+
+        assert_eq!(diff.git_config.len(), 1);
+        assert_eq!(diff.git_config[0].key, "core.sshCommand");
+
+        assert!(diff_profiles("work", &left, "copy", &left).is_identical());
+    }
+}
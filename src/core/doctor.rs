@@ -0,0 +1,142 @@
+use crate::util::{
+    git, keychain,
+    system::{expand_home, resolve_binary},
+};
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    // worth fixing, but octopush keeps working
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    pub fn pass(name: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: None,
+        }
+    }
+
+    pub fn warn(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            detail: Some(detail.into()),
+        }
+    }
+
+    pub fn fail(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.name, detail),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+pub fn check_git() -> Check {
+    let name = "git is installed";
+    match git::require() {
+        Ok(version) => Check::pass(format!("{} (version {}.{})", name, version.0, version.1)),
+        Err(e) => Check::fail(name, e.to_string()),
+    }
+}
+
+pub fn check_gh(gh_path: &str) -> Check {
+    let name = format!("gh is installed ({})", gh_path);
+    match resolve_binary(gh_path) {
+        Ok(_) => Check::pass(name),
+        Err(e) => Check::fail(name, e.to_string()),
+    }
+}
+
+pub fn check_gh_auth(host: &str) -> Check {
+    let name = format!("gh is authenticated for '{}'", host);
+    if git::is_gh_authenticated(host) {
+        Check::pass(name)
+    } else {
+        Check::fail(name, format!("run `gh auth login --hostname {}`", host))
+    }
+}
+
+pub fn check_glab_auth(host: &str) -> Check {
+    let name = format!("glab is authenticated for '{}'", host);
+    if git::is_glab_authenticated(host) {
+        Check::pass(name)
+    } else {
+        Check::fail(name, format!("run `glab auth login --hostname {}`", host))
+    }
+}
+
+pub fn check_token(profile_name: &str) -> Check {
+    let name = format!("token of '{}' is in the keychain", profile_name);
+    match keychain::get_token(profile_name) {
+        Ok(Some(_)) => Check::pass(name),
+        Ok(None) => Check::fail(name, format!("run `octopush set-token {}`", profile_name)),
+        Err(e) => Check::fail(name, e.to_string()),
+    }
+}
+
+pub fn check_ssh_key(profile_name: &str, key: &str) -> Check {
+    let name = format!("ssh key of '{}' is readable", profile_name);
+    match File::open(expand_home(key)) {
+        Ok(_) => Check::pass(name),
+        Err(e) => Check::fail(name, format!("{}: {}", key, e)),
+    }
+}
+
+pub fn check_ca_info(profile_name: &str, path: &str) -> Check {
+    let name = format!("CA bundle of '{}' is readable", profile_name);
+    match File::open(expand_home(path)) {
+        Ok(_) => Check::pass(name),
+        Err(e) => Check::fail(name, format!("{}: {}", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn checks_keys_and_gh_auth() {
+        let cfg = TempConfig::new().unwrap();
+        let key = cfg.base.join("id_test");
+        std::fs::write(&key, "key").unwrap();
+
+        assert_eq!(
+            check_ssh_key("work", &key.to_string_lossy()).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_ssh_key("work", "/nonexistent/octopush/key").status,
+            CheckStatus::Fail
+        );
+        assert_eq!(check_gh_auth("github.com").status, CheckStatus::Pass);
+        assert_eq!(check_git().status, CheckStatus::Pass);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::core::{plan, profile::Profile};
+use crate::util::system::expand_home;
+use std::io;
+use std::process::{Command, ExitStatus, Stdio};
+
+// names the profile to `octopush credential` running under exec, which
+// otherwise answers for the repository's own profile
+pub const PROFILE_ENV: &str = "OCTOPUSH_PROFILE";
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExecOptions {
+    // inject GIT_SSH_COMMAND built from the profile's ssh key, which also
+    // wins over host aliases and fails for profiles without a key
+    pub with_key: bool,
+    // load the key into a throwaway ssh-agent for the child process
+    pub agent: bool,
+}
+
+// environment that makes git commit/push as the given profile without
+// touching any repository configuration
+pub fn profile_env(profile: &Profile) -> Vec<(String, String)> {
+    vec![
+        ("GIT_AUTHOR_NAME".to_string(), profile.name.clone()),
+        ("GIT_AUTHOR_EMAIL".to_string(), profile.email.clone()),
+        ("GIT_COMMITTER_NAME".to_string(), profile.name.clone()),
+        ("GIT_COMMITTER_EMAIL".to_string(), profile.email.clone()),
+    ]
+}
+
+pub fn ssh_env(profile: &Profile) -> Result<(String, String), io::Error> {
+    match profile.ssh_command() {
+        Some(command) => Ok(("GIT_SSH_COMMAND".to_string(), command)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "profile has no ssh key to use",
+        )),
+    }
+}
+
+// The config `use-profile` would write (ssh command, credential helper,
+// http and signing settings, url rewrites) as GIT_CONFIG_COUNT/KEY/VALUE
+// variables, which git reads as if passed with `-c` and so ahead of the
+// repository's own config. Keys the profile leaves unset cannot be unset
+// this way and keep their repository value.
+pub fn config_env(profile: &Profile) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for (key, value) in plan::desired_config(profile) {
+        let Some(value) = value else {
+            continue;
+        };
+        // an empty helper drops those configured before it
+        if key == "credential.helper" {
+            entries.push((key.to_string(), String::new()));
+        }
+        entries.push((key.to_string(), value));
+    }
+    entries.extend(plan::rewrite_config(profile));
+
+    let mut env = vec![("GIT_CONFIG_COUNT".to_string(), entries.len().to_string())];
+    for (i, (key, value)) in entries.into_iter().enumerate() {
+        env.push((format!("GIT_CONFIG_KEY_{}", i), key));
+        env.push((format!("GIT_CONFIG_VALUE_{}", i), value));
+    }
+    env
+}
+
+// `profile_env` plus GIT_SSH_COMMAND when the profile has an ssh key
+pub fn shell_env(profile: &Profile) -> Vec<(String, String)> {
+    let mut env = profile_env(profile);
+    env.extend(ssh_env(profile).ok());
+    env
+}
+
+// `export NAME='value'` lines that a POSIX shell can evaluate
+pub fn exports(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(name, value)| format!("export {}='{}'\n", name, value.replace('\'', r"'\''")))
+        .collect()
+}
+
+pub fn run(
+    profile_name: &str,
+    profile: &Profile,
+    command: &[String],
+    options: ExecOptions,
+) -> Result<ExitStatus, io::Error> {
+    let (program, args) = command.split_first().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no command given to execute")
+    })?;
+
+    let mut child = Command::new(program);
+    child
+        .args(args)
+        .envs(profile_env(profile))
+        .envs(config_env(profile))
+        .env(PROFILE_ENV, profile_name);
+
+    // the agent is killed when dropped, after the child has exited
+    let mut _agent = None;
+    if options.with_key {
+        let (key, value) = ssh_env(profile)?;
+        child.env(key, value);
+
+        if options.agent {
+            let agent = SshAgent::start()?;
+            agent.add_key(profile.ssh_key_path.as_deref().unwrap_or_default())?;
+            child.envs(agent.env());
+            _agent = Some(agent);
+        }
+    }
+
+    child.status()
+}
+
+// a private ssh-agent living only for the duration of one exec
+struct SshAgent {
+    socket: String,
+    pid: String,
+}
+
+impl SshAgent {
+    fn start() -> Result<Self, io::Error> {
+        let out = Command::new("ssh-agent").arg("-s").output()?;
+        if !out.status.success() {
+            return Err(io::Error::other("failed to start ssh-agent"));
+        }
+
+        let text = String::from_utf8_lossy(&out.stdout);
+        let socket = Self::parse_var(&text, "SSH_AUTH_SOCK");
+        let pid = Self::parse_var(&text, "SSH_AGENT_PID");
+
+        match (socket, pid) {
+            (Some(socket), Some(pid)) => Ok(SshAgent { socket, pid }),
+            _ => Err(io::Error::other("unexpected ssh-agent output")),
+        }
+    }
+
+    // ssh-agent -s prints `SSH_AUTH_SOCK=/tmp/...; export SSH_AUTH_SOCK;`
+    fn parse_var(text: &str, name: &str) -> Option<String> {
+        text.split(';')
+            .map(str::trim)
+            .find_map(|part| part.strip_prefix(&format!("{}=", name)))
+            .map(|value| value.to_string())
+    }
+
+    fn env(&self) -> [(&str, &str); 2] {
+        [
+            ("SSH_AUTH_SOCK", self.socket.as_str()),
+            ("SSH_AGENT_PID", self.pid.as_str()),
+        ]
+    }
+
+    fn add_key(&self, key_path: &str) -> Result<(), io::Error> {
+        let status = Command::new("ssh-add")
+            .arg(expand_home(key_path))
+            .envs(self.env())
+            .stdout(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "failed to add '{}' to the temporary ssh-agent",
+                key_path
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SshAgent {
+    fn drop(&mut self) {
+        let _ = Command::new("ssh-agent")
+            .arg("-k")
+            .envs(self.env())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+
+    #[test]
+    fn parses_ssh_agent_output() {
+        let text = "SSH_AUTH_SOCK=/tmp/ssh-abc/agent.1; export SSH_AUTH_SOCK;\nSSH_AGENT_PID=42; export SSH_AGENT_PID;\necho Agent pid 42;\n";
+
+        assert_eq!(
+            SshAgent::parse_var(text, "SSH_AUTH_SOCK").as_deref(),
+            Some("/tmp/ssh-abc/agent.1")
+        );
+        assert_eq!(
+            SshAgent::parse_var(text, "SSH_AGENT_PID").as_deref(),
+            Some("42")
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn runs_command_with_profile_identity_and_key() {
+        let profile = Profile::build(
+            "Exec User".to_string(),
+            "exec@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("/tmp/exec_key".to_string()),
+        );
+
+        let command = [
+            "sh".to_string(),
+            "-c".to_string(),
+            r#"test "$GIT_AUTHOR_EMAIL" = exec@example.com && test "$GIT_SSH_COMMAND" = "ssh -i /tmp/exec_key -F /dev/null""#.to_string(),
+        ];
+
+        let status = run(
+            "exec",
+            &profile,
+            &command,
+            ExecOptions {
+                with_key: true,
+                agent: false,
+            },
+        )
+        .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn exports_profile_env_for_eval() {
+        let mut profile = Profile::build(
+            "Jane O'Brien".to_string(),
+            "jane@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("/home/jane/my keys/id_work".to_string()),
+        );
+
+        let exports = exports(&shell_env(&profile));
+        assert!(exports.starts_with("export GIT_AUTHOR_NAME='Jane O'\\''Brien'\n"));
+        let script = format!(
+            "{}{}",
+            exports,
+            r#"test "$GIT_COMMITTER_NAME" = "Jane O'Brien" && test "$GIT_SSH_COMMAND" = 'ssh -i "/home/jane/my keys/id_work" -F /dev/null'"#
+        );
+        let status = Command::new("sh").args(["-c", &script]).status().unwrap();
+        assert!(status.success());
+
+        profile.ssh_key_path = None;
+        assert!(!super::exports(&shell_env(&profile)).contains("GIT_SSH_COMMAND"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn runs_command_with_profile_credentials() {
+        let mut profile = Profile::build(
+            "Exec User".to_string(),
+            "exec@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+        profile.url_rewrites.insert(
+            "https://github.com/acme/".to_string(),
+            "https://github.com/acme-mirror/".to_string(),
+        );
+
+        let command = [
+            "sh".to_string(),
+            "-c".to_string(),
+            [
+                r#"test "$(git config --get-all credential.helper | tail -n 2)" = "$(printf '\n!gh auth git-credential')""#,
+                r#"test "$(git config credential.useHttpPath)" = true"#,
+                r#"test "$(git config url.https://github.com/acme-mirror/.insteadOf)" = https://github.com/acme/"#,
+                r#"test "$OCTOPUSH_PROFILE" = work"#,
+            ]
+            .join(" && "),
+        ];
+
+        let status = run("work", &profile, &command, ExecOptions::default()).unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn with_key_requires_ssh_key() {
+        let profile = Profile::build(
+            "Exec User".to_string(),
+            "exec@example.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+
+        let err = run(
+            "exec",
+            &profile,
+            &["true".to_string()],
+            ExecOptions {
+                with_key: true,
+                agent: false,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::core::{plan::desired_config, profile::Profile};
+use std::path::Path;
+
+// A profile as a standalone gitconfig file, holding the same identity and
+// auth settings `use-profile` writes into a repository.
+pub fn profile_snippet(profile: &Profile) -> String {
+    let mut out = String::new();
+    let mut section = "";
+
+    for (key, value) in desired_config(profile) {
+        let Some(value) = value else {
+            continue;
+        };
+        let Some((name, variable)) = key.split_once('.') else {
+            continue;
+        };
+
+        if name != section {
+            out.push_str(&format!("[{}]\n", name));
+            section = name;
+        }
+        // credential helpers accumulate across config files; an empty value
+        // drops the ones configured globally first
+        if key == "credential.helper" && profile.uses_credential_helper() {
+            out.push_str("\thelper =\n");
+        }
+        out.push_str(&format!("\t{} = {}\n", variable, quote(&value)));
+    }
+
+    for (from, to) in &profile.url_rewrites {
+        out.push_str(&format!(
+            "[url {}]\n\tinsteadOf = {}\n",
+            quote(to),
+            quote(from)
+        ));
+    }
+
+    out
+}
+
+// `[includeIf "<condition>"]` pointing at a profile snippet
+pub fn include_section(condition: &str, snippet: &Path) -> String {
+    format!(
+        "[includeIf {}]\n\tpath = {}\n",
+        quote(condition),
+        quote(&snippet.to_string_lossy())
+    )
+}
+
+// a mapped repository and everything below it, worktrees included
+pub fn gitdir_condition(repo_root: &str) -> String {
+    format!("gitdir:{}/", repo_root.trim_end_matches('/'))
+}
+
+// Remote rules match `host/owner/repo`, but git matches the remote url as
+// written, so every url shape a clone may have is listed.
+pub fn remote_conditions(pattern: &str) -> Vec<String> {
+    let Some((host, path)) = pattern.split_once('/') else {
+        return Vec::new();
+    };
+
+    let mut paths = vec![path.to_string()];
+    if !path.ends_with('*') {
+        paths.push(format!("{}.git", path));
+    }
+
+    paths
+        .iter()
+        .flat_map(|path| {
+            [
+                format!("hasconfig:remote.*.url:https://{}/{}", host, path),
+                format!("hasconfig:remote.*.url:git@{}:{}", host, path),
+            ]
+        })
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+
+    #[test]
+    fn renders_snippets_and_conditions() {
+        let profile = Profile::build(
+            "Jane".to_string(),
+            "jane@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+
+        assert_eq!(
+            profile_snippet(&profile),
+            "[user]\n\tname = \"Jane\"\n\temail = \"jane@example.com\"\n[credential]\n\thelper =\n\thelper = \"!gh auth git-credential\"\n\tuseHttpPath = \"true\"\n"
+        );
+        assert_eq!(gitdir_condition("/home/jane/app"), "gitdir:/home/jane/app/");
+        assert_eq!(
+            remote_conditions("github.com/acme/**"),
+            [
+                "hasconfig:remote.*.url:https://github.com/acme/**",
+                "hasconfig:remote.*.url:git@github.com:acme/**"
+            ]
+        );
+        assert_eq!(remote_conditions("github.com/acme/app").len(), 4);
+    }
+}
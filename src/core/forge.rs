@@ -0,0 +1,70 @@
+// The code hosts octopush knows how to link to. Anything else is assumed to
+// serve repositories at `https://host/owner/repo`, which covers most forges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    Bitbucket,
+    Other,
+}
+
+impl Forge {
+    // enterprise installs are recognised by their usual hostnames
+    pub fn detect(host: &str) -> Self {
+        let host = host.to_lowercase();
+        if host.contains("github") {
+            Forge::GitHub
+        } else if host.contains("gitlab") {
+            Forge::GitLab
+        } else if host.contains("bitbucket") {
+            Forge::Bitbucket
+        } else {
+            Forge::Other
+        }
+    }
+
+    pub fn repo_url(&self, host: &str, owner: &str, repo: &str) -> String {
+        format!("https://{}/{}/{}", host, owner, repo)
+    }
+
+    // a page that asks the browser to continue as `login` before landing on
+    // the repository, for forges that support prefilling the account
+    pub fn account_url(&self, host: &str, owner: &str, repo: &str, login: &str) -> Option<String> {
+        match self {
+            Forge::GitHub => Some(format!(
+                "https://{}/login?login={}&return_to=%2F{}%2F{}",
+                host, login, owner, repo
+            )),
+            Forge::GitLab | Forge::Bitbucket | Forge::Other => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_repo_and_account_urls() {
+        let forge = Forge::detect("github.com");
+        assert_eq!(forge, Forge::GitHub);
+        assert_eq!(Forge::detect("gitlab.mycorp.com"), Forge::GitLab);
+        assert_eq!(Forge::detect("bitbucket.corp.com"), Forge::Bitbucket);
+        assert_eq!(Forge::detect("git.sr.ht"), Forge::Other);
+
+        assert_eq!(
+            forge.repo_url("github.com", "acme", "app"),
+            "https://github.com/acme/app"
+        );
+        assert_eq!(
+            forge
+                .account_url("github.com", "acme", "app", "jane")
+                .as_deref(),
+            Some("https://github.com/login?login=jane&return_to=%2Facme%2Fapp")
+        );
+        assert_eq!(
+            Forge::GitLab.account_url("gitlab.com", "acme", "app", "jane"),
+            None
+        );
+    }
+}
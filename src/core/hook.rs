@@ -0,0 +1,86 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// identifies hooks octopush wrote, so they can be replaced without --force
+pub const HOOK_MARKER: &str = "# installed by octopush";
+
+// Aborts the commit unless the committing identity matches the repository's
+// mapped profile. A missing octopush binary lets the commit through rather
+// than locking the user out of their own repository.
+pub const PRE_COMMIT_HOOK: &str = r#"#!/bin/sh
+# installed by octopush: refuses commits made under the wrong identity
+command -v octopush >/dev/null 2>&1 || exit 0
+exec octopush check-identity
+"#;
+
+// Applies the profile picked by rules (or an existing mapping) right after
+// `git clone`, the only checkout whose previous HEAD is the null ref. Clones
+// nothing decides for are left alone, as is any later branch checkout.
+pub const POST_CHECKOUT_HOOK: &str = r#"#!/bin/sh
+# installed by octopush: applies the matching profile to fresh clones
+[ "$1" = "0000000000000000000000000000000000000000" ] || exit 0
+command -v octopush >/dev/null 2>&1 || exit 0
+octopush auto-apply || true
+"#;
+
+// writes the pre-commit hook into `hooks_dir`, refusing to replace a hook
+// octopush did not write unless `force` is set
+pub fn install_pre_commit(hooks_dir: &Path, force: bool) -> Result<PathBuf, io::Error> {
+    install(hooks_dir, "pre-commit", PRE_COMMIT_HOOK, force)
+}
+
+pub fn install_post_checkout(hooks_dir: &Path, force: bool) -> Result<PathBuf, io::Error> {
+    install(hooks_dir, "post-checkout", POST_CHECKOUT_HOOK, force)
+}
+
+fn install(hooks_dir: &Path, name: &str, script: &str, force: bool) -> Result<PathBuf, io::Error> {
+    let path = hooks_dir.join(name);
+
+    if !force
+        && let Ok(existing) = fs::read_to_string(&path)
+        && !existing.contains(HOOK_MARKER)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "'{}' already exists and was not written by octopush, use --force to replace it",
+                path.display()
+            ),
+        ));
+    }
+
+    fs::create_dir_all(hooks_dir)?;
+    fs::write(&path, script)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn keeps_foreign_hooks_unless_forced() {
+        let cfg = TempConfig::new().unwrap();
+        let hooks = cfg.base.join("hooks");
+
+        let path = install_pre_commit(&hooks, false).unwrap();
+        // reinstalling over our own hook is fine
+        install_pre_commit(&hooks, false).unwrap();
+
+        fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+        let err = install_pre_commit(&hooks, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        install_pre_commit(&hooks, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), PRE_COMMIT_HOOK);
+    }
+}
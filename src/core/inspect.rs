@@ -0,0 +1,248 @@
+use crate::core::{
+    app::App, auth::AuthType, plan::PlanOperation, profile::Profile, project::Project,
+    status::RepoState,
+};
+use crate::util::git;
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Everything an external tool needs to know about a repository's identity,
+// gathered without writing anything or prompting.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RepoIdentityReport {
+    pub repo: PathBuf,
+    // profile the repository is mapped to, if any
+    pub profile_name: Option<String>,
+    pub profile: Option<Profile>,
+    // what git will actually use when committing here
+    pub effective_name: Option<String>,
+    pub effective_email: Option<String>,
+    // auth mode detected from the repository's local config
+    pub auth_type: AuthType,
+    // changes needed to bring the repository back to its mapped profile
+    pub drift: Vec<PlanOperation>,
+    // remotes reached over plain http:// or git://
+    pub insecure_remotes: Vec<String>,
+}
+
+impl RepoIdentityReport {
+    pub fn is_mapped(&self) -> bool {
+        self.profile_name.is_some()
+    }
+
+    pub fn has_drift(&self) -> bool {
+        !self.drift.is_empty()
+    }
+}
+
+pub fn inspect(path: impl AsRef<Path>) -> Result<RepoIdentityReport, io::Error> {
+    let repo = path.as_ref();
+    let project_path = repo.to_string_lossy().into_owned();
+
+    let (profile_name, profile) = match App::get_project_profile(project_path.clone()) {
+        Ok((profile_name, profile, _)) => (Some(profile_name), Some(profile)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (None, None),
+        Err(e) => return Err(e),
+    };
+
+    let drift = match App::repo_state(project_path)? {
+        RepoState::Drifted { plan, .. } => plan.operations,
+        _ => Vec::new(),
+    };
+
+    Ok(RepoIdentityReport {
+        repo: repo.to_path_buf(),
+        profile_name,
+        profile,
+        effective_name: git::get_config(repo, "user.name")?,
+        effective_email: git::get_config(repo, "user.email")?,
+        auth_type: detect_auth_type(repo)?,
+        drift,
+        insecure_remotes: git::insecure_remotes(repo)?
+            .into_iter()
+            .map(|(remote, _)| remote)
+            .collect(),
+    })
+}
+
+// What git will commit as in a repository, and where it got it from, next
+// to the profile octopush resolves for the repository.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Whoami {
+    pub repo: PathBuf,
+    pub name: Option<IdentityValue>,
+    pub email: Option<IdentityValue>,
+    // from the `.octopush` file, rules, the mapping or the default profile
+    pub profile_name: Option<String>,
+    pub profile: Option<Profile>,
+    // `user.name` and `user.email` when they differ from the profile's
+    pub mismatches: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IdentityValue {
+    pub value: String,
+    // `env` for GIT_AUTHOR_NAME/GIT_AUTHOR_EMAIL, which beat any config,
+    // otherwise the config scope: `command`, `worktree`, `local`, `global`
+    // or `system`
+    pub source: String,
+}
+
+pub fn whoami(path: impl AsRef<Path>) -> Result<Whoami, io::Error> {
+    let repo = &Project::new(path.as_ref())?.get_repo_root()?;
+
+    let identity = |key: &str, env: &str| -> Result<Option<IdentityValue>, io::Error> {
+        if let Ok(value) = std::env::var(env)
+            && !value.is_empty()
+        {
+            return Ok(Some(IdentityValue {
+                value,
+                source: "env".to_string(),
+            }));
+        }
+        Ok(git::get_config_with_scope(repo, key)?
+            .map(|(value, source)| IdentityValue { value, source }))
+    };
+    let name = identity("user.name", "GIT_AUTHOR_NAME")?;
+    let email = identity("user.email", "GIT_AUTHOR_EMAIL")?;
+
+    let profile_name = match App::resolve_profile_name(repo.to_string_lossy().into_owned()) {
+        Ok(profile_name) => Some(profile_name),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let profile = match &profile_name {
+        Some(profile_name) => Some(App::get_profile(profile_name.clone())?),
+        None => None,
+    };
+
+    let mut mismatches = Vec::new();
+    if let Some(profile) = &profile {
+        let value = |identity: &Option<IdentityValue>| identity.as_ref().map(|v| v.value.clone());
+        if value(&name).as_deref() != Some(profile.name.as_str()) {
+            mismatches.push("user.name".to_string());
+        }
+        if value(&email).as_deref() != Some(profile.email.as_str()) {
+            mismatches.push("user.email".to_string());
+        }
+    }
+
+    Ok(Whoami {
+        repo: repo.to_path_buf(),
+        name,
+        email,
+        profile_name,
+        profile,
+        mismatches,
+    })
+}
+
+// infers the auth mode from what is configured locally in the repository
+pub fn detect_auth_type(repo: &Path) -> Result<AuthType, io::Error> {
+    if let Some(helper) = git::get_local_config(repo, "credential.helper")? {
+        if helper == git::TOKEN_CREDENTIAL_HELPER {
+            return Ok(AuthType::Token);
+        }
+        if git::is_glab_credential_helper(&helper) {
+            return Ok(AuthType::GitLab);
+        }
+        if git::is_gh_credential_helper(&helper) {
+            return Ok(AuthType::GH);
+        }
+    }
+    if git::get_local_config(repo, "core.sshCommand")?.is_some() {
+        return Ok(AuthType::SSH);
+    }
+    Ok(AuthType::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn inspects_unmapped_repo_without_writing_config() {
+        let cfg = TempConfig::new().unwrap();
+
+        git::set_local_config(&cfg.repo, "user.email", "hand@set.com").unwrap();
+
+        let report = inspect(&cfg.repo).unwrap();
+
+        assert!(!report.is_mapped());
+        assert!(!report.has_drift());
+        assert_eq!(report.effective_email.as_deref(), Some("hand@set.com"));
+        assert_eq!(report.auth_type, AuthType::None);
+        assert!(!cfg.base.join("octopush-test").exists());
+    }
+
+    #[test]
+    fn whoami_reports_sources_and_mismatches() {
+        let cfg = TempConfig::new().unwrap();
+
+        let report = whoami(&cfg.repo).unwrap();
+        assert_eq!(report.profile_name, None);
+        assert!(report.mismatches.is_empty());
+
+        let profile = Profile::build(
+            "Work".to_string(),
+            "work@example.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+        App::add_profile("work".to_string(), profile).unwrap();
+        App::use_profile("work".to_string(), cfg.repo.to_string_lossy().into_owned()).unwrap();
+
+        let report = whoami(&cfg.repo).unwrap();
+        assert_eq!(report.profile_name.as_deref(), Some("work"));
+        assert_eq!(
+            report.email,
+            Some(IdentityValue {
+                value: "work@example.com".to_string(),
+                source: "local".to_string(),
+            })
+        );
+        assert!(report.mismatches.is_empty());
+
+        git::unset_local(&cfg.repo, "user.email").unwrap();
+        let report = whoami(&cfg.repo).unwrap();
+        assert_eq!(report.mismatches, ["user.email"]);
+        assert_ne!(
+            report.email.map(|email| email.source).as_deref(),
+            Some("local")
+        );
+    }
+
+    #[test]
+    fn inspects_mapped_repo_with_drift() {
+        let cfg = TempConfig::new().unwrap();
+
+        let profile = Profile::build(
+            "Work".to_string(),
+            "work@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+        App::add_profile("work".to_string(), profile.clone()).unwrap();
+        App::use_profile("work".to_string(), cfg.repo.to_string_lossy().into_owned()).unwrap();
+
+        git::set_local_config(&cfg.repo, "user.email", "oops@example.com").unwrap();
+
+        let report = inspect(&cfg.repo).unwrap();
+
+        assert_eq!(report.profile_name.as_deref(), Some("work"));
+        assert_eq!(report.profile, Some(profile));
+        assert_eq!(report.auth_type, AuthType::GH);
+        assert_eq!(
+            report.drift,
+            vec![PlanOperation::GitConfig {
+                key: "user.email".to_string(),
+                before: Some("oops@example.com".to_string()),
+                after: Some("work@example.com".to_string()),
+            }]
+        );
+    }
+}
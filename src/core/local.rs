@@ -0,0 +1,143 @@
+use crate::core::profile::{Profile, validate_profile_name};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A `.octopush` file at the repository root lets a team commit the identity
+// the repository expects. It either pins a profile from profiles.toml:
+//
+// profile = "acme"
+//
+// or inlines the profile, which is added to profiles.toml the first time it
+// is used:
+//
+// [acme]
+// name = "John Doe"
+// email = "john@acme.com"
+// auth_type = "GH"
+// hostname = "github.com"
+//
+// Anyone can commit this file, so inline profiles are limited to identity
+// fields; settings that make git run a program or trust another server
+// (credential helpers, gpg programs, template dirs, proxies, CA bundles)
+// only come from the user's own profiles.
+
+pub const FILE_NAME: &str = ".octopush";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalProfile {
+    Pinned(String),
+    Inline(String, Box<Profile>),
+}
+
+impl LocalProfile {
+    pub fn profile_name(&self) -> &str {
+        match self {
+            LocalProfile::Pinned(profile_name) | LocalProfile::Inline(profile_name, _) => {
+                profile_name
+            }
+        }
+    }
+}
+
+// the `.octopush` file of the repository rooted at `repo_root`, if it has one
+pub fn read(repo_root: &Path) -> Result<Option<LocalProfile>, io::Error> {
+    let path = repo_root.join(FILE_NAME);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    log::debug!("reading {}", path.display());
+
+    parse(&content).map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(content: &str) -> Result<LocalProfile, String> {
+    let table: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+    let mut entries = table.into_iter();
+
+    let local = match (entries.next(), entries.next()) {
+        (Some((key, toml::Value::String(profile_name))), None) if key == "profile" => {
+            LocalProfile::Pinned(profile_name)
+        }
+        (Some((profile_name, value @ toml::Value::Table(_))), None) => {
+            let profile: Profile = value.try_into().map_err(|e| e.to_string())?;
+            LocalProfile::Inline(profile_name, Box::new(identity_only(profile)?))
+        }
+        _ => {
+            return Err(
+                "expected `profile = \"<name>\"` or a single `[<name>]` profile table".to_string(),
+            );
+        }
+    };
+
+    validate_profile_name(local.profile_name()).map_err(|e| e.to_string())?;
+    Ok(local)
+}
+
+fn identity_only(profile: Profile) -> Result<Profile, String> {
+    let identity = Profile {
+        tags: profile.tags.clone(),
+        default_branch: profile.default_branch.clone(),
+        signing_key: profile.signing_key.clone(),
+        ssh_host_alias: profile.ssh_host_alias,
+        ..Profile::build(
+            profile.name.clone(),
+            profile.email.clone(),
+            profile.auth_type,
+            profile.hostname.clone(),
+            profile.ssh_key_path.clone(),
+        )
+    };
+
+    if identity != profile {
+        return Err("inline profiles may only set name, email, auth_type, hostname, ssh_key_path, tags, default_branch, signing_key and ssh_host_alias".to_string());
+    }
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+
+    #[test]
+    fn parses_pinned_and_inline_profiles() {
+        assert_eq!(
+            parse("profile = \"acme\"\n").unwrap(),
+            LocalProfile::Pinned("acme".to_string())
+        );
+
+        let inline = parse(
+            "[acme]\nname = \"John\"\nemail = \"john@acme.com\"\nauth_type = \"GH\"\nhostname = \"github.com\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            inline,
+            LocalProfile::Inline(
+                "acme".to_string(),
+                Box::new(Profile::build(
+                    "John".to_string(),
+                    "john@acme.com".to_string(),
+                    AuthType::GH,
+                    Some("github.com".to_string()),
+                    None,
+                ))
+            )
+        );
+
+        // a committed file must not be able to make git run anything
+        assert!(
+            parse("[acme]\nname = \"John\"\nemail = \"j@acme.com\"\nauth_type = \"None\"\ncredential_helper = \"!sh evil.sh\"\n")
+                .is_err()
+        );
+        assert!(parse("profile = \"acme\"\nextra = 1\n").is_err());
+        assert!(parse("profile = \"two words\"\n").is_err());
+    }
+}
@@ -0,0 +1,1283 @@
+use crate::core::{
+    auth::AuthType,
+    profile::{self, Profile},
+};
+use crate::util::{
+    git::{self, GitBackend},
+    ssh_config,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const PLAN_VERSION: u32 = 1;
+
+// config keys use-profile previews, see PlanOperation::is_notable
+const PREVIEW_KEYS: [&str; 4] = [
+    "user.name",
+    "user.email",
+    "core.sshCommand",
+    "credential.helper",
+];
+
+// An ApplyPlan is the full list of changes `use-profile` would make to a
+// repository. It is serializable so it can be reviewed (or approved by
+// external tooling) and applied verbatim later with `--execute-plan`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApplyPlan {
+    pub version: u32,
+    pub repo: PathBuf,
+    pub profile_name: String,
+    pub operations: Vec<PlanOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "target", rename_all = "snake_case")]
+pub enum PlanOperation {
+    GitConfig {
+        key: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    RemoteUrl {
+        remote: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    PushUrl {
+        remote: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    // a `Host` entry in the managed block of ~/.ssh/config
+    SshHost {
+        alias: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    Mapping {
+        key: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+}
+
+impl PlanOperation {
+    pub fn before(&self) -> Option<&str> {
+        match self {
+            PlanOperation::GitConfig { before, .. }
+            | PlanOperation::RemoteUrl { before, .. }
+            | PlanOperation::PushUrl { before, .. }
+            | PlanOperation::SshHost { before, .. }
+            | PlanOperation::Mapping { before, .. } => before.as_deref(),
+        }
+    }
+
+    pub fn after(&self) -> Option<&str> {
+        match self {
+            PlanOperation::GitConfig { after, .. }
+            | PlanOperation::RemoteUrl { after, .. }
+            | PlanOperation::PushUrl { after, .. }
+            | PlanOperation::SshHost { after, .. }
+            | PlanOperation::Mapping { after, .. } => after.as_deref(),
+        }
+    }
+
+    pub fn target(&self) -> String {
+        match self {
+            PlanOperation::GitConfig { key, .. } => format!("git config {}", key),
+            PlanOperation::RemoteUrl { remote, .. } => format!("remote {}", remote),
+            PlanOperation::PushUrl { remote, .. } => format!("remote {} pushurl", remote),
+            PlanOperation::SshHost { alias, .. } => format!("ssh config Host {}", alias),
+            PlanOperation::Mapping { key, .. } => format!("mapping {}", key),
+        }
+    }
+
+    // identity, how git authenticates and where it pushes: what use-profile
+    // shows before asking to go ahead
+    pub fn is_notable(&self) -> bool {
+        match self {
+            PlanOperation::GitConfig { key, .. } => PREVIEW_KEYS.contains(&key.as_str()),
+            PlanOperation::RemoteUrl { .. }
+            | PlanOperation::PushUrl { .. }
+            | PlanOperation::SshHost { .. } => true,
+            PlanOperation::Mapping { .. } => false,
+        }
+    }
+
+    // why applying `profile_name` makes this change, in one line for
+    // `--explain`: `forces ssh pushes and fetches to use the work key`
+    pub fn explain(&self, profile_name: &str) -> String {
+        let after = self.after();
+        match self {
+            PlanOperation::GitConfig { key, .. } => match (key.as_str(), after) {
+                ("user.name", Some(name)) => format!("commits are authored by {}", name),
+                ("user.email", Some(email)) => format!(
+                    "commits carry {}, which forges use to link them to the {} account",
+                    email, profile_name
+                ),
+                ("core.sshCommand", Some(_)) => format!(
+                    "forces ssh pushes and fetches to use the {} key, whatever the agent offers",
+                    profile_name
+                ),
+                ("core.sshCommand", None) => {
+                    "stops forcing an ssh key, the profile authenticates another way".to_string()
+                }
+                ("credential.helper", Some(helper)) => format!(
+                    "https pushes and fetches get the {} credentials from {}",
+                    profile_name,
+                    helper_name(helper)
+                ),
+                ("credential.helper", None) => {
+                    "stops a credential helper from answering for this repository".to_string()
+                }
+                ("credential.useHttpPath", Some(_)) => {
+                    "credentials are looked up per repository, so accounts on one host do not mix"
+                        .to_string()
+                }
+                ("credential.useHttpPath", None) => {
+                    "credentials are looked up per host again".to_string()
+                }
+                ("http.sslCAInfo", Some(path)) => {
+                    format!("https trusts the certificate authority in {}", path)
+                }
+                ("http.sslVerify", Some("false")) => {
+                    "https skips certificate checks for this repository".to_string()
+                }
+                ("http.sslVerify", Some(_)) => "https checks certificates".to_string(),
+                ("http.proxy", Some(proxy)) => format!("https traffic goes through {}", proxy),
+                ("user.signingKey", Some(_)) => {
+                    format!("commits and tags are signed with the {} key", profile_name)
+                }
+                ("gpg.format", Some(format)) => format!("signatures are made with {}", format),
+                ("gpg.program", Some(program)) => format!("signing runs {}", program),
+                ("commit.gpgSign", Some(_)) => "every commit is signed".to_string(),
+                ("tag.gpgSign", Some(_)) => "every tag is signed".to_string(),
+                (key, Some(prefix)) if key.starts_with("url.") && key.ends_with(".insteadOf") => {
+                    format!(
+                        "urls starting with {} are fetched from {} instead",
+                        prefix,
+                        &key["url.".len()..key.len() - ".insteadOf".len()]
+                    )
+                }
+                (_, Some(_)) => format!("the {} profile sets it", profile_name),
+                (_, None) => format!("the {} profile does not use it", profile_name),
+            },
+            PlanOperation::RemoteUrl { remote, .. } => format!(
+                "{} moves to the url scheme the {} profile authenticates with",
+                remote, profile_name
+            ),
+            PlanOperation::PushUrl { remote, .. } => format!(
+                "pushes to {} use the {} credentials, fetches keep their url",
+                remote, profile_name
+            ),
+            PlanOperation::SshHost { alias, .. } => {
+                format!("ssh connections to {} pick the {} key", alias, profile_name)
+            }
+            PlanOperation::Mapping { after: Some(_), .. } => format!(
+                "octopush remembers that this repository uses {}",
+                profile_name
+            ),
+            PlanOperation::Mapping { after: None, .. } => {
+                "octopush forgets the repository's profile".to_string()
+            }
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        match self {
+            PlanOperation::GitConfig { key, .. }
+                if key.starts_with("user.")
+                    || key.starts_with("gpg.")
+                    || key.ends_with(".gpgSign") =>
+            {
+                Stage::Identity
+            }
+            PlanOperation::GitConfig { .. } | PlanOperation::SshHost { .. } => Stage::Auth,
+            PlanOperation::RemoteUrl { .. } | PlanOperation::PushUrl { .. } => Stage::Remote,
+            PlanOperation::Mapping { .. } => Stage::Mapping,
+        }
+    }
+}
+
+// the steps a plan is applied in, so progress can show where it is
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    // name, email and signing
+    Identity,
+    // ssh command, credential helper, proxy and url rewrites
+    Auth,
+    Remote,
+    Mapping,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::Identity, Stage::Auth, Stage::Remote, Stage::Mapping];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stage::Identity => "identity",
+            Stage::Auth => "auth",
+            Stage::Remote => "remote",
+            Stage::Mapping => "mapping",
+        }
+    }
+}
+
+impl fmt::Display for PlanOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.target(),
+            self.before().unwrap_or("(unset)"),
+            self.after().unwrap_or("(unset)")
+        )
+    }
+}
+
+// how --explain names a credential helper
+fn helper_name(helper: &str) -> String {
+    if git::is_gh_credential_helper(helper) {
+        "gh".to_string()
+    } else if git::is_glab_credential_helper(helper) {
+        "glab".to_string()
+    } else if helper == git::TOKEN_CREDENTIAL_HELPER {
+        "the token octopush keeps in the keychain".to_string()
+    } else if profile::is_cache_helper(helper) {
+        "git's credential cache".to_string()
+    } else {
+        format!("`{}`", helper)
+    }
+}
+
+// which remotes get their URL rewritten to the profile's scheme
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Remotes {
+    // the remote octopush manages, see `git::primary_remote`
+    #[default]
+    Primary,
+    Named(String),
+    All,
+}
+
+impl Remotes {
+    fn resolve(&self, git: &dyn GitBackend, repo: &Path) -> Result<Vec<String>, io::Error> {
+        match self {
+            Remotes::Primary => Ok(git.primary_remote(repo)?.into_iter().collect()),
+            Remotes::Named(name) => {
+                if git.list_remotes(repo)?.contains(name) {
+                    Ok(vec![name.clone()])
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no remote named '{}'", name),
+                    ))
+                }
+            }
+            Remotes::All => git.list_remotes(repo),
+        }
+    }
+}
+
+impl ApplyPlan {
+    // builds the git-side operations needed to make `repo` match `profile`;
+    // the mapping operation is added by the caller that owns the mapping file
+    pub fn build(
+        git: &dyn GitBackend,
+        repo: &Path,
+        profile_name: &str,
+        profile: &Profile,
+    ) -> Result<Self, io::Error> {
+        Self::build_with_remotes(git, repo, profile_name, profile, &Remotes::Primary)
+    }
+
+    pub fn build_with_remotes(
+        git: &dyn GitBackend,
+        repo: &Path,
+        profile_name: &str,
+        profile: &Profile,
+        remotes: &Remotes,
+    ) -> Result<Self, io::Error> {
+        git.ensure_repo(repo)?;
+
+        let mut operations = Vec::new();
+
+        for (key, after) in desired_config(profile) {
+            let before = git.get_local_config(repo, key)?;
+            if before != after {
+                log::info!(
+                    "{} is {}, the profile wants {}",
+                    key,
+                    before.as_deref().unwrap_or("unset"),
+                    after.as_deref().unwrap_or("it unset")
+                );
+                operations.push(PlanOperation::GitConfig {
+                    key: key.to_string(),
+                    before,
+                    after,
+                });
+            }
+        }
+
+        for (key, from) in rewrite_config(profile) {
+            let before = git.get_local_config(repo, &key)?;
+            if before.as_deref() != Some(from.as_str()) {
+                operations.push(PlanOperation::GitConfig {
+                    key,
+                    before,
+                    after: Some(from),
+                });
+            }
+        }
+
+        let mut aliases: Vec<String> = Vec::new();
+        for remote in remotes.resolve(git, repo)? {
+            // A separate push URL means fetching and pushing were split on
+            // purpose, e.g. fetching upstream over https and pushing to a
+            // fork over ssh. Only the push side needs the profile's
+            // credentials, so the fetch URL is left alone.
+            let push_url = git.get_push_url(repo, &remote)?;
+            let before = match &push_url {
+                Some(url) => Some(url.clone()),
+                None => git.get_remote_url(repo, &remote)?,
+            };
+            let Some(before) = before else {
+                log::info!("remote {} has no url, leaving it alone", remote);
+                continue;
+            };
+            if push_url.is_some() {
+                log::info!(
+                    "remote {} has a separate push url, rewriting only that",
+                    remote
+                );
+            }
+            let Some((after, host)) = desired_remote_url(profile_name, profile, &before) else {
+                continue;
+            };
+
+            // remotes that could not move onto the alias do not need it
+            let uses_alias = git::parse_remote(&after)
+                .is_some_and(|(h, _, _)| h == ssh_config::alias_for(&host, profile_name));
+            if uses_alias && !aliases.contains(&host) {
+                aliases.push(host);
+            }
+            if after == before {
+                log::info!("remote {} already fits the profile", remote);
+                continue;
+            }
+            log::info!(
+                "remote {} is {}, rewriting to {}",
+                remote,
+                scheme(&before),
+                scheme(&after)
+            );
+
+            operations.push(if push_url.is_some() {
+                PlanOperation::PushUrl {
+                    remote,
+                    before: Some(before),
+                    after: Some(after),
+                }
+            } else {
+                PlanOperation::RemoteUrl {
+                    remote,
+                    before: Some(before),
+                    after: Some(after),
+                }
+            });
+        }
+
+        if let Some(path) = ssh_config::config_path() {
+            for host in aliases {
+                let alias = ssh_config::alias_for(&host, profile_name);
+                let after = Some(ssh_config::host_entry(
+                    &alias,
+                    &host,
+                    profile.ssh_key_path.as_deref(),
+                    profile.ssh_identity_agent.as_deref(),
+                ));
+                let before = ssh_config::read_entry(&path, &alias)?;
+                if before != after {
+                    operations.push(PlanOperation::SshHost {
+                        alias,
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+
+        Ok(ApplyPlan {
+            version: PLAN_VERSION,
+            repo: repo.to_path_buf(),
+            profile_name: profile_name.to_string(),
+            operations,
+        })
+    }
+
+    // the targets applying the plan writes, `git config user.email`
+    pub fn changed(&self) -> Vec<String> {
+        self.operations.iter().map(PlanOperation::target).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+// the local git config `use-profile` converges a repository to
+pub fn desired_config(profile: &Profile) -> Vec<(&'static str, Option<String>)> {
+    let mut config = vec![
+        ("user.name", Some(profile.name.clone())),
+        ("user.email", Some(profile.email.clone())),
+    ];
+
+    match profile.auth_type {
+        AuthType::SSH => {
+            // with host aliases the key comes from ~/.ssh/config instead;
+            // without a key an existing ssh command is left alone
+            if profile.ssh_host_alias {
+                config.push(("core.sshCommand", None));
+            } else if let Some(command) = profile.ssh_command() {
+                config.push(("core.sshCommand", Some(command)));
+            }
+            config.push(("credential.helper", None));
+            config.push(("credential.useHttpPath", None));
+        }
+        AuthType::GH => {
+            config.push(("core.sshCommand", None));
+            config.push((
+                "credential.helper",
+                Some(git::gh_credential_helper(
+                    profile.gh_path.as_deref().unwrap_or("gh"),
+                )),
+            ));
+            config.push(("credential.useHttpPath", Some("true".to_string())));
+        }
+        AuthType::GitLab => {
+            config.push(("core.sshCommand", None));
+            config.push((
+                "credential.helper",
+                Some(git::GLAB_CREDENTIAL_HELPER.to_string()),
+            ));
+            config.push(("credential.useHttpPath", Some("true".to_string())));
+        }
+        AuthType::Token => {
+            config.push(("core.sshCommand", None));
+            config.push((
+                "credential.helper",
+                Some(git::TOKEN_CREDENTIAL_HELPER.to_string()),
+            ));
+            config.push(("credential.useHttpPath", None));
+        }
+        AuthType::None => {
+            config.push(("core.sshCommand", None));
+            config.push(("credential.helper", None));
+            config.push(("credential.useHttpPath", None));
+        }
+    }
+
+    config.push(("http.sslCAInfo", profile.ssl_ca_info.clone()));
+    config.push(("http.sslVerify", profile.ssl_verify.map(|v| v.to_string())));
+    config.push(("http.proxy", profile.http_proxy.clone()));
+    config.push(("user.signingKey", profile.signing_key.clone()));
+    config.push(("gpg.format", profile.signing_format().map(str::to_string)));
+    config.push(("gpg.program", profile.gpg_program.clone()));
+    let sign = profile.signing_key.as_ref().map(|_| "true".to_string());
+    config.push(("commit.gpgSign", sign.clone()));
+    config.push(("tag.gpgSign", sign));
+
+    if let Some(helper) = profile.credential_helper_config()
+        && profile.auth_type != AuthType::SSH
+        && let Some(entry) = config
+            .iter_mut()
+            .find(|(key, _)| *key == "credential.helper")
+    {
+        entry.1 = Some(helper);
+    }
+
+    config
+}
+
+// the repo-local `url.<replacement>.insteadOf` keys holding a profile's url
+// rewrites, with the prefix each one replaces
+pub fn rewrite_config(profile: &Profile) -> Vec<(String, String)> {
+    profile
+        .url_rewrites
+        .iter()
+        .map(|(from, to)| (format!("url.{}.insteadOf", to), from.clone()))
+        .collect()
+}
+
+// Removes the url rewrites other profiles left in `repo`. git does not
+// record who wrote a rewrite, so only those matching one of `profiles`
+// exactly are treated as octopush's; `keep` are the keys still wanted.
+pub fn rewrite_removals<'a>(
+    git: &dyn GitBackend,
+    repo: &Path,
+    keep: &[String],
+    profiles: impl IntoIterator<Item = &'a Profile>,
+) -> Result<Vec<PlanOperation>, io::Error> {
+    let mut operations = Vec::new();
+    for profile in profiles {
+        for (key, from) in rewrite_config(profile) {
+            if keep.contains(&key)
+                || operations
+                    .iter()
+                    .any(|op| matches!(op, PlanOperation::GitConfig { key: k, .. } if *k == key))
+            {
+                continue;
+            }
+
+            let before = git.get_local_config(repo, &key)?;
+            if before.as_deref() == Some(from.as_str()) {
+                operations.push(PlanOperation::GitConfig {
+                    key,
+                    before,
+                    after: None,
+                });
+            }
+        }
+    }
+
+    Ok(operations)
+}
+
+// The URL `url` should have under `profile`, along with the real host it
+// points at. A host that is one of octopush's ssh config aliases counts as
+// its real host, so switching profiles also switches aliases.
+fn desired_remote_url(
+    profile_name: &str,
+    profile: &Profile,
+    url: &str,
+) -> Option<(String, String)> {
+    let (host, owner, repo_name) = git::parse_remote(url)?;
+    let real_host = ssh_config::real_host(&host);
+    let aliased = real_host != host;
+    let is_ssh = url.starts_with("git@") || url.starts_with("ssh://");
+    // ports are specific to a scheme and Bitbucket Server serves https under
+    // `/scm/`, so only plain urls switch scheme; a port is kept otherwise
+    let switchable = git::is_rewritable_remote(url);
+    let ssh = |host: &str| {
+        git::to_ssh(
+            &git::with_port(host, git::remote_port(url)),
+            &owner,
+            &repo_name,
+        )
+    };
+
+    let after = match profile.auth_type {
+        AuthType::SSH if profile.ssh_host_alias && (is_ssh || switchable) => {
+            ssh(&ssh_config::alias_for(&real_host, profile_name))
+        }
+        AuthType::SSH if (!is_ssh && switchable) || (is_ssh && aliased) => ssh(&real_host),
+        AuthType::GH | AuthType::GitLab | AuthType::Token if is_ssh && switchable => {
+            git::to_https(&real_host, &owner, &repo_name)
+        }
+        // another profile's alias would keep using that profile's key
+        AuthType::None if aliased => ssh(&real_host),
+        _ => url.to_string(),
+    };
+
+    Some((after, real_host))
+}
+
+// for logging which way a remote gets rewritten
+fn scheme(url: &str) -> &'static str {
+    if url.starts_with("git@") || url.starts_with("ssh://") {
+        "ssh"
+    } else {
+        "https"
+    }
+}
+
+// reads the live value of a git-side operation's target
+pub fn current_value(
+    git: &dyn GitBackend,
+    repo: &Path,
+    operation: &PlanOperation,
+) -> Result<Option<String>, io::Error> {
+    match operation {
+        PlanOperation::GitConfig { key, .. } => git.get_local_config(repo, key),
+        PlanOperation::RemoteUrl { remote, .. } => git.get_remote_url(repo, remote),
+        PlanOperation::PushUrl { remote, .. } => git.get_push_url(repo, remote),
+        PlanOperation::SshHost { alias, .. } => match ssh_config::config_path() {
+            Some(path) => ssh_config::read_entry(&path, alias),
+            None => Ok(None),
+        },
+        PlanOperation::Mapping { .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mapping operations are not stored in the repository",
+        )),
+    }
+}
+
+// applies a single git-side operation to the repository
+pub fn apply_operation(
+    git: &dyn GitBackend,
+    repo: &Path,
+    operation: &PlanOperation,
+) -> Result<(), io::Error> {
+    match operation {
+        PlanOperation::GitConfig {
+            key,
+            after: Some(value),
+            ..
+        } => git.set_local_config(repo, key, value),
+        PlanOperation::GitConfig {
+            key, after: None, ..
+        } => git.unset_local(repo, key),
+        PlanOperation::RemoteUrl {
+            remote,
+            after: Some(url),
+            ..
+        } => git.set_remote_url(repo, remote, url),
+        PlanOperation::RemoteUrl { after: None, .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "removing a remote is not supported",
+        )),
+        PlanOperation::PushUrl {
+            remote,
+            after: Some(url),
+            ..
+        } => git.set_push_url(repo, remote, url),
+        PlanOperation::PushUrl { after: None, .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "removing a push url is not supported",
+        )),
+        PlanOperation::SshHost { alias, after, .. } => {
+            let path = ssh_config::config_path().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no home directory for ~/.ssh/config",
+                )
+            })?;
+            ssh_config::write_entry(&path, alias, after.as_deref())
+        }
+        PlanOperation::Mapping { .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mapping operations are not stored in the repository",
+        )),
+    }
+}
+
+// makes `repo` match `profile` right away, returning what was changed; the
+// repository mapping is left to the caller, as with `ApplyPlan::build`
+pub fn apply_profile_to_repo(
+    git: &dyn GitBackend,
+    repo: &Path,
+    profile_name: &str,
+    profile: &Profile,
+) -> Result<ApplyPlan, io::Error> {
+    let plan = ApplyPlan::build(git, repo, profile_name, profile)?;
+    for operation in &plan.operations {
+        apply_operation(git, repo, operation)?;
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::preflight::{self, Warning};
+    use crate::test_helpers::TempConfig;
+    use crate::util::git::SystemGit;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fs;
+
+    // a repository held in memory, so plans can be tested without git
+    #[derive(Default)]
+    struct FakeGit {
+        config: RefCell<HashMap<String, String>>,
+        remotes: RefCell<HashMap<String, String>>,
+    }
+
+    impl GitBackend for FakeGit {
+        fn ensure_repo(&self, _: &Path) -> Result<(), io::Error> {
+            Ok(())
+        }
+
+        fn get_local_config(&self, _: &Path, key: &str) -> Result<Option<String>, io::Error> {
+            Ok(self.config.borrow().get(key).cloned())
+        }
+
+        fn get_config(&self, repo: &Path, key: &str) -> Result<Option<String>, io::Error> {
+            self.get_local_config(repo, key)
+        }
+
+        fn set_local_config(&self, _: &Path, key: &str, value: &str) -> Result<(), io::Error> {
+            self.config
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn unset_local(&self, _: &Path, key: &str) -> Result<(), io::Error> {
+            self.config.borrow_mut().remove(key);
+            Ok(())
+        }
+
+        fn list_remotes(&self, _: &Path) -> Result<Vec<String>, io::Error> {
+            Ok(self.remotes.borrow().keys().cloned().collect())
+        }
+
+        fn current_branch(&self, _: &Path) -> Result<Option<String>, io::Error> {
+            Ok(Some("main".to_string()))
+        }
+
+        fn get_remote_url(&self, _: &Path, remote: &str) -> Result<Option<String>, io::Error> {
+            Ok(self.remotes.borrow().get(remote).cloned())
+        }
+
+        fn set_remote_url(&self, _: &Path, remote: &str, url: &str) -> Result<(), io::Error> {
+            self.remotes
+                .borrow_mut()
+                .insert(remote.to_string(), url.to_string());
+            Ok(())
+        }
+
+        fn set_push_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), io::Error> {
+            self.set_local_config(repo, &format!("remote.{}.pushurl", remote), url)
+        }
+    }
+
+    #[test]
+    fn applies_profile_through_any_backend() {
+        let git = FakeGit::default();
+        let repo = Path::new("/nowhere/app");
+        git.set_local_config(repo, "credential.helper", "!gh auth git-credential")
+            .unwrap();
+        git.set_remote_url(repo, "upstream", "https://github.com/acme/app.git")
+            .unwrap();
+        let profile = Profile::build(
+            "John".to_string(),
+            "john@acme.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("~/.ssh/id_acme".to_string()),
+        );
+
+        let plan = apply_profile_to_repo(&git, repo, "acme", &profile).unwrap();
+        assert!(!plan.is_empty());
+        assert_eq!(
+            git.get_local_config(repo, "user.email").unwrap().as_deref(),
+            Some("john@acme.com")
+        );
+        assert_eq!(
+            git.get_local_config(repo, "credential.helper").unwrap(),
+            None
+        );
+        // the only remote is the primary one
+        assert_eq!(
+            git.get_remote_url(repo, "upstream").unwrap().as_deref(),
+            Some("git@github.com:acme/app.git")
+        );
+
+        assert!(
+            ApplyPlan::build(&git, repo, "acme", &profile)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn explains_each_change_in_stage_order() {
+        let git = FakeGit::default();
+        let repo = Path::new("/nowhere/app");
+        git.set_local_config(repo, "credential.helper", "!gh auth git-credential")
+            .unwrap();
+        git.set_remote_url(repo, "origin", "https://github.com/acme/app.git")
+            .unwrap();
+        let profile = Profile::build(
+            "John".to_string(),
+            "john@acme.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("~/.ssh/id_acme".to_string()),
+        );
+
+        let plan = ApplyPlan::build(&git, repo, "work", &profile).unwrap();
+        let explained: HashMap<String, (Stage, String)> = plan
+            .operations
+            .iter()
+            .map(|operation| {
+                (
+                    operation.target(),
+                    (operation.stage(), operation.explain("work")),
+                )
+            })
+            .collect();
+        assert_eq!(
+            explained["git config core.sshCommand"],
+            (
+                Stage::Auth,
+                "forces ssh pushes and fetches to use the work key, whatever the agent offers"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            explained["git config credential.helper"].1,
+            "stops a credential helper from answering for this repository"
+        );
+        assert_eq!(explained["git config user.name"].0, Stage::Identity);
+        assert_eq!(explained["remote origin"].0, Stage::Remote);
+        assert_eq!(
+            helper_name(git::TOKEN_CREDENTIAL_HELPER),
+            "the token octopush keeps in the keychain"
+        );
+        assert_eq!(helper_name("cache --timeout=60"), "git's credential cache");
+    }
+
+    #[test]
+    fn builds_plan_with_before_and_after_values() {
+        let t = TempConfig::new().unwrap();
+
+        git::run_git(
+            &t.repo,
+            ["remote", "add", "origin", "https://github.com/acme/app.git"],
+        )
+        .unwrap();
+        git::set_local_config(&t.repo, "user.name", "Old Name").unwrap();
+
+        let profile = Profile::build(
+            "New Name".to_string(),
+            "new@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("/tmp/key".to_string()),
+        );
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+
+        assert!(plan.operations.contains(&PlanOperation::GitConfig {
+            key: "user.name".to_string(),
+            before: Some("Old Name".to_string()),
+            after: Some("New Name".to_string()),
+        }));
+        assert!(plan.operations.contains(&PlanOperation::RemoteUrl {
+            remote: "origin".to_string(),
+            before: Some("https://github.com/acme/app.git".to_string()),
+            after: Some("git@github.com:acme/app.git".to_string()),
+        }));
+        // credential.helper is already unset, so there is nothing to do for it
+        assert!(
+            !plan
+                .operations
+                .iter()
+                .any(|op| op.target() == "git config credential.helper")
+        );
+
+        let notable: Vec<String> = plan
+            .operations
+            .iter()
+            .filter(|op| op.is_notable())
+            .map(|op| op.target())
+            .collect();
+        assert!(notable.contains(&"git config user.email".to_string()));
+        assert!(notable.contains(&"git config core.sshCommand".to_string()));
+        assert!(notable.contains(&"remote origin".to_string()));
+    }
+
+    #[test]
+    fn targets_primary_remote_when_origin_is_missing() {
+        let t = TempConfig::new().unwrap();
+
+        git::run_git(
+            &t.repo,
+            ["remote", "add", "gitlab", "git@gitlab.com:acme/app.git"],
+        )
+        .unwrap();
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::GH,
+            Some("gitlab.com".to_string()),
+            None,
+        );
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "oss", &profile).unwrap();
+
+        assert!(plan.operations.contains(&PlanOperation::RemoteUrl {
+            remote: "gitlab".to_string(),
+            before: Some("git@gitlab.com:acme/app.git".to_string()),
+            after: Some("https://gitlab.com/acme/app.git".to_string()),
+        }));
+    }
+
+    #[test]
+    fn gitlab_profile_uses_glab_credential_helper() {
+        let t = TempConfig::new().unwrap();
+
+        git::run_git(
+            &t.repo,
+            [
+                "remote",
+                "add",
+                "origin",
+                "git@gitlab.mycorp.com:team/app.git",
+            ],
+        )
+        .unwrap();
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::GitLab,
+            Some("gitlab.mycorp.com".to_string()),
+            None,
+        );
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "corp", &profile).unwrap();
+        for operation in &plan.operations {
+            apply_operation(&SystemGit, &t.repo, operation).unwrap();
+        }
+
+        assert_eq!(
+            git::get_local_config(&t.repo, "credential.helper")
+                .unwrap()
+                .as_deref(),
+            Some(git::GLAB_CREDENTIAL_HELPER)
+        );
+        assert_eq!(
+            git::get_remote_url(&t.repo, "origin").unwrap().as_deref(),
+            Some("https://gitlab.mycorp.com/team/app.git")
+        );
+        assert_eq!(
+            crate::core::inspect::detect_auth_type(&t.repo).unwrap(),
+            AuthType::GitLab
+        );
+    }
+
+    #[test]
+    fn warns_instead_of_guessing_between_remotes() {
+        let t = TempConfig::new().unwrap();
+
+        for (name, url) in [
+            ("upstream", "https://github.com/acme/app.git"),
+            ("fork", "https://github.com/me/app.git"),
+        ] {
+            git::run_git(&t.repo, ["remote", "add", name, url]).unwrap();
+        }
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            None,
+        );
+        let plan =
+            ApplyPlan::build_with_remotes(&SystemGit, &t.repo, "oss", &profile, &Remotes::Primary)
+                .unwrap();
+        assert!(
+            !plan
+                .operations
+                .iter()
+                .any(|op| matches!(op, PlanOperation::RemoteUrl { .. }))
+        );
+        assert_eq!(
+            preflight::ambiguous_remote(&t.repo, &Remotes::Primary).unwrap(),
+            Some(Warning::AmbiguousRemote {
+                remotes: vec!["fork".to_string(), "upstream".to_string()]
+            })
+        );
+        assert_eq!(
+            preflight::ambiguous_remote(&t.repo, &Remotes::Named("fork".to_string())).unwrap(),
+            None
+        );
+
+        git::run_git(&t.repo, ["config", "remote.pushDefault", "fork"]).unwrap();
+        assert_eq!(
+            preflight::ambiguous_remote(&t.repo, &Remotes::Primary).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rewrites_named_or_all_remotes() {
+        let t = TempConfig::new().unwrap();
+
+        for (name, url) in [
+            ("origin", "https://github.com/me/app.git"),
+            ("upstream", "https://github.com/acme/app.git"),
+        ] {
+            git::run_git(&t.repo, ["remote", "add", name, url]).unwrap();
+        }
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            None,
+        );
+        let rewritten = |remotes: &Remotes| -> Vec<String> {
+            ApplyPlan::build_with_remotes(&SystemGit, &t.repo, "oss", &profile, remotes)
+                .unwrap()
+                .operations
+                .iter()
+                .filter_map(|op| match op {
+                    PlanOperation::RemoteUrl { remote, .. } => Some(remote.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(rewritten(&Remotes::Primary), ["origin"]);
+        assert_eq!(
+            rewritten(&Remotes::Named("upstream".to_string())),
+            ["upstream"]
+        );
+        assert_eq!(rewritten(&Remotes::All), ["origin", "upstream"]);
+
+        let err = ApplyPlan::build_with_remotes(
+            &SystemGit,
+            &t.repo,
+            "oss",
+            &profile,
+            &Remotes::Named("fork".to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rewrites_push_url_and_keeps_split_fetch_url() {
+        let t = TempConfig::new().unwrap();
+
+        git::run_git(
+            &t.repo,
+            ["remote", "add", "origin", "https://github.com/acme/app.git"],
+        )
+        .unwrap();
+        git::run_git(
+            &t.repo,
+            [
+                "remote",
+                "set-url",
+                "--push",
+                "origin",
+                "git@github.com:me/app.git",
+            ],
+        )
+        .unwrap();
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "oss", &profile).unwrap();
+        let remote_ops: Vec<_> = plan
+            .operations
+            .iter()
+            .filter(|op| op.target().starts_with("remote "))
+            .collect();
+        assert_eq!(
+            remote_ops,
+            [&PlanOperation::PushUrl {
+                remote: "origin".to_string(),
+                before: Some("git@github.com:me/app.git".to_string()),
+                after: Some("https://github.com/me/app.git".to_string()),
+            }]
+        );
+
+        apply_operation(&SystemGit, &t.repo, remote_ops[0]).unwrap();
+        assert_eq!(
+            git::get_remote_url(&t.repo, "origin").unwrap().as_deref(),
+            Some("https://github.com/acme/app.git")
+        );
+        assert_eq!(
+            git::get_push_url(&t.repo, "origin").unwrap().as_deref(),
+            Some("https://github.com/me/app.git")
+        );
+    }
+
+    #[test]
+    fn applied_plan_leaves_nothing_to_do() {
+        let t = TempConfig::new().unwrap();
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "oss", &profile).unwrap();
+        assert!(!plan.is_empty());
+
+        for operation in &plan.operations {
+            apply_operation(&SystemGit, &t.repo, operation).unwrap();
+            assert_eq!(
+                current_value(&SystemGit, &t.repo, operation)
+                    .unwrap()
+                    .as_deref(),
+                operation.after()
+            );
+        }
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "oss", &profile).unwrap();
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn ssh_host_alias_rewrites_remote_to_managed_host() {
+        let t = TempConfig::new().unwrap();
+        let prev_home = std::env::var_os("HOME");
+        unsafe {
+            std::env::set_var("HOME", &t.base);
+        }
+
+        git::run_git(
+            &t.repo,
+            ["remote", "add", "origin", "https://github.com/acme/app.git"],
+        )
+        .unwrap();
+
+        let mut profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            Some("github.com".to_string()),
+            Some("~/.ssh/id_work".to_string()),
+        );
+        profile.ssh_host_alias = true;
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+        for operation in &plan.operations {
+            apply_operation(&SystemGit, &t.repo, operation).unwrap();
+        }
+
+        let remote = git::get_remote_url(&t.repo, "origin").unwrap();
+        let config = fs::read_to_string(t.base.join(".ssh").join("config")).unwrap();
+        let ssh_command = git::get_config(&t.repo, "core.sshCommand").unwrap();
+        let replanned = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+
+        // an explicit ssh port stays on the alias url
+        git::set_remote_url(
+            &t.repo,
+            "origin",
+            "ssh://git@git.company.com:2222/team/app.git",
+        )
+        .unwrap();
+        let ported = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+
+        unsafe {
+            match prev_home {
+                Some(v) => std::env::set_var("HOME", v),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+
+        assert_eq!(remote.as_deref(), Some("git@github.com-work:acme/app.git"));
+        assert!(config.contains("Host github.com-work\n    HostName github.com\n"));
+        assert!(config.contains("IdentityFile ~/.ssh/id_work"));
+        assert_eq!(ssh_command, None);
+        assert!(replanned.is_empty());
+        assert!(ported.operations.iter().any(|op| matches!(
+            op,
+            PlanOperation::RemoteUrl { after: Some(url), .. }
+                if url == "ssh://git@git.company.com-work:2222/team/app.git"
+        )));
+    }
+
+    #[test]
+    fn ssh_profile_without_key_keeps_existing_ssh_command() {
+        let t = TempConfig::new().unwrap();
+        git::set_local_config(&t.repo, "core.sshCommand", "ssh -i ~/.ssh/id_other").unwrap();
+
+        let profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            None,
+        );
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+        for operation in &plan.operations {
+            apply_operation(&SystemGit, &t.repo, operation).unwrap();
+        }
+
+        assert!(!plan.operations.iter().any(|op| matches!(
+            op,
+            PlanOperation::GitConfig { key, .. } if key == "core.sshCommand"
+        )));
+        assert_eq!(
+            git::get_config(&t.repo, "core.sshCommand")
+                .unwrap()
+                .as_deref(),
+            Some("ssh -i ~/.ssh/id_other")
+        );
+    }
+
+    #[test]
+    fn identity_agent_is_passed_to_ssh() {
+        let t = TempConfig::new().unwrap();
+
+        let mut profile = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("~/.ssh/id_work.pub".to_string()),
+        );
+        profile.ssh_identity_agent = Some("~/.1password/agent.sock".to_string());
+        profile.validate().unwrap();
+
+        let plan = ApplyPlan::build(&SystemGit, &t.repo, "work", &profile).unwrap();
+        let ssh_command = plan.operations.iter().find_map(|op| match op {
+            PlanOperation::GitConfig { key, after, .. } if key == "core.sshCommand" => {
+                after.clone()
+            }
+            _ => None,
+        });
+
+        assert_eq!(
+            ssh_command.as_deref(),
+            Some(
+                format!(
+                    "ssh -i ~/.ssh/id_work.pub -o IdentitiesOnly=yes -o \"IdentityAgent=~/.1password/agent.sock\" -F {}",
+                    git::NULL_DEVICE
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(
+            git::ssh_key_from_command(ssh_command.as_deref().unwrap()).as_deref(),
+            Some("~/.ssh/id_work.pub")
+        );
+
+        // the agent serves a key, so it is useless without one
+        profile.ssh_key_path = None;
+        assert!(profile.validate().is_err());
+    }
+
+    #[test]
+    fn plan_round_trips_through_json() {
+        let plan = ApplyPlan {
+            version: PLAN_VERSION,
+            repo: PathBuf::from("/tmp/repo"),
+            profile_name: "work".to_string(),
+            operations: vec![PlanOperation::GitConfig {
+                key: "user.email".to_string(),
+                before: None,
+                after: Some("work@example.com".to_string()),
+            }],
+        };
+
+        let json = serde_json::to_string(&plan).unwrap();
+        assert!(json.contains(r#""target":"git_config""#));
+
+        let parsed: ApplyPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, plan);
+    }
+}
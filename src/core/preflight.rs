@@ -0,0 +1,249 @@
+use crate::core::{auth::AuthType, plan::Remotes, profile::Profile};
+use crate::util::{git, system::expand_home};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Soft problems that do not stop a command on their own, but leave the
+// identity half-configured. Strict mode turns them into failures.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    InsecureRemote {
+        remote: String,
+        url: String,
+    },
+    // no remote is the primary one, so none gets rewritten
+    AmbiguousRemote {
+        remotes: Vec<String>,
+    },
+    GhUnauthenticated {
+        host: String,
+    },
+    GlabUnauthenticated {
+        host: String,
+    },
+    MissingKey {
+        path: String,
+    },
+    HostMismatch {
+        expected: String,
+        actual: String,
+    },
+    Drift {
+        profile_name: String,
+        changes: usize,
+    },
+    // `version` is None for repositories configured before octopush kept
+    // records
+    Outdated {
+        version: Option<String>,
+    },
+    // the repository's `.octopush` file asks for another profile
+    LocalMismatch {
+        expected: String,
+    },
+    // git would commit with another name or email than the profile's
+    IdentityMismatch {
+        profile_name: String,
+        key: String,
+        expected: String,
+        // the value and where it comes from, `'jane@home.org' (global)`
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::InsecureRemote { remote, url } => write!(
+                f,
+                "Remote '{}' uses an unencrypted connection ({})",
+                remote, url
+            ),
+            Warning::AmbiguousRemote { remotes } => write!(
+                f,
+                "No remote URL is rewritten: none of {} is the primary remote; pass `--remote <name>` or set `remote.pushDefault`",
+                remotes
+                    .iter()
+                    .map(|remote| format!("'{}'", remote))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Warning::GhUnauthenticated { host } => write!(
+                f,
+                "gh is not authenticated for '{}', run `gh auth login --hostname {}`",
+                host, host
+            ),
+            Warning::GlabUnauthenticated { host } => write!(
+                f,
+                "glab is not authenticated for '{}', run `glab auth login --hostname {}`",
+                host, host
+            ),
+            Warning::MissingKey { path } => write!(f, "SSH key '{}' does not exist", path),
+            Warning::HostMismatch { expected, actual } => write!(
+                f,
+                "Profile is set up for '{}' but the repository's remote points to '{}'",
+                expected, actual
+            ),
+            Warning::Drift {
+                profile_name,
+                changes,
+            } => write!(
+                f,
+                "Repository drifted from profile '{}' ({} change{} pending)",
+                profile_name,
+                changes,
+                if *changes == 1 { "" } else { "s" }
+            ),
+            Warning::Outdated {
+                version: Some(version),
+            } => write!(
+                f,
+                "Repository was configured by octopush {}, whose managed config layout differs; run `octopush sync` to upgrade it",
+                version
+            ),
+            Warning::Outdated { version: None } => write!(
+                f,
+                "Repository was configured before octopush kept records of its managed config layout; run `octopush sync` to upgrade it"
+            ),
+            Warning::LocalMismatch { expected } => write!(
+                f,
+                "The repository's .octopush file asks for profile '{}'",
+                expected
+            ),
+            Warning::IdentityMismatch {
+                profile_name,
+                key,
+                expected,
+                actual,
+            } => match actual {
+                Some(actual) => write!(
+                    f,
+                    "git uses {} {} but profile '{}' expects '{}'",
+                    key, actual, profile_name, expected
+                ),
+                None => write!(
+                    f,
+                    "{} is not set but profile '{}' expects '{}'",
+                    key, profile_name, expected
+                ),
+            },
+        }
+    }
+}
+
+// remotes that would send credentials in the clear, whatever the profile
+pub fn remote_warnings(repo: &Path) -> Result<Vec<Warning>, io::Error> {
+    Ok(git::insecure_remotes(repo)?
+        .into_iter()
+        .map(|(remote, url)| Warning::InsecureRemote { remote, url })
+        .collect())
+}
+
+// Without `origin`, a tracked remote or `remote.pushDefault`, several
+// remotes leave nothing to rewrite for `Remotes::Primary`; say so instead of
+// applying the profile to none of them.
+pub fn ambiguous_remote(repo: &Path, remotes: &Remotes) -> Result<Option<Warning>, io::Error> {
+    if *remotes != Remotes::Primary || git::primary_remote(repo)?.is_some() {
+        return Ok(None);
+    }
+
+    let remotes = git::list_remotes(repo)?;
+    Ok((remotes.len() > 1).then_some(Warning::AmbiguousRemote { remotes }))
+}
+
+// everything worth flagging before `profile` is applied to `repo`
+pub fn check(repo: &Path, profile: &Profile) -> Result<Vec<Warning>, io::Error> {
+    let mut warnings = remote_warnings(repo)?;
+
+    match profile.auth_type {
+        AuthType::SSH => {
+            if let Some(path) = &profile.ssh_key_path
+                && !expand_home(path).exists()
+            {
+                warnings.push(Warning::MissingKey { path: path.clone() });
+            }
+        }
+        AuthType::GH | AuthType::GitLab | AuthType::Token => {
+            let host = profile
+                .hostname
+                .as_deref()
+                .or(profile.auth_type.default_hostname())
+                .unwrap_or_default();
+            // a profile's own credential helper does not go through gh or glab
+            let cli_auth = profile.credential_helper.is_none();
+            if cli_auth && profile.auth_type == AuthType::GH && !git::is_gh_authenticated(host) {
+                warnings.push(Warning::GhUnauthenticated {
+                    host: host.to_string(),
+                });
+            }
+            if cli_auth
+                && profile.auth_type == AuthType::GitLab
+                && !git::is_glab_authenticated(host)
+            {
+                warnings.push(Warning::GlabUnauthenticated {
+                    host: host.to_string(),
+                });
+            }
+
+            if let Some(remote) = git::primary_remote(repo)?
+                && let Some(url) = git::get_remote_url(repo, &remote)?
+                && let Some((actual, _, _)) = git::parse_remote(&url)
+                && actual != host
+            {
+                warnings.push(Warning::HostMismatch {
+                    expected: host.to_string(),
+                    actual,
+                });
+            }
+        }
+        AuthType::None => {}
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn flags_missing_key_and_host_mismatch() {
+        let t = TempConfig::new().unwrap();
+
+        let ssh = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some("/nonexistent/octopush/key".to_string()),
+        );
+        assert_eq!(
+            check(&t.repo, &ssh).unwrap(),
+            vec![Warning::MissingKey {
+                path: "/nonexistent/octopush/key".to_string()
+            }]
+        );
+
+        git::run_git(
+            &t.repo,
+            ["remote", "add", "origin", "https://gitlab.com/acme/app.git"],
+        )
+        .unwrap();
+        let gh = Profile::build(
+            "Name".to_string(),
+            "name@example.com".to_string(),
+            AuthType::GH,
+            Some("github.com".to_string()),
+            None,
+        );
+        assert_eq!(
+            check(&t.repo, &gh).unwrap(),
+            vec![Warning::HostMismatch {
+                expected: "github.com".to_string(),
+                actual: "gitlab.com".to_string(),
+            }]
+        );
+    }
+}
@@ -1,5 +1,20 @@
 use crate::core::auth::AuthType;
+use crate::core::signers;
+use crate::util::git;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+// what `git credential-cache` keeps credentials for unless told otherwise
+const DEFAULT_CACHE_TIMEOUT: u64 = 900;
+
+// `cache`, `cache --socket ~/.cache/git.sock` or the full helper name
+pub fn is_cache_helper(helper: &str) -> bool {
+    matches!(
+        helper.split_whitespace().next(),
+        Some("cache" | "credential-cache" | "git-credential-cache")
+    )
+}
 
 #[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
 pub struct Profile {
@@ -10,6 +25,53 @@ pub struct Profile {
     pub hostname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ssh_key_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    // new-repo defaults used by `init-repo` and `clone`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_dir: Option<String>,
+    // gh binary for this profile's credential helper, overriding settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gh_path: Option<String>,
+    // credential helper written instead of the auth type's own, such as
+    // `osxkeychain`, `manager` or a corporate helper
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_helper: Option<String>,
+    // seconds the `cache` helper keeps credentials after their last use,
+    // passed as its `--timeout`; git's default is 900
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_cache_timeout: Option<u64>,
+    // for enterprise hosts behind TLS-intercepting proxies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_ca_info: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl_verify: Option<bool>,
+    // written as http.proxy, which git uses for https remotes too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    // key commits and tags are signed with: a gpg key id or fingerprint, or
+    // an ssh public key (path to the `.pub` file or `key::<key>`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    // gpg binary used to sign, such as `gpg2` or a wrapper script
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpg_program: Option<String>,
+    // reach the host through a `Host <host>-<profile>` alias in ~/.ssh/config
+    // instead of core.sshCommand
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ssh_host_alias: bool,
+    // socket of an ssh agent holding the key, such as 1Password's
+    // `~/.1password/agent.sock`; `ssh_key_path` then names its public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_identity_agent: Option<String>,
+    // url prefix -> replacement, written as repo-local `url.<replacement>.insteadOf`
+    // so tools fetching dependencies over git pick up the profile too
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub url_rewrites: BTreeMap<String, String>,
 }
 
 impl Profile {
@@ -26,6 +88,317 @@ impl Profile {
             auth_type,
             hostname,
             ssh_key_path,
+            tags: Vec::new(),
+            archived: false,
+            default_branch: None,
+            template_dir: None,
+            gh_path: None,
+            credential_helper: None,
+            credential_cache_timeout: None,
+            ssl_ca_info: None,
+            ssl_verify: None,
+            http_proxy: None,
+            signing_key: None,
+            gpg_program: None,
+            ssh_host_alias: false,
+            ssh_identity_agent: None,
+            url_rewrites: BTreeMap::new(),
+        }
+    }
+
+    // checks the fields that must hold for any stored profile
+    pub fn validate(&self) -> Result<(), io::Error> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+
+        if self.name.trim().is_empty() {
+            return invalid("'name' cannot be empty".to_string());
+        }
+        if !self.email.contains('@') {
+            return invalid(format!("'{}' is not a valid email", self.email));
+        }
+
+        if let Some(branch) = &self.default_branch
+            && (branch.is_empty() || branch.chars().any(char::is_whitespace))
+        {
+            return invalid(format!("'{}' is not a valid branch name", branch));
+        }
+
+        let auth_type: &str = self.auth_type.into();
+        if self.ssh_host_alias && self.auth_type != AuthType::SSH {
+            return invalid(format!(
+                "'ssh_host_alias' is not allowed for '{auth_type}' auth type"
+            ));
+        }
+
+        if self.ssh_identity_agent.is_some() {
+            if self.auth_type != AuthType::SSH {
+                return invalid(format!(
+                    "'ssh_identity_agent' is not allowed for '{auth_type}' auth type"
+                ));
+            }
+            if self.ssh_key_path.is_none() {
+                return invalid(
+                    "'ssh_identity_agent' needs 'ssh_key_path' set to the public key the agent holds"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(helper) = &self.credential_helper {
+            if self.auth_type == AuthType::SSH {
+                return invalid(format!(
+                    "'credential_helper' is not allowed for '{auth_type}' auth type"
+                ));
+            }
+            if helper.trim().is_empty() {
+                return invalid("'credential_helper' cannot be empty".to_string());
+            }
+        }
+
+        self.validate_credential_cache()?;
+
+        if self
+            .signing_key
+            .as_ref()
+            .is_some_and(|key| key.trim().is_empty())
+        {
+            return invalid("'signing_key' cannot be empty".to_string());
+        }
+
+        let mut targets: Vec<&String> = Vec::new();
+        for (from, to) in &self.url_rewrites {
+            if from.trim().is_empty() || to.trim().is_empty() {
+                return invalid("'url_rewrites' cannot contain empty urls".to_string());
+            }
+            if targets.contains(&to) {
+                return invalid(format!(
+                    "'url_rewrites' rewrites more than one url to '{}'",
+                    to
+                ));
+            }
+            targets.push(to);
+        }
+
+        match self.auth_type {
+            AuthType::None if self.hostname.is_some() || self.ssh_key_path.is_some() => {
+                invalid(format!(
+                    "'hostname' and 'ssh_key_path' are not allowed for '{auth_type}' auth type"
+                ))
+            }
+            AuthType::SSH if self.hostname.is_some() => invalid(format!(
+                "'hostname' is not allowed for '{auth_type}' auth type"
+            )),
+            AuthType::Token if self.hostname.is_none() => invalid(format!(
+                "'hostname' is required for '{auth_type}' auth type"
+            )),
+            AuthType::GH | AuthType::GitLab | AuthType::Token if self.ssh_key_path.is_some() => {
+                invalid(format!(
+                    "'ssh_key_path' is not allowed for '{auth_type}' auth type"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // a cache timeout only goes with the cache helper, which must not set
+    // its own
+    pub fn validate_credential_cache(&self) -> Result<(), io::Error> {
+        let Some(timeout) = self.credential_cache_timeout else {
+            return Ok(());
+        };
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        match self.credential_helper.as_deref() {
+            Some(helper) if is_cache_helper(helper) && !helper.contains("--timeout") => {}
+            _ => {
+                return Err(invalid(
+                    "'credential_cache_timeout' needs 'credential_helper' set to `cache` without its own --timeout",
+                ));
+            }
+        }
+        if timeout == 0 {
+            return Err(invalid(
+                "'credential_cache_timeout' must be at least 1 second",
+            ));
+        }
+        Ok(())
+    }
+
+    // core.sshCommand for the profile's key, going through its agent if any
+    pub fn ssh_command(&self) -> Option<String> {
+        let key_path = self.ssh_key_path.as_deref()?;
+        Some(match &self.ssh_identity_agent {
+            Some(agent) => git::ssh_command_with_agent(key_path, agent),
+            None => git::ssh_command(key_path),
+        })
+    }
+
+    // gpg.format for the profile's signing key
+    pub fn signing_format(&self) -> Option<&'static str> {
+        let key = self.signing_key.as_deref()?;
+        Some(if signers::is_ssh_key(key) {
+            "ssh"
+        } else {
+            "openpgp"
+        })
+    }
+
+    // credential.helper as written to the repository, the cache helper with
+    // the profile's timeout
+    pub fn credential_helper_config(&self) -> Option<String> {
+        let helper = self.credential_helper.as_deref()?;
+        Some(match self.credential_cache_timeout {
+            Some(timeout) => format!("{} --timeout={}", helper, timeout),
+            None => helper.to_string(),
+        })
+    }
+
+    // how long git's cache helper keeps the profile's credentials, when it
+    // uses that helper
+    pub fn credential_cache_lifetime(&self) -> Option<u64> {
+        let helper = self.credential_helper.as_deref()?;
+        if !is_cache_helper(helper) {
+            return None;
+        }
+        let given = helper
+            .split_whitespace()
+            .find_map(|arg| arg.strip_prefix("--timeout="))
+            .and_then(|timeout| timeout.parse().ok());
+        Some(
+            self.credential_cache_timeout
+                .or(given)
+                .unwrap_or(DEFAULT_CACHE_TIMEOUT),
+        )
+    }
+
+    // whether applying the profile leaves a credential helper in the repo
+    pub fn uses_credential_helper(&self) -> bool {
+        self.auth_type.uses_credential_helper() || self.credential_helper.is_some()
+    }
+}
+
+// Builds a profile field by field and validates it once at the end, so a
+// combination `validate` rejects never makes it into a Profile value.
+#[derive(Debug, Clone)]
+pub struct ProfileBuilder {
+    profile: Profile,
+}
+
+impl Profile {
+    pub fn builder(name: String, email: String, auth_type: AuthType) -> ProfileBuilder {
+        ProfileBuilder {
+            profile: Profile::build(name, email, auth_type, None, None),
         }
     }
 }
+
+impl ProfileBuilder {
+    pub fn hostname(mut self, hostname: impl Into<Option<String>>) -> Self {
+        self.profile.hostname = hostname.into();
+        self
+    }
+
+    pub fn ssh_key_path(mut self, ssh_key_path: impl Into<Option<String>>) -> Self {
+        self.profile.ssh_key_path = ssh_key_path.into();
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.profile.tags = tags;
+        self
+    }
+
+    pub fn default_branch(mut self, default_branch: impl Into<Option<String>>) -> Self {
+        self.profile.default_branch = default_branch.into();
+        self
+    }
+
+    pub fn template_dir(mut self, template_dir: impl Into<Option<String>>) -> Self {
+        self.profile.template_dir = template_dir.into();
+        self
+    }
+
+    pub fn gh_path(mut self, gh_path: impl Into<Option<String>>) -> Self {
+        self.profile.gh_path = gh_path.into();
+        self
+    }
+
+    pub fn credential_helper(mut self, credential_helper: impl Into<Option<String>>) -> Self {
+        self.profile.credential_helper = credential_helper.into();
+        self
+    }
+
+    pub fn credential_cache_timeout(
+        mut self,
+        credential_cache_timeout: impl Into<Option<u64>>,
+    ) -> Self {
+        self.profile.credential_cache_timeout = credential_cache_timeout.into();
+        self
+    }
+
+    pub fn ssl_ca_info(mut self, ssl_ca_info: impl Into<Option<String>>) -> Self {
+        self.profile.ssl_ca_info = ssl_ca_info.into();
+        self
+    }
+
+    pub fn ssl_verify(mut self, ssl_verify: impl Into<Option<bool>>) -> Self {
+        self.profile.ssl_verify = ssl_verify.into();
+        self
+    }
+
+    pub fn http_proxy(mut self, http_proxy: impl Into<Option<String>>) -> Self {
+        self.profile.http_proxy = http_proxy.into();
+        self
+    }
+
+    pub fn signing_key(mut self, signing_key: impl Into<Option<String>>) -> Self {
+        self.profile.signing_key = signing_key.into();
+        self
+    }
+
+    pub fn gpg_program(mut self, gpg_program: impl Into<Option<String>>) -> Self {
+        self.profile.gpg_program = gpg_program.into();
+        self
+    }
+
+    pub fn ssh_host_alias(mut self, ssh_host_alias: bool) -> Self {
+        self.profile.ssh_host_alias = ssh_host_alias;
+        self
+    }
+
+    pub fn ssh_identity_agent(mut self, ssh_identity_agent: impl Into<Option<String>>) -> Self {
+        self.profile.ssh_identity_agent = ssh_identity_agent.into();
+        self
+    }
+
+    pub fn url_rewrites(mut self, url_rewrites: BTreeMap<String, String>) -> Self {
+        self.profile.url_rewrites = url_rewrites;
+        self
+    }
+
+    pub fn build(self) -> Result<Profile, io::Error> {
+        self.profile.validate()?;
+        Ok(self.profile)
+    }
+}
+
+pub fn validate_profile_name(profile_name: &str) -> Result<(), io::Error> {
+    if profile_name.is_empty() || profile_name.chars().any(char::is_whitespace) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' is not a valid profile name, whitespace is not allowed",
+                profile_name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// usage statistics kept in a separate state file, never in profiles.toml
+#[derive(Serialize, Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct ProfileUsage {
+    // seconds since the unix epoch
+    pub last_used: u64,
+    pub use_count: u64,
+}
@@ -1,3 +1,4 @@
+use crate::util::{git, ssh_config};
 use std::{
     io,
     path::{Path, PathBuf},
@@ -29,17 +30,116 @@ impl Project {
         })
     }
 
+    // linked worktrees share their main repository's name
     pub fn get_repo_name(&self) -> Result<String, io::Error> {
-        match Self::resolve_git_repo_name(&self.path)? {
-            Some(name) => Ok(name),
+        let root = self.get_main_root()?;
+        match root.file_name().and_then(|n| n.to_str()) {
+            Some(name) => Ok(name.trim_end_matches(".git").to_string()),
             None => Err(io::Error::other(
                 "no git repository found for given project path",
             )),
         }
     }
 
-    fn resolve_git_repo_name(start: &Path) -> Result<Option<String>, io::Error> {
-        let mut cur = if start.is_file() {
+    // the canonical repository root identifies a clone uniquely, unlike its
+    // directory name, so it is what mappings are keyed by; worktrees share
+    // one config and therefore one key with their main working tree
+    pub fn get_repo_key(&self) -> Result<String, io::Error> {
+        let root = self.get_main_root()?.canonicalize()?;
+        Ok(root.to_string_lossy().to_string())
+    }
+
+    // `host/owner/repo` of the primary remote, shared by every clone; an
+    // ssh config alias octopush manages counts as the host it stands for
+    pub fn get_remote_identity(&self) -> Result<Option<String>, io::Error> {
+        let root = self.get_repo_root()?;
+        let Some(remote) = git::primary_remote(&root)? else {
+            return Ok(None);
+        };
+
+        Ok(git::get_remote_url(&root, &remote)?
+            .as_deref()
+            .and_then(git::parse_remote)
+            .map(|(host, owner, repo)| {
+                format!("{}/{}/{}", ssh_config::real_host(&host), owner, repo)
+            }))
+    }
+
+    pub fn get_repo_root(&self) -> Result<PathBuf, io::Error> {
+        match Self::resolve_git_root(&self.path) {
+            Ok(root) => Ok(root),
+            // such as git refusing a repository another user owns
+            Err(e) if e.kind() != io::ErrorKind::InvalidInput => Err(e),
+            Err(_) => Err(io::Error::other(
+                "no git repository found for given project path",
+            )),
+        }
+    }
+
+    // the main working tree, or the repository itself when it is bare
+    pub fn get_main_root(&self) -> Result<PathBuf, io::Error> {
+        let common_dir = git::common_dir(&self.get_repo_root()?)?;
+        match common_dir.file_name() {
+            Some(name) if name == ".git" => Ok(common_dir.parent().unwrap_or(&common_dir).into()),
+            _ => Ok(common_dir),
+        }
+    }
+
+    // The main working tree and `.git` directory of the repository holding
+    // `start`, found without spawning git for `current`, which runs on every
+    // prompt. A linked worktree's `.git` file points into the main
+    // repository's `.git`, whose `commondir` leads back to it.
+    pub fn locate(start: &Path) -> Option<(PathBuf, PathBuf)> {
+        let dot_git = start
+            .ancestors()
+            .map(|dir| dir.join(".git"))
+            .find(|p| p.exists())?;
+        if dot_git.is_dir() {
+            return Some((dot_git.parent()?.to_path_buf(), dot_git));
+        }
+
+        let content = std::fs::read_to_string(&dot_git).ok()?;
+        let git_dir = dot_git
+            .parent()?
+            .join(content.strip_prefix("gitdir:")?.trim());
+        let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()).canonicalize().ok()?,
+            Err(_) => git_dir,
+        };
+        Some((common_dir.parent()?.to_path_buf(), common_dir))
+    }
+
+    // the url of `origin`, or of the only remote, straight from the config
+    // file; `current` only needs it for remote-keyed mappings
+    pub fn config_remote_url(git_dir: &Path) -> Option<String> {
+        let content = std::fs::read_to_string(git_dir.join("config")).ok()?;
+        let mut remotes: Vec<(String, String)> = Vec::new();
+        let mut section: Option<String> = None;
+
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('[') {
+                section = line
+                    .strip_prefix("[remote \"")
+                    .and_then(|rest| rest.strip_suffix("\"]"))
+                    .map(str::to_string);
+            } else if let Some(name) = &section
+                && let Some((key, value)) = line.split_once('=')
+                && key.trim() == "url"
+                && !remotes.iter().any(|(remote, _)| remote == name)
+            {
+                remotes.push((name.clone(), value.trim().to_string()));
+            }
+        }
+
+        match remotes.iter().find(|(name, _)| name == "origin") {
+            Some((_, url)) => Some(url.clone()),
+            None if remotes.len() == 1 => remotes.pop().map(|(_, url)| url),
+            None => None,
+        }
+    }
+
+    fn resolve_git_root(start: &Path) -> Result<PathBuf, io::Error> {
+        let dir = if start.is_file() {
             start
                 .parent()
                 .map(|p| p.to_path_buf())
@@ -48,21 +148,6 @@ impl Project {
             start.to_path_buf()
         };
 
-        loop {
-            let git_dir = cur.join(".git");
-            if git_dir.is_dir() {
-                if let Some(name) = cur.file_name().and_then(|n| n.to_str()) {
-                    return Ok(Some(name.to_string()));
-                } else {
-                    return Ok(None);
-                }
-            }
-
-            if !cur.pop() {
-                break;
-            }
-        }
-
-        Ok(None)
+        git::toplevel(&dir)
     }
 }
@@ -0,0 +1,192 @@
+use crate::util::system::expand_home;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Rules pick a profile for a repository from where it is checked out or
+// where its code is hosted. They are read from `rules.toml`:
+//
+// [[rule]]
+// gitdir = "~/work/"
+// profile = "acme"
+//
+// [[rule]]
+// remote = "gitlab.mycorp.com/**"
+// profile = "corp"
+//
+// A rule matches when every condition it sets matches. Remote rules are
+// checked before gitdir rules, and as with git's `includeIf`, the last
+// matching rule of each kind wins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Rules {
+    #[serde(default, rename = "rule", skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gitdir: Option<String>,
+    // glob over the primary remote as `host/owner/repo`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    pub profile: String,
+}
+
+impl Rule {
+    fn matches(&self, git_dir: &str, remote: Option<&str>) -> bool {
+        let gitdir_matches = match &self.gitdir {
+            Some(pattern) => glob_match(&gitdir_pattern(pattern), git_dir),
+            None => true,
+        };
+        let remote_matches = match (&self.remote, remote) {
+            (Some(pattern), Some(remote)) => glob_match(pattern, remote),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        (self.gitdir.is_some() || self.remote.is_some()) && gitdir_matches && remote_matches
+    }
+}
+
+impl Rules {
+    // `repo_root` is the working tree root of the repository and `remote`
+    // the `host/owner/repo` identity of its primary remote
+    pub fn resolve(&self, repo_root: &Path, remote: Option<&str>) -> Option<&Rule> {
+        let git_dir = repo_root.join(".git");
+        let git_dir = git_dir.to_string_lossy().replace('\\', "/");
+
+        let matching = || {
+            self.rules
+                .iter()
+                .rev()
+                .filter(|rule| rule.matches(&git_dir, remote))
+        };
+
+        matching()
+            .find(|rule| rule.remote.is_some())
+            .or_else(|| matching().next())
+    }
+}
+
+// normalizes a gitdir pattern the way git does: `~/` is the home directory,
+// relative patterns match at any depth and a trailing `/` matches everything
+// below it
+fn gitdir_pattern(pattern: &str) -> String {
+    let mut pattern = if pattern.starts_with("~/") {
+        expand_home(pattern).to_string_lossy().replace('\\', "/")
+    } else {
+        pattern.replace('\\', "/")
+    };
+
+    if !pattern.starts_with('/') && !is_windows_absolute(&pattern) {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    pattern
+}
+
+fn is_windows_absolute(pattern: &str) -> bool {
+    let bytes = pattern.as_bytes();
+    bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'/'
+}
+
+// `*` and `?` stay within one path segment, `**` crosses segments and
+// `**/` may also match no directory at all
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn go(p: &[u8], t: &[u8]) -> bool {
+        match p {
+            [] => t.is_empty(),
+            [b'*', b'*', b'/', rest @ ..] => {
+                go(rest, t) || (0..t.len()).any(|i| t[i] == b'/' && go(rest, &t[i + 1..]))
+            }
+            [b'*', b'*', rest @ ..] => (0..=t.len()).any(|i| go(rest, &t[i..])),
+            [b'*', rest @ ..] => (0..=t.len())
+                .take_while(|&i| i == 0 || t[i - 1] != b'/')
+                .any(|i| go(rest, &t[i..])),
+            [b'?', rest @ ..] => matches!(t, [c, ..] if *c != b'/') && go(rest, &t[1..]),
+            [c, rest @ ..] => matches!(t, [d, ..] if d == c) && go(rest, &t[1..]),
+        }
+    }
+
+    go(pattern.as_bytes(), text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("/work/**", "/work/acme/app/.git"));
+        assert!(glob_match("/work/*/app/.git", "/work/acme/app/.git"));
+        assert!(!glob_match("/work/*/.git", "/work/acme/app/.git"));
+        assert!(glob_match("/work/**/app/.git", "/work/app/.git"));
+        assert!(glob_match("/work/ap?/.git", "/work/app/.git"));
+        assert!(!glob_match("/oss/**", "/work/app/.git"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = Rules {
+            rules: vec![
+                Rule {
+                    gitdir: Some("/code/".to_string()),
+                    remote: None,
+                    profile: "personal".to_string(),
+                },
+                Rule {
+                    gitdir: Some("/code/acme/".to_string()),
+                    remote: None,
+                    profile: "acme".to_string(),
+                },
+                Rule {
+                    gitdir: Some("vendor/".to_string()),
+                    remote: None,
+                    profile: "vendor".to_string(),
+                },
+            ],
+        };
+
+        let profile = |path: &str| {
+            rules
+                .resolve(Path::new(path), None)
+                .map(|rule| rule.profile.as_str())
+        };
+
+        assert_eq!(profile("/code/app"), Some("personal"));
+        assert_eq!(profile("/code/acme/app"), Some("acme"));
+        assert_eq!(profile("/code/acme/vendor/lib"), Some("vendor"));
+        assert_eq!(profile("/elsewhere/app"), None);
+    }
+
+    #[test]
+    fn remote_rules_take_precedence_over_gitdir_rules() {
+        let rules = Rules {
+            rules: vec![
+                Rule {
+                    gitdir: None,
+                    remote: Some("github.com/acme-*/**".to_string()),
+                    profile: "acme".to_string(),
+                },
+                Rule {
+                    gitdir: Some("/code/".to_string()),
+                    remote: None,
+                    profile: "personal".to_string(),
+                },
+            ],
+        };
+
+        let profile = |remote: Option<&str>| {
+            rules
+                .resolve(Path::new("/code/app"), remote)
+                .map(|rule| rule.profile.as_str())
+        };
+
+        assert_eq!(profile(Some("github.com/acme-web/app")), Some("acme"));
+        assert_eq!(profile(Some("github.com/someone/app")), Some("personal"));
+        assert_eq!(profile(None), Some("personal"));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::core::app::App;
+use crate::util::git;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ScanState {
+    // mapped to an octopush profile
+    Mapped {
+        profile_name: String,
+    },
+    // not mapped, but a local identity was configured by hand
+    Manual {
+        name: Option<String>,
+        email: Option<String>,
+    },
+    // neither mapped nor configured locally
+    Unconfigured,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScannedRepo {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub state: ScanState,
+}
+
+// inventories every git repository below `root` without changing anything
+pub fn scan(root: &Path) -> Result<Vec<ScannedRepo>, io::Error> {
+    scan_with(root, &mut |_| {})
+}
+
+// scan, calling `on_repo` with the number of repositories found as each one
+// is inventoried
+pub fn scan_with(
+    root: &Path,
+    on_repo: &mut dyn FnMut(usize),
+) -> Result<Vec<ScannedRepo>, io::Error> {
+    let repos = find_repos(root)?;
+    let total = repos.len();
+    let mut scanned = Vec::new();
+
+    for path in repos {
+        let state = match App::get_project_profile(path.to_string_lossy().into_owned()) {
+            Ok((profile_name, _, _)) => ScanState::Mapped { profile_name },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let name = git::get_local_config(&path, "user.name")?;
+                let email = git::get_local_config(&path, "user.email")?;
+                if name.is_some() || email.is_some() {
+                    ScanState::Manual { name, email }
+                } else {
+                    ScanState::Unconfigured
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        scanned.push(ScannedRepo { path, state });
+        on_repo(total);
+    }
+
+    Ok(scanned)
+}
+
+// walks `root` depth-first and returns every directory that holds a `.git`
+// entry; repositories are not descended into and symlinks are not followed
+pub fn find_repos(root: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut repos = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        if dir.join(".git").exists() {
+            repos.push(dir);
+            continue;
+        }
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // unreadable directories are skipped rather than failing the scan
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && dir != root => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+
+    repos.sort();
+    Ok(repos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{auth::AuthType, profile::Profile};
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn reports_mapped_manual_and_unconfigured_repos() {
+        let cfg = TempConfig::new().unwrap();
+
+        let code = cfg.base.join("code");
+        let mapped = code.join("mapped");
+        let manual = code.join("nested").join("manual");
+        let bare = code.join("bare");
+        for repo in [&mapped, &manual, &bare] {
+            TempConfig::init_repo_at(repo);
+        }
+        fs::create_dir_all(code.join("not-a-repo")).unwrap();
+
+        App::add_profile(
+            "work".to_string(),
+            Profile::build(
+                "Work".to_string(),
+                "work@example.com".to_string(),
+                AuthType::None,
+                None,
+                None,
+            ),
+        )
+        .unwrap();
+        App::use_profile("work".to_string(), mapped.to_string_lossy().to_string()).unwrap();
+        git::set_local_identity(&manual, "Manual", "manual@example.com").unwrap();
+
+        let scanned = scan(&code).unwrap();
+
+        assert_eq!(
+            scanned,
+            vec![
+                ScannedRepo {
+                    path: bare,
+                    state: ScanState::Unconfigured,
+                },
+                ScannedRepo {
+                    path: mapped,
+                    state: ScanState::Mapped {
+                        profile_name: "work".to_string(),
+                    },
+                },
+                ScannedRepo {
+                    path: manual,
+                    state: ScanState::Manual {
+                        name: Some("Manual".to_string()),
+                        email: Some("manual@example.com".to_string()),
+                    },
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+// User preferences stored in `settings.toml` next to the profiles. Every
+// field has a default so older or partial files keep loading.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    // configure credentials even when a remote is reached over plain
+    // http:// or git://
+    pub allow_insecure_remotes: bool,
+    // fail commands on any warning, as `--strict` does
+    pub strict: bool,
+    // gh binary used by the credential helper when a profile does not set
+    // one; a path or a name looked up on PATH, `gh` when unset
+    pub gh_path: Option<String>,
+    // how new mappings identify a repository
+    pub mapping_key: MappingKey,
+    // plain-text markers instead of emoji even on capable terminals
+    pub disable_emoji: bool,
+    // no colors or emoji, as `--no-color` does
+    pub no_color: bool,
+    // the profile for repositories no `.octopush` file, rule or mapping
+    // decides for
+    pub default_profile: Option<String>,
+    // scheme of the urls `clone` builds from `owner/repo`; by default ssh
+    // for ssh profiles and https otherwise
+    pub protocol: Option<Protocol>,
+    // answer confirmations with yes, as `--yes` does
+    pub assume_yes: bool,
+    // rules.toml somewhere else, e.g. in a dotfiles repository
+    pub rules_file: Option<String>,
+    // keep profiles.toml encrypted with a key held in the OS keychain; the
+    // files git reads itself, gitconfig exports and allowed_signers, cannot be
+    // and stay plaintext
+    pub encrypt_profiles: bool,
+    // what to do about repositories git refuses because another user owns
+    // them
+    pub safe_directory: SafeDirectory,
+    // keep spinners on screen at least this many milliseconds
+    pub spinner_min_ms: u64,
+}
+
+// git only reads safe.directory from the global and system config, so
+// there is no repository-local way to trust a checkout
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SafeDirectory {
+    // offer to add the repository to the global config, on a terminal
+    #[default]
+    Ask,
+    // add it to the global config without asking
+    Global,
+    // fail and say how to trust it
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MappingKey {
+    // the canonical path of the repository root
+    #[default]
+    Path,
+    // the parsed primary remote, e.g. `github.com/acme/app`, so fresh
+    // clones of the same project inherit its profile
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Ssh,
+    Https,
+}
+
+impl Settings {
+    // every key `octopush config` knows, in file order
+    pub const KEYS: [&str; 13] = [
+        "allow_insecure_remotes",
+        "strict",
+        "gh_path",
+        "mapping_key",
+        "disable_emoji",
+        "no_color",
+        "default_profile",
+        "protocol",
+        "assume_yes",
+        "rules_file",
+        "encrypt_profiles",
+        "safe_directory",
+        "spinner_min_ms",
+    ];
+
+    // the value of `key` as written in settings.toml, None when unset
+    pub fn get(&self, key: &str) -> Result<Option<String>, io::Error> {
+        check_key(key)?;
+        let table = toml::Table::try_from(self).map_err(io::Error::other)?;
+
+        Ok(table.get(key).map(|value| match value {
+            toml::Value::String(s) => s.clone(),
+            value => value.to_string(),
+        }))
+    }
+
+    // sets `key` from its command-line form (`true`, `remote`, `~/bin/gh`);
+    // an empty value restores the default
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), io::Error> {
+        check_key(key)?;
+        let mut table = toml::Table::try_from(&*self).map_err(io::Error::other)?;
+
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut t| t.remove("value"));
+        let candidates = match parsed {
+            _ if value.is_empty() => vec![None],
+            // `123` or `true` for a string setting still means the string
+            Some(parsed) => vec![Some(parsed), Some(toml::Value::String(value.to_string()))],
+            None => vec![Some(toml::Value::String(value.to_string()))],
+        };
+
+        let mut error = None;
+        for candidate in candidates {
+            match candidate {
+                Some(candidate) => table.insert(key.to_string(), candidate),
+                None => table.remove(key),
+            };
+            match table.clone().try_into() {
+                Ok(settings) => {
+                    *self = settings;
+                    return Ok(());
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid value '{}' for {}: {}",
+                value,
+                key,
+                error
+                    .map(|e: toml::de::Error| e.message().to_string())
+                    .unwrap_or_default()
+            ),
+        ))
+    }
+}
+
+fn check_key(key: &str) -> Result<(), io::Error> {
+    if Settings::KEYS.contains(&key) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "unknown setting '{}', expected one of: {}",
+            key,
+            Settings::KEYS.join(", ")
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gets_and_sets_settings_by_key() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.get("strict").unwrap().as_deref(), Some("false"));
+        assert_eq!(settings.get("default_profile").unwrap(), None);
+
+        settings.set("strict", "true").unwrap();
+        settings.set("mapping_key", "remote").unwrap();
+        settings.set("protocol", "https").unwrap();
+        settings.set("default_profile", "1234").unwrap();
+        assert!(settings.strict);
+        assert_eq!(settings.mapping_key, MappingKey::Remote);
+        assert_eq!(settings.protocol, Some(Protocol::Https));
+        assert_eq!(settings.default_profile.as_deref(), Some("1234"));
+
+        settings.set("protocol", "").unwrap();
+        assert_eq!(settings.protocol, None);
+
+        let invalid = settings.set("protocol", "ftp").unwrap_err();
+        assert_eq!(invalid.kind(), io::ErrorKind::InvalidInput);
+        assert!(settings.set("colour", "never").is_err());
+        assert!(settings.get("colour").is_err());
+    }
+}
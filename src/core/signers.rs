@@ -0,0 +1,249 @@
+use crate::core::profile::Profile;
+use crate::util::system::{expand_home, home_dir};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// The allowed_signers file git hands to `ssh-keygen -Y verify` through
+// gpg.ssh.allowedSignersFile, so `git log --show-signature` can tell which
+// of the user's identities made an ssh signature.
+
+pub const CONFIG_KEY: &str = "gpg.ssh.allowedSignersFile";
+
+const SSH_KEY_TYPES: [&str; 3] = ["ssh-", "ecdsa-", "sk-"];
+
+// a key offered by the signing key picker
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningKey {
+    // the value stored as the profile's signing key
+    pub key: String,
+    pub label: String,
+}
+
+// ssh keys are given as a public key file or inline, anything else is a
+// gpg key id or fingerprint
+pub fn is_ssh_key(key: &str) -> bool {
+    key.starts_with("key::")
+        || key.ends_with(".pub")
+        || SSH_KEY_TYPES.iter().any(|prefix| key.starts_with(prefix))
+}
+
+// `~/.ssh/id_work.pub` for `~/.ssh/id_work`; agent-backed profiles already
+// point at the public key
+pub fn public_key_path(key_path: &str) -> PathBuf {
+    let path = expand_home(key_path);
+    if path.extension().is_some_and(|ext| ext == "pub") {
+        path
+    } else {
+        PathBuf::from(format!("{}.pub", path.display()))
+    }
+}
+
+// `<type> <base64>` without the trailing comment, from a key file or an
+// inline `key::` value
+fn read_public_key(key: &str) -> Option<String> {
+    let content = match key.strip_prefix("key::") {
+        Some(inline) => inline.to_string(),
+        None => fs::read_to_string(public_key_path(key)).ok()?,
+    };
+    let mut fields = content.split_whitespace();
+    let (kind, key) = (fields.next()?, fields.next()?);
+    SSH_KEY_TYPES
+        .iter()
+        .any(|prefix| kind.starts_with(prefix))
+        .then(|| format!("{} {}", kind, key))
+}
+
+// one `<email> namespaces="git" <key>` line per profile with a readable
+// public key, its ssh signing key or else its auth key, in profile name
+// order so rewrites are stable
+pub fn render(profiles: &HashMap<String, Profile>) -> String {
+    let mut names: Vec<&String> = profiles.keys().collect();
+    names.sort();
+
+    let mut out = String::from("# managed by octopush from the ssh keys of its profiles\n");
+    for name in names {
+        let profile = &profiles[name];
+        let source = profile
+            .signing_key
+            .as_deref()
+            .filter(|key| is_ssh_key(key))
+            .or(profile.ssh_key_path.as_deref());
+        let Some(key) = source.and_then(read_public_key) else {
+            continue;
+        };
+        let line = format!("{} namespaces=\"git\" {}\n", profile.email, key);
+        if !out.contains(&line) {
+            out.push_str(&line);
+        }
+    }
+    out
+}
+
+// secret gpg keys and ssh public keys in ~/.ssh, for picking a signing key
+pub fn available_keys(gpg_program: &str) -> Vec<SigningKey> {
+    let mut keys = Command::new(gpg_program)
+        .args(["--list-secret-keys", "--with-colons"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| gpg_secret_keys(&String::from_utf8_lossy(&out.stdout)))
+        .unwrap_or_default();
+    keys.extend(ssh_public_keys());
+    keys
+}
+
+// `gpg --with-colons` lists each secret key as a `sec` record followed by
+// its fingerprint (`fpr`) and user ids (`uid`), the value in field 10
+fn gpg_secret_keys(listing: &str) -> Vec<SigningKey> {
+    let mut keys: Vec<(String, Option<String>)> = Vec::new();
+    let mut in_key = false;
+
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        let value = fields.get(9).copied().unwrap_or_default();
+        match fields[0] {
+            // revoked and expired keys cannot sign
+            "sec" => {
+                in_key = !matches!(fields.get(1), Some(&"r") | Some(&"e"));
+                if in_key {
+                    keys.push((String::new(), None));
+                }
+            }
+            "ssb" => in_key = false,
+            "fpr" if in_key => {
+                if let Some((fingerprint, _)) = keys.last_mut()
+                    && fingerprint.is_empty()
+                {
+                    *fingerprint = value.to_string();
+                }
+            }
+            "uid" if in_key => {
+                if let Some((_, uid)) = keys.last_mut()
+                    && uid.is_none()
+                {
+                    *uid = Some(value.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    keys.into_iter()
+        .filter(|(fingerprint, _)| !fingerprint.is_empty())
+        .map(|(fingerprint, uid)| SigningKey {
+            label: format!(
+                "gpg {} {}",
+                &fingerprint[fingerprint.len().saturating_sub(16)..],
+                uid.unwrap_or_default()
+            ),
+            key: fingerprint,
+        })
+        .collect()
+}
+
+fn ssh_public_keys() -> Vec<SigningKey> {
+    let Some(dir) = home_dir().map(|home| home.join(".ssh")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pub"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let key = path.to_string_lossy().to_string();
+            read_public_key(&key)?;
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            let comment = content.split_whitespace().nth(2).unwrap_or_default();
+            Some(SigningKey {
+                label: format!("ssh {} {}", key, comment),
+                key,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn lists_readable_public_keys_by_email() {
+        let cfg = TempConfig::new().unwrap();
+        let key = cfg.base.join("id_work");
+        fs::write(
+            cfg.base.join("id_work.pub"),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIwork john@laptop\n",
+        )
+        .unwrap();
+
+        let work = Profile::build(
+            "John".to_string(),
+            "john@work.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some(key.to_string_lossy().to_string()),
+        );
+        let missing = Profile::build(
+            "John".to_string(),
+            "john@home.com".to_string(),
+            AuthType::SSH,
+            None,
+            Some(cfg.base.join("id_missing").to_string_lossy().to_string()),
+        );
+        let profiles = HashMap::from([
+            ("work".to_string(), work.clone()),
+            ("work-copy".to_string(), work),
+            ("home".to_string(), missing),
+        ]);
+
+        assert_eq!(
+            render(&profiles)
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .collect::<Vec<_>>(),
+            ["john@work.com namespaces=\"git\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIwork"]
+        );
+        assert_eq!(
+            public_key_path(&format!("{}.pub", key.display())),
+            cfg.base.join("id_work.pub")
+        );
+    }
+
+    #[test]
+    fn reads_usable_gpg_secret_keys() {
+        let listing = "\
+sec:u:255:22:AAAA1111BBBB2222:1700000000:::u:::scESC:::+:::ed25519:::0:
+fpr:::::::::0123456789ABCDEF0123AAAA1111BBBB2222:
+grp:::::::::GRIP:
+uid:u::::1700000000::HASH::John Doe <john@doe.com>::::::::::0:
+ssb:u:255:18:CCCC3333DDDD4444:1700000000::::::e:::+:::cv25519::
+fpr:::::::::SUBKEYFINGERPRINTCCCC3333DDDD4444:
+sec:e:255:22:EEEE5555FFFF6666:1500000000:1600000000::u:::scESC:::+:::ed25519:::0:
+fpr:::::::::EXPIREDFINGERPRINTEEEE5555FFFF6666:
+uid:e::::1500000000::HASH::Old <old@doe.com>::::::::::0:
+";
+
+        assert_eq!(
+            gpg_secret_keys(listing),
+            [SigningKey {
+                key: "0123456789ABCDEF0123AAAA1111BBBB2222".to_string(),
+                label: "gpg AAAA1111BBBB2222 John Doe <john@doe.com>".to_string(),
+            }]
+        );
+        assert!(is_ssh_key("~/.ssh/id_work.pub"));
+        assert!(is_ssh_key("key::ssh-ed25519 AAAAC3"));
+        assert!(!is_ssh_key("AAAA1111BBBB2222"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::core::plan::ApplyPlan;
+use serde::{Deserialize, Serialize};
+
+// The set of git config keys octopush manages, bumped whenever it changes.
+// Repositories whose record carries an older layout, or none because it
+// predates the marker, need re-applying.
+pub const MANAGED_LAYOUT: u32 = 1;
+
+// what octopush last did to a repository it configured, kept in `repos.toml`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepoRecord {
+    pub octopush_version: String,
+    #[serde(default)]
+    pub layout: u32,
+    // seconds since the unix epoch
+    pub configured_at: u64,
+    // profiles the repository was switched away from, most recent first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_profiles: Vec<String>,
+    // `host/owner/repo` of its origin, to recognise the checkout after a move
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+}
+
+impl RepoRecord {
+    const HISTORY_LEN: usize = 5;
+
+    pub fn current(configured_at: u64) -> Self {
+        RepoRecord {
+            octopush_version: env!("CARGO_PKG_VERSION").to_string(),
+            layout: MANAGED_LAYOUT,
+            configured_at,
+            previous_profiles: Vec::new(),
+            remote: None,
+        }
+    }
+
+    pub fn push_previous(&mut self, profile_name: &str) {
+        self.previous_profiles.retain(|name| name != profile_name);
+        self.previous_profiles.insert(0, profile_name.to_string());
+        self.previous_profiles.truncate(Self::HISTORY_LEN);
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        self.layout < MANAGED_LAYOUT
+    }
+}
+
+// how a repository's live git config relates to its mapped profile
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepoState {
+    Unmapped,
+    InSync {
+        profile_name: String,
+    },
+    Drifted {
+        profile_name: String,
+        plan: ApplyPlan,
+    },
+}
+
+impl RepoState {
+    pub fn profile_name(&self) -> Option<&str> {
+        match self {
+            RepoState::Unmapped => None,
+            RepoState::InSync { profile_name } | RepoState::Drifted { profile_name, .. } => {
+                Some(profile_name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn older_layouts_are_outdated() {
+        // written before records carried a layout
+        let record: RepoRecord =
+            toml::from_str("octopush_version = \"0.1.0\"\nconfigured_at = 0\n").unwrap();
+        assert_eq!(record.layout, 0);
+        assert!(record.is_outdated());
+
+        assert!(!RepoRecord::current(0).is_outdated());
+        assert!(
+            RepoRecord {
+                layout: MANAGED_LAYOUT - 1,
+                ..RepoRecord::current(0)
+            }
+            .is_outdated()
+        );
+    }
+
+    #[test]
+    fn keeps_recent_distinct_previous_profiles() {
+        let mut record = RepoRecord::current(0);
+        for name in ["a", "b", "a", "c", "d", "e", "f"] {
+            record.push_previous(name);
+        }
+
+        assert_eq!(record.previous_profiles, ["f", "e", "d", "c", "a"]);
+    }
+}
@@ -0,0 +1,882 @@
+use crate::core::app::{BulkAddSummary, MergeSummary};
+use crate::core::auth::AuthType;
+use crate::core::plan::{self, ApplyPlan, PLAN_VERSION, PlanOperation, Stage};
+use crate::core::project::Project;
+use crate::core::{diff, signers};
+use crate::core::{
+    profile::{Profile, ProfileUsage, validate_profile_name},
+    rules::Rules,
+    settings::{MappingKey, Settings},
+    status::RepoRecord,
+};
+use crate::util::{
+    encryption,
+    git::GitBackend,
+    keychain,
+    lock::ConfigLock,
+    system::{expand_home, write_atomic},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// profile fields that may differ between profiles merged without --force,
+// since they do not change how a repository authenticates or signs
+const MERGE_NEUTRAL_FIELDS: [&str; 7] = [
+    "name",
+    "email",
+    "tags",
+    "archived",
+    "default_branch",
+    "template_dir",
+    "credential_cache_timeout",
+];
+
+// the whole configuration directory, replacing `$XDG_CONFIG_HOME/octopush`;
+// `--config-dir` sets it for the process and the git commands it runs
+pub const CONFIG_HOME_ENV: &str = "OCTOPUSH_CONFIG_HOME";
+// the same for per-machine state, replacing `$XDG_DATA_HOME/octopush`
+pub const DATA_HOME_ENV: &str = "OCTOPUSH_DATA_HOME";
+// the hex key for an encrypted profiles.toml, for machines without an OS
+// keychain such as CI runners
+pub const PROFILES_KEY_ENV: &str = "OCTOPUSH_PROFILES_KEY";
+
+// The files octopush keeps: profiles, settings and rules in a configuration
+// directory, mappings, usage stats and repository records in a data
+// directory. The CLI resolves both from the environment; library users and
+// tests can point a store anywhere without touching process-wide variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileStore {
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+}
+
+impl ProfileStore {
+    pub(crate) const CONFIG_DIR_NAME: &str = if cfg!(test) {
+        "octopush-test"
+    } else {
+        "octopush"
+    };
+    pub(crate) const PROFILES_FILE_NAME: &str = "profiles.toml";
+    pub(crate) const PROJECT_PROFILES_FILE_NAME: &str = "project_profiles.toml";
+    pub(crate) const USAGE_FILE_NAME: &str = "usage.toml";
+    pub(crate) const SETTINGS_FILE_NAME: &str = "settings.toml";
+    pub(crate) const RULES_FILE_NAME: &str = "rules.toml";
+    pub(crate) const LOCK_FILE_NAME: &str = "octopush.lock";
+    pub(crate) const REPOS_FILE_NAME: &str = "repos.toml";
+    pub(crate) const GITCONFIG_DIR_NAME: &str = "gitconfig";
+    pub(crate) const INCLUDES_FILE_NAME: &str = "includes.gitconfig";
+    pub(crate) const TEMPLATE_DIR_NAME: &str = "template";
+    pub(crate) const HOOKS_DIR_NAME: &str = "hooks";
+    pub(crate) const GLOBAL_GITCONFIG_FILE_NAME: &str = "global.gitconfig";
+    pub(crate) const ALLOWED_SIGNERS_FILE_NAME: &str = "allowed_signers";
+
+    // a store keeping configuration and state together in `config_dir`
+    pub fn new(config_dir: impl Into<PathBuf>) -> Self {
+        let config_dir = config_dir.into();
+        ProfileStore {
+            data_dir: config_dir.clone(),
+            config_dir,
+        }
+    }
+
+    // keeps mappings, usage stats and repository records in `data_dir`
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    // the store the CLI uses: `--config-dir`/OCTOPUSH_CONFIG_HOME or
+    // `$XDG_CONFIG_HOME/octopush`, and OCTOPUSH_DATA_HOME, the custom config
+    // dir or `$XDG_DATA_HOME/octopush`
+    pub fn from_env() -> Result<Self, io::Error> {
+        let custom_config_dir = std::env::var_os(CONFIG_HOME_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        let config_dir = match &custom_config_dir {
+            Some(dir) => dir.clone(),
+            None => base_config_dir()?.join(Self::CONFIG_DIR_NAME),
+        };
+        // mappings, usage stats and repository records are per-machine
+        // state, kept apart from the configuration so syncing dotfiles does
+        // not carry one machine's repository paths to another. A custom
+        // config dir keeps its own, or two of them would share one set.
+        let data_dir = match std::env::var_os(DATA_HOME_ENV).filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => match custom_config_dir {
+                Some(dir) => dir,
+                None => base_data_dir()?.join(Self::CONFIG_DIR_NAME),
+            },
+        };
+
+        Ok(ProfileStore::new(config_dir).with_data_dir(data_dir))
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    fn ensure_app_data_dir(&self) -> Result<PathBuf, io::Error> {
+        fs::create_dir_all(&self.data_dir)?;
+        Ok(self.data_dir.clone())
+    }
+
+    // the state file `file_name`, moved out of the config dir where older
+    // versions kept it the first time it is looked up
+    fn state_path(&self, file_name: &str) -> Result<PathBuf, io::Error> {
+        let path = self.data_dir.join(file_name);
+        let legacy = self.config_dir.join(file_name);
+        if path.exists() || !legacy.exists() || path == legacy {
+            return Ok(path);
+        }
+
+        self.ensure_app_data_dir()?;
+        log::info!("moving {} to {}", legacy.display(), path.display());
+        match fs::rename(&legacy, &path) {
+            Ok(()) => {}
+            // another process got there first
+            Err(_) if !legacy.exists() => {}
+            // the data dir is on another filesystem
+            Err(_) => {
+                write_atomic(&path, &fs::read(&legacy)?)?;
+                fs::remove_file(&legacy)?;
+            }
+        }
+        Ok(path)
+    }
+
+    pub(crate) fn ensure_app_config_dir(&self) -> Result<PathBuf, io::Error> {
+        fs::create_dir_all(&self.config_dir)?;
+        Ok(self.config_dir.clone())
+    }
+
+    pub fn profiles_config_path(&self) -> PathBuf {
+        self.config_dir.join(Self::PROFILES_FILE_NAME)
+    }
+
+    pub fn project_profiles_path(&self) -> Result<PathBuf, io::Error> {
+        self.state_path(Self::PROJECT_PROFILES_FILE_NAME)
+    }
+
+    pub fn usage_path(&self) -> Result<PathBuf, io::Error> {
+        self.state_path(Self::USAGE_FILE_NAME)
+    }
+
+    pub fn repos_path(&self) -> Result<PathBuf, io::Error> {
+        self.state_path(Self::REPOS_FILE_NAME)
+    }
+
+    pub fn settings_path(&self) -> PathBuf {
+        self.config_dir.join(Self::SETTINGS_FILE_NAME)
+    }
+
+    pub fn rules_path(&self) -> Result<PathBuf, io::Error> {
+        if let Some(rules_file) = self.read_settings()?.rules_file {
+            return Ok(expand_home(&rules_file));
+        }
+        Ok(self.config_dir.join(Self::RULES_FILE_NAME))
+    }
+
+    pub fn allowed_signers_path(&self) -> PathBuf {
+        self.config_dir.join(Self::ALLOWED_SIGNERS_FILE_NAME)
+    }
+
+    pub fn lock_path(&self) -> PathBuf {
+        self.config_dir.join(Self::LOCK_FILE_NAME)
+    }
+
+    // held around every read-modify-write of the configuration files
+    pub fn lock(&self) -> Result<ConfigLock, io::Error> {
+        self.ensure_app_config_dir()?;
+        ConfigLock::acquire(&self.lock_path())
+    }
+
+    pub fn read_profile(&self, profile_name: String) -> Result<Option<Profile>, io::Error> {
+        let profiles = self.read_profiles()?;
+
+        Ok(profiles.get(&profile_name).cloned())
+    }
+
+    pub fn read_profiles(&self) -> Result<HashMap<String, Profile>, io::Error> {
+        let path = self.profiles_config_path();
+        log::debug!("reading {}", path.display());
+        let mut content = fs::read_to_string(&path).unwrap_or_default();
+        if encryption::is_encrypted(&content) {
+            content = encryption::decrypt(&self.profiles_key(false)?, &content)?;
+        }
+        if content.trim().is_empty() {
+            return Ok(HashMap::new());
+        }
+        let profiles: HashMap<String, Profile> = toml::from_str(&content)
+            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
+        Ok(profiles)
+    }
+
+    pub fn write_profiles(&self, profiles: &HashMap<String, Profile>) -> Result<(), io::Error> {
+        self.ensure_app_config_dir()?;
+        let path = self.profiles_config_path();
+        let mut toml_string = toml::to_string_pretty(profiles)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        if self.read_settings()?.encrypt_profiles {
+            toml_string = encryption::encrypt(&self.profiles_key(true)?, &toml_string)?;
+        }
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+
+        // keep the signers file in step once a repository verifies with it
+        if self.allowed_signers_path().exists() {
+            self.write_allowed_signers(profiles)?;
+        }
+        Ok(())
+    }
+
+    // the key of an encrypted profiles.toml: from the environment, else the
+    // OS keychain, where a new one is stored the first time it is needed
+    fn profiles_key(&self, create: bool) -> Result<encryption::Key, io::Error> {
+        if let Ok(hex) = std::env::var(PROFILES_KEY_ENV)
+            && !hex.is_empty()
+        {
+            return encryption::Key::from_hex(&hex);
+        }
+        if let Some(hex) = keychain::get_profiles_key()? {
+            return encryption::Key::from_hex(&hex);
+        }
+        if !create {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "profiles.toml is encrypted but its key is neither in the OS keychain nor in {}",
+                    PROFILES_KEY_ENV
+                ),
+            ));
+        }
+
+        let key = encryption::Key::generate()?;
+        keychain::set_profiles_key(&key.to_hex())?;
+        Ok(key)
+    }
+
+    pub fn write_allowed_signers(
+        &self,
+        profiles: &HashMap<String, Profile>,
+    ) -> Result<(), io::Error> {
+        self.ensure_app_config_dir()?;
+        let path = self.allowed_signers_path();
+        log::debug!("writing {}", path.display());
+        fs::write(path, signers::render(profiles))
+    }
+
+    pub fn read_project_profile(
+        &self,
+        repo_key: &str,
+    ) -> Result<Option<(String, Profile)>, io::Error> {
+        let map = self.read_project_profiles()?;
+        if let Some(profile_name) = map.get(repo_key) {
+            let profiles = self.read_profiles()?;
+
+            Ok(profiles
+                .get(profile_name)
+                .cloned()
+                .map(|profile| (profile_name.clone(), profile)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn read_project_profiles(&self) -> Result<HashMap<String, String>, io::Error> {
+        let path = self.project_profiles_path()?;
+        log::debug!("reading {}", path.display());
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        if content.trim().is_empty() {
+            return Ok(HashMap::new());
+        }
+        let map: HashMap<String, String> = toml::from_str(&content)
+            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
+        Ok(map)
+    }
+
+    // Resolves which mapping entry belongs to a repository: its remote
+    // identity if one is mapped, then its path, then a legacy entry keyed by
+    // the directory name. Repositories without any entry get the key chosen
+    // in settings. Lookups never write; see migrate_project_mapping.
+    pub fn read_project_mapping(
+        &self,
+        project: &Project,
+    ) -> Result<(String, HashMap<String, String>), io::Error> {
+        let map = self.read_project_profiles()?;
+
+        let remote_key = project.get_remote_identity()?;
+        if let Some(remote_key) = &remote_key
+            && map.contains_key(remote_key)
+        {
+            return Ok((remote_key.clone(), map));
+        }
+
+        let path_key = project.get_repo_key()?;
+        if map.contains_key(&path_key) {
+            return Ok((path_key, map));
+        }
+        let name_key = project.get_repo_name()?;
+        if map.contains_key(&name_key) {
+            return Ok((name_key, map));
+        }
+
+        let key = match (self.read_settings()?.mapping_key, remote_key) {
+            (MappingKey::Remote, Some(remote_key)) => remote_key,
+            _ => path_key,
+        };
+        Ok((key, map))
+    }
+
+    // Mappings used to be keyed by the directory name, which let two clones
+    // with the same name clobber each other. Moves such an entry of
+    // `project` to its path key; commands that change the repository call
+    // it before planning.
+    pub fn migrate_project_mapping(&self, project: &Project) -> Result<(), io::Error> {
+        let _lock = self.lock()?;
+        let (key, mut map) = self.read_project_mapping(project)?;
+        if key != project.get_repo_name()? {
+            return Ok(());
+        }
+
+        if let Some(profile_name) = map.remove(&key) {
+            map.insert(project.get_repo_key()?, profile_name);
+            self.write_project_profiles(&map)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_project_profiles(&self, map: &HashMap<String, String>) -> Result<(), io::Error> {
+        self.ensure_app_data_dir()?;
+        let path = self.project_profiles_path()?;
+        let toml_string = toml::to_string_pretty(map)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_usage(&self) -> Result<HashMap<String, ProfileUsage>, io::Error> {
+        let path = self.usage_path()?;
+        log::debug!("reading {}", path.display());
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        if content.trim().is_empty() {
+            return Ok(HashMap::new());
+        }
+        let usage: HashMap<String, ProfileUsage> = toml::from_str(&content)
+            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
+        Ok(usage)
+    }
+
+    pub fn write_usage(&self, usage: &HashMap<String, ProfileUsage>) -> Result<(), io::Error> {
+        self.ensure_app_data_dir()?;
+        let path = self.usage_path()?;
+        let toml_string = toml::to_string_pretty(usage)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_settings(&self) -> Result<Settings, io::Error> {
+        let path = self.settings_path();
+        log::debug!("reading {}", path.display());
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        toml::from_str(&content).map_err(|e| io::Error::other(format!("TOML parse error: {e}")))
+    }
+
+    pub fn read_rules(&self) -> Result<Rules, io::Error> {
+        let path = self.rules_path()?;
+        log::debug!("reading {}", path.display());
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        toml::from_str(&content).map_err(|e| io::Error::other(format!("TOML parse error: {e}")))
+    }
+
+    pub fn write_rules(&self, rules: &Rules) -> Result<(), io::Error> {
+        self.ensure_app_config_dir()?;
+        let path = self.rules_path()?;
+        let toml_string = toml::to_string_pretty(rules)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn write_settings(&self, settings: &Settings) -> Result<(), io::Error> {
+        self.ensure_app_config_dir()?;
+        let path = self.settings_path();
+        let toml_string = toml::to_string_pretty(settings)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_repos(&self) -> Result<HashMap<String, RepoRecord>, io::Error> {
+        let path = self.repos_path()?;
+        log::debug!("reading {}", path.display());
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        if content.trim().is_empty() {
+            return Ok(HashMap::new());
+        }
+        let repos: HashMap<String, RepoRecord> = toml::from_str(&content)
+            .map_err(|e| io::Error::other(format!("TOML parse error: {e}")))?;
+        Ok(repos)
+    }
+
+    pub fn write_repos(&self, repos: &HashMap<String, RepoRecord>) -> Result<(), io::Error> {
+        self.ensure_app_data_dir()?;
+        let path = self.repos_path()?;
+        let toml_string = toml::to_string_pretty(repos)
+            .map_err(|e| io::Error::other(format!("TOML serialize error: {e}")))?;
+        log::debug!("writing {}", path.display());
+        write_atomic(&path, toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    // stamps a repository with the octopush version that just configured it,
+    // remembering the profile it was switched away from
+    pub fn record_repo(
+        &self,
+        repo_key: &str,
+        remote: Option<String>,
+        previous: Option<&str>,
+    ) -> Result<(), io::Error> {
+        let mut repos = self.read_repos()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let mut record = RepoRecord::current(now);
+        record.remote = remote;
+        if let Some(old) = repos.remove(repo_key) {
+            record.previous_profiles = old.previous_profiles;
+        }
+        if let Some(previous) = previous {
+            record.push_previous(previous);
+        }
+        repos.insert(repo_key.to_string(), record);
+        self.write_repos(&repos)
+    }
+
+    pub fn record_usage(&self, profile_name: &str) -> Result<(), io::Error> {
+        let mut usage = self.read_usage()?;
+        let entry = usage.entry(profile_name.to_string()).or_default();
+        entry.use_count += 1;
+        entry.last_used = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.write_usage(&usage)
+    }
+
+    pub fn add_profile(&self, profile_name: String, profile: Profile) -> Result<(), io::Error> {
+        profile.validate()?;
+        let mut profiles = self.read_profiles()?;
+        if profiles.contains_key(&profile_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("profile '{}' already exists", profile_name),
+            ));
+        }
+        profiles.insert(profile_name, profile);
+        self.write_profiles(&profiles)
+    }
+
+    pub fn add_profiles(
+        &self,
+        manifest: BTreeMap<String, Profile>,
+    ) -> Result<BulkAddSummary, io::Error> {
+        let mut profiles = self.read_profiles()?;
+        let mut summary = BulkAddSummary::default();
+
+        for (profile_name, profile) in manifest {
+            if profiles.contains_key(&profile_name) {
+                summary
+                    .skipped
+                    .push((profile_name, "profile already exists".to_string()));
+                continue;
+            }
+            if let Err(e) = validate_profile_name(&profile_name).and_then(|_| profile.validate()) {
+                summary.skipped.push((profile_name, e.to_string()));
+                continue;
+            }
+
+            profiles.insert(profile_name.clone(), profile);
+            summary.created.push(profile_name);
+        }
+
+        if !summary.created.is_empty() {
+            self.write_profiles(&profiles)?;
+        }
+        Ok(summary)
+    }
+
+    pub fn update_profile(&self, profile_name: String, profile: Profile) -> Result<(), io::Error> {
+        let mut profiles = self.read_profiles()?;
+
+        // validate against the incoming auth type so an edit can switch
+        // between auth types as long as the fields match the new one
+        match profiles.get(&profile_name) {
+            Some(_) => match profile.auth_type {
+                AuthType::None => {
+                    if profile.hostname.is_some() || profile.ssh_key_path.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "you cannot update 'hostname' or 'ssh_key_path' for 'none' auth type"
+                                .to_string(),
+                        ));
+                    }
+                }
+                AuthType::SSH => {
+                    if profile.hostname.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "you cannot update 'hostname' for 'ssh' auth type".to_string(),
+                        ));
+                    }
+                    if profile.credential_helper.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "you cannot update 'credential_helper' for 'ssh' auth type".to_string(),
+                        ));
+                    }
+                }
+                AuthType::GH | AuthType::GitLab | AuthType::Token => {
+                    if profile.ssh_key_path.is_some() {
+                        let auth_type: &str = profile.auth_type.into();
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "you cannot update 'ssh_key_path' for '{}' auth type",
+                                auth_type
+                            ),
+                        ));
+                    }
+                }
+            },
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("profile '{}' not found", profile_name),
+                ));
+            }
+        }
+
+        profile.validate_credential_cache()?;
+
+        profiles.insert(profile_name, profile);
+        self.write_profiles(&profiles)
+    }
+
+    pub fn archive_profile(&self, profile_name: String, archived: bool) -> Result<(), io::Error> {
+        let mut profiles = self.read_profiles()?;
+        match profiles.get_mut(&profile_name) {
+            Some(profile) => profile.archived = archived,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("profile '{}' not found", profile_name),
+                ));
+            }
+        }
+        self.write_profiles(&profiles)
+    }
+
+    pub fn delete_profile(&self, profile_name: String) -> Result<(), io::Error> {
+        let mut profiles = self.read_profiles()?;
+        let removed = profiles.remove(&profile_name);
+        if removed.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("profile '{}' not found", profile_name),
+            ));
+        }
+        self.write_profiles(&profiles)?;
+
+        // best effort: a keychain that cannot be reached must not keep the
+        // profile from being deleted
+        if removed.is_some_and(|profile| profile.auth_type == AuthType::Token) {
+            let _ = keychain::delete_token(&profile_name);
+        }
+
+        let mut usage = self.read_usage()?;
+        if usage.remove(&profile_name).is_some() {
+            self.write_usage(&usage)?;
+        }
+        Ok(())
+    }
+
+    pub fn merge_profiles(
+        &self,
+        into: String,
+        from: String,
+        force: bool,
+    ) -> Result<MergeSummary, io::Error> {
+        if into == from {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot merge a profile into itself",
+            ));
+        }
+
+        let mut profiles = self.read_profiles()?;
+        let not_found = |name: &str| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("profile '{}' not found", name),
+            )
+        };
+        let target = profiles.get(&into).ok_or_else(|| not_found(&into))?;
+        let source = profiles.get(&from).ok_or_else(|| not_found(&from))?;
+
+        // repos mapped to the source must keep authenticating, connecting and
+        // signing the same way, unless forced
+        let conflicts: Vec<&str> = diff::diff_profiles(&into, target, &from, source)
+            .fields
+            .iter()
+            .filter(|field| !field.is_equal() && !MERGE_NEUTRAL_FIELDS.contains(&field.field))
+            .map(|field| field.field)
+            .collect();
+        if !conflicts.is_empty() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{}' and '{}' differ in {}, so repositories mapped to '{}' would switch accounts; pass --force to merge anyway",
+                    from,
+                    into,
+                    conflicts.join(", "),
+                    from
+                ),
+            ));
+        }
+
+        let mut summary = MergeSummary::default();
+
+        let mut map = self.read_project_profiles()?;
+        for profile_name in map.values_mut().filter(|name| **name == from) {
+            *profile_name = into.clone();
+            summary.mappings += 1;
+        }
+        if summary.mappings > 0 {
+            self.write_project_profiles(&map)?;
+        }
+
+        let mut rules = self.read_rules()?;
+        for rule in rules.rules.iter_mut().filter(|rule| rule.profile == from) {
+            rule.profile = into.clone();
+            summary.rules += 1;
+        }
+        if summary.rules > 0 {
+            self.write_rules(&rules)?;
+        }
+
+        let mut usage = self.read_usage()?;
+        if let Some(merged) = usage.remove(&from) {
+            let entry = usage.entry(into).or_default();
+            entry.use_count += merged.use_count;
+            entry.last_used = entry.last_used.max(merged.last_used);
+            self.write_usage(&usage)?;
+        }
+
+        profiles.remove(&from);
+        self.write_profiles(&profiles)?;
+
+        Ok(summary)
+    }
+
+    pub fn apply_plan(&self, git: &dyn GitBackend, plan: &ApplyPlan) -> Result<(), io::Error> {
+        self.apply_plan_in_stages(git, plan, &mut |_| {})
+    }
+
+    // applies the plan one stage at a time, identity first, telling
+    // `on_stage` as each stage with something to do begins
+    pub fn apply_plan_in_stages(
+        &self,
+        git: &dyn GitBackend,
+        plan: &ApplyPlan,
+        on_stage: &mut dyn FnMut(Stage),
+    ) -> Result<(), io::Error> {
+        if plan.operations.iter().any(|operation| {
+            matches!(operation, PlanOperation::GitConfig { key, after: Some(_), .. } if key == signers::CONFIG_KEY)
+        }) {
+            self.write_allowed_signers(&self.read_profiles()?)?;
+        }
+
+        for stage in Stage::ALL {
+            let mut operations = plan
+                .operations
+                .iter()
+                .filter(|operation| operation.stage() == stage)
+                .peekable();
+            if operations.peek().is_some() {
+                on_stage(stage);
+            }
+
+            for operation in operations {
+                match operation {
+                    PlanOperation::Mapping { key, after, .. } => {
+                        let mut map = self.read_project_profiles()?;
+                        match after {
+                            Some(profile_name) => map.insert(key.clone(), profile_name.clone()),
+                            None => map.remove(key),
+                        };
+                        self.write_project_profiles(&map)?;
+                    }
+                    _ => plan::apply_operation(git, &plan.repo, operation)?,
+                }
+            }
+        }
+
+        let previous = plan
+            .operations
+            .iter()
+            .find_map(|operation| match operation {
+                PlanOperation::Mapping {
+                    before: Some(before),
+                    after: Some(after),
+                    ..
+                } if before != after => Some(before.as_str()),
+                _ => None,
+            });
+
+        let project = Project::new(&plan.repo)?;
+        let (repo_key, map) = self.read_project_mapping(&project)?;
+        if map.contains_key(&repo_key) {
+            self.record_repo(&repo_key, project.get_remote_identity()?, previous)?;
+        }
+        Ok(())
+    }
+
+    // makes sure nothing a plan touches has changed since it was created
+    pub fn verify_plan(&self, git: &dyn GitBackend, plan: &ApplyPlan) -> Result<(), io::Error> {
+        if plan.version != PLAN_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported plan version {} (expected {})",
+                    plan.version, PLAN_VERSION
+                ),
+            ));
+        }
+
+        let map = self.read_project_profiles()?;
+        for operation in &plan.operations {
+            let current = match operation {
+                PlanOperation::Mapping { key, .. } => map.get(key).cloned(),
+                _ => plan::current_value(git, &plan.repo, operation)?,
+            };
+
+            if current.as_deref() != operation.before() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "plan is stale: '{}' changed since the plan was created",
+                        operation.target()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn base_config_dir() -> Result<PathBuf, io::Error> {
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Ok(PathBuf::from(xdg));
+    }
+
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "HOME/USERPROFILE environment variable not set",
+            )
+        })?;
+
+    Ok(PathBuf::from(home).join(".config"))
+}
+
+fn base_data_dir() -> Result<PathBuf, io::Error> {
+    if let Some(xdg) = std::env::var_os("XDG_DATA_HOME").filter(|xdg| !xdg.is_empty()) {
+        return Ok(PathBuf::from(xdg));
+    }
+
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "HOME/USERPROFILE environment variable not set",
+            )
+        })?;
+
+    Ok(PathBuf::from(home).join(".local").join("share"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::AuthType;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn custom_config_dir_keeps_its_own_state() {
+        let t = TempConfig::new().unwrap();
+        let custom = t.base.join("custom");
+        unsafe { std::env::set_var(CONFIG_HOME_ENV, &custom) };
+        let store = ProfileStore::from_env();
+
+        unsafe { std::env::set_var(DATA_HOME_ENV, t.base.join("state")) };
+        let with_data_home = ProfileStore::from_env();
+        unsafe {
+            std::env::remove_var(CONFIG_HOME_ENV);
+            std::env::remove_var(DATA_HOME_ENV);
+        }
+
+        assert_eq!(store.unwrap(), ProfileStore::new(&custom));
+        assert_eq!(with_data_home.unwrap().data_dir(), t.base.join("state"));
+    }
+
+    #[test]
+    fn keeps_stores_in_their_own_directories() {
+        let base = std::env::temp_dir().join(format!("octopush-store-{}", std::process::id()));
+        let work = ProfileStore::new(base.join("work"));
+        let home = ProfileStore::new(base.join("home")).with_data_dir(base.join("home-state"));
+
+        let profile = Profile::build(
+            "John".to_string(),
+            "john@acme.com".to_string(),
+            AuthType::None,
+            None,
+            None,
+        );
+        work.add_profile("acme".to_string(), profile.clone())
+            .unwrap();
+        home.write_project_profiles(&HashMap::from([(
+            "/src/app".to_string(),
+            "personal".to_string(),
+        )]))
+        .unwrap();
+
+        assert_eq!(
+            work.read_profile("acme".to_string()).unwrap(),
+            Some(profile)
+        );
+        assert!(home.read_profiles().unwrap().is_empty());
+        assert!(work.read_project_profiles().unwrap().is_empty());
+        assert_eq!(work.profiles_config_path(), base.join("work/profiles.toml"));
+        assert!(base.join("home-state/project_profiles.toml").exists());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -1,15 +1,44 @@
 pub mod core {
     pub mod app;
     pub mod auth;
+    pub mod connection;
+    pub mod credential;
+    pub mod diff;
+    pub mod doctor;
+    pub mod dump;
+    pub mod exec;
+    pub mod export;
+    pub mod forge;
+    pub mod hook;
+    pub mod inspect;
+    pub mod local;
+    pub mod plan;
+    pub mod preflight;
     pub mod profile;
     pub mod project;
+    pub mod remote;
+    pub mod rules;
+    pub mod scan;
+    pub mod settings;
+    pub mod signers;
+    pub mod status;
+    pub mod store;
 }
 
+pub use core::inspect::{RepoIdentityReport, inspect};
+
 pub mod util {
     pub mod cli;
+    pub mod encryption;
+    pub mod exit;
     pub mod git;
+    pub mod keychain;
+    #[cfg(feature = "git2")]
+    pub mod libgit2;
+    pub mod lock;
     pub mod output;
     pub mod path_completer;
+    pub mod ssh_config;
     pub mod system;
 }
 
@@ -1,8 +1,15 @@
-use clap::Parser;
-use octopush::util::cli;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use octopush::util::{cli, exit};
 
-fn main() -> Result<(), std::io::Error> {
-    cli::run(cli::Cli::parse())?;
+fn main() {
+    // answers shell completion requests (`COMPLETE=zsh octopush`) and exits
+    CompleteEnv::with_factory(cli::Cli::command).complete();
 
-    Ok(())
+    if let Err(e) = cli::run(cli::Cli::parse()) {
+        if !exit::is_reported(&e) {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(exit::code(&e));
+    }
 }
@@ -1,7 +1,9 @@
 use crate::core::{auth::AuthType, profile::Profile};
 use crate::util::git;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::process::{Command, Output};
+use std::sync::{Barrier, Mutex};
+use std::thread;
 use std::{env, sync::MutexGuard, time::SystemTime};
 use std::{fs, io, time::UNIX_EPOCH};
 
@@ -11,6 +13,9 @@ pub struct TempConfig {
     pub _guard: MutexGuard<'static, ()>,
     pub base: PathBuf,
     pub prev: Option<std::ffi::OsString>,
+    pub prev_data: Option<std::ffi::OsString>,
+    // octopush's own overrides, cleared for the test and restored after it
+    pub prev_overrides: Vec<(&'static str, Option<std::ffi::OsString>)>,
     pub repo: PathBuf,
     pub gh_dir: PathBuf,
 }
@@ -34,8 +39,24 @@ impl TempConfig {
         fs::create_dir_all(&base)?;
 
         let prev = env::var_os("XDG_CONFIG_HOME");
+        let prev_data = env::var_os("XDG_DATA_HOME");
+        // the dirs would take precedence over the XDG ones, and may be left
+        // over from a test that ran with `--config-dir`; the key keeps tests
+        // that encrypt profiles.toml away from the keychain
+        let prev_overrides: Vec<_> = [
+            crate::core::app::CONFIG_HOME_ENV,
+            crate::core::app::DATA_HOME_ENV,
+            crate::core::app::PROFILES_KEY_ENV,
+        ]
+        .into_iter()
+        .map(|key| (key, env::var_os(key)))
+        .collect();
         unsafe {
             env::set_var("XDG_CONFIG_HOME", &base);
+            env::set_var("XDG_DATA_HOME", base.join("share"));
+            for (key, _) in &prev_overrides {
+                env::remove_var(key);
+            }
         }
 
         let repo = base.join("repo");
@@ -48,17 +69,81 @@ impl TempConfig {
             _guard: guard,
             base,
             prev,
+            prev_data,
+            prev_overrides,
             repo,
             gh_dir,
         })
     }
 
-    fn init_repo_at(path: &Path) {
+    // the key for an encrypted profiles.toml, until this config is dropped
+    pub fn set_profiles_key(&self, hex: &str) {
+        unsafe { env::set_var(crate::core::app::PROFILES_KEY_ENV, hex) };
+    }
+
+    pub fn init_repo_at(path: &Path) {
         fs::create_dir_all(path).unwrap();
         let o = git::run_git(path, ["init"]).unwrap();
         assert!(o.status.success(), "git init failed: {:?}", o);
     }
 
+    // runs `op` on `n` threads released at the same moment, all sharing this
+    // config; results come back in thread order so callers can assert on them
+    pub fn run_concurrently<T, F>(&self, n: usize, op: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(usize) -> T + Sync,
+    {
+        let barrier = Barrier::new(n);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..n)
+                .map(|i| {
+                    let (barrier, op) = (&barrier, &op);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        op(i)
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("concurrent operation panicked"))
+                .collect()
+        })
+    }
+
+    // same as `run_concurrently` but with `n` separate processes of `program`,
+    // which is what two shells racing each other actually look like
+    pub fn spawn_concurrently<F>(
+        &self,
+        program: &Path,
+        n: usize,
+        args: F,
+    ) -> Result<Vec<Output>, io::Error>
+    where
+        F: Fn(usize) -> Vec<String>,
+    {
+        let children = (0..n)
+            .map(|i| {
+                Command::new(program)
+                    .args(args(i))
+                    .env("XDG_CONFIG_HOME", &self.base)
+                    .current_dir(&self.repo)
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::piped())
+                    .spawn()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        children
+            .into_iter()
+            .map(|child| child.wait_with_output())
+            .collect()
+    }
+
     fn init_gh_hosts(path: &Path) {
         fs::create_dir_all(path).unwrap();
         fs::write(
@@ -80,6 +165,16 @@ impl Drop for TempConfig {
                 env::remove_var("XDG_CONFIG_HOME");
             }
         }
+        match &self.prev_data {
+            Some(v) => unsafe { env::set_var("XDG_DATA_HOME", v) },
+            None => unsafe { env::remove_var("XDG_DATA_HOME") },
+        }
+        for (key, prev) in &self.prev_overrides {
+            match prev {
+                Some(v) => unsafe { env::set_var(key, v) },
+                None => unsafe { env::remove_var(key) },
+            }
+        }
         let _ = fs::remove_dir_all(&self.base);
         // _lock is released automatically here
     }
@@ -87,7 +182,11 @@ impl Drop for TempConfig {
 
 static PROFILE_NAMES: [&str; 3] = ["profile_1", "profile_2", "profile_3"];
 static NAMES: [&str; 3] = ["profile_1_name", "profile_2_name", "profile_3_name"];
-static EMAILS: [&str; 3] = ["profile_1_email", "profile_2_email", "profile_3_email"];
+static EMAILS: [&str; 3] = [
+    "profile_1@example.com",
+    "profile_2@example.com",
+    "profile_3@example.com",
+];
 static AUTH_TYPES: [AuthType; 3] = [AuthType::None, AuthType::SSH, AuthType::GH];
 static HOSTNAMES: [Option<&str>; 3] = [None, None, Some("github.com")];
 static SSH_KEY_PATHS: [Option<&str>; 3] = [None, Some("~/.ssh/id_ed25519"), None];
@@ -1,21 +1,79 @@
-use crate::util::path_completer::dialoguer_path_input;
+use crate::util::path_completer::{dialoguer_path_input, dialoguer_path_input_with_initial};
 use crate::{
-    core::{app::App, auth::AuthType, profile::Profile},
+    core::{
+        app::{self, App, SyncProgress},
+        auth::AuthType,
+        diff::ProfileDiff,
+        doctor::CheckStatus,
+        exec::{self, ExecOptions},
+        inspect::{self, IdentityValue, Whoami},
+        plan::{self, ApplyPlan, PlanOperation, Remotes},
+        preflight::Warning,
+        profile::{Profile, ProfileUsage},
+        scan::{self, ScanState, ScannedRepo},
+        settings::{SafeDirectory, Settings},
+        signers,
+        status::RepoState,
+    },
     util::{
-        output::{OperationType, Runner},
-        system::cwd,
+        exit, git,
+        lock::RemovedLock,
+        output::{
+            Format, OperationResult, OperationType, OutputFormat, Progress, PromptColor,
+            PromptFormat, Runner, TerminalCaps, format_age, format_duration, init_logging,
+            prompt_segment, table,
+        },
+        system::{cwd, open_url},
     },
 };
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use clap_complete::env::Shells;
 use colored::Colorize;
-use dialoguer::{Input, Select};
+use dialoguer::{Confirm, FuzzySelect, Input, Password, Select};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read, Write};
+use std::{fs, path::PathBuf};
 
 #[derive(Debug, Parser)]
 pub struct Cli {
+    /// Run as if octopush was started in <PATH> (like `git -C`)
+    #[arg(short = 'C', long = "chdir", value_name = "PATH", global = true)]
+    chdir: Option<PathBuf>,
+    /// Keep profiles, mappings and settings in <PATH>; also set by OCTOPUSH_CONFIG_HOME
+    #[arg(long, value_name = "PATH", global = true)]
+    config_dir: Option<PathBuf>,
+    /// Treat every warning as an error and exit with a distinct status
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Write a machine-readable `OCTOPUSH_RESULT=...` line to this file descriptor when done
+    #[arg(long, value_name = "FD", global = true)]
+    result_fd: Option<i32>,
+    /// Log decisions to stderr; repeat (-vv) to also log every command run and file touched
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Echo every git, gh and ssh command to stderr with its exit status and stderr
+    #[arg(long, global = true)]
+    trace_git: bool,
+    /// Print only requested data and errors: no spinners, success banners or warnings
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Fail instead of prompting when an argument is missing; also set by OCTOPUSH_NONINTERACTIVE
+    #[arg(long, global = true)]
+    no_input: bool,
+    /// Disable colors and emoji; also honored through the NO_COLOR environment variable
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Output of list-profiles, list-projects, get-profile, status, config-dump, diff-profiles, doctor, scan and dry-run plans
+    #[arg(long, value_enum, global = true, default_value_t = Format::Human)]
+    format: Format,
     #[command(subcommand)]
     command: Command,
 }
 
+const COMPLETION_SHELLS: [&str; 5] = ["bash", "elvish", "fish", "powershell", "zsh"];
+
 #[derive(Debug, Subcommand)]
 enum Command {
     AddProfile {
@@ -32,18 +90,445 @@ enum Command {
         hostname: Option<String>,
         #[arg(short, long)]
         ssh_key_path: Option<String>,
+        /// Free-form labels used by `search` (repeatable)
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
+        /// Prefill the profile from the identity configured in the current repository
+        #[arg(long)]
+        from_repo: bool,
+        /// Map the current repository to the new profile right away
+        #[arg(long, requires = "from_repo")]
+        map: bool,
+        /// Use a `Host <host>-<profile>` alias in ~/.ssh/config instead of core.sshCommand
+        #[arg(long)]
+        ssh_host_alias: bool,
+        /// Socket of the ssh agent holding the key, e.g. `~/.1password/agent.sock`
+        #[arg(long, value_name = "PATH", requires = "ssh_key_path")]
+        ssh_identity_agent: Option<String>,
+        /// Initial branch for repositories created with `init-repo`
+        #[arg(long)]
+        default_branch: Option<String>,
+        /// Template directory passed to git by `init-repo` and `clone`
+        #[arg(long)]
+        template_dir: Option<String>,
+        /// gh binary used by the credential helper (path or name on PATH)
+        #[arg(long)]
+        gh_path: Option<String>,
+        /// Credential helper to use instead of the auth type's own, e.g. `osxkeychain`
+        #[arg(long, value_name = "HELPER")]
+        credential_helper: Option<String>,
+        /// How long the `cache` credential helper keeps credentials after use (its --timeout)
+        #[arg(long, value_name = "SECONDS", requires = "credential_helper")]
+        credential_cache_timeout: Option<u64>,
+        /// CA bundle for hosts behind a TLS-intercepting proxy (http.sslCAInfo)
+        #[arg(long, value_name = "PATH")]
+        ssl_ca_info: Option<String>,
+        /// Set http.sslVerify for the profile's repositories
+        #[arg(long, value_name = "BOOL")]
+        ssl_verify: Option<bool>,
+        /// Proxy for the profile's http and https remotes (http.proxy)
+        #[arg(long, value_name = "URL")]
+        http_proxy: Option<String>,
+        /// Sign commits and tags with this gpg key id or ssh public key (path or `key::<key>`)
+        #[arg(long, value_name = "KEY")]
+        signing_key: Option<String>,
+        /// Sign commits and tags, picking the key from gpg and ~/.ssh
+        #[arg(long, conflicts_with = "signing_key")]
+        sign: bool,
+        /// gpg binary used for signing, e.g. `gpg2` or a wrapper (gpg.program)
+        #[arg(long, value_name = "PROGRAM")]
+        gpg_program: Option<String>,
+        /// Rewrite urls starting with FROM to TO through url.<TO>.insteadOf (repeatable)
+        #[arg(long = "url-rewrite", value_name = "FROM=TO", value_parser = parse_url_rewrite)]
+        url_rewrites: Vec<(String, String)>,
+    },
+    /// Add many profiles at once from a TOML (profiles.toml layout) or JSON manifest
+    AddProfiles {
+        #[arg(short, long)]
+        file: PathBuf,
+    },
+    /// Edit an existing profile; runs a guided wizard when no field flags are given
+    EditProfile {
+        #[arg(add = ArgValueCompleter::new(complete_profiles))]
+        profile_name: String,
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long)]
+        email: Option<String>,
+        #[arg(short, long)]
+        auth_type: Option<AuthType>,
+        #[arg(short('g'), long)]
+        hostname: Option<String>,
+        #[arg(short, long)]
+        ssh_key_path: Option<String>,
+        /// Replace the profile's tags (repeatable)
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
+        /// Use a `Host <host>-<profile>` alias in ~/.ssh/config instead of core.sshCommand
+        #[arg(long, value_name = "BOOL")]
+        ssh_host_alias: Option<bool>,
+        /// Socket of the ssh agent holding the key; an empty value removes it
+        #[arg(long, value_name = "PATH")]
+        ssh_identity_agent: Option<String>,
+        /// Initial branch for repositories created with `init-repo`
+        #[arg(long)]
+        default_branch: Option<String>,
+        /// Template directory passed to git by `init-repo` and `clone`
+        #[arg(long)]
+        template_dir: Option<String>,
+        /// gh binary used by the credential helper (path or name on PATH)
+        #[arg(long)]
+        gh_path: Option<String>,
+        /// Credential helper to use instead of the auth type's own; an empty value removes it
+        #[arg(long, value_name = "HELPER")]
+        credential_helper: Option<String>,
+        /// How long the `cache` credential helper keeps credentials after use; 0 removes it
+        #[arg(long, value_name = "SECONDS")]
+        credential_cache_timeout: Option<u64>,
+        /// CA bundle for hosts behind a TLS-intercepting proxy; an empty value removes it
+        #[arg(long, value_name = "PATH")]
+        ssl_ca_info: Option<String>,
+        /// Set http.sslVerify for the profile's repositories
+        #[arg(long, value_name = "BOOL")]
+        ssl_verify: Option<bool>,
+        /// Proxy for the profile's http and https remotes; an empty value removes it
+        #[arg(long, value_name = "URL")]
+        http_proxy: Option<String>,
+        /// Sign commits and tags with this key; an empty value turns signing off
+        #[arg(long, value_name = "KEY")]
+        signing_key: Option<String>,
+        /// gpg binary used for signing; an empty value removes it
+        #[arg(long, value_name = "PROGRAM")]
+        gpg_program: Option<String>,
+        /// Replace the profile's url rewrites (repeatable)
+        #[arg(long = "url-rewrite", value_name = "FROM=TO", value_parser = parse_url_rewrite)]
+        url_rewrites: Vec<(String, String)>,
     },
     DeleteProfile {
+        #[arg(short, long, add = ArgValueCompleter::new(complete_profiles))]
+        profile_name: String,
+        /// Delete without asking for confirmation
         #[arg(short, long)]
+        yes: bool,
+    },
+    /// Fold a near-duplicate profile into another, repointing its mappings, rules and usage
+    MergeProfiles {
+        /// Profile that is kept
+        #[arg(long, value_name = "PROFILE", add = ArgValueCompleter::new(complete_profiles))]
+        into: String,
+        /// Profile that is merged and then deleted
+        #[arg(long, value_name = "PROFILE", add = ArgValueCompleter::new(complete_profiles))]
+        from: String,
+        /// Merge even if the profiles authenticate or sign differently
+        #[arg(long)]
+        force: bool,
+    },
+    /// Fuzzy-search profiles by id, name, email and tags
+    Search {
+        query: String,
+        /// Include archived profiles
+        #[arg(long)]
+        all: bool,
+    },
+    /// Compare two profiles field by field, marking differences that change applied git config
+    DiffProfiles {
+        #[arg(add = ArgValueCompleter::new(complete_profiles))]
+        left: String,
+        #[arg(add = ArgValueCompleter::new(complete_profiles))]
+        right: String,
+        /// Deprecated spelling of the global `--format json`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+    },
+    /// Store the access token of a `token` profile in the OS keychain
+    SetToken {
+        #[arg(add = ArgValueCompleter::new(complete_profiles))]
+        profile_name: String,
+    },
+    /// Used as git's credential helper for `token` profiles
+    #[command(hide = true)]
+    Credential {
+        /// get, store or erase, as passed by git
+        operation: String,
+    },
+    /// Hide a profile from listings and selection without deleting it
+    ArchiveProfile {
+        #[arg(add = ArgValueCompleter::new(complete_profiles))]
         profile_name: String,
+        /// Restore an archived profile
+        #[arg(long)]
+        unarchive: bool,
     },
-    ListProfiles,
+    ListProfiles {
+        /// Include archived profiles
+        #[arg(long)]
+        all: bool,
+        /// Also show when each profile was last used and how often
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// List the repositories mapped to a profile
+    ListProjects,
     UseProfile {
+        /// Resolved from rules.toml (or the existing mapping) when omitted, or else picked from a searchable list
+        #[arg(short, long, add = ArgValueCompleter::new(complete_repo_profiles))]
+        profile_name: Option<String>,
+        /// Print the planned changes without touching the repository
+        #[arg(long, conflicts_with = "execute_plan")]
+        dry_run: bool,
+        /// Deprecated spelling of the global `--format json`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+        /// Rewrite this remote's URL instead of the primary one
+        #[arg(long, value_name = "NAME", conflicts_with = "all_remotes")]
+        remote: Option<String>,
+        /// Rewrite the URL of every remote, not just the primary one
+        #[arg(long)]
+        all_remotes: bool,
+        /// Run `gh auth login` (or `glab auth login`) without asking when the profile's host is not logged in
+        #[arg(long, conflicts_with = "dry_run")]
+        login: bool,
+        /// Write the profile to the global git config instead of a repository
+        #[arg(long, requires = "profile_name", conflicts_with_all = ["dry_run", "remote", "all_remotes", "login", "execute_plan", "paths"])]
+        global: bool,
+        /// Apply a plan produced by `--dry-run --output json` verbatim
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["profile_name", "paths", "remote", "all_remotes"])]
+        execute_plan: Option<PathBuf>,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+        /// Apply without previewing the changes and asking first
+        #[arg(short, long)]
+        yes: bool,
+        /// Print why each setting is changed before applying it
+        #[arg(long, conflicts_with_all = ["global", "execute_plan"])]
+        explain: bool,
+    },
+    /// Apply the profile picked by rules.toml (or the existing mapping) to the current repository
+    Apply {
+        /// Print the planned changes without touching the repository
+        #[arg(long)]
+        dry_run: bool,
+        /// Deprecated spelling of the global `--format json`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+        /// Rewrite this remote's URL instead of the primary one
+        #[arg(long, value_name = "NAME", conflicts_with = "all_remotes")]
+        remote: Option<String>,
+        /// Rewrite the URL of every remote, not just the primary one
+        #[arg(long)]
+        all_remotes: bool,
+        /// Run `gh auth login` (or `glab auth login`) without asking when the profile's host is not logged in
+        #[arg(long, conflicts_with = "dry_run")]
+        login: bool,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+    },
+    /// Re-apply the mapped profile, picking up edits made to it since
+    Sync {
+        /// Sync every mapped repository instead of the current one
+        #[arg(long, conflicts_with = "paths")]
+        all: bool,
+        /// Print the changes a sync would make without touching anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+    },
+    /// Write profiles and mappings as gitconfig `includeIf` snippets for the global config
+    ExportGitconfig,
+    /// Install a post-checkout hook that applies the matching profile to every new clone
+    SetupAutoApply {
+        /// Install through core.hooksPath instead of init.templateDir, covering existing repositories too
+        #[arg(long)]
+        hooks_path: bool,
+        /// Replace an existing post-checkout hook that octopush did not write
+        #[arg(long)]
+        force: bool,
+    },
+    /// Used by the post-checkout hook: apply the profile picked by rules, if any
+    #[command(hide = true)]
+    AutoApply,
+    /// Install a pre-commit hook that refuses commits under the wrong identity
+    InstallHook {
+        /// Replace an existing pre-commit hook that octopush did not write
+        #[arg(long)]
+        force: bool,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+    },
+    /// Used by the pre-commit hook: fail unless the commit identity matches the mapped profile
+    #[command(hide = true)]
+    CheckIdentity,
+    /// Check the environment: git, gh, ssh keys, config files and mappings
+    Doctor {
+        /// Deprecated spelling of the global `--format json`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+    },
+    /// Print the resolved paths, parsed profiles, mappings, settings and rules, and the environment overrides in effect
+    ConfigDump,
+    /// Connect to the host as a profile and report which account it authenticates as
+    TestConnection {
+        /// Resolved from rules.toml (or the existing mapping) when omitted
+        #[arg(short, long, add = ArgValueCompleter::new(complete_repo_profiles))]
+        profile_name: Option<String>,
+        /// Host to connect to instead of the profile's or the repository's
+        #[arg(long)]
+        hostname: Option<String>,
+    },
+    /// Compare the repository's git config with its mapped profile and report drift
+    Status {
+        /// Reconcile any drift by re-applying the mapped profile
+        #[arg(long)]
+        fix: bool,
+        /// Say what each setting the profile manages is for
+        #[arg(long)]
+        explain: bool,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+    },
+    GetProfile {
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+    },
+    ResetProfile {
+        /// Remove the profile applied with `use-profile --global` instead
+        #[arg(long, conflicts_with = "paths")]
+        global: bool,
+        /// Repository to operate on instead of the current directory (repeatable)
+        #[arg(long = "path", value_name = "DIR")]
+        paths: Vec<PathBuf>,
+        /// Reset without asking for confirmation
         #[arg(short, long)]
+        yes: bool,
+        /// Print what would be removed without touching anything
+        #[arg(long, conflicts_with = "global")]
+        dry_run: bool,
+    },
+    /// Create a repository with a profile's new-repo defaults and map it
+    InitRepo {
+        #[arg(short, long, add = ArgValueCompleter::new(complete_repo_profiles))]
+        profile_name: String,
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Clone a repository as a profile and map the clone
+    Clone {
+        #[arg(short, long, add = ArgValueCompleter::new(complete_repo_profiles))]
+        profile_name: String,
+        url: String,
+        /// Defaults to the repository name taken from the url
+        directory: Option<PathBuf>,
+    },
+    /// Move a repository's mapping after the repository was moved or renamed on disk
+    MoveRepo {
+        /// Where the repository used to live
+        #[arg(long, value_name = "PATH")]
+        from: PathBuf,
+        /// Where the repository lives now (defaults to the current directory)
+        #[arg(long, value_name = "PATH")]
+        to: Option<PathBuf>,
+    },
+    /// Inventory the git repositories under a directory and how their identity is set
+    Scan {
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Deprecated spelling of the global `--format json`
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+    },
+    /// Pick a profile for every unmapped repository under a directory
+    Assign {
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Repository and configuration maintenance
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand,
+    },
+    /// Read and change settings.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Open the repository's page on its forge in the browser
+    Open {
+        /// Sign in as the account of the repository's profile first, where the forge supports it
+        #[arg(long)]
+        account: bool,
+        /// Print the url instead of opening it
+        #[arg(long)]
+        print: bool,
+    },
+    /// Print the profile mapped to the current repository, or nothing; fast enough for shell prompts
+    Current,
+    /// Print a colored prompt segment for the current repository's profile
+    #[command(visible_alias = "annotate-shell")]
+    Prompt {
+        /// Shell whose prompt syntax the segment uses; separate from the global --format
+        #[arg(long, value_enum, default_value_t = PromptFormat::Ps1)]
+        shell: PromptFormat,
+    },
+    /// Print the shell script that sets up completion, profile names included
+    Completions {
+        #[arg(value_parser = COMPLETION_SHELLS)]
+        shell: String,
+    },
+    /// Show the name and email git will commit with, where they come from, and the profile octopush expects
+    Whoami,
+    /// Print shell exports of a profile's identity and ssh command, for `eval "$(octopush env)"`
+    Env {
+        /// Resolved from rules.toml (or the existing mapping) when omitted
+        #[arg(short, long, alias = "profile", add = ArgValueCompleter::new(complete_repo_profiles))]
+        profile_name: Option<String>,
+    },
+    /// Run a single command as the given profile, with its identity, ssh key and credentials, without touching repository config
+    Exec {
+        #[arg(short, long, alias = "profile", add = ArgValueCompleter::new(complete_repo_profiles))]
         profile_name: String,
+        /// Force the profile's ssh key through GIT_SSH_COMMAND, even over host aliases
+        #[arg(long)]
+        with_key: bool,
+        /// Also load the key into a temporary ssh-agent that is killed afterwards
+        #[arg(long, requires = "with_key")]
+        agent: bool,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print a setting's value, or nothing when it is unset
+    Get {
+        #[arg(value_parser = Settings::KEYS)]
+        key: String,
+    },
+    /// Change a setting; an empty value restores its default
+    Set {
+        #[arg(value_parser = Settings::KEYS)]
+        key: String,
+        value: String,
+    },
+    /// Print every setting with its current value
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum RepoCommand {
+    /// Remove a configuration lock left behind by a stuck or crashed process
+    Unlock {
+        /// Remove the lock even if the process holding it is still running
+        #[arg(long)]
+        force: bool,
     },
-    GetProfile,
-    ResetProfile,
 }
 
 // NOTE:
@@ -51,8 +536,187 @@ enum Command {
 // validate email in the email field
 // make the ssh_key_path input autocompletable
 
+pub fn is_strict_failure(e: &std::io::Error) -> bool {
+    exit::code(e) == exit::STRICT
+}
+
+// a failed command under a structured `--format`, printed to stderr
+#[derive(Serialize)]
+struct ErrorReport {
+    error: ErrorBody,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: i32,
+    kind: &'static str,
+    message: String,
+    // everything reported along the way, the cause usually among them
+    errors: Vec<String>,
+}
+
+// what commands that change things did, printed to stdout under a
+// structured `--format` in place of their success lines
+#[derive(Serialize)]
+struct OperationsReport {
+    operations: Vec<OperationResult>,
+}
+
 pub fn run(cli: Cli) -> Result<(), std::io::Error> {
-    let runner = Runner::new();
+    if let Some(dir) = &cli.config_dir {
+        let dir = std::path::absolute(dir)?;
+        // SAFETY: set before anything else runs, while the process is
+        // still single-threaded
+        unsafe { std::env::set_var(app::CONFIG_HOME_ENV, dir) };
+    }
+
+    // `current` runs on every prompt: no terminal detection, settings or
+    // spinner, only the mappings file is read
+    if let Command::Current = cli.command {
+        let dir = match cli.chdir {
+            Some(dir) => dir,
+            None => std::env::current_dir()?,
+        };
+        if let Some(profile_name) = App::current_profile_name(&dir) {
+            println!("{}", profile_name);
+        }
+        return Ok(());
+    }
+    if let Command::Completions { shell } = &cli.command {
+        return write_completions(shell);
+    }
+
+    // a settings.toml that does not parse is reported by the command itself
+    let settings = App::settings().unwrap_or_default();
+    let mut caps = TerminalCaps::detect();
+    if cli.no_color || settings.no_color {
+        caps = caps.without_color();
+    }
+    if settings.disable_emoji {
+        caps = caps.without_emoji();
+    }
+    caps.apply_color();
+    init_logging(cli.verbose);
+    git::set_trace(cli.trace_git);
+    let runner = Runner::with_caps(caps)
+        .quiet(cli.quiet)
+        .no_input(
+            cli.no_input
+                || std::env::var("OCTOPUSH_NONINTERACTIVE")
+                    .is_ok_and(|v| !v.is_empty() && v != "0" && v != "false"),
+        )
+        .structured(cli.format.is_structured())
+        .min_spinner_duration(std::time::Duration::from_millis(settings.spinner_min_ms));
+    let result_fd = cli.result_fd;
+    let format = cli.format;
+
+    // a missing or outdated git fails here, saying what to install, rather
+    // than as a spawn error in whatever runs it first; doctor reports it
+    // among its checks instead
+    let result = match cli.command {
+        Command::Doctor { .. } | Command::ConfigDump => Ok(()),
+        _ => git::require().map(|_| ()),
+    };
+    // errors the Runner reported, without the command returning one, still
+    // fail it
+    let result = result.and_then(|()| dispatch(cli, &runner)).and_then(|()| {
+        match runner.errors().into_iter().next() {
+            Some(reported) => Err(std::io::Error::other(reported)),
+            None => Ok(()),
+        }
+    });
+    let operations = runner.results();
+    if format.is_structured() && !operations.is_empty() {
+        runner.message(format.render(&OperationsReport { operations })?.trim_end());
+    }
+    if let Some(fd) = result_fd {
+        write_result(fd, &runner, &result)?;
+    }
+
+    match result {
+        Err(e) if format.is_structured() => {
+            let code = exit::code(&e);
+            let report = ErrorReport {
+                error: ErrorBody {
+                    code,
+                    kind: exit::kind(code),
+                    message: e.to_string(),
+                    errors: runner.errors().into_iter().map(|r| r.message).collect(),
+                },
+            };
+            eprintln!("{}", format.render(&report)?.trim_end());
+            Err(std::io::Error::other(exit::Reported {
+                code,
+                message: e.to_string(),
+            }))
+        }
+        result => result,
+    }
+}
+
+// The scripting contract: one line of shell-evaluable assignments, e.g.
+// `OCTOPUSH_RESULT=error OCTOPUSH_ERRORS=1 OCTOPUSH_WARNINGS=0`. The result
+// is `error` when anything failed (strict mode included), `warning` when
+// something was only warned about, and `ok` otherwise.
+fn write_result(
+    fd: i32,
+    runner: &Runner,
+    result: &Result<(), std::io::Error>,
+) -> Result<(), std::io::Error> {
+    let outcome = if result.is_err() || runner.error_count() > 0 {
+        "error"
+    } else if runner.warning_count() > 0 {
+        "warning"
+    } else {
+        "ok"
+    };
+    let line = format!(
+        "OCTOPUSH_RESULT={} OCTOPUSH_ERRORS={} OCTOPUSH_WARNINGS={}\n",
+        outcome,
+        runner.error_count(),
+        runner.warning_count()
+    );
+
+    write_to_fd(fd, line.as_bytes())
+}
+
+#[cfg(unix)]
+fn write_to_fd(fd: i32, bytes: &[u8]) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    // reopening through /dev/fd leaves the caller's descriptor untouched
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(format!("/dev/fd/{}", fd))?;
+    file.write_all(bytes)
+}
+
+#[cfg(not(unix))]
+fn write_to_fd(_fd: i32, _bytes: &[u8]) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--result-fd is only supported on unix",
+    ))
+}
+
+fn dispatch(cli: Cli, runner: &Runner) -> Result<(), std::io::Error> {
+    let settings = App::settings().unwrap_or_default();
+    let strict = cli.strict || settings.strict;
+    let assume_yes = settings.assume_yes;
+    let format = cli.format;
+
+    // every repo-scoped command resolves its repository from the working
+    // directory, so switching it here covers all of them at once
+    if let Some(dir) = &cli.chdir
+        && let Err(e) = std::env::set_current_dir(dir)
+    {
+        runner.error(&format!(
+            "Failed to change directory to '{}': {}",
+            dir.display(),
+            e
+        ));
+        return Ok(());
+    }
 
     match cli.command {
         Command::AddProfile {
@@ -62,35 +726,89 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
             auth_type,
             hostname,
             ssh_key_path,
+            tags,
+            from_repo,
+            map,
+            ssh_host_alias,
+            ssh_identity_agent,
+            default_branch,
+            template_dir,
+            gh_path,
+            credential_helper,
+            credential_cache_timeout,
+            ssl_ca_info,
+            ssl_verify,
+            http_proxy,
+            signing_key,
+            sign,
+            gpg_program,
+            url_rewrites,
         } => {
+            let adopted = if from_repo {
+                match App::profile_from_repo(cwd()?) {
+                    Ok(adopted) => Some(adopted),
+                    Err(e) => {
+                        runner.error(&format!(
+                            "Failed to read the identity of the current repository: {}",
+                            e
+                        ));
+                        return Ok(());
+                    }
+                }
+            } else {
+                None
+            };
+
             let profile_name = match profile_name {
                 Some(profile_name) => profile_name.clone(),
-                None => Input::new()
-                    .with_prompt("Enter a profile name for the new profile")
-                    .interact_text()
-                    .unwrap(),
+                None => {
+                    require_prompt(runner, "--profile-name")?;
+                    Input::new()
+                        .with_prompt("Enter a profile name for the new profile")
+                        .interact_text()
+                        .unwrap()
+                }
+            };
+
+            // values from the repository fill in anything not given as a flag
+            let name = name.or_else(|| adopted.as_ref().map(|p| p.name.clone()));
+            let email = email.or_else(|| adopted.as_ref().map(|p| p.email.clone()));
+            let auth_type = auth_type.or_else(|| adopted.as_ref().map(|p| p.auth_type));
+            let (hostname, ssh_key_path) = match &adopted {
+                Some(adopted) if auth_type == Some(adopted.auth_type) => (
+                    hostname.or_else(|| adopted.hostname.clone()),
+                    ssh_key_path.or_else(|| adopted.ssh_key_path.clone()),
+                ),
+                _ => (hostname, ssh_key_path),
             };
 
             let name = match name {
                 Some(name) => name.clone(),
-                None => Input::new()
-                    .with_prompt("Enter a name for the new profile")
-                    .interact_text()
-                    .unwrap(),
+                None => {
+                    require_prompt(runner, "--name")?;
+                    Input::new()
+                        .with_prompt("Enter a name for the new profile")
+                        .interact_text()
+                        .unwrap()
+                }
             };
 
             let email = match email {
                 Some(email) => email.clone(),
-                None => Input::new()
-                    .with_prompt("Enter an email for the new profile")
-                    .interact_text()
-                    .unwrap(),
+                None => {
+                    require_prompt(runner, "--email")?;
+                    Input::new()
+                        .with_prompt("Enter an email for the new profile")
+                        .interact_text()
+                        .unwrap()
+                }
             };
 
             let auth_type = match auth_type {
                 Some(auth_type) => auth_type,
                 None => {
-                    let auth_items = ["none", "ssh", "gh"];
+                    require_prompt(runner, "--auth-type")?;
+                    let auth_items = ["none", "ssh", "gh", "gitlab", "token"];
                     let auth_type = Select::new()
                         .with_prompt("Select the authentication type...")
                         .items(auth_items)
@@ -108,7 +826,8 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
             match auth_type {
                 AuthType::None => {}
                 AuthType::SSH => {
-                    if ssh_key_path.is_none() {
+                    // the key is optional, so there is nothing to fail on
+                    if ssh_key_path.is_none() && runner.can_prompt() {
                         let input = dialoguer_path_input("Enter the path of your ssh key: ");
                         ssh_key_path = if input.trim().is_empty() {
                             None
@@ -117,23 +836,57 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
                         };
                     }
                 }
-                AuthType::GH => {
+                AuthType::GH | AuthType::GitLab | AuthType::Token => {
                     if hostname.is_none() {
-                        hostname = Some(
-                            Input::new()
-                                .with_prompt("Enter the hostname of authenticated account")
-                                .interact_text()
-                                .unwrap(),
-                        );
+                        let default = auth_type.default_hostname();
+                        // without prompts the default host is taken as is
+                        hostname = match default {
+                            Some(default) if !runner.can_prompt() => Some(default.to_string()),
+                            _ => {
+                                require_prompt(runner, "--hostname")?;
+                                let mut input = Input::<String>::new()
+                                    .with_prompt("Enter the hostname of authenticated account");
+                                if let Some(default) = default {
+                                    input = input.default(default.to_string());
+                                }
+                                Some(input.interact_text().unwrap())
+                            }
+                        };
                     }
                 }
             }
 
-            let profile = Profile::build(name, email, auth_type, hostname, ssh_key_path);
+            let signing_key = if sign {
+                require_prompt(runner, "--signing-key")?;
+                pick_signing_key(gpg_program.as_deref().unwrap_or("gpg"))
+            } else {
+                signing_key
+            };
+
+            let profile = Profile::builder(name, email, auth_type)
+                .hostname(hostname)
+                .ssh_key_path(ssh_key_path)
+                .tags(tags)
+                .default_branch(default_branch)
+                .template_dir(template_dir)
+                .gh_path(gh_path)
+                .credential_helper(credential_helper)
+                .credential_cache_timeout(credential_cache_timeout)
+                .ssl_ca_info(ssl_ca_info)
+                .ssl_verify(ssl_verify)
+                .http_proxy(http_proxy)
+                .signing_key(signing_key)
+                .gpg_program(gpg_program)
+                .url_rewrites(url_rewrites.into_iter().collect())
+                .ssh_host_alias(ssh_host_alias)
+                .ssh_identity_agent(ssh_identity_agent);
 
-            let _ = runner.run(
+            let added = runner.run(
                 || {
-                    App::add_profile(profile_name.clone(), profile)?;
+                    App::add_profile(profile_name.clone(), profile.build()?)?;
+                    if map {
+                        App::use_profile(profile_name.clone(), cwd()?)?;
+                    }
 
                     Ok(())
                 },
@@ -142,9 +895,218 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
                 },
             );
 
+            if added.is_ok() && auth_type == AuthType::Token {
+                if runner.can_prompt() {
+                    set_token_cmd(runner, profile_name)?;
+                } else {
+                    runner.message(&format!(
+                        "Store the access token with `octopush set-token {}`",
+                        profile_name
+                    ));
+                }
+            }
+
+            Ok(())
+        }
+        Command::SetToken { profile_name } => set_token_cmd(runner, profile_name),
+        Command::Credential { operation } => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+
+            // anything but the answer itself would corrupt git's protocol,
+            // so failures just leave git to its next helper
+            if let Ok(Some(answer)) = App::credential(&operation, &input, cwd()?) {
+                std::io::stdout().write_all(answer.as_bytes())?;
+            }
+
+            Ok(())
+        }
+        Command::AddProfiles { file } => {
+            let summary = runner.run(
+                || Ok(App::add_profiles_from_file(&file)?),
+                OperationType::AddProfiles {
+                    path: file.to_string_lossy().into_owned(),
+                },
+            );
+
+            if let Ok(summary) = summary {
+                runner.message(&format!(
+                    "Created {} profile(s), skipped {}.",
+                    summary.created.len(),
+                    summary.skipped.len()
+                ));
+                for profile_name in &summary.created {
+                    runner.message(&format!("  {} {}", "created".green(), profile_name));
+                }
+                for (profile_name, reason) in &summary.skipped {
+                    runner.message(&format!(
+                        "  {} {} ({})",
+                        "skipped".yellow(),
+                        profile_name,
+                        reason
+                    ));
+                }
+            }
+
+            Ok(())
+        }
+        Command::EditProfile {
+            profile_name,
+            name,
+            email,
+            auth_type,
+            hostname,
+            ssh_key_path,
+            tags,
+            ssh_host_alias,
+            ssh_identity_agent,
+            default_branch,
+            template_dir,
+            gh_path,
+            credential_helper,
+            credential_cache_timeout,
+            ssl_ca_info,
+            ssl_verify,
+            http_proxy,
+            signing_key,
+            gpg_program,
+            url_rewrites,
+        } => {
+            let current = match App::get_profile(profile_name.clone()) {
+                Ok(current) => current,
+                Err(e) => {
+                    runner.error(&format!(
+                        "Failed to update profile '{}': {}",
+                        profile_name, e
+                    ));
+                    return Ok(());
+                }
+            };
+
+            let has_flags = name.is_some()
+                || email.is_some()
+                || auth_type.is_some()
+                || hostname.is_some()
+                || ssh_key_path.is_some()
+                || !tags.is_empty()
+                || ssh_host_alias.is_some()
+                || ssh_identity_agent.is_some()
+                || default_branch.is_some()
+                || template_dir.is_some()
+                || gh_path.is_some()
+                || credential_helper.is_some()
+                || credential_cache_timeout.is_some()
+                || ssl_ca_info.is_some()
+                || ssl_verify.is_some()
+                || http_proxy.is_some()
+                || signing_key.is_some()
+                || gpg_program.is_some()
+                || !url_rewrites.is_empty();
+
+            let profile = if has_flags {
+                let auth_type = auth_type.unwrap_or(current.auth_type);
+                // carry over the stored auth fields only while the auth type is unchanged
+                let (current_hostname, current_ssh_key_path) = if auth_type == current.auth_type {
+                    (current.hostname.clone(), current.ssh_key_path.clone())
+                } else {
+                    (None, None)
+                };
+                let (hostname, ssh_key_path) = auth_fields(
+                    auth_type,
+                    hostname.or(current_hostname),
+                    ssh_key_path.or(current_ssh_key_path),
+                );
+
+                let mut profile = current.clone();
+                profile.name = name.unwrap_or(current.name);
+                profile.email = email.unwrap_or(current.email);
+                profile.auth_type = auth_type;
+                profile.hostname = hostname;
+                profile.ssh_key_path = ssh_key_path;
+                if !tags.is_empty() {
+                    profile.tags = tags;
+                }
+                if let Some(ssh_host_alias) = ssh_host_alias {
+                    profile.ssh_host_alias = ssh_host_alias;
+                }
+                if let Some(agent) = ssh_identity_agent {
+                    profile.ssh_identity_agent = Some(agent).filter(|agent| !agent.is_empty());
+                } else if auth_type != AuthType::SSH {
+                    // the agent only serves ssh keys
+                    profile.ssh_identity_agent = None;
+                }
+                if default_branch.is_some() {
+                    profile.default_branch = default_branch;
+                }
+                if template_dir.is_some() {
+                    profile.template_dir = template_dir;
+                }
+                if gh_path.is_some() {
+                    profile.gh_path = gh_path;
+                }
+                if let Some(ssl_ca_info) = ssl_ca_info {
+                    profile.ssl_ca_info = Some(ssl_ca_info).filter(|path| !path.is_empty());
+                }
+                if ssl_verify.is_some() {
+                    profile.ssl_verify = ssl_verify;
+                }
+                if let Some(http_proxy) = http_proxy {
+                    profile.http_proxy = Some(http_proxy).filter(|proxy| !proxy.is_empty());
+                }
+                if let Some(signing_key) = signing_key {
+                    profile.signing_key = Some(signing_key).filter(|key| !key.is_empty());
+                }
+                if let Some(gpg_program) = gpg_program {
+                    profile.gpg_program = Some(gpg_program).filter(|program| !program.is_empty());
+                }
+                if !url_rewrites.is_empty() {
+                    profile.url_rewrites = url_rewrites.into_iter().collect();
+                }
+                if let Some(helper) = credential_helper {
+                    profile.credential_helper = Some(helper).filter(|helper| !helper.is_empty());
+                } else if auth_type == AuthType::SSH {
+                    // ssh profiles authenticate with their key alone
+                    profile.credential_helper = None;
+                }
+                if let Some(timeout) = credential_cache_timeout {
+                    profile.credential_cache_timeout = Some(timeout).filter(|timeout| *timeout > 0);
+                } else if profile.credential_cache_lifetime().is_none() {
+                    // only the cache helper has a timeout
+                    profile.credential_cache_timeout = None;
+                }
+                profile
+            } else {
+                require_prompt(runner, "one of the edit-profile flags")?;
+                edit_profile_wizard(&current)
+            };
+
+            let _ = runner.run(
+                || {
+                    App::update_profile(profile_name.clone(), profile)?;
+
+                    Ok(())
+                },
+                OperationType::EditProfile {
+                    profile_name: profile_name.clone(),
+                },
+            );
+
             Ok(())
         }
-        Command::DeleteProfile { profile_name } => {
+        Command::DeleteProfile { profile_name, yes } => {
+            // a missing profile fails below without a pointless question
+            if App::get_profile(profile_name.clone()).is_ok() {
+                let prompt = format!(
+                    "Delete profile '{}'?{}",
+                    profile_name,
+                    still_mapped(&profile_name)
+                );
+                if !confirm(runner, &prompt, yes || assume_yes)? {
+                    runner.message("Aborted.");
+                    return Ok(());
+                }
+            }
+
             let _ = runner.run(
                 || {
                     App::delete_profile(profile_name.clone())?;
@@ -158,10 +1120,81 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
 
             Ok(())
         }
-        Command::ListProfiles => {
+        Command::MergeProfiles { into, from, force } => {
+            let summary = runner.run(
+                || Ok(App::merge_profiles(into.clone(), from.clone(), force)?),
+                OperationType::MergeProfiles {
+                    into: into.clone(),
+                    from: from.clone(),
+                },
+            );
+
+            if let Ok(summary) = summary {
+                runner.message(&format!(
+                    "Repointed {} mapping(s) and {} rule(s) to '{}'.",
+                    summary.mappings, summary.rules, into
+                ));
+                for repo in &summary.reapplied {
+                    runner.message(&format!("Re-applied '{}' to {}", into, repo));
+                }
+                for (repo, reason) in &summary.skipped {
+                    runner.warning(&format!(
+                        "Could not re-apply '{}' to {}: {}",
+                        into, repo, reason
+                    ));
+                }
+            }
+
+            Ok(())
+        }
+        Command::ArchiveProfile {
+            profile_name,
+            unarchive,
+        } => {
+            let _ = runner.run(
+                || {
+                    App::archive_profile(profile_name.clone(), !unarchive)?;
+
+                    Ok(())
+                },
+                OperationType::ArchiveProfile {
+                    profile_name: profile_name.clone(),
+                    archived: !unarchive,
+                },
+            );
+
+            Ok(())
+        }
+        Command::ListProfiles { all, long } if format.is_structured() => {
+            match App::list_profiles() {
+                Ok(profiles) => {
+                    let usage = if long {
+                        App::list_usage().unwrap_or_default()
+                    } else {
+                        Default::default()
+                    };
+                    let listed: BTreeMap<String, ListedProfile> = profiles
+                        .into_iter()
+                        .filter(|(_, profile)| all || !profile.archived)
+                        .map(|(profile_name, profile)| {
+                            let usage = usage.get(&profile_name).cloned();
+                            (profile_name, ListedProfile { profile, usage })
+                        })
+                        .collect();
+                    runner.message(format.render(&listed)?.trim_end());
+                }
+                Err(e) => runner.error(&format!("Failed to fetch profiles: {}", e)),
+            }
+
+            Ok(())
+        }
+        Command::ListProfiles { all, long } => {
             let profiles = runner.run(
                 || {
-                    let profiles = App::list_profiles()?;
+                    let mut profiles = App::list_profiles()?;
+                    if !all {
+                        profiles.retain(|_, p| !p.archived);
+                    }
 
                     Ok(profiles)
                 },
@@ -174,108 +1207,2048 @@ pub fn run(cli: Cli) -> Result<(), std::io::Error> {
                     return Ok(());
                 }
 
-                let headers = ["Profiles", "Name", "Email", "Auth", "Host", "SSH Key"];
-                let mut rows: Vec<[String; 6]> = profiles
-                    .iter()
-                    .map(|(profile_name, p)| {
-                        [
-                            profile_name.to_string(),
-                            p.name.clone(),
-                            p.email.clone(),
-                            String::from(p.auth_type),
-                            p.hostname.clone().unwrap_or_else(|| "-".into()),
-                            p.ssh_key_path.clone().unwrap_or_else(|| "-".into()),
-                        ]
-                    })
-                    .collect();
+                let mut profiles: Vec<(String, Profile)> = profiles.into_iter().collect();
+                profiles.sort_by(|a, b| a.0.cmp(&b.0));
 
-                rows.sort_by(|a, b| a[0].cmp(&b[0]));
+                print_profile_table(runner, &profiles, long);
+            }
 
-                let mut widths = [0usize; 6];
-                for i in 0..6 {
-                    widths[i] = headers[i]
-                        .len()
-                        .max(rows.iter().map(|r| r[i].len()).max().unwrap_or(0));
+            Ok(())
+        }
+        Command::ListProjects => {
+            if format.is_structured() {
+                match App::list_projects() {
+                    Ok(projects) => runner.message(format.render(&projects)?.trim_end()),
+                    Err(e) => runner.error(&format!("Failed to fetch mapped repositories: {}", e)),
                 }
+                return Ok(());
+            }
 
-                let sep = " ";
-                let header_line = headers
-                    .iter()
-                    .enumerate()
-                    .map(|(i, h)| format!("{:<width$}", h.bold().cyan(), width = widths[i]))
-                    .collect::<Vec<_>>()
-                    .join(sep);
-                runner.message(&header_line);
-
-                let underline = widths
-                    .iter()
-                    .map(|w| "-".repeat(*w))
-                    .collect::<Vec<_>>()
-                    .join(sep);
-                runner.message(&underline);
+            if let Ok(projects) =
+                runner.run(|| Ok(App::list_projects()?), OperationType::ListProjects)
+            {
+                if projects.is_empty() {
+                    runner.message("No repositories are mapped to a profile.");
+                    return Ok(());
+                }
 
-                for row in rows {
-                    let line = (0..6)
-                        .map(|i| format!("{:<width$}", row[i], width = widths[i]))
-                        .collect::<Vec<_>>()
-                        .join(sep);
-                    runner.message(&line);
+                let width = projects.keys().map(|key| key.len()).max().unwrap_or(0);
+                for (repo_key, profile_name) in &projects {
+                    runner.message(&format!("{:<width$}  {}", repo_key, profile_name));
                 }
             }
 
             Ok(())
         }
-        Command::UseProfile { profile_name } => {
-            let cwd = cwd()?;
+        Command::DiffProfiles {
+            left,
+            right,
+            output,
+        } => {
+            let format = output_format(format, output)?;
+            if format.is_structured() {
+                match App::diff_profiles(left.clone(), right.clone()) {
+                    Ok(diff) => runner.message(format.render(&diff)?.trim_end()),
+                    Err(e) => runner.error(&format!(
+                        "Failed to compare profiles '{}' and '{}': {}",
+                        left, right, e
+                    )),
+                }
 
-            let _ = runner.run(
-                || {
-                    App::use_profile(profile_name.clone(), cwd)?;
+                return Ok(());
+            }
 
-                    Ok(())
-                },
-                OperationType::UseProfile {
-                    profile_name: profile_name.clone(),
+            let diff = runner.run(
+                || Ok(App::diff_profiles(left.clone(), right.clone())?),
+                OperationType::DiffProfiles {
+                    left: left.clone(),
+                    right: right.clone(),
                 },
             );
 
+            if let Ok(diff) = diff {
+                print_diff(runner, &diff);
+            }
+
             Ok(())
         }
-        Command::GetProfile => {
-            let cwd = cwd()?;
-
-            let _ = runner.run(
+        Command::Search { query, all } => {
+            let profiles = runner.run(
                 || {
-                    let (profile_name, _profile, repo_name) = App::get_project_profile(cwd)?;
-
-                    runner.message(
-                        format!(
-                            "The repository '{}' is associated with profile {}.",
-                            repo_name, profile_name,
-                        )
-                        .as_str(),
-                    );
+                    let mut profiles = App::search_profiles(&query)?;
+                    if !all {
+                        profiles.retain(|(_, p)| !p.archived);
+                    }
 
-                    Ok(())
+                    Ok(profiles)
+                },
+                OperationType::SearchProfiles {
+                    query: query.clone(),
                 },
-                OperationType::GetProfile,
             );
 
+            if let Ok(profiles) = profiles {
+                if profiles.is_empty() {
+                    runner.message(&format!("No profiles match '{}'.", query));
+                    return Ok(());
+                }
+
+                print_profile_table(runner, &profiles, false);
+            }
+
             Ok(())
         }
-        Command::ResetProfile => {
-            let cwd = cwd()?;
+        Command::UseProfile {
+            profile_name,
+            dry_run,
+            output,
+            remote,
+            all_remotes,
+            login,
+            global,
+            execute_plan,
+            paths,
+            yes,
+            explain,
+        } => {
+            if let Some(plan_path) = execute_plan {
+                let plan_path = plan_path.to_string_lossy().into_owned();
 
-            let _ = runner.run(
-                || {
-                    App::reset_profile_for_project(cwd)?;
+                let _ = runner.run_steps(
+                    |steps| {
+                        let raw_plan = fs::read_to_string(&plan_path)?;
+                        let plan: ApplyPlan = serde_json::from_str(&raw_plan)?;
 
-                    Ok(())
-                },
-                OperationType::ResetProfile,
-            );
+                        App::execute_plan(&plan)?;
+                        steps.changed(plan.changed());
+
+                        Ok(())
+                    },
+                    OperationType::ExecutePlan {
+                        plan_path: plan_path.clone(),
+                    },
+                );
+
+                return Ok(());
+            }
+
+            if global && let Some(profile_name) = profile_name {
+                let _ = runner.run(
+                    || Ok(App::use_profile_globally(profile_name.clone())?),
+                    OperationType::UseGlobalProfile {
+                        profile_name: profile_name.clone(),
+                    },
+                );
+
+                return Ok(());
+            }
+
+            let options = UseOptions {
+                dry_run,
+                output: output_format(format, output)?,
+                remotes: remotes(remote, all_remotes),
+                login,
+                strict,
+                confirm: !(yes || assume_yes),
+                safe_directory: settings.safe_directory,
+                explain,
+            };
+            let repos = repo_paths(paths)?;
+            for repo in &repos {
+                repo_header(runner, repo, repos.len());
+                use_profile_cmd(runner, profile_name.clone(), repo.clone(), &options)?;
+            }
 
             Ok(())
         }
+        Command::Apply {
+            dry_run,
+            output,
+            remote,
+            all_remotes,
+            login,
+            paths,
+        } => {
+            let options = UseOptions {
+                dry_run,
+                output: output_format(format, output)?,
+                remotes: remotes(remote, all_remotes),
+                login,
+                strict,
+                confirm: false,
+                safe_directory: settings.safe_directory,
+                explain: false,
+            };
+            let repos = repo_paths(paths)?;
+            for repo in &repos {
+                repo_header(runner, repo, repos.len());
+                use_profile_cmd(runner, None, repo.clone(), &options)?;
+            }
+
+            Ok(())
+        }
+        Command::Sync {
+            all,
+            dry_run,
+            paths,
+        } => {
+            if dry_run {
+                let repos = if all {
+                    App::mapped_repo_paths()?
+                } else {
+                    repo_paths(paths)?
+                };
+                for repo in repos {
+                    match App::plan_sync(repo.clone()) {
+                        Ok(plan) => print_plan(runner, &plan, Format::Human, false)?,
+                        Err(e) => runner.warning(&format!("Skipped '{}': {}", repo, e)),
+                    }
+                }
+
+                return Ok(());
+            }
+
+            if all {
+                let summary = runner.run_with_progress(
+                    "Syncing",
+                    |progress| {
+                        let mut item = None;
+                        Ok(App::sync_all_with(&mut |event| match event {
+                            SyncProgress::Repo { repo, total } => {
+                                progress.set_length(total as u64);
+                                item = Some(progress.item(repo));
+                            }
+                            SyncProgress::Stage(stage) => {
+                                if let Some(item) = &item {
+                                    item.step(stage.label());
+                                }
+                            }
+                            SyncProgress::Done {
+                                repo,
+                                skipped,
+                                changed,
+                            } => {
+                                if let Some(item) = item.take() {
+                                    item.changed(
+                                        changed
+                                            .into_iter()
+                                            .map(|target| format!("{}: {}", repo, target)),
+                                    );
+                                    progress.finish_item(item, skipped);
+                                }
+                            }
+                        })?)
+                    },
+                    OperationType::SyncAll,
+                );
+
+                if let Ok(summary) = summary {
+                    runner.message(&format!(
+                        "Synced {} of {} mapped repositories",
+                        summary.synced.len(),
+                        summary.synced.len() + summary.skipped.len()
+                    ));
+                    for (repo, reason) in summary.skipped {
+                        runner.warning(&format!("Skipped '{}': {}", repo, reason));
+                    }
+                }
+
+                return Ok(());
+            }
+
+            for repo in repo_paths(paths)? {
+                let _ = runner.run_steps(
+                    |steps| {
+                        let plan = App::sync_repo_in_stages(repo.clone(), &mut |stage| {
+                            steps.step(stage.label())
+                        })?;
+                        steps.changed(plan.changed());
+
+                        Ok(())
+                    },
+                    OperationType::Sync { repo: repo.clone() },
+                );
+            }
+
+            Ok(())
+        }
+        Command::ExportGitconfig => {
+            let summary = runner.run(
+                || Ok(App::export_gitconfig()?),
+                OperationType::ExportGitconfig,
+            );
+
+            if let Ok(summary) = summary {
+                runner.message(&format!(
+                    "Wrote {} profile snippet(s) and {} includeIf section(s) to '{}'.",
+                    summary.profiles,
+                    summary.sections,
+                    summary.includes_path.display()
+                ));
+                for (source, reason) in summary.skipped {
+                    runner.warning(&format!("Skipped '{}': {}", source, reason));
+                }
+                if App::settings().is_ok_and(|settings| settings.encrypt_profiles) {
+                    runner.warning(
+                        "encrypt_profiles only covers profiles.toml; git reads these snippets itself, so their names, emails and key paths are in plaintext",
+                    );
+                }
+                runner.message(&format!(
+                    "Include it from your global config once with:\n  git config --global include.path '{}'",
+                    summary.includes_path.display()
+                ));
+            }
+
+            Ok(())
+        }
+        Command::InstallHook { force, paths } => {
+            for repo in repo_paths(paths)? {
+                let _ = runner.run(
+                    || Ok(App::install_hook(repo.clone(), force)?),
+                    OperationType::InstallHook { repo: repo.clone() },
+                );
+            }
+
+            Ok(())
+        }
+        Command::SetupAutoApply { hooks_path, force } => {
+            let setup = runner.run(
+                || Ok(App::setup_auto_apply(hooks_path, force)?),
+                OperationType::SetupAutoApply,
+            );
+
+            if let Ok(setup) = setup {
+                runner.message(&format!("Hook written to '{}'", setup.hook.display()));
+                if setup.configured {
+                    runner.message(&format!("Set global {} so git picks it up", setup.key));
+                } else {
+                    runner.message(&format!(
+                        "Kept your existing global {}, the hook was added to it",
+                        setup.key
+                    ));
+                }
+            }
+
+            Ok(())
+        }
+        Command::AutoApply => {
+            // never fail the clone, only report what happened
+            match App::auto_apply(cwd()?) {
+                Ok(Some(profile_name)) => runner.message(&format!(
+                    "octopush: applied profile '{}' to the new clone",
+                    profile_name
+                )),
+                Ok(None) => {}
+                Err(e) => runner.warning(&format!(
+                    "octopush: could not apply a profile to the new clone: {}",
+                    e
+                )),
+            }
+
+            Ok(())
+        }
+        Command::CheckIdentity => {
+            if let Err(e) = App::check_identity(cwd()?) {
+                runner.error(&format!("octopush refused the commit: {}", e));
+                return Err(std::io::Error::other(exit::ChecksFailedError(1)));
+            }
+
+            Ok(())
+        }
+        Command::ConfigDump => {
+            let dump = match App::config_dump() {
+                Ok(dump) => dump,
+                Err(e) => {
+                    runner.error(&format!("Failed to locate the configuration: {}", e));
+                    return Ok(());
+                }
+            };
+            // toml reads best for humans and is what the files hold
+            let format = match format {
+                Format::Human => Format::Toml,
+                format => format,
+            };
+            runner.message(format.render(&dump)?.trim_end());
+
+            Ok(())
+        }
+        Command::Doctor { output } => {
+            let format = output_format(format, output)?;
+            let checks = App::doctor();
+
+            if format.is_structured() {
+                runner.message(render_list(format, "checks", &checks)?.trim_end());
+            } else {
+                for check in &checks {
+                    let line = check.to_string();
+                    match check.status {
+                        CheckStatus::Pass => runner.message(&format!("{} {}", "✓".green(), line)),
+                        CheckStatus::Warn => {
+                            runner.message(&format!("{} {}", "!".yellow(), line.yellow()))
+                        }
+                        CheckStatus::Fail => {
+                            runner.message(&format!("{} {}", "✗".red(), line.red()))
+                        }
+                    }
+                }
+            }
+
+            let failed = checks
+                .iter()
+                .filter(|check| check.status == CheckStatus::Fail)
+                .count();
+            if failed > 0 {
+                return Err(std::io::Error::other(exit::ChecksFailedError(failed)));
+            }
+
+            Ok(())
+        }
+        Command::TestConnection {
+            profile_name,
+            hostname,
+        } => {
+            let project_path = cwd()?;
+            let report = match runner.run(
+                || Ok(App::test_connection(profile_name, hostname, project_path)?),
+                OperationType::TestConnection,
+            ) {
+                Ok(report) => report,
+                Err(_) => return Err(std::io::Error::other(exit::ChecksFailedError(1))),
+            };
+
+            match &report.account {
+                Some(account) => runner.message(&format!(
+                    "{} knows profile '{}' as '{}'",
+                    report.host,
+                    report.profile_name,
+                    account.bold()
+                )),
+                None => runner.message(&format!(
+                    "{} accepted profile '{}' without naming the account",
+                    report.host, report.profile_name
+                )),
+            }
+
+            Ok(())
+        }
+        Command::Status { fix, paths, .. } if format.is_structured() => {
+            let mut reports = Vec::new();
+            let mut warnings = Vec::new();
+            for repo in repo_paths(paths)? {
+                match status_report(repo.clone(), fix) {
+                    Ok((report, found)) => {
+                        reports.push(report);
+                        warnings.extend(found);
+                    }
+                    Err(e) => {
+                        runner.error(&format!("Failed to read the status of '{}': {}", repo, e))
+                    }
+                }
+            }
+
+            runner.message(format.render(&RepoReports { repos: reports })?.trim_end());
+            report_warnings(runner, &warnings, strict, false)
+        }
+        Command::Status {
+            fix,
+            explain,
+            paths,
+        } => {
+            let repos = repo_paths(paths)?;
+            for repo in &repos {
+                repo_header(runner, repo, repos.len());
+                status_cmd(runner, repo.clone(), fix, explain, strict)?;
+            }
+
+            Ok(())
+        }
+        Command::GetProfile { paths } if format.is_structured() => {
+            let mut reports = Vec::new();
+            let mut warnings = Vec::new();
+            for repo in repo_paths(paths)? {
+                match profile_report(repo.clone()) {
+                    Ok((report, found)) => {
+                        reports.push(report);
+                        warnings.extend(found);
+                    }
+                    Err(e) => {
+                        runner.error(&format!("Failed to read the profile of '{}': {}", repo, e))
+                    }
+                }
+            }
+
+            runner.message(format.render(&RepoReports { repos: reports })?.trim_end());
+            report_warnings(runner, &warnings, strict, false)
+        }
+        Command::GetProfile { paths } => {
+            let repos = repo_paths(paths)?;
+            for repo in &repos {
+                repo_header(runner, repo, repos.len());
+                get_profile_cmd(runner, repo.clone(), strict)?;
+            }
+
+            Ok(())
+        }
+        Command::ResetProfile {
+            global,
+            paths,
+            yes,
+            dry_run,
+        } => {
+            if dry_run {
+                for repo in repo_paths(paths)? {
+                    match App::plan_reset(repo.clone()) {
+                        Ok(operations) if operations.is_empty() => {
+                            runner.message(&format!("Nothing to reset in '{}'.", repo))
+                        }
+                        Ok(operations) => {
+                            runner.message(&format!("Planned changes for '{}' (reset):", repo));
+                            for operation in &operations {
+                                runner.message(&format!("  {}", operation));
+                            }
+                        }
+                        Err(e) => runner.error(&format!("Failed to plan the reset: {}", e)),
+                    }
+                }
+
+                return Ok(());
+            }
+
+            if global {
+                if !confirm(
+                    runner,
+                    "Remove the profile applied to the global git config?",
+                    yes,
+                )? {
+                    runner.message("Aborted.");
+                    return Ok(());
+                }
+                match App::reset_global_profile() {
+                    Ok(true) => runner.success("Global profile removed from the global git config"),
+                    Ok(false) => runner.message("No profile is applied globally"),
+                    Err(e) => runner.error(&format!("Failed to remove the global profile: {}", e)),
+                }
+
+                return Ok(());
+            }
+
+            let repos = repo_paths(paths)?;
+            let prompt = match repos.as_slice() {
+                [repo] => match App::get_project_profile(repo.clone()) {
+                    Ok((profile_name, _, _)) => format!(
+                        "Reset profile '{}' in '{}'?{}",
+                        profile_name,
+                        repo,
+                        still_mapped(&profile_name)
+                    ),
+                    Err(_) => format!("Reset '{}'?", repo),
+                },
+                repos => format!("Reset {} repositories?", repos.len()),
+            };
+            if !confirm(runner, &prompt, yes || assume_yes)? {
+                runner.message("Aborted.");
+                return Ok(());
+            }
+
+            for repo in repos {
+                let _ = runner.run(
+                    || {
+                        App::reset_profile_for_project(repo.clone())?;
+
+                        Ok(())
+                    },
+                    OperationType::ResetProfile,
+                );
+            }
+
+            Ok(())
+        }
+        Command::InitRepo { profile_name, path } => {
+            let path = path.to_string_lossy().into_owned();
+
+            let _ = runner.run(
+                || {
+                    App::init_repo(profile_name.clone(), path.clone())?;
+
+                    Ok(())
+                },
+                OperationType::InitRepo {
+                    profile_name: profile_name.clone(),
+                    path: path.clone(),
+                },
+            );
+
+            Ok(())
+        }
+        Command::Clone {
+            profile_name,
+            url,
+            directory,
+        } => {
+            let directory = match directory {
+                Some(directory) => directory.to_string_lossy().into_owned(),
+                None => clone_directory(&url),
+            };
+
+            let _ = runner.run(
+                || {
+                    App::clone_repo(profile_name.clone(), url.clone(), directory.clone())?;
+
+                    Ok(())
+                },
+                OperationType::Clone {
+                    profile_name: profile_name.clone(),
+                    url: url.clone(),
+                },
+            );
+
+            Ok(())
+        }
+        Command::MoveRepo { from, to } => {
+            let to = match to {
+                Some(to) => to.to_string_lossy().into_owned(),
+                None => cwd()?,
+            };
+
+            let _ = runner.run(
+                || {
+                    App::move_repo(&from, to.clone())?;
+
+                    Ok(())
+                },
+                OperationType::MoveRepo {
+                    from: from.to_string_lossy().into_owned(),
+                    to: to.clone(),
+                },
+            );
+
+            Ok(())
+        }
+        Command::Scan { path, output } => {
+            let format = output_format(format, output)?;
+            if format.is_structured() {
+                match scan::scan(&path) {
+                    Ok(scanned) => {
+                        runner.message(render_list(format, "repos", &scanned)?.trim_end())
+                    }
+                    Err(e) => runner.error(&format!("Failed to scan '{}': {}", path.display(), e)),
+                }
+
+                return Ok(());
+            }
+
+            let scanned = runner.run_with_progress(
+                "Scanning",
+                |progress| Ok(scan_with_progress(&path, progress)?),
+                OperationType::Scan {
+                    path: path.to_string_lossy().into_owned(),
+                },
+            );
+
+            if let Ok(scanned) = scanned {
+                print_scan(runner, &scanned);
+            }
+
+            Ok(())
+        }
+        Command::Assign { path } => {
+            require_prompt(
+                runner,
+                "a terminal to pick profiles on (use `use-profile --path` in scripts)",
+            )?;
+            let scanned = runner.run_with_progress(
+                "Scanning",
+                |progress| Ok(scan_with_progress(&path, progress)?),
+                OperationType::Scan {
+                    path: path.to_string_lossy().into_owned(),
+                },
+            );
+            let Ok(scanned) = scanned else {
+                return Ok(());
+            };
+
+            let pending: Vec<PathBuf> = scanned
+                .into_iter()
+                .filter(|repo| !matches!(repo.state, ScanState::Mapped { .. }))
+                .map(|repo| repo.path)
+                .collect();
+            if pending.is_empty() {
+                runner.message("Every repository is already mapped.");
+                return Ok(());
+            }
+
+            let mut profile_names: Vec<String> = match App::list_profiles() {
+                Ok(profiles) => profiles
+                    .into_iter()
+                    .filter(|(_, p)| !p.archived)
+                    .map(|(name, _)| name)
+                    .collect(),
+                Err(e) => {
+                    runner.error(&format!("Failed to fetch profiles: {}", e));
+                    return Ok(());
+                }
+            };
+            if profile_names.is_empty() {
+                runner.error("No profiles found, add one with `octopush add-profile` first");
+                return Ok(());
+            }
+            profile_names.sort();
+
+            let progress = runner.multi_progress(pending.len() as u64, "Assigning");
+            let mut for_all_remaining: Option<String> = None;
+            let mut last: Option<String> = None;
+            let mut assigned = 0;
+            let mut failures = Vec::new();
+
+            for repo in &pending {
+                let profile_name = match &for_all_remaining {
+                    Some(profile_name) => Some(profile_name.clone()),
+                    None => match progress
+                        .suspend(|| pick_assignment(repo, &profile_names, last.as_deref()))?
+                    {
+                        Assignment::Profile(profile_name) => Some(profile_name),
+                        Assignment::AllRemaining(profile_name) => {
+                            for_all_remaining = Some(profile_name.clone());
+                            Some(profile_name)
+                        }
+                        Assignment::Skip => None,
+                    },
+                };
+
+                let Some(profile_name) = profile_name else {
+                    progress.inc();
+                    continue;
+                };
+                let item = progress.item(&repo.to_string_lossy());
+                match App::use_profile_in_stages(
+                    profile_name.clone(),
+                    repo.to_string_lossy().into_owned(),
+                    &Remotes::Primary,
+                    &mut |stage| item.step(stage.label()),
+                ) {
+                    Ok(_) => {
+                        assigned += 1;
+                        progress.finish_item(item, None);
+                    }
+                    Err(e) => {
+                        progress.finish_item(item, Some(&e.to_string()));
+                        failures.push(format!("{}: {}", repo.display(), e));
+                    }
+                }
+                last = Some(profile_name);
+            }
+            progress.clear();
+
+            runner.success(&format!(
+                "Assigned {} of {} unmapped repositories",
+                assigned,
+                pending.len()
+            ));
+            for failure in failures {
+                runner.error(&failure);
+            }
+
+            Ok(())
+        }
+        Command::Repo {
+            command: RepoCommand::Unlock { force },
+        } => {
+            match App::unlock(force) {
+                Ok(None) => runner.message("No configuration lock is held."),
+                Ok(Some(RemovedLock::Unreadable)) => {
+                    runner.success("Removed a corrupt configuration lock without a readable pid")
+                }
+                Ok(Some(RemovedLock::Owned(owner))) if owner.alive => {
+                    runner.warning(&format!(
+                        "Removed the lock of process {}, which is still running; changes it makes may be lost",
+                        owner.pid
+                    ));
+                }
+                Ok(Some(RemovedLock::Owned(owner))) => {
+                    runner.success(&format!("Removed the stale lock of process {}", owner.pid))
+                }
+                Err(e) => runner.error(&format!("Failed to remove the configuration lock: {}", e)),
+            }
+
+            Ok(())
+        }
+        Command::Config { command } => {
+            // unlike elsewhere in dispatch, a broken settings.toml is an error here
+            let settings = App::settings()?;
+            match command {
+                ConfigCommand::Get { key } => {
+                    if let Some(value) = settings.get(&key)? {
+                        runner.message(&value);
+                    }
+                }
+                ConfigCommand::Set { key, value } => {
+                    App::set_setting(&key, &value)?;
+                    runner.success(&format!("Set {}", key));
+                }
+                ConfigCommand::List => {
+                    let width = Settings::KEYS
+                        .iter()
+                        .map(|key| key.len())
+                        .max()
+                        .unwrap_or(0);
+                    for key in Settings::KEYS {
+                        let value = match settings.get(key)? {
+                            Some(value) => value,
+                            None => "(unset)".dimmed().to_string(),
+                        };
+                        runner.message(&format!("{:<width$}  {}", key, value));
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        Command::Open { account, print } => {
+            let url = match App::web_url(cwd()?, account) {
+                Ok(url) => url,
+                Err(e) => {
+                    runner.error(&format!("Failed to find the repository's page: {}", e));
+                    return Ok(());
+                }
+            };
+
+            if print {
+                runner.message(&url);
+                return Ok(());
+            }
+
+            let _ = runner.run(
+                || Ok(open_url(&url)?),
+                OperationType::Open { url: url.clone() },
+            );
+
+            Ok(())
+        }
+        // normally answered in `run` already
+        Command::Current => {
+            if let Some(profile_name) = App::current_profile_name(&std::env::current_dir()?) {
+                runner.message(&profile_name);
+            }
+
+            Ok(())
+        }
+        // normally answered in `run` already
+        Command::Completions { shell } => write_completions(&shell),
+        Command::Prompt { shell } => {
+            // prompts run on every keystroke, so no spinner and no error output
+            let state = match App::repo_state(cwd()?) {
+                Ok(state) => state,
+                Err(_) => return Ok(()),
+            };
+
+            let segment = match &state {
+                RepoState::Unmapped => prompt_segment("unmapped", PromptColor::Yellow, shell),
+                RepoState::InSync { profile_name } => {
+                    prompt_segment(profile_name, PromptColor::Green, shell)
+                }
+                RepoState::Drifted { profile_name, .. } => {
+                    prompt_segment(&format!("{}!", profile_name), PromptColor::Red, shell)
+                }
+            };
+            runner.message(&segment);
+
+            Ok(())
+        }
+        Command::Whoami => {
+            let report = inspect::whoami(cwd()?)?;
+            let warnings = whoami_warnings(&report);
+            if format.is_structured() {
+                runner.message(format.render(&report)?.trim_end());
+                return report_warnings(runner, &warnings, strict, false);
+            }
+
+            let value = |identity: &Option<IdentityValue>| match identity {
+                Some(identity) => format!("{} ({})", identity.value, identity.source),
+                None => "-".to_string(),
+            };
+            runner.message(&format!("{:<12}{}", "user.name", value(&report.name)));
+            runner.message(&format!("{:<12}{}", "user.email", value(&report.email)));
+            runner.message(&match (&report.profile_name, &report.profile) {
+                (Some(profile_name), Some(profile)) => format!(
+                    "{:<12}{} ({} <{}>)",
+                    "profile", profile_name, profile.name, profile.email
+                ),
+                _ => format!("{:<12}-", "profile"),
+            });
+
+            report_warnings(runner, &warnings, strict, true)
+        }
+        Command::Env { profile_name } => {
+            let profile_name = match profile_name {
+                Some(profile_name) => Ok(profile_name),
+                None => App::resolve_profile_name(cwd()?),
+            };
+            let env = profile_name
+                .and_then(App::get_profile)
+                .map(|profile| exec::shell_env(&profile));
+
+            // plain stdout, since it is meant to be evaluated
+            match env {
+                Ok(env) => print!("{}", exec::exports(&env)),
+                Err(e) => runner.error(&format!("Failed to print the profile environment: {}", e)),
+            }
+
+            Ok(())
+        }
+        Command::Exec {
+            profile_name,
+            with_key,
+            agent,
+            command,
+        } => {
+            let status = App::get_profile(profile_name.clone()).and_then(|profile| {
+                exec::run(
+                    &profile_name,
+                    &profile,
+                    &command,
+                    ExecOptions { with_key, agent },
+                )
+            });
+
+            match status {
+                Ok(status) if !status.success() => std::process::exit(status.code().unwrap_or(1)),
+                Ok(_) => {}
+                Err(e) => runner.error(&format!(
+                    "Failed to run command as profile '{}': {}",
+                    profile_name, e
+                )),
+            }
+
+            Ok(())
+        }
+    }
+}
+
+// `--url-rewrite https://github.com/acme/=git@github.com-work:acme/`
+fn parse_url_rewrite(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err(format!("'{}' is not in FROM=TO form", value)),
+    }
+}
+
+// keeps only the auth fields that are meaningful for the given auth type
+fn auth_fields(
+    auth_type: AuthType,
+    hostname: Option<String>,
+    ssh_key_path: Option<String>,
+) -> (Option<String>, Option<String>) {
+    match auth_type {
+        AuthType::None => (None, None),
+        AuthType::SSH => (None, ssh_key_path),
+        AuthType::GH | AuthType::GitLab | AuthType::Token => (hostname, None),
+    }
+}
+
+// lists the signing keys found on the machine, falling back to typing one in
+fn pick_signing_key(gpg_program: &str) -> Option<String> {
+    let keys = signers::available_keys(gpg_program);
+
+    if !keys.is_empty() {
+        let mut items: Vec<&str> = keys.iter().map(|key| key.label.as_str()).collect();
+        items.push("Enter another key...");
+        let selection = Select::new()
+            .with_prompt("Select the key to sign commits and tags with")
+            .items(&items)
+            .default(0)
+            .interact()
+            .unwrap();
+        if let Some(key) = keys.get(selection) {
+            return Some(key.key.clone());
+        }
+    }
+
+    let key: String = Input::new()
+        .with_prompt("Enter the signing key (gpg key id or path to an ssh public key)")
+        .allow_empty(true)
+        .interact_text()
+        .unwrap();
+    Some(key).filter(|key| !key.trim().is_empty())
+}
+
+fn edit_profile_wizard(current: &Profile) -> Profile {
+    let name: String = Input::new()
+        .with_prompt("Enter a name for the profile")
+        .default(current.name.clone())
+        .interact_text()
+        .unwrap();
+
+    let email: String = Input::new()
+        .with_prompt("Enter an email for the profile")
+        .default(current.email.clone())
+        .interact_text()
+        .unwrap();
+
+    let auth_items = ["none", "ssh", "gh", "gitlab", "token"];
+    let current_auth: &str = current.auth_type.into();
+    let auth_index = Select::new()
+        .with_prompt("Select the authentication type...")
+        .items(auth_items)
+        .default(
+            auth_items
+                .iter()
+                .position(|item| *item == current_auth)
+                .unwrap_or(0),
+        )
+        .interact()
+        .unwrap();
+    let auth_type: AuthType = auth_items[auth_index].parse().unwrap();
+
+    // only prefill the auth-specific field when the auth type is unchanged,
+    // otherwise the newly relevant field is asked from scratch
+    let unchanged = auth_type == current.auth_type;
+
+    let (hostname, ssh_key_path) = match auth_type {
+        AuthType::None => (None, None),
+        AuthType::SSH => {
+            let initial = if unchanged {
+                current.ssh_key_path.clone().unwrap_or_default()
+            } else {
+                String::new()
+            };
+            let input =
+                dialoguer_path_input_with_initial("Enter the path of your ssh key: ", &initial);
+            let ssh_key_path = if input.trim().is_empty() {
+                None
+            } else {
+                Some(input)
+            };
+
+            (None, ssh_key_path)
+        }
+        AuthType::GH | AuthType::GitLab | AuthType::Token => {
+            let mut input =
+                Input::<String>::new().with_prompt("Enter the hostname of authenticated account");
+            if unchanged && let Some(hostname) = &current.hostname {
+                input = input.default(hostname.clone());
+            } else if let Some(hostname) = auth_type.default_hostname() {
+                input = input.default(hostname.to_string());
+            }
+
+            (Some(input.interact_text().unwrap()), None)
+        }
+    };
+
+    let mut profile = current.clone();
+    profile.name = name;
+    profile.email = email;
+    profile.auth_type = auth_type;
+    profile.hostname = hostname;
+    profile.ssh_key_path = ssh_key_path;
+    profile
+}
+
+fn status_cmd(
+    runner: &Runner,
+    repo: String,
+    fix: bool,
+    explain: bool,
+    strict: bool,
+) -> Result<(), std::io::Error> {
+    let mut warnings = App::remote_warnings(repo.clone()).unwrap_or_default();
+
+    match App::repo_state(repo.clone()) {
+        Ok(RepoState::Unmapped) => {
+            runner.message("The repository is not mapped to a profile.");
+            if let Ok(Some((from, profile_name))) = App::moved_from(repo.clone()) {
+                runner.message(&format!(
+                    "It has the origin of {}, which was mapped to profile '{}' and no longer exists; if it moved here, run `octopush move-repo --from {} --to {}`",
+                    from, profile_name, from, repo
+                ));
+            }
+        }
+        Ok(RepoState::InSync { profile_name }) => {
+            runner.success(&format!("In sync with profile '{}'", profile_name));
+            if explain && let Ok(profile) = App::get_profile(profile_name.clone()) {
+                for (key, value) in plan::desired_config(&profile) {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    let operation = PlanOperation::GitConfig {
+                        key: key.to_string(),
+                        before: Some(value.clone()),
+                        after: Some(value),
+                    };
+                    runner.message(&format!("  {}: {}", key, operation.explain(&profile_name)));
+                }
+            }
+        }
+        Ok(RepoState::Drifted { profile_name, plan }) => {
+            runner.message(&format!(
+                "The repository drifted from profile '{}':",
+                profile_name
+            ));
+            for operation in &plan.operations {
+                runner.message(&format!(
+                    "  {}: expected {}, found {}",
+                    operation.target(),
+                    operation.after().unwrap_or("(unset)").green(),
+                    operation.before().unwrap_or("(unset)").red()
+                ));
+                if explain {
+                    runner.message(&format!(
+                        "    {}",
+                        operation.explain(&profile_name).dimmed()
+                    ));
+                }
+            }
+
+            if fix {
+                let _ = runner.run(
+                    || {
+                        App::sync_repo(repo.clone())?;
+
+                        Ok(())
+                    },
+                    OperationType::Sync { repo: repo.clone() },
+                );
+            } else {
+                warnings.push(Warning::Drift {
+                    changes: plan.operations.len(),
+                    profile_name,
+                });
+            }
+        }
+        Err(e) => {
+            runner.error(&format!("Failed to read the repository's status: {}", e));
+            return Ok(());
+        }
+    }
+
+    warnings.extend(outdated_warning(repo));
+    report_warnings(runner, &warnings, strict, true)
+}
+
+// `--format` documents, keyed so every format has a table at the top
+#[derive(Serialize)]
+struct ListedProfile {
+    #[serde(flatten)]
+    profile: Profile,
+    #[serde(flatten)]
+    usage: Option<ProfileUsage>,
+}
+
+#[derive(Serialize)]
+struct RepoReports<T> {
+    repos: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct ProfileReport {
+    path: String,
+    repo: String,
+    profile: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    octopush_version: Option<String>,
+    // seconds git's cache helper keeps the profile's credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credential_cache_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct StatusReport {
+    path: String,
+    // `unmapped`, `in_sync` or `drifted`
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changes: Vec<DriftReport>,
+    fixed: bool,
+    outdated: bool,
+}
+
+#[derive(Serialize)]
+struct DriftReport {
+    target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    found: Option<String>,
+}
+
+// a warning for each part of git's identity that differs from the profile
+fn whoami_warnings(report: &Whoami) -> Vec<Warning> {
+    let (Some(profile_name), Some(profile)) = (&report.profile_name, &report.profile) else {
+        return Vec::new();
+    };
+
+    report
+        .mismatches
+        .iter()
+        .map(|key| {
+            let (expected, actual) = match key.as_str() {
+                "user.name" => (&profile.name, &report.name),
+                _ => (&profile.email, &report.email),
+            };
+            Warning::IdentityMismatch {
+                profile_name: profile_name.clone(),
+                key: key.clone(),
+                expected: expected.clone(),
+                actual: actual
+                    .as_ref()
+                    .map(|actual| format!("'{}' ({})", actual.value, actual.source)),
+            }
+        })
+        .collect()
+}
+
+// what `get-profile` prints, plus the warnings it would raise
+fn profile_report(repo: String) -> Result<(ProfileReport, Vec<Warning>), std::io::Error> {
+    let (profile_name, profile, repo_name) = App::get_project_profile(repo.clone())?;
+    let record = App::repo_record(repo.clone())?;
+
+    let mut warnings = Vec::new();
+    if let Ok(RepoState::Drifted { profile_name, plan }) = App::repo_state(repo.clone()) {
+        warnings.push(Warning::Drift {
+            profile_name,
+            changes: plan.operations.len(),
+        });
+    }
+    warnings.extend(outdated_warning(repo.clone()));
+
+    Ok((
+        ProfileReport {
+            path: repo,
+            repo: repo_name,
+            profile: profile_name,
+            octopush_version: record.map(|record| record.octopush_version),
+            credential_cache_seconds: profile.credential_cache_lifetime(),
+        },
+        warnings,
+    ))
+}
+
+// what `status` prints, plus the warnings it would raise; drift is
+// reported as found even when `fix` reconciles it
+fn status_report(repo: String, fix: bool) -> Result<(StatusReport, Vec<Warning>), std::io::Error> {
+    let mut report = StatusReport {
+        path: repo.clone(),
+        state: "unmapped",
+        profile: None,
+        changes: Vec::new(),
+        fixed: false,
+        outdated: false,
+    };
+    let mut warnings = Vec::new();
+
+    match App::repo_state(repo.clone())? {
+        RepoState::Unmapped => {}
+        RepoState::InSync { profile_name } => {
+            report.state = "in_sync";
+            report.profile = Some(profile_name);
+        }
+        RepoState::Drifted { profile_name, plan } => {
+            report.state = "drifted";
+            report.changes = plan
+                .operations
+                .iter()
+                .map(|operation| DriftReport {
+                    target: operation.target(),
+                    expected: operation.after().map(str::to_string),
+                    found: operation.before().map(str::to_string),
+                })
+                .collect();
+            if fix {
+                App::sync_repo(repo.clone())?;
+                report.fixed = true;
+            } else {
+                warnings.push(Warning::Drift {
+                    changes: plan.operations.len(),
+                    profile_name: profile_name.clone(),
+                });
+            }
+            report.profile = Some(profile_name);
+        }
+    }
+
+    if let Some(warning) = outdated_warning(repo) {
+        report.outdated = true;
+        warnings.push(warning);
+    }
+
+    Ok((report, warnings))
+}
+
+fn get_profile_cmd(runner: &Runner, repo: String, strict: bool) -> Result<(), std::io::Error> {
+    let _ = runner.run(
+        || {
+            let (profile_name, profile, repo_name) = App::get_project_profile(repo.clone())?;
+            let configured_by = match App::repo_record(repo.clone())? {
+                Some(record) => {
+                    format!(" (configured by octopush {})", record.octopush_version)
+                }
+                None => String::new(),
+            };
+
+            runner.message(
+                format!(
+                    "The repository '{}' is associated with profile {}.{}",
+                    repo_name, profile_name, configured_by,
+                )
+                .as_str(),
+            );
+            if let Some(lifetime) = profile.credential_cache_lifetime() {
+                runner.message(&format!(
+                    "Credentials stay in git's credential cache for {} after their last use.",
+                    format_duration(lifetime)
+                ));
+            }
+
+            Ok(())
+        },
+        OperationType::GetProfile,
+    );
+
+    let mut warnings = Vec::new();
+    if let Ok(RepoState::Drifted { profile_name, plan }) = App::repo_state(repo.clone()) {
+        warnings.push(Warning::Drift {
+            profile_name,
+            changes: plan.operations.len(),
+        });
+    }
+    warnings.extend(outdated_warning(repo));
+    report_warnings(runner, &warnings, strict, true)?;
+
+    Ok(())
+}
+
+// Asks before a destructive change. `--yes` answers for scripts; without a
+// terminal to ask on, the change is refused rather than assumed.
+fn confirm(runner: &Runner, prompt: &str, yes: bool) -> Result<bool, std::io::Error> {
+    if yes {
+        return Ok(true);
+    }
+    require_prompt(runner, "--yes")?;
+
+    Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .map_err(std::io::Error::other)
+}
+
+// Without a terminal, or with `--no-input`/OCTOPUSH_NONINTERACTIVE, what
+// would have been asked for has to be passed instead.
+fn require_prompt(runner: &Runner, argument: &str) -> Result<(), std::io::Error> {
+    if runner.can_prompt() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{} is required when octopush cannot prompt", argument),
+    ))
+}
+
+// ` 3 repositories are still mapped to it.`, or nothing
+fn still_mapped(profile_name: &str) -> String {
+    let count = App::list_projects()
+        .unwrap_or_default()
+        .values()
+        .filter(|mapped| *mapped == profile_name)
+        .count();
+    match count {
+        0 => String::new(),
+        1 => " 1 repository is still mapped to it.".to_string(),
+        n => format!(" {} repositories are still mapped to it.", n),
+    }
+}
+
+// reads the token from a hidden prompt, or from stdin when it is piped in
+fn set_token_cmd(runner: &Runner, profile_name: String) -> Result<(), std::io::Error> {
+    let token = if std::io::stdin().is_terminal() {
+        require_prompt(runner, "the token on stdin")?;
+        Password::new()
+            .with_prompt("Enter the access token (stored in the OS keychain)")
+            .interact()
+            .map_err(std::io::Error::other)?
+    } else {
+        let mut token = String::new();
+        std::io::stdin().read_to_string(&mut token)?;
+        token
+    };
+
+    let _ = runner.run(
+        || Ok(App::set_token(profile_name.clone(), token.clone())?),
+        OperationType::SetToken {
+            profile_name: profile_name.clone(),
+        },
+    );
+
+    Ok(())
+}
+
+fn remotes(remote: Option<String>, all_remotes: bool) -> Remotes {
+    match (remote, all_remotes) {
+        (_, true) => Remotes::All,
+        (Some(name), false) => Remotes::Named(name),
+        (None, false) => Remotes::Primary,
+    }
+}
+
+// how use-profile and apply treat every repository they are given
+struct UseOptions {
+    dry_run: bool,
+    output: Format,
+    remotes: Remotes,
+    // log in without asking when the profile's host is not authenticated
+    login: bool,
+    strict: bool,
+    // preview the changes and ask before applying, when there is someone to ask
+    confirm: bool,
+    safe_directory: SafeDirectory,
+    // print the plan with the reason for each change first
+    explain: bool,
+}
+
+fn use_profile_cmd(
+    runner: &Runner,
+    profile_name: Option<String>,
+    cwd: String,
+    options: &UseOptions,
+) -> Result<(), std::io::Error> {
+    if !options.dry_run && !trust_repository(runner, &cwd, options.safe_directory)? {
+        return Ok(());
+    }
+
+    let profile_name = match profile_name {
+        Some(profile_name) => profile_name,
+        None => match App::resolve_profile_name(cwd.clone()) {
+            Ok(profile_name) => profile_name,
+            // nothing decides for us, so let the user pick with a best guess preselected
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && runner.can_prompt() => {
+                match pick_profile(&cwd)? {
+                    Some(profile_name) => profile_name,
+                    None => {
+                        runner
+                            .error("No profiles found, add one with `octopush add-profile` first");
+                        return Ok(());
+                    }
+                }
+            }
+            Err(e) => {
+                runner.error(&format!(
+                    "Failed to pick a profile for the repository: {}",
+                    e
+                ));
+                return Ok(());
+            }
+        },
+    };
+
+    let mut warnings =
+        App::preflight(profile_name.clone(), cwd.clone(), &options.remotes).unwrap_or_default();
+    if !options.dry_run {
+        warnings = offer_login(runner, &profile_name, warnings, options.login);
+    }
+    report_warnings(
+        runner,
+        &warnings,
+        options.strict,
+        !options.output.is_structured(),
+    )?;
+
+    if options.dry_run {
+        match App::plan_use_profile_with_remotes(profile_name.clone(), cwd, &options.remotes) {
+            Ok(plan) => print_plan(runner, &plan, options.output, options.explain)?,
+            Err(e) => runner.error(&format!(
+                "Failed to plan profile '{}' for the repository: {}",
+                profile_name, e
+            )),
+        }
+
+        return Ok(());
+    }
+
+    let confirm = options.confirm && runner.can_prompt();
+    if (confirm || options.explain)
+        && let Ok(plan) =
+            App::plan_use_profile_with_remotes(profile_name.clone(), cwd.clone(), &options.remotes)
+    {
+        // the explained plan already lists every change, the prompt then
+        // only asks
+        if options.explain {
+            print_plan(runner, &plan, Format::Human, true)?;
+        }
+        if confirm && !confirm_plan(runner, &plan, !options.explain)? {
+            runner.message("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let _ = runner.run_steps(
+        |steps| {
+            let plan = App::use_profile_in_stages(
+                profile_name.clone(),
+                cwd,
+                &options.remotes,
+                &mut |stage| steps.step(stage.label()),
+            )?;
+            steps.changed(plan.changed());
+
+            Ok(())
+        },
+        OperationType::UseProfile {
+            profile_name: profile_name.clone(),
+        },
+    );
+
+    Ok(())
+}
+
+// Git refuses repositories another user owns (devcontainers, mounted
+// volumes) until they are listed in safe.directory, which it only reads
+// from the global config. Adds the repository there as `safe_directory`
+// says; false when it stays untrusted.
+fn trust_repository(
+    runner: &Runner,
+    repo: &str,
+    policy: SafeDirectory,
+) -> Result<bool, std::io::Error> {
+    let Some(dir) = git::dubious_ownership(std::path::Path::new(repo))? else {
+        return Ok(true);
+    };
+
+    let prompt = format!(
+        "git refuses '{}' because another user owns it. Trust it in the global git config?",
+        dir.display()
+    );
+    let trust = match policy {
+        SafeDirectory::Global => true,
+        SafeDirectory::Ask if runner.can_prompt() => confirm(runner, &prompt, false)?,
+        SafeDirectory::Ask | SafeDirectory::Never => false,
+    };
+    if !trust {
+        runner.error(&format!(
+            "git refuses to work in '{}' because another user owns it, trust it with `git config --global --add safe.directory '{}'`",
+            dir.display(),
+            dir.display()
+        ));
+        return Ok(false);
+    }
+
+    git::add_safe_directory(&dir)?;
+    runner.message(&format!(
+        "Added '{}' to safe.directory in the global git config",
+        dir.display()
+    ));
+    Ok(true)
+}
+
+// Shows the identity, auth and remote changes of a plan and asks to apply
+// them; the rest of the plan is only counted. A plan without notable
+// changes is applied without asking.
+fn confirm_plan(runner: &Runner, plan: &ApplyPlan, preview: bool) -> Result<bool, std::io::Error> {
+    let (notable, rest): (Vec<_>, Vec<_>) = plan
+        .operations
+        .iter()
+        .filter(|operation| !matches!(operation, PlanOperation::Mapping { .. }))
+        .partition(|operation| operation.is_notable());
+    if notable.is_empty() {
+        return Ok(true);
+    }
+
+    if preview {
+        runner.message(&format!("Profile '{}' changes:", plan.profile_name));
+        for operation in notable {
+            runner.message(&format!(
+                "  {}: {} -> {}",
+                operation.target(),
+                operation.before().unwrap_or("(unset)").red(),
+                operation.after().unwrap_or("(unset)").green()
+            ));
+        }
+        if !rest.is_empty() {
+            runner.message(&format!("  and {} other setting(s)", rest.len()));
+        }
+    }
+
+    Confirm::new()
+        .with_prompt("Apply these changes?")
+        .default(true)
+        .interact()
+        .map_err(std::io::Error::other)
+}
+
+// offers to log the profile's host in through gh or glab before applying a
+// profile whose pushes would otherwise fail; returns the warnings still open
+fn offer_login(
+    runner: &Runner,
+    profile_name: &str,
+    warnings: Vec<Warning>,
+    login: bool,
+) -> Vec<Warning> {
+    warnings
+        .into_iter()
+        .filter(|warning| {
+            let (program, host) = match warning {
+                Warning::GhUnauthenticated { host } => ("gh", host),
+                Warning::GlabUnauthenticated { host } => ("glab", host),
+                _ => return true,
+            };
+
+            let confirmed = login
+                || (runner.can_prompt()
+                    && Confirm::new()
+                        .with_prompt(format!(
+                            "{} is not authenticated for '{}', run `{} auth login` now?",
+                            program, host, program
+                        ))
+                        .default(true)
+                        .interact()
+                        .unwrap_or(false));
+            if !confirmed {
+                return true;
+            }
+
+            match App::login(profile_name.to_string()) {
+                Ok(()) => false,
+                Err(e) => {
+                    runner.error(&format!("Failed to log in to '{}': {}", host, e));
+                    true
+                }
+            }
+        })
+        .collect()
+}
+
+// dynamic completion for `use-profile -p`: the repository's current and
+// previous profiles come first, then everything else alphabetically
+// every profile, archived ones included, for commands that manage profiles
+fn complete_profiles(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let Ok(profiles) = App::list_profiles() else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = profiles
+        .into_keys()
+        .filter(|name| name.starts_with(current))
+        .collect();
+    names.sort();
+    names.into_iter().map(CompletionCandidate::new).collect()
+}
+
+// the same script `COMPLETE=<shell> octopush` prints: it calls back into
+// octopush on every <TAB>, so profile names are always current
+fn write_completions(shell: &str) -> Result<(), std::io::Error> {
+    let shells = Shells::builtins();
+    let completer = shells.completer(shell).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unsupported shell `{}`", shell),
+        )
+    })?;
+
+    let mut buf = Vec::new();
+    completer.write_registration("COMPLETE", "octopush", "octopush", "octopush", &mut buf)?;
+    std::io::stdout().write_all(&buf)
+}
+
+fn complete_repo_profiles(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let Ok(names) = cwd().and_then(App::ranked_profile_names) else {
+        return Vec::new();
+    };
+
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(current))
+        .enumerate()
+        .map(|(order, name)| CompletionCandidate::new(name).display_order(Some(order)))
+        .collect()
+}
+
+// the repositories a repo-scoped command works on: every `--path`, or the
+// current directory when none is given
+fn repo_paths(paths: Vec<PathBuf>) -> Result<Vec<String>, std::io::Error> {
+    if paths.is_empty() {
+        return Ok(vec![cwd()?]);
+    }
+
+    paths
+        .iter()
+        .map(|path| Ok(std::path::absolute(path)?.to_string_lossy().into_owned()))
+        .collect()
+}
+
+// names the repository each block of output belongs to when there are several
+fn repo_header(runner: &Runner, repo: &str, count: usize) {
+    if count > 1 {
+        runner.message(&format!("{}:", repo).bold().to_string());
+    }
+}
+
+fn pick_profile(repo: &str) -> Result<Option<String>, std::io::Error> {
+    let mut profiles: Vec<(String, Profile)> = App::list_profiles()?
+        .into_iter()
+        .filter(|(_, p)| !p.archived)
+        .collect();
+    if profiles.is_empty() {
+        return Ok(None);
+    }
+    profiles.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let suggested = App::suggest_profile(repo.to_string()).unwrap_or_default();
+    let default = suggested
+        .and_then(|suggested| profiles.iter().position(|(name, _)| *name == suggested))
+        .unwrap_or_default();
+
+    // the search matches the whole line, so typing part of an email works too
+    let width = profiles
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let items: Vec<String> = profiles
+        .iter()
+        .map(|(name, p)| {
+            format!(
+                "{:<width$}  {} <{}>  {}",
+                name,
+                p.name,
+                p.email,
+                String::from(p.auth_type)
+            )
+        })
+        .collect();
+
+    let selection = FuzzySelect::new()
+        .with_prompt("Select a profile for this repository")
+        .items(&items)
+        .default(default)
+        .interact()
+        .map_err(std::io::Error::other)?;
+
+    Ok(Some(profiles.swap_remove(selection).0))
+}
+
+// same default as `git clone`: the last path segment without `.git`
+fn clone_directory(url: &str) -> String {
+    url.trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url)
+        .trim_end_matches(".git")
+        .to_string()
+}
+
+enum Assignment {
+    Profile(String),
+    AllRemaining(String),
+    Skip,
+}
+
+fn pick_assignment(
+    repo: &std::path::Path,
+    profile_names: &[String],
+    last: Option<&str>,
+) -> Result<Assignment, std::io::Error> {
+    let mut items: Vec<String> = profile_names.to_vec();
+    items.push("(skip)".to_string());
+    if let Some(last) = last {
+        items.push(format!("(use '{}' for all remaining)", last));
+    }
+
+    let selection = FuzzySelect::new()
+        .with_prompt(format!("Profile for {}", repo.display()))
+        .items(&items)
+        .default(0)
+        .interact()
+        .map_err(std::io::Error::other)?;
+
+    Ok(match selection {
+        i if i < profile_names.len() => Assignment::Profile(profile_names[i].clone()),
+        i if i == profile_names.len() => Assignment::Skip,
+        _ => Assignment::AllRemaining(last.unwrap_or_default().to_string()),
+    })
+}
+
+// prints soft warnings; under strict mode they are reported as errors and
+// the command fails
+// a repository whose managed config layout `sync` would upgrade
+fn outdated_warning(repo: String) -> Option<Warning> {
+    App::outdated_warning(repo).ok().flatten()
+}
+
+fn report_warnings(
+    runner: &Runner,
+    warnings: &[Warning],
+    strict: bool,
+    print: bool,
+) -> Result<(), std::io::Error> {
+    if print {
+        for warning in warnings {
+            if strict {
+                runner.error(&warning.to_string());
+            } else {
+                runner.warning(&warning.to_string());
+            }
+        }
+    }
+
+    if strict && !warnings.is_empty() {
+        return Err(std::io::Error::other(exit::StrictModeError(warnings.len())));
+    }
+
+    Ok(())
+}
+
+fn print_diff(runner: &Runner, diff: &ProfileDiff) {
+    let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".into());
+    let width = diff
+        .fields
+        .iter()
+        .map(|f| value(&f.left).chars().count())
+        .max()
+        .unwrap_or_default()
+        .max(diff.left.chars().count());
+
+    runner.message(
+        &format!("{:<16}{:<width$}  {}", "", diff.left, diff.right)
+            .bold()
+            .to_string(),
+    );
+    for field in &diff.fields {
+        let (left, right) = (value(&field.left), value(&field.right));
+        let line = format!("{:<16}{:<width$}  {}", field.field, left, right);
+
+        if field.is_equal() {
+            runner.message(&line.dimmed().to_string());
+        } else if field.changes_git_config {
+            runner.message(&format!("{}  {}", line.red(), "(git config)".yellow()));
+        } else {
+            runner.message(&line.yellow().to_string());
+        }
+    }
+
+    if diff.is_identical() {
+        runner.message("\nThe profiles are identical.");
+    } else if diff.git_config.is_empty() {
+        runner.message("\nApplying either profile writes the same git config.");
+    } else {
+        runner.message("\nGit config that differs when applied:");
+        for config in &diff.git_config {
+            runner.message(&format!(
+                "  {}: {} -> {}",
+                config.key,
+                config.left.as_deref().unwrap_or("(unset)"),
+                config.right.as_deref().unwrap_or("(unset)")
+            ));
+        }
+    }
+}
+
+fn scan_with_progress(
+    path: &std::path::Path,
+    progress: &Progress,
+) -> Result<Vec<ScannedRepo>, std::io::Error> {
+    scan::scan_with(path, &mut |total| {
+        progress.set_length(total as u64);
+        progress.inc();
+    })
+}
+
+fn print_scan(runner: &Runner, scanned: &[ScannedRepo]) {
+    if scanned.is_empty() {
+        runner.message("No git repositories found.");
+        return;
+    }
+
+    let (mut mapped, mut manual, mut unconfigured) = (0, 0, 0);
+    for repo in scanned {
+        let (state, detail) = match &repo.state {
+            ScanState::Mapped { profile_name } => {
+                mapped += 1;
+                ("mapped".green(), profile_name.clone())
+            }
+            ScanState::Manual { name, email } => {
+                manual += 1;
+                (
+                    "manual".yellow(),
+                    format!(
+                        "{} <{}>",
+                        name.as_deref().unwrap_or("-"),
+                        email.as_deref().unwrap_or("-")
+                    ),
+                )
+            }
+            ScanState::Unconfigured => {
+                unconfigured += 1;
+                ("none".red(), String::new())
+            }
+        };
+
+        runner.message(format!("{:<6}  {}  {}", state, repo.path.display(), detail).trim_end());
+    }
+
+    runner.message(&format!(
+        "\n{} repositories: {} mapped, {} manual, {} unconfigured",
+        scanned.len(),
+        mapped,
+        manual,
+        unconfigured
+    ));
+}
+
+// `--output json` predates the global `--format` and is kept as a deprecated
+// spelling of `--format json`; asking for two different formats is an error
+fn output_format(format: Format, output: OutputFormat) -> Result<Format, std::io::Error> {
+    match (output, format) {
+        (OutputFormat::Human, format) => Ok(format),
+        (OutputFormat::Json, Format::Human | Format::Json) => Ok(Format::Json),
+        (OutputFormat::Json, format) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "--output json conflicts with --format {}; --output is deprecated, use --format alone",
+                format
+                    .to_possible_value()
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+// lists stay top-level arrays in json and yaml, as `--output json` printed
+// them; toml needs a table at the top, so there they go under `key`
+fn render_list<T: Serialize>(
+    format: Format,
+    key: &str,
+    items: &[T],
+) -> Result<String, std::io::Error> {
+    match format {
+        Format::Toml => format.render(&BTreeMap::from([(key, items)])),
+        format => format.render(&items),
+    }
+}
+
+fn print_plan(
+    runner: &Runner,
+    plan: &ApplyPlan,
+    output: Format,
+    explain: bool,
+) -> Result<(), std::io::Error> {
+    if output.is_structured() {
+        runner.message(output.render(plan)?.trim_end());
+        return Ok(());
+    }
+
+    if plan.is_empty() {
+        runner.message(&format!(
+            "Nothing to change, the repository already uses profile '{}'.",
+            plan.profile_name
+        ));
+        return Ok(());
+    }
+
+    runner.message(&format!(
+        "Planned changes for '{}' (profile '{}'):",
+        plan.repo.display(),
+        plan.profile_name
+    ));
+    for operation in &plan.operations {
+        runner.message(&format!("  {}", operation));
+        if explain {
+            runner.message(&format!(
+                "    {}",
+                operation.explain(&plan.profile_name).dimmed()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// `--long` splits host and key and adds every other column
+fn print_profile_table(runner: &Runner, profiles: &[(String, Profile)], long: bool) {
+    let usage = if long {
+        App::list_usage().unwrap_or_default()
+    } else {
+        Default::default()
+    };
+    let show_tags = long || profiles.iter().any(|(_, p)| !p.tags.is_empty());
+    let or_dash = |value: Option<&String>| value.cloned().unwrap_or_else(|| "-".into());
+
+    let mut headers = vec!["Profile", "Name", "Email", "Auth"];
+    if long {
+        headers.extend(["Host", "SSH Key", "Signing Key"]);
+    } else {
+        headers.extend(["Host/Key", "Signing"]);
+    }
+    if show_tags {
+        headers.push("Tags");
+    }
+    if long {
+        headers.extend(["Last Used", "Uses"]);
+    }
+
+    let rows: Vec<Vec<String>> = profiles
+        .iter()
+        .map(|(profile_name, p)| {
+            let mut row = vec![
+                if p.archived {
+                    format!("{} (archived)", profile_name)
+                } else {
+                    profile_name.to_string()
+                },
+                p.name.clone(),
+                p.email.clone(),
+                String::from(p.auth_type),
+            ];
+            if long {
+                row.extend([
+                    or_dash(p.hostname.as_ref()),
+                    or_dash(p.ssh_key_path.as_ref()),
+                    or_dash(p.signing_key.as_ref()),
+                ]);
+            } else {
+                let host_key: Vec<&str> = [&p.hostname, &p.ssh_key_path]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect();
+                row.extend([
+                    if host_key.is_empty() {
+                        "-".into()
+                    } else {
+                        host_key.join(" / ")
+                    },
+                    p.signing_format().unwrap_or("-").to_string(),
+                ]);
+            }
+            if show_tags {
+                row.push(if p.tags.is_empty() {
+                    "-".into()
+                } else {
+                    p.tags.join(",")
+                });
+            }
+            if long {
+                let entry = usage.get(profile_name);
+                row.push(
+                    entry
+                        .map(|u| format_age(u.last_used))
+                        .unwrap_or_else(|| "never".into()),
+                );
+                row.push(entry.map(|u| u.use_count).unwrap_or(0).to_string());
+            }
+            row
+        })
+        .collect();
+
+    for line in table(&headers, &rows) {
+        runner.message(&line);
     }
 }
@@ -0,0 +1,124 @@
+use aes::Aes256;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+
+// profiles.toml can be kept encrypted at rest: AES-256-CBC, then an
+// HMAC-SHA256 over the header, iv and ciphertext, with separate random keys
+// for both that live in the OS keychain rather than on disk. The file stays
+// text, a marker line followed by the hex of iv || ciphertext || tag, so it
+// is recognised on read whatever the current setting says.
+
+const HEADER: &str = "# octopush encrypted profiles v1, the key is kept in the OS keychain\n";
+const IV_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+
+type Encryptor = cbc::Encryptor<Aes256>;
+type Decryptor = cbc::Decryptor<Aes256>;
+
+// the cipher key followed by the mac key
+pub struct Key([u8; 64]);
+
+impl Key {
+    pub fn generate() -> Result<Self, io::Error> {
+        let mut bytes = [0u8; 64];
+        getrandom::fill(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Key(bytes))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, io::Error> {
+        let mut bytes = [0u8; 64];
+        hex::decode_to_slice(hex.trim(), &mut bytes).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the profiles key is not 64 hex-encoded bytes",
+            )
+        })?;
+        Ok(Key(bytes))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.0[32..]).expect("hmac takes any key length")
+    }
+}
+
+pub fn is_encrypted(content: &str) -> bool {
+    content.starts_with(HEADER)
+}
+
+pub fn encrypt(key: &Key, plaintext: &str) -> Result<String, io::Error> {
+    let mut iv = [0u8; IV_LEN];
+    getrandom::fill(&mut iv).map_err(|e| io::Error::other(e.to_string()))?;
+
+    let ciphertext = Encryptor::new(key.0[..32].into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+
+    let mut sealed = iv.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    let mut mac = key.mac();
+    mac.update(HEADER.as_bytes());
+    mac.update(&sealed);
+    sealed.extend_from_slice(&mac.finalize().into_bytes());
+
+    Ok(format!("{}{}\n", HEADER, hex::encode(sealed)))
+}
+
+pub fn decrypt(key: &Key, content: &str) -> Result<String, io::Error> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let sealed = content
+        .strip_prefix(HEADER)
+        .and_then(|body| hex::decode(body.trim()).ok())
+        .filter(|sealed| sealed.len() >= IV_LEN + TAG_LEN)
+        .ok_or_else(|| invalid("encrypted profiles.toml is corrupt"))?;
+    let (sealed, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+
+    let mut mac = key.mac();
+    mac.update(HEADER.as_bytes());
+    mac.update(sealed);
+    mac.verify_slice(tag).map_err(|_| {
+        invalid("encrypted profiles.toml does not match the key in the keychain, or was altered")
+    })?;
+
+    let (iv, ciphertext) = sealed.split_at(IV_LEN);
+    let plaintext = Decryptor::new(key.0[..32].into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| invalid("encrypted profiles.toml is corrupt"))?;
+
+    String::from_utf8(plaintext).map_err(|_| invalid("encrypted profiles.toml is corrupt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_tampering() {
+        let key = Key::generate().unwrap();
+        let plaintext = "[work]\nname = \"John\"\nhostname = \"git.corp.example\"\n";
+
+        let sealed = encrypt(&key, plaintext).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("corp"));
+        assert_eq!(decrypt(&key, &sealed).unwrap(), plaintext);
+        assert_eq!(
+            decrypt(&Key::from_hex(&key.to_hex()).unwrap(), &sealed).unwrap(),
+            plaintext
+        );
+
+        // flip one hex digit of the ciphertext
+        let at = HEADER.len() + 2 * IV_LEN + 1;
+        let mut tampered = sealed.clone().into_bytes();
+        tampered[at] = if tampered[at] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(decrypt(&key, &tampered).is_err());
+
+        assert!(decrypt(&Key::generate().unwrap(), &sealed).is_err());
+        assert!(!is_encrypted(plaintext));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::util::git::GitError;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Exit statuses, documented in the README. Invalid input shares 2 with the
+// usage errors clap exits with on its own.
+pub const FAILURE: i32 = 1;
+pub const INVALID_INPUT: i32 = 2;
+pub const NOT_FOUND: i32 = 3;
+pub const GIT_FAILURE: i32 = 4;
+pub const STRICT: i32 = 5;
+pub const AUTH_FAILURE: i32 = 6;
+
+// a command that only failed because of strict mode
+#[derive(Debug)]
+pub struct StrictModeError(pub usize);
+
+impl fmt::Display for StrictModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "strict mode: {} warning{} treated as errors",
+            self.0,
+            if self.0 == 1 { "" } else { "s" }
+        )
+    }
+}
+
+impl Error for StrictModeError {}
+
+#[derive(Debug)]
+pub struct ChecksFailedError(pub usize);
+
+impl fmt::Display for ChecksFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} check{} failed",
+            self.0,
+            if self.0 == 1 { "" } else { "s" }
+        )
+    }
+}
+
+impl Error for ChecksFailedError {}
+
+// an error the Runner already showed the user, with the status it maps to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Reported {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Reported {}
+
+pub fn code(e: &(dyn Error + 'static)) -> i32 {
+    let Some(e) = e.downcast_ref::<io::Error>() else {
+        return FAILURE;
+    };
+
+    if let Some(inner) = e.get_ref() {
+        if let Some(reported) = inner.downcast_ref::<Reported>() {
+            return reported.code;
+        }
+        if inner.is::<StrictModeError>() {
+            return STRICT;
+        }
+        if inner.is::<GitError>() {
+            return GIT_FAILURE;
+        }
+    }
+
+    match e.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => INVALID_INPUT,
+        io::ErrorKind::NotFound => NOT_FOUND,
+        io::ErrorKind::PermissionDenied => AUTH_FAILURE,
+        _ => FAILURE,
+    }
+}
+
+// the `kind` of a structured error, so scripts need not know the numbers
+pub fn kind(code: i32) -> &'static str {
+    match code {
+        INVALID_INPUT => "invalid_input",
+        NOT_FOUND => "not_found",
+        GIT_FAILURE => "git_failure",
+        STRICT => "strict",
+        AUTH_FAILURE => "auth_failure",
+        _ => "failure",
+    }
+}
+
+// errors already printed by the time they reach main, which then only
+// sets the exit status
+pub fn is_reported(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| {
+        inner.is::<Reported>() || inner.is::<StrictModeError>() || inner.is::<ChecksFailedError>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        let code_of = |e: io::Error| code(&e);
+
+        assert_eq!(code_of(io::Error::from(io::ErrorKind::NotFound)), NOT_FOUND);
+        assert_eq!(
+            code_of(io::Error::new(io::ErrorKind::InvalidInput, "bad name")),
+            INVALID_INPUT
+        );
+        assert_eq!(
+            code_of(io::Error::other(GitError("git init failed".into()))),
+            GIT_FAILURE
+        );
+        assert_eq!(code_of(io::Error::other(StrictModeError(2))), STRICT);
+        assert_eq!(code_of(io::Error::other(ChecksFailedError(1))), FAILURE);
+        assert_eq!(
+            code_of(io::Error::other(Reported {
+                code: NOT_FOUND,
+                message: "profile not found".into(),
+            })),
+            NOT_FOUND
+        );
+        assert_eq!(code_of(io::Error::other("boom")), FAILURE);
+
+        assert!(is_reported(&io::Error::other(StrictModeError(1))));
+        assert!(!is_reported(&io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(kind(NOT_FOUND), "not_found");
+    }
+}
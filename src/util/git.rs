@@ -1,9 +1,14 @@
+use crate::core::remote::{RemoteUrl, Scheme};
 use std::{
     env,
     ffi::OsStr,
     fs,
     path::{Path, PathBuf},
     process::{Command, Output},
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 pub fn run_git<I, S>(repo: &Path, args: I) -> Result<Output, std::io::Error>
@@ -11,12 +16,88 @@ where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    Command::new("git").arg("-C").arg(repo).args(args).output()
+    output(Command::new("git").arg("-C").arg(repo).args(args))
+}
+
+// a git, gh or glab command that ran and failed
+#[derive(Debug)]
+pub struct GitError(pub String);
+
+impl std::fmt::Display for GitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GitError {}
+
+fn failed(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::other(GitError(message.into()))
+}
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+// `--trace-git`: echo every command `output` runs to stderr, with how it
+// ended and what it wrote to stderr
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+// runs `command` for its output; `-vv` logs every command octopush runs and
+// its exit status, `--trace-git` echoes them along with their stderr
+pub fn output(command: &mut Command) -> Result<Output, std::io::Error> {
+    let line = command_line(command);
+    let trace = TRACE.load(Ordering::Relaxed);
+    log::debug!("running {}", line);
+    if trace {
+        eprintln!("+ {}", line);
+    }
+
+    let result = command.output();
+    let status = match &result {
+        Ok(o) => match o.status.code() {
+            Some(code) => format!("exit {}", code),
+            None => "killed by a signal".to_string(),
+        },
+        Err(e) => format!("failed to start: {}", e),
+    };
+    log::debug!("{} {}", command.get_program().to_string_lossy(), status);
+    if trace {
+        eprintln!("  {}", status);
+        if let Ok(o) = &result {
+            for stderr in String::from_utf8_lossy(&o.stderr).lines() {
+                eprintln!("  | {}", stderr);
+            }
+        }
+    }
+
+    result
+}
+
+// `command` as it could be pasted into a shell, with the environment it
+// sets up front: `GIT_SSH_COMMAND="ssh -i ~/.ssh/work" git -C /src/app fetch`
+fn command_line(command: &Command) -> String {
+    let envs = command.get_envs().filter_map(|(key, value)| {
+        Some(format!(
+            "{}={}",
+            key.to_string_lossy(),
+            quote_ssh_arg(&value?.to_string_lossy())
+        ))
+    });
+    let program = command.get_program().to_string_lossy().into_owned();
+    let args = command
+        .get_args()
+        .map(|arg| quote_ssh_arg(&arg.to_string_lossy()));
+
+    envs.chain([program])
+        .chain(args)
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 pub fn ensure_repo(repo: &Path) -> Result<(), std::io::Error> {
-    let git_dir = repo.join(".git");
-    if git_dir.is_dir() {
+    // linked worktrees have a `.git` file pointing into the main repository
+    if repo.join(".git").exists() && toplevel(repo).is_ok() {
         Ok(())
     } else {
         Err(std::io::Error::new(
@@ -26,18 +107,227 @@ pub fn ensure_repo(repo: &Path) -> Result<(), std::io::Error> {
     }
 }
 
+// root of the working tree containing `path`, which is the linked worktree
+// itself when `path` is inside one
+pub fn toplevel(path: &Path) -> Result<PathBuf, std::io::Error> {
+    rev_parse_path(path, &["--show-toplevel"])
+}
+
+// the `.git` directory shared by the main working tree and all its linked
+// worktrees; it holds the config that `git config --local` writes to
+pub fn common_dir(path: &Path) -> Result<PathBuf, std::io::Error> {
+    rev_parse_path(path, &["--path-format=absolute", "--git-common-dir"])
+}
+
+fn rev_parse_path(path: &Path, args: &[&str]) -> Result<PathBuf, std::io::Error> {
+    let out = run_git(
+        path,
+        std::iter::once("rev-parse").chain(args.iter().copied()),
+    )?;
+    if let Some(repo) = dubious_repository(&String::from_utf8_lossy(&out.stderr)) {
+        return Err(failed(format!(
+            "git refuses to work in '{}' because another user owns it, trust it with `git config --global --add safe.directory '{}'`",
+            repo.display(),
+            repo.display()
+        )));
+    }
+    let value = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !out.status.success() || value.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "not a git repository",
+        ));
+    }
+    Ok(PathBuf::from(value))
+}
+
+// The repository git refuses to touch since another user owns it, as in
+// containers and mounted volumes, until it is listed in safe.directory.
+pub fn dubious_ownership(path: &Path) -> Result<Option<PathBuf>, std::io::Error> {
+    let out = run_git(path, ["rev-parse", "--git-dir"])?;
+    Ok(dubious_repository(&String::from_utf8_lossy(&out.stderr)))
+}
+
+// `fatal: detected dubious ownership in repository at '/srv/app'`
+fn dubious_repository(stderr: &str) -> Option<PathBuf> {
+    let (_, rest) = stderr.split_once("detected dubious ownership in repository at '")?;
+    let (repo, _) = rest.split_once('\'')?;
+    Some(PathBuf::from(repo))
+}
+
+pub fn add_safe_directory(repo: &Path) -> Result<(), std::io::Error> {
+    let repo = repo.to_string_lossy();
+    let o = output(Command::new("git").args([
+        "config",
+        "--global",
+        "--add",
+        "safe.directory",
+        repo.as_ref(),
+    ]))?;
+    if !o.status.success() {
+        return Err(failed("failed to add the repository to safe.directory"));
+    }
+    Ok(())
+}
+
+pub fn init_repo(
+    path: &Path,
+    default_branch: Option<&str>,
+    template_dir: Option<&Path>,
+) -> Result<(), std::io::Error> {
+    let mut command = Command::new("git");
+    command.arg("init");
+    if let Some(branch) = default_branch {
+        command.arg("--initial-branch").arg(branch);
+    }
+    if let Some(dir) = template_dir {
+        command.arg("--template").arg(dir);
+    }
+
+    let o = output(command.arg(path))?;
+    if !o.status.success() {
+        return Err(failed(format!(
+            "git init failed: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+pub fn clone_repo(
+    url: &str,
+    dest: &Path,
+    template_dir: Option<&Path>,
+    envs: &[(String, String)],
+) -> Result<(), std::io::Error> {
+    let mut command = Command::new("git");
+    command.arg("clone");
+    if let Some(dir) = template_dir {
+        command.arg("--template").arg(dir);
+    }
+
+    let o = output(
+        command
+            .arg("--")
+            .arg(url)
+            .arg(dest)
+            .envs(envs.iter().map(|(k, v)| (k, v))),
+    )?;
+    if !o.status.success() {
+        return Err(failed(format!(
+            "git clone failed: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
 pub fn set_local_identity(repo: &Path, name: &str, email: &str) -> Result<(), std::io::Error> {
     let o1 = run_git(repo, ["config", "--local", "user.name", name])?;
     if !o1.status.success() {
-        return Err(std::io::Error::other("failed to set user.name"));
+        return Err(failed("failed to set user.name"));
     }
     let o2 = run_git(repo, ["config", "--local", "user.email", email])?;
     if !o2.status.success() {
-        return Err(std::io::Error::other("failed to set user.email"));
+        return Err(failed("failed to set user.email"));
+    }
+    Ok(())
+}
+
+pub fn get_local_config(repo: &Path, key: &str) -> Result<Option<String>, std::io::Error> {
+    let o = run_git(repo, ["config", "--local", "--get", key])?;
+    if o.status.success() {
+        Ok(Some(String::from_utf8_lossy(&o.stdout).trim().to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+// effective value as git resolves it (local, then global, then system)
+pub fn get_config(repo: &Path, key: &str) -> Result<Option<String>, std::io::Error> {
+    let o = run_git(repo, ["config", "--get", key])?;
+    if o.status.success() {
+        Ok(Some(String::from_utf8_lossy(&o.stdout).trim().to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+// `key` as git resolves it in `repo`, with the scope it was found in:
+// `command` (`-c` or GIT_CONFIG_*), `worktree`, `local`, `global` or `system`
+pub fn get_config_with_scope(
+    repo: &Path,
+    key: &str,
+) -> Result<Option<(String, String)>, std::io::Error> {
+    let o = run_git(repo, ["config", "--show-scope", "--get", key])?;
+    if !o.status.success() {
+        return Ok(None);
+    }
+    let out = String::from_utf8_lossy(&o.stdout);
+    Ok(out
+        .trim_end()
+        .split_once('\t')
+        .map(|(scope, value)| (value.to_string(), scope.to_string())))
+}
+
+pub fn get_global_config(key: &str) -> Result<Option<String>, std::io::Error> {
+    let o = output(Command::new("git").args(["config", "--global", "--get", key]))?;
+    if o.status.success() {
+        Ok(Some(String::from_utf8_lossy(&o.stdout).trim().to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn set_global_config(key: &str, value: &str) -> Result<(), std::io::Error> {
+    let o = output(Command::new("git").args(["config", "--global", key, value]))?;
+    if !o.status.success() {
+        return Err(failed(format!("failed to set global {}", key)));
+    }
+    Ok(())
+}
+
+pub fn set_local_config(repo: &Path, key: &str, value: &str) -> Result<(), std::io::Error> {
+    let o = run_git(repo, ["config", "--local", key, value])?;
+    if !o.status.success() {
+        return Err(failed(format!("failed to set {}", key)));
+    }
+    Ok(())
+}
+
+// adds `path` to the global config's include.path values unless it is there
+pub fn add_global_include(path: &str) -> Result<(), std::io::Error> {
+    let o = output(Command::new("git").args(["config", "--global", "--get-all", "include.path"]))?;
+    if String::from_utf8_lossy(&o.stdout)
+        .lines()
+        .any(|line| line == path)
+    {
+        return Ok(());
+    }
+
+    let o =
+        output(Command::new("git").args(["config", "--global", "--add", "include.path", path]))?;
+    if !o.status.success() {
+        return Err(failed("failed to add global include.path"));
     }
     Ok(())
 }
 
+// removes `path` from the global config's include.path values, returning
+// whether it was there
+pub fn remove_global_include(path: &str) -> Result<bool, std::io::Error> {
+    let o = output(Command::new("git").args([
+        "config",
+        "--global",
+        "--fixed-value",
+        "--unset-all",
+        "include.path",
+        path,
+    ]))?;
+    // status 5 means the value was not there
+    Ok(o.status.success())
+}
+
 pub fn unset_local(repo: &Path, key: &str) -> Result<(), std::io::Error> {
     let _ = run_git(repo, ["config", "--local", "--unset", key]);
     Ok(())
@@ -53,19 +343,367 @@ pub fn get_remote_url(repo: &Path, remote: &str) -> Result<Option<String>, std::
     }
 }
 
+// `git rev-parse --path-format`, used to find worktree roots, needs 2.31
+pub const MIN_VERSION: (u32, u32) = (2, 31);
+
+const INSTALL_HINT: &str = "install it from https://git-scm.com/downloads";
+
+// (major, minor) of the installed git, asked once per process
+pub fn version() -> Result<(u32, u32), std::io::Error> {
+    static VERSION: OnceLock<Result<(u32, u32), (std::io::ErrorKind, String)>> = OnceLock::new();
+    VERSION
+        .get_or_init(|| read_version().map_err(|e| (e.kind(), e.to_string())))
+        .clone()
+        .map_err(|(kind, message)| std::io::Error::new(kind, message))
+}
+
+fn read_version() -> Result<(u32, u32), std::io::Error> {
+    let out = match output(Command::new("git").arg("--version")) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("git was not found on PATH, {}", INSTALL_HINT),
+            ));
+        }
+        out => out?,
+    };
+    let text = String::from_utf8_lossy(&out.stdout);
+    parse_version(&text).ok_or_else(|| {
+        failed(format!(
+            "unexpected `git --version` output: {}",
+            text.trim()
+        ))
+    })
+}
+
+// `git version 2.39.5`, `git version 2.39.5 (Apple Git-154)` or
+// `git version 2.45.1.windows.1`
+fn parse_version(text: &str) -> Option<(u32, u32)> {
+    let mut numbers = text
+        .split_whitespace()
+        .nth(2)?
+        .split('.')
+        .map(|part| part.parse::<u32>().ok());
+    Some((numbers.next()??, numbers.next()??))
+}
+
+// the installed git when it is recent enough, otherwise an error saying what
+// to install
+pub fn require() -> Result<(u32, u32), std::io::Error> {
+    let version = version()?;
+    if version < MIN_VERSION {
+        return Err(failed(format!(
+            "git {}.{} is older than the {}.{} octopush needs, {}",
+            version.0, version.1, MIN_VERSION.0, MIN_VERSION.1, INSTALL_HINT
+        )));
+    }
+    Ok(version)
+}
+
+// where git looks for the repository's hooks, honouring core.hooksPath and
+// linked worktrees
+pub fn hooks_dir(repo: &Path) -> Result<PathBuf, std::io::Error> {
+    let path = rev_parse_path(repo, &["--git-path", "hooks"])?;
+    Ok(if path.is_relative() {
+        repo.join(path)
+    } else {
+        path
+    })
+}
+
+pub fn list_remotes(repo: &Path) -> Result<Vec<String>, std::io::Error> {
+    let o = run_git(repo, ["remote"])?;
+    if !o.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(String::from_utf8_lossy(&o.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+pub fn current_branch(repo: &Path) -> Result<Option<String>, std::io::Error> {
+    let o = run_git(repo, ["symbolic-ref", "--quiet", "--short", "HEAD"])?;
+    if o.status.success() {
+        let s = String::from_utf8_lossy(&o.stdout).trim().to_string();
+        if s.is_empty() { Ok(None) } else { Ok(Some(s)) }
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn primary_remote(repo: &Path) -> Result<Option<String>, std::io::Error> {
+    SystemGit.primary_remote(repo)
+}
+
 pub fn set_remote_url(repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error> {
     let o = run_git(repo, ["remote", "set-url", remote, url])?;
     if !o.status.success() {
-        return Err(std::io::Error::other("failed to set remote url"));
+        return Err(failed("failed to set remote url"));
     }
     Ok(())
 }
 
+pub fn get_push_url(repo: &Path, remote: &str) -> Result<Option<String>, std::io::Error> {
+    SystemGit.get_push_url(repo, remote)
+}
+
+pub fn set_push_url(repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error> {
+    let o = run_git(repo, ["remote", "set-url", "--push", remote, url])?;
+    if !o.status.success() {
+        return Err(failed("failed to set remote push url"));
+    }
+    Ok(())
+}
+
+// The reads and writes a profile needs from a repository. Plans are built
+// and applied through it, so library users and tests can swap the git
+// binary for their own implementation.
+pub trait GitBackend {
+    // fails unless `repo` is the root of a working tree
+    fn ensure_repo(&self, repo: &Path) -> Result<(), std::io::Error>;
+    fn get_local_config(&self, repo: &Path, key: &str) -> Result<Option<String>, std::io::Error>;
+    // effective value as git resolves it (local, then global, then system)
+    fn get_config(&self, repo: &Path, key: &str) -> Result<Option<String>, std::io::Error>;
+    fn set_local_config(&self, repo: &Path, key: &str, value: &str) -> Result<(), std::io::Error>;
+    fn unset_local(&self, repo: &Path, key: &str) -> Result<(), std::io::Error>;
+    fn list_remotes(&self, repo: &Path) -> Result<Vec<String>, std::io::Error>;
+    fn current_branch(&self, repo: &Path) -> Result<Option<String>, std::io::Error>;
+    fn get_remote_url(&self, repo: &Path, remote: &str) -> Result<Option<String>, std::io::Error>;
+    fn set_remote_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error>;
+    fn set_push_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error>;
+
+    // the separate push URL of a remote, when it has one
+    fn get_push_url(&self, repo: &Path, remote: &str) -> Result<Option<String>, std::io::Error> {
+        self.get_local_config(repo, &format!("remote.{}.pushurl", remote))
+    }
+
+    // Picks the remote octopush should manage. `origin` wins when present,
+    // otherwise the remote the current branch pushes to / tracks, otherwise
+    // the only remote. Returns None when there is no remote or it's ambiguous.
+    fn primary_remote(&self, repo: &Path) -> Result<Option<String>, std::io::Error> {
+        let remotes = self.list_remotes(repo)?;
+        if remotes.iter().any(|r| r == "origin") {
+            return Ok(Some("origin".to_string()));
+        }
+
+        let mut candidates = Vec::new();
+        if let Some(branch) = self.current_branch(repo)? {
+            candidates.push(format!("branch.{}.pushRemote", branch));
+            candidates.push("remote.pushDefault".to_string());
+            candidates.push(format!("branch.{}.remote", branch));
+        } else {
+            candidates.push("remote.pushDefault".to_string());
+        }
+
+        for key in candidates {
+            if let Some(name) = self.get_config(repo, &key)?
+                && remotes.contains(&name)
+            {
+                return Ok(Some(name));
+            }
+        }
+
+        if remotes.len() == 1 {
+            return Ok(remotes.into_iter().next());
+        }
+
+        Ok(None)
+    }
+}
+
+// the git binary on PATH, through the functions above
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemGit;
+
+impl GitBackend for SystemGit {
+    fn ensure_repo(&self, repo: &Path) -> Result<(), std::io::Error> {
+        ensure_repo(repo)
+    }
+
+    fn get_local_config(&self, repo: &Path, key: &str) -> Result<Option<String>, std::io::Error> {
+        get_local_config(repo, key)
+    }
+
+    fn get_config(&self, repo: &Path, key: &str) -> Result<Option<String>, std::io::Error> {
+        get_config(repo, key)
+    }
+
+    fn set_local_config(&self, repo: &Path, key: &str, value: &str) -> Result<(), std::io::Error> {
+        set_local_config(repo, key, value)
+    }
+
+    fn unset_local(&self, repo: &Path, key: &str) -> Result<(), std::io::Error> {
+        unset_local(repo, key)
+    }
+
+    fn list_remotes(&self, repo: &Path) -> Result<Vec<String>, std::io::Error> {
+        list_remotes(repo)
+    }
+
+    fn current_branch(&self, repo: &Path) -> Result<Option<String>, std::io::Error> {
+        current_branch(repo)
+    }
+
+    fn get_remote_url(&self, repo: &Path, remote: &str) -> Result<Option<String>, std::io::Error> {
+        get_remote_url(repo, remote)
+    }
+
+    fn set_remote_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error> {
+        set_remote_url(repo, remote, url)
+    }
+
+    fn set_push_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), std::io::Error> {
+        set_push_url(repo, remote, url)
+    }
+}
+
+pub const GH_CREDENTIAL_HELPER: &str = "!gh auth git-credential";
+
+// credential helper running a specific gh binary
+pub fn gh_credential_helper(gh: &str) -> String {
+    if gh == "gh" {
+        GH_CREDENTIAL_HELPER.to_string()
+    } else {
+        format!("!'{}' auth git-credential", gh.replace('\'', r"'\''"))
+    }
+}
+
+pub fn is_gh_credential_helper(helper: &str) -> bool {
+    helper.starts_with('!') && helper.ends_with(" auth git-credential") && !helper.contains("glab")
+}
+
+pub const GLAB_CREDENTIAL_HELPER: &str = "!glab auth git-credential";
+
+// octopush's own helper, answering with the token of `token` profiles
+pub const TOKEN_CREDENTIAL_HELPER: &str = "!octopush credential";
+
+pub fn is_glab_credential_helper(helper: &str) -> bool {
+    helper.starts_with('!') && helper.ends_with(" auth git-credential") && helper.contains("glab")
+}
+
+// the empty ssh config, so ~/.ssh/config cannot swap in another identity
+pub const NULL_DEVICE: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+pub fn ssh_command(key_path: &str) -> String {
+    format!("ssh -i {} -F {}", quote_ssh_arg(key_path), NULL_DEVICE)
+}
+
+// `ssh_command` for a key held by an ssh agent such as 1Password's: the key
+// path names the public key to ask the agent for
+pub fn ssh_command_with_agent(key_path: &str, agent: &str) -> String {
+    format!(
+        "ssh -i {} -o IdentitiesOnly=yes -o \"IdentityAgent={}\" -F {}",
+        quote_ssh_arg(key_path),
+        escape_double_quoted(agent),
+        NULL_DEVICE
+    )
+}
+
+// git hands core.sshCommand to a shell, also on Windows, so paths with
+// spaces or backslashes (`C:\Users\John Doe\.ssh\id_ed25519`) are double
+// quoted; plain ones stay as they are so `~` still reads naturally
+fn quote_ssh_arg(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+~@:,=%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("\"{}\"", escape_double_quoted(arg))
+    }
+}
+
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// extracts the identity file from a core.sshCommand value such as
+// `ssh -i ~/.ssh/id_work -F /dev/null`
+pub fn ssh_key_from_command(command: &str) -> Option<String> {
+    let words = split_command(command);
+    let mut it = words.iter();
+    while let Some(word) = it.next() {
+        if word == "-i" {
+            return it.next().cloned();
+        }
+        if let Some(key) = word.strip_prefix("-i")
+            && !key.is_empty()
+        {
+            return Some(key.to_string());
+        }
+    }
+    None
+}
+
+// splits a command line on whitespace, honouring single and double quotes
+// and backslash escapes the way sh does
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"')
+                if c == '\\'
+                    && let Some(&next) = chars.peek()
+                    && matches!(next, '"' | '\\' | '$' | '`') =>
+            {
+                current.push(next);
+                chars.next();
+            }
+            Some(_) => current.push(c),
+            // only before what sh would otherwise split or unquote on, so
+            // unquoted `C:\Users\john\.ssh\id` from older versions reads back
+            None if c == '\\'
+                && let Some(&next) = chars.peek()
+                && (next.is_whitespace() || "\"'\\$`".contains(next)) =>
+            {
+                current.push(next);
+                chars.next();
+                in_word = true;
+            }
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+
+    words
+}
+
 pub fn ensure_ssh_command(repo: &Path, key_path: &str) -> Result<(), std::io::Error> {
-    let val = format!("ssh -i {} -F /dev/null", key_path);
+    let val = ssh_command(key_path);
     let o = run_git(repo, ["config", "--local", "core.sshCommand", &val])?;
     if !o.status.success() {
-        return Err(std::io::Error::other("failed to set core.sshCommand"));
+        return Err(failed("failed to set core.sshCommand"));
     }
     Ok(())
 }
@@ -81,11 +719,11 @@ pub fn set_gh_credential_helper(repo: &Path) -> Result<(), std::io::Error> {
             "config",
             "--local",
             "credential.helper",
-            "!gh auth git-credential",
+            GH_CREDENTIAL_HELPER,
         ],
     )?;
     if !o.status.success() {
-        return Err(std::io::Error::other("failed to set gh credential helper"));
+        return Err(failed("failed to set gh credential helper"));
     }
     let _ = run_git(
         repo,
@@ -100,44 +738,81 @@ pub fn clear_gh_credential_helper(repo: &Path) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+// Splits a remote URL into (host, owner, repo). Besides the usual GitHub
+// shapes this understands user info (`https://jane@bitbucket.org/...`),
+// explicit ports (`ssh://git@bitbucket.corp.com:7999/...`) and Bitbucket
+// Server's `/scm/` prefix on https clone URLs.
 pub fn parse_remote(url: &str) -> Option<(String, String, String)> {
-    if let Some(rest) = url.strip_prefix("git@") {
-        let mut parts = rest.splitn(2, ":");
-        let host = parts.next()?.to_string();
-        let path = parts.next()?;
-        return split_path(host, path);
+    let remote: RemoteUrl = url.parse().ok()?;
+    // scp-like urls for another user than `git` are left alone, since
+    // rewriting them to `git@` would lose the user
+    if remote.scheme == Scheme::Scp && remote.user.as_deref() != Some("git") {
+        return None;
     }
+    Some((
+        remote.host.clone(),
+        remote.owner()?,
+        remote.repo_name().to_string(),
+    ))
+}
 
-    if let Some(rest) = url.strip_prefix("ssh://") {
-        let after_user = rest.split('@').next_back().unwrap_or(rest);
-        let mut parts = after_user.splitn(2, '/');
-        let host = parts.next()?.to_string();
-        let path = parts.next()?;
-        return split_path(host, path);
+// Whether `url` has the plain shape `to_ssh` / `to_https` produce, so that
+// converting it between schemes keeps pointing at the same repository, see
+// `RemoteUrl::is_rewritable`.
+pub fn is_rewritable_remote(url: &str) -> bool {
+    parse_remote(url).is_some()
+        && url
+            .parse()
+            .is_ok_and(|remote: RemoteUrl| remote.is_rewritable())
+}
+
+// the explicit port of an `ssh://` or `http(s)://` remote
+pub fn remote_port(url: &str) -> Option<u16> {
+    let remote: RemoteUrl = url.parse().ok()?;
+    if remote.scheme == Scheme::Git {
+        return None;
     }
+    remote.port
+}
 
-    if let Some(rest) = url.strip_prefix("https://") {
-        let mut parts = rest.splitn(2, '/');
-        let host = parts.next()?.to_string();
-        let path = parts.next()?;
-        return split_path(host, path);
+// `host:port` for urls that need an explicit port, `host` otherwise
+pub fn with_port(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
     }
-    None
 }
 
-fn split_path(host: String, path: &str) -> Option<(String, String, String)> {
-    let mut it = path.trim_matches('/').splitn(2, '/');
-    let owner = it.next()?.to_string();
-    let repo = it.next()?.trim_end_matches(".git").to_string();
-    Some((host, owner, repo))
+// plain http:// and git:// remotes send everything, credentials included,
+// unencrypted
+pub fn is_insecure_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("git://")
 }
 
+pub fn insecure_remotes(repo: &Path) -> Result<Vec<(String, String)>, std::io::Error> {
+    let mut insecure = Vec::new();
+    for remote in list_remotes(repo)? {
+        if let Some(url) = get_remote_url(repo, &remote)?
+            && is_insecure_url(&url)
+        {
+            insecure.push((remote, url));
+        }
+    }
+    Ok(insecure)
+}
+
+// `host` may carry a port (see `with_port`), which scp-like urls cannot
+// express, so those come out in `ssh://` form
 pub fn to_ssh(host: &str, owner: &str, repo: &str) -> String {
-    format!("git@{}:{}/{}.git", host, owner, repo)
+    let (host, port) = match host.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()),
+        None => (host, None),
+    };
+    RemoteUrl::ssh(host, port, owner, repo).to_string()
 }
 
 pub fn to_https(host: &str, owner: &str, repo: &str) -> String {
-    format!("https://{}/{}/{}.git", host, owner, repo)
+    RemoteUrl::https(host, owner, repo).to_string()
 }
 
 pub fn gh_hosts_file() -> Option<PathBuf> {
@@ -170,9 +845,7 @@ pub fn is_gh_authenticated(host: &str) -> bool {
         return true;
     }
 
-    let out = std::process::Command::new("gh")
-        .args(["auth", "status", "--hostname", host])
-        .output();
+    let out = output(std::process::Command::new("gh").args(["auth", "status", "--hostname", host]));
 
     match out {
         Ok(o) => o.status.success(),
@@ -180,6 +853,52 @@ pub fn is_gh_authenticated(host: &str) -> bool {
     }
 }
 
+pub fn is_glab_authenticated(host: &str) -> bool {
+    if std::env::var_os("GITLAB_TOKEN").is_some() {
+        return true;
+    }
+
+    let out =
+        output(std::process::Command::new("glab").args(["auth", "status", "--hostname", host]));
+
+    match out {
+        Ok(o) => o.status.success(),
+        Err(_) => false,
+    }
+}
+
+// runs `gh auth login` (or glab's) attached to the terminal so the user can
+// answer its prompts
+pub fn auth_login(program: &str, host: &str) -> Result<(), std::io::Error> {
+    let status = Command::new(program)
+        .args(["auth", "login", "--hostname", host])
+        .status()?;
+
+    if !status.success() {
+        return Err(failed(format!(
+            "`{} auth login --hostname {}` failed",
+            program, host
+        )));
+    }
+
+    Ok(())
+}
+
+// the account gh is logged into for `host`, read from gh's hosts.yml
+pub fn gh_user(host: &str) -> Option<String> {
+    let text = fs::read_to_string(gh_hosts_file()?).ok()?;
+
+    let mut in_host = false;
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            in_host = line.trim_end() == format!("{}:", host);
+        } else if in_host && let Some(user) = line.trim().strip_prefix("user:") {
+            return Some(user.trim().to_string()).filter(|user| !user.is_empty());
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -212,6 +931,56 @@ mod tests {
         assert_eq!(url.as_deref(), Some("https://example.com/acme/app.git"));
     }
 
+    #[test]
+    fn primary_remote_prefers_origin_then_tracking_then_single() {
+        let t = TempConfig::new().unwrap();
+
+        assert_eq!(primary_remote(&t.repo).unwrap(), None);
+
+        run_git(
+            &t.repo,
+            [
+                "remote",
+                "add",
+                "upstream",
+                "https://example.com/up/app.git",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            primary_remote(&t.repo).unwrap().as_deref(),
+            Some("upstream")
+        );
+
+        // two remotes and no hint is ambiguous
+        run_git(
+            &t.repo,
+            ["remote", "add", "fork", "https://example.com/me/app.git"],
+        )
+        .unwrap();
+        assert_eq!(primary_remote(&t.repo).unwrap(), None);
+
+        let branch = current_branch(&t.repo).unwrap().unwrap();
+        run_git(
+            &t.repo,
+            ["config", &format!("branch.{}.remote", branch), "fork"],
+        )
+        .unwrap();
+        assert_eq!(primary_remote(&t.repo).unwrap().as_deref(), Some("fork"));
+
+        run_git(
+            &t.repo,
+            [
+                "remote",
+                "add",
+                "origin",
+                "https://example.com/acme/app.git",
+            ],
+        )
+        .unwrap();
+        assert_eq!(primary_remote(&t.repo).unwrap().as_deref(), Some("origin"));
+    }
+
     #[test]
     fn set_and_clear_local_identity() {
         let t = TempConfig::new().unwrap();
@@ -230,6 +999,22 @@ mod tests {
         unset_local(&t.repo, "user.signingkey").unwrap();
     }
 
+    #[test]
+    fn get_set_and_unset_local_config() {
+        let t = TempConfig::new().unwrap();
+
+        assert_eq!(get_local_config(&t.repo, "user.name").unwrap(), None);
+
+        set_local_config(&t.repo, "user.name", "Test User").unwrap();
+        assert_eq!(
+            get_local_config(&t.repo, "user.name").unwrap().as_deref(),
+            Some("Test User")
+        );
+
+        unset_local(&t.repo, "user.name").unwrap();
+        assert_eq!(get_local_config(&t.repo, "user.name").unwrap(), None);
+    }
+
     #[cfg(not(windows))]
     #[test]
     fn set_and_clear_ssh_command() {
@@ -244,6 +1029,172 @@ mod tests {
         assert!(!g2.status.success());
     }
 
+    #[cfg(windows)]
+    #[test]
+    fn sets_ssh_command_for_windows_key_paths() {
+        let t = TempConfig::new().unwrap();
+        let key = r"C:\Users\John Doe\.ssh\id_ed25519";
+
+        ensure_ssh_command(&t.repo, key).unwrap();
+        let command = get_local_config(&t.repo, "core.sshCommand")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            command,
+            r#"ssh -i "C:\\Users\\John Doe\\.ssh\\id_ed25519" -F NUL"#
+        );
+        assert_eq!(ssh_key_from_command(&command).as_deref(), Some(key));
+    }
+
+    #[test]
+    fn traces_commands_as_shell_lines() {
+        let mut command = Command::new("git");
+        command.env("GIT_SSH_COMMAND", "ssh -i ~/.ssh/work").args([
+            "-C",
+            "/src/my app",
+            "config",
+            "user.email",
+        ]);
+        assert_eq!(
+            command_line(&command),
+            "GIT_SSH_COMMAND=\"ssh -i ~/.ssh/work\" git -C \"/src/my app\" config user.email"
+        );
+    }
+
+    #[test]
+    fn quotes_ssh_key_paths_for_the_shell() {
+        assert_eq!(
+            ssh_command("~/.ssh/id_work"),
+            format!("ssh -i ~/.ssh/id_work -F {}", NULL_DEVICE)
+        );
+        for key in [
+            "/home/john doe/.ssh/id_work",
+            r"C:\Users\John Doe\.ssh\id_ed25519",
+            r#"/tmp/it's "quoted" $HOME"#,
+        ] {
+            assert_eq!(
+                ssh_key_from_command(&ssh_command(key)).as_deref(),
+                Some(key)
+            );
+            assert_eq!(
+                ssh_key_from_command(&ssh_command_with_agent(key, r"\\.\pipe\agent")).as_deref(),
+                Some(key)
+            );
+        }
+        assert_eq!(
+            ssh_command_with_agent("~/.ssh/id.pub", r"\\.\pipe\openssh-ssh-agent"),
+            format!(
+                r#"ssh -i ~/.ssh/id.pub -o IdentitiesOnly=yes -o "IdentityAgent=\\\\.\\pipe\\openssh-ssh-agent" -F {}"#,
+                NULL_DEVICE
+            )
+        );
+        // written unquoted by older versions
+        assert_eq!(
+            ssh_key_from_command(r"ssh -i C:\Users\john\.ssh\id -F NUL").as_deref(),
+            Some(r"C:\Users\john\.ssh\id")
+        );
+    }
+
+    #[test]
+    fn parses_and_requires_git_versions() {
+        assert_eq!(parse_version("git version 2.39.5\n"), Some((2, 39)));
+        assert_eq!(
+            parse_version("git version 2.39.5 (Apple Git-154)"),
+            Some((2, 39))
+        );
+        assert_eq!(parse_version("git version 2.45.1.windows.1"), Some((2, 45)));
+        assert_eq!(parse_version("git: command not found"), None);
+        assert_eq!(parse_version("git version 2"), None);
+
+        assert!(require().unwrap() >= MIN_VERSION);
+        assert_eq!(version().unwrap(), require().unwrap());
+    }
+
+    #[test]
+    fn finds_repositories_owned_by_another_user() {
+        let stderr = "fatal: detected dubious ownership in repository at '/srv/shared app'\nTo add an exception for this directory, call:\n\n\tgit config --global --add safe.directory '/srv/shared app'\n";
+        assert_eq!(
+            dubious_repository(stderr),
+            Some(PathBuf::from("/srv/shared app"))
+        );
+        assert_eq!(
+            dubious_repository(
+                "fatal: not a git repository (or any of the parent directories): .git"
+            ),
+            None
+        );
+
+        let t = TempConfig::new().unwrap();
+        assert_eq!(dubious_ownership(&t.repo).unwrap(), None);
+    }
+
+    #[test]
+    fn lists_insecure_remotes() {
+        let t = TempConfig::new().unwrap();
+
+        run_git(
+            &t.repo,
+            [
+                "remote",
+                "add",
+                "origin",
+                "https://example.com/acme/app.git",
+            ],
+        )
+        .unwrap();
+        run_git(
+            &t.repo,
+            ["remote", "add", "mirror", "http://example.com/acme/app.git"],
+        )
+        .unwrap();
+
+        assert_eq!(
+            insecure_remotes(&t.repo).unwrap(),
+            vec![(
+                "mirror".to_string(),
+                "http://example.com/acme/app.git".to_string()
+            )]
+        );
+        assert!(is_insecure_url("git://example.com/acme/app.git"));
+        assert!(!is_insecure_url("git@example.com:acme/app.git"));
+    }
+
+    #[test]
+    fn builds_gh_credential_helper_for_custom_binary() {
+        assert_eq!(gh_credential_helper("gh"), GH_CREDENTIAL_HELPER);
+        let helper = gh_credential_helper("/nix/store/abc-gh/bin/gh");
+        assert_eq!(helper, "!'/nix/store/abc-gh/bin/gh' auth git-credential");
+        assert!(is_gh_credential_helper(&helper));
+        assert!(!is_gh_credential_helper("store"));
+        assert!(!is_gh_credential_helper(GLAB_CREDENTIAL_HELPER));
+        assert!(is_glab_credential_helper(GLAB_CREDENTIAL_HELPER));
+    }
+
+    #[test]
+    fn extracts_ssh_key_from_command() {
+        assert_eq!(
+            ssh_key_from_command("ssh -i ~/.ssh/id_work -F /dev/null").as_deref(),
+            Some("~/.ssh/id_work")
+        );
+        assert_eq!(
+            ssh_key_from_command(r#"ssh -i "/home/john doe/.ssh/key" -F /dev/null"#).as_deref(),
+            Some("/home/john doe/.ssh/key")
+        );
+        assert_eq!(
+            ssh_key_from_command("ssh -i/tmp/key").as_deref(),
+            Some("/tmp/key")
+        );
+        assert_eq!(ssh_key_from_command("ssh -F /dev/null"), None);
+    }
+
+    #[test]
+    fn reads_gh_user_for_host() {
+        let _t = TempConfig::new().unwrap();
+
+        assert_eq!(gh_user("github.com").as_deref(), Some("someone"));
+        assert_eq!(gh_user("gitlab.com"), None);
+    }
+
     #[test]
     fn gh_auth_env_token_is_authenticated() {
         let _cfg = TempConfig::new().unwrap();
@@ -284,6 +1235,25 @@ mod tests {
             ("github.com".into(), "acme".into(), "app".into())
         );
 
+        let bitbucket = ("bitbucket.org".into(), "team".into(), "app".into());
+        for url in [
+            "git@bitbucket.org:team/app.git",
+            "https://jane@bitbucket.org/team/app.git",
+            "ssh://git@bitbucket.org:7999/team/app.git",
+            "https://bitbucket.org/scm/team/app.git",
+        ] {
+            assert_eq!(parse_remote(url), Some(bitbucket.clone()), "{}", url);
+        }
+        assert!(is_rewritable_remote(
+            "https://jane@bitbucket.org/team/app.git"
+        ));
+        assert!(!is_rewritable_remote(
+            "ssh://git@bitbucket.org:7999/team/app.git"
+        ));
+        assert!(!is_rewritable_remote(
+            "https://bitbucket.org/scm/team/app.git"
+        ));
+
         assert_eq!(
             to_ssh("github.com", "acme", "app"),
             "git@github.com:acme/app.git"
@@ -293,4 +1263,52 @@ mod tests {
             "https://github.com/acme/app.git"
         );
     }
+
+    #[test]
+    fn parses_gitlab_subgroups() {
+        let subgroup = (
+            "gitlab.com".into(),
+            "group/subgroup".into(),
+            "project".into(),
+        );
+        for url in [
+            "git@gitlab.com:group/subgroup/project.git",
+            "https://gitlab.com/group/subgroup/project.git",
+            "ssh://git@gitlab.com/group/subgroup/project",
+        ] {
+            assert_eq!(parse_remote(url), Some(subgroup.clone()), "{}", url);
+        }
+
+        assert_eq!(
+            to_https("gitlab.com", "group/subgroup", "project"),
+            "https://gitlab.com/group/subgroup/project.git"
+        );
+        assert_eq!(
+            to_ssh("gitlab.com", "group/subgroup", "project"),
+            "git@gitlab.com:group/subgroup/project.git"
+        );
+    }
+
+    #[test]
+    fn keeps_explicit_ports() {
+        let url = "ssh://git@git.company.com:2222/team/app.git";
+        assert_eq!(
+            parse_remote(url),
+            Some(("git.company.com".into(), "team".into(), "app".into()))
+        );
+        assert_eq!(remote_port(url), Some(2222));
+        assert_eq!(
+            remote_port("https://jane@git.company.com:8443/team/app.git"),
+            Some(8443)
+        );
+        assert_eq!(remote_port("git@git.company.com:team/app.git"), None);
+        assert_eq!(remote_port("ssh://git@git.company.com/team/app.git"), None);
+
+        let host = with_port("git.company.com", remote_port(url));
+        assert_eq!(to_ssh(&host, "team", "app"), url);
+        assert_eq!(
+            to_https(&host, "team", "app"),
+            "https://git.company.com:2222/team/app.git"
+        );
+    }
 }
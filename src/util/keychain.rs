@@ -0,0 +1,44 @@
+use keyring::Entry;
+use std::io;
+
+const SERVICE: &str = "octopush";
+
+// Access tokens of `token` profiles live in the OS keychain (macOS Keychain,
+// Windows Credential Manager or the Secret Service), keyed by profile name,
+// and are never written to profiles.toml.
+fn entry(profile_name: &str) -> Result<Entry, io::Error> {
+    Entry::new(SERVICE, profile_name).map_err(io::Error::other)
+}
+
+pub fn set_token(profile_name: &str, token: &str) -> Result<(), io::Error> {
+    entry(profile_name)?
+        .set_password(token)
+        .map_err(io::Error::other)
+}
+
+pub fn get_token(profile_name: &str) -> Result<Option<String>, io::Error> {
+    match entry(profile_name)?.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+pub fn delete_token(profile_name: &str) -> Result<(), io::Error> {
+    match entry(profile_name)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+// The key encrypting profiles.toml when `encrypt_profiles` is on. Profile
+// names cannot contain spaces, so this entry never collides with a token.
+const PROFILES_KEY: &str = "profiles encryption key";
+
+pub fn set_profiles_key(key: &str) -> Result<(), io::Error> {
+    set_token(PROFILES_KEY, key)
+}
+
+pub fn get_profiles_key() -> Result<Option<String>, io::Error> {
+    get_token(PROFILES_KEY)
+}
@@ -0,0 +1,154 @@
+use crate::util::git::{GitBackend, GitError};
+use git2::{ConfigLevel, ErrorCode, Repository};
+use std::io;
+use std::path::Path;
+
+// A GitBackend on libgit2, built with the `git2` feature. App hands it
+// only to plans, so building and applying them reads and writes config and
+// remotes in-process; status, scan, preflight and the `git::` functions
+// still spawn the git binary.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Git2Backend;
+
+fn failed(e: git2::Error) -> io::Error {
+    io::Error::other(GitError(e.message().to_string()))
+}
+
+fn open(repo: &Path) -> Result<Repository, io::Error> {
+    Repository::open(repo)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "not a git repository"))
+}
+
+// a lookup that found nothing is None rather than an error
+fn found<T>(result: Result<T, git2::Error>) -> Result<Option<T>, io::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(failed(e)),
+    }
+}
+
+fn local_config(repo: &Path) -> Result<git2::Config, io::Error> {
+    open(repo)?
+        .config()
+        .and_then(|config| config.open_level(ConfigLevel::Local))
+        .map_err(failed)
+}
+
+impl GitBackend for Git2Backend {
+    fn ensure_repo(&self, repo: &Path) -> Result<(), io::Error> {
+        // linked worktrees have a `.git` file pointing into the main repository
+        if repo.join(".git").exists() && open(repo)?.workdir().is_some() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a git repository",
+            ))
+        }
+    }
+
+    fn get_local_config(&self, repo: &Path, key: &str) -> Result<Option<String>, io::Error> {
+        found(local_config(repo)?.get_string(key))
+    }
+
+    fn get_config(&self, repo: &Path, key: &str) -> Result<Option<String>, io::Error> {
+        found(open(repo)?.config().map_err(failed)?.get_string(key))
+    }
+
+    fn set_local_config(&self, repo: &Path, key: &str, value: &str) -> Result<(), io::Error> {
+        local_config(repo)?.set_str(key, value).map_err(failed)
+    }
+
+    fn unset_local(&self, repo: &Path, key: &str) -> Result<(), io::Error> {
+        found(local_config(repo)?.remove(key)).map(|_| ())
+    }
+
+    fn list_remotes(&self, repo: &Path) -> Result<Vec<String>, io::Error> {
+        let remotes = open(repo)?.remotes().map_err(failed)?;
+        Ok(remotes.iter().flatten().map(str::to_string).collect())
+    }
+
+    fn current_branch(&self, repo: &Path) -> Result<Option<String>, io::Error> {
+        // HEAD may point at a branch with no commits yet
+        let repository = open(repo)?;
+        let head = repository.find_reference("HEAD").map_err(failed)?;
+        Ok(head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .map(str::to_string))
+    }
+
+    fn get_remote_url(&self, repo: &Path, remote: &str) -> Result<Option<String>, io::Error> {
+        let repository = open(repo)?;
+        let remote = found(repository.find_remote(remote))?;
+        Ok(remote
+            .and_then(|remote| remote.url().map(str::to_string))
+            .filter(|url| !url.is_empty()))
+    }
+
+    fn set_remote_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), io::Error> {
+        open(repo)?.remote_set_url(remote, url).map_err(failed)
+    }
+
+    fn set_push_url(&self, repo: &Path, remote: &str, url: &str) -> Result<(), io::Error> {
+        open(repo)?
+            .remote_set_pushurl(remote, Some(url))
+            .map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+    use crate::util::git::{self, SystemGit};
+
+    #[test]
+    fn agrees_with_the_git_binary() {
+        let t = TempConfig::new().unwrap();
+        let repo = t.repo.as_path();
+        git::run_git(
+            repo,
+            ["remote", "add", "origin", "https://github.com/acme/app.git"],
+        )
+        .unwrap();
+
+        Git2Backend.ensure_repo(repo).unwrap();
+        assert!(Git2Backend.ensure_repo(&t.base).is_err());
+
+        Git2Backend
+            .set_local_config(repo, "user.email", "john@acme.com")
+            .unwrap();
+        Git2Backend
+            .set_push_url(repo, "origin", "git@github.com:john/app.git")
+            .unwrap();
+        for backend in [&Git2Backend as &dyn GitBackend, &SystemGit] {
+            assert_eq!(
+                backend.get_local_config(repo, "user.email").unwrap(),
+                Some("john@acme.com".to_string())
+            );
+            assert_eq!(
+                backend.get_push_url(repo, "origin").unwrap().as_deref(),
+                Some("git@github.com:john/app.git")
+            );
+            assert_eq!(backend.list_remotes(repo).unwrap(), vec!["origin"]);
+            assert_eq!(
+                backend.primary_remote(repo).unwrap().as_deref(),
+                Some("origin")
+            );
+            assert_eq!(backend.get_remote_url(repo, "upstream").unwrap(), None);
+        }
+        assert_eq!(
+            Git2Backend.current_branch(repo).unwrap(),
+            SystemGit.current_branch(repo).unwrap()
+        );
+
+        Git2Backend.unset_local(repo, "user.email").unwrap();
+        Git2Backend.unset_local(repo, "user.email").unwrap();
+        assert_eq!(
+            SystemGit.get_local_config(repo, "user.email").unwrap(),
+            None
+        );
+    }
+}
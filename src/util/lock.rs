@@ -0,0 +1,328 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+// how long a lock file without a pid is left alone before it counts as
+// abandoned
+const CORRUPT_GRACE: Duration = Duration::from_secs(2);
+
+// Threads of one process share a pid, so the lock file alone cannot keep
+// them apart. The holder is tracked here as well, which also makes the lock
+// reentrant for the thread that holds it.
+static HOLDER: Mutex<Option<(ThreadId, usize)>> = Mutex::new(None);
+static RELEASED: Condvar = Condvar::new();
+
+// Guards read-modify-write cycles on the configuration files. The lock file
+// holds the owner's pid so a lock left behind by a crashed process can be
+// told apart from a live one and recovered.
+#[derive(Debug)]
+pub struct ConfigLock {
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub alive: bool,
+}
+
+// what `octopush repo unlock` removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovedLock {
+    Owned(LockOwner),
+    // a lock file without a readable pid
+    Unreadable,
+}
+
+impl ConfigLock {
+    pub fn acquire(path: &Path) -> Result<Self, io::Error> {
+        Self::acquire_with_timeout(path, LOCK_TIMEOUT)
+    }
+
+    pub fn acquire_with_timeout(path: &Path, timeout: Duration) -> Result<Self, io::Error> {
+        let started_at = Instant::now();
+        let me = thread::current().id();
+        {
+            let mut holder = HOLDER.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                match *holder {
+                    Some((id, depth)) if id == me => {
+                        *holder = Some((id, depth + 1));
+                        return Ok(ConfigLock {
+                            path: path.to_path_buf(),
+                        });
+                    }
+                    Some(_) => {
+                        let remaining = timeout.saturating_sub(started_at.elapsed());
+                        if remaining.is_zero() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::WouldBlock,
+                                "configuration is locked by another thread of this process",
+                            ));
+                        }
+                        holder = RELEASED
+                            .wait_timeout(holder, remaining)
+                            .map(|(holder, _)| holder)
+                            .unwrap_or_else(|e| e.into_inner().0);
+                    }
+                    None => {
+                        *holder = Some((me, 1));
+                        break;
+                    }
+                }
+            }
+        }
+
+        match Self::create_lock_file(path, started_at, timeout) {
+            Ok(()) => Ok(ConfigLock {
+                path: path.to_path_buf(),
+            }),
+            Err(e) => {
+                release_holder();
+                Err(e)
+            }
+        }
+    }
+
+    // `started_at` is when acquiring began, so waiting on another thread
+    // counts against the same timeout
+    fn create_lock_file(
+        path: &Path,
+        started_at: Instant,
+        timeout: Duration,
+    ) -> Result<(), io::Error> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(std::process::id().to_string().as_bytes())?;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                // released in the meantime
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let pid = contents.trim().parse::<u32>().ok();
+            let stale = match pid {
+                // a crashed process left its lock behind
+                Some(pid) => !is_process_alive(pid),
+                // a crash between creating the file and writing the pid
+                // leaves it empty; a live writer gets a moment to finish
+                None => is_older_than(path, CORRUPT_GRACE),
+            };
+            if stale && take_over(path, &contents) {
+                continue;
+            }
+
+            if started_at.elapsed() >= timeout {
+                let holder = match pid {
+                    Some(pid) => format!("process {}", pid),
+                    None => "an unreadable lock file".to_string(),
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!(
+                        "configuration is locked by {}, run `octopush repo unlock` if it is stuck",
+                        holder
+                    ),
+                ));
+            }
+
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let mut holder = HOLDER.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((id, depth)) = *holder
+            && depth > 1
+        {
+            *holder = Some((id, depth - 1));
+            return;
+        }
+        drop(holder);
+
+        // `repo unlock --force` may have handed the lock to someone else
+        if owner(&self.path).is_some_and(|owner| owner.pid == std::process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+        release_holder();
+    }
+}
+
+fn release_holder() {
+    *HOLDER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    RELEASED.notify_all();
+}
+
+// who holds the lock at `path`, if anyone
+pub fn owner(path: &Path) -> Option<LockOwner> {
+    let pid = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Some(LockOwner {
+        pid,
+        alive: is_process_alive(pid),
+    })
+}
+
+// Moves a stale lock aside before deleting it. Had another process replaced
+// it since `seen` was read, its fresh lock is put back instead.
+fn take_over(path: &Path, seen: &str) -> bool {
+    let aside = path.with_extension(format!("stale.{}", std::process::id()));
+    if fs::rename(path, &aside).is_err() {
+        return false;
+    }
+
+    let taken = fs::read_to_string(&aside).is_ok_and(|taken| taken == seen);
+    if !taken {
+        let _ = fs::hard_link(&aside, path);
+    }
+    let _ = fs::remove_file(&aside);
+    taken
+}
+
+fn is_older_than(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed >= age))
+}
+
+pub fn is_process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+
+    if cfg!(unix) {
+        return Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+    }
+
+    // without a cheap check, assume the owner is alive and leave it to
+    // `octopush repo unlock`
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+    use std::time::SystemTime;
+
+    #[test]
+    fn recovers_lock_of_dead_process() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        fs::write(&path, u32::MAX.to_string()).unwrap();
+        assert_eq!(owner(&path).map(|o| o.alive), Some(false));
+
+        let lock = ConfigLock::acquire_with_timeout(&path, Duration::from_millis(200)).unwrap();
+        assert_eq!(owner(&path).map(|o| o.pid), Some(std::process::id()));
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn waits_for_live_owner_then_fails() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        // another process of ours: alive, but not this thread's lock
+        fs::write(&path, std::process::id().to_string()).unwrap();
+
+        let err = ConfigLock::acquire_with_timeout(&path, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn gives_up_on_and_recovers_unreadable_locks() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        // possibly still being written
+        fs::write(&path, "").unwrap();
+        let err = ConfigLock::acquire_with_timeout(&path, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // left behind by a crash right after creating it
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - CORRUPT_GRACE * 2)
+            .unwrap();
+        drop(file);
+
+        let lock = ConfigLock::acquire_with_timeout(&path, Duration::from_millis(100)).unwrap();
+        assert_eq!(owner(&path).map(|o| o.pid), Some(std::process::id()));
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn leaves_a_lock_it_no_longer_owns() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        let lock = ConfigLock::acquire(&path).unwrap();
+        fs::write(&path, u32::MAX.to_string()).unwrap();
+        drop(lock);
+        assert_eq!(owner(&path).map(|o| o.pid), Some(u32::MAX));
+    }
+
+    #[test]
+    fn puts_back_a_lock_taken_in_the_meantime() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        fs::write(&path, std::process::id().to_string()).unwrap();
+        assert!(!take_over(&path, &u32::MAX.to_string()));
+        assert_eq!(owner(&path).map(|o| o.pid), Some(std::process::id()));
+    }
+
+    #[test]
+    fn waits_for_another_thread_then_fails() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        let lock = ConfigLock::acquire(&path).unwrap();
+        let err = thread::scope(|s| {
+            s.spawn(|| ConfigLock::acquire_with_timeout(&path, Duration::from_millis(100)))
+                .join()
+                .unwrap()
+                .unwrap_err()
+        });
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn is_reentrant_for_the_holding_thread() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("octopush.lock");
+
+        let outer = ConfigLock::acquire(&path).unwrap();
+        let inner = ConfigLock::acquire_with_timeout(&path, Duration::ZERO).unwrap();
+
+        drop(inner);
+        assert!(path.exists());
+        drop(outer);
+        assert!(!path.exists());
+    }
+}
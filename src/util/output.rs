@@ -1,14 +1,225 @@
+use crate::util::exit::{self, Reported};
+use clap::ValueEnum;
 use colored::Colorize;
 use console::Emoji;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::io::Write;
-use std::time::{Duration, Instant};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 static GEAR: Emoji<'_, '_> = Emoji("⚙️ ", "");
 static CHECK: Emoji<'_, '_> = Emoji("✅ ", "✓ ");
 static CROSS: Emoji<'_, '_> = Emoji("❌ ", "✗ ");
+static WARN: Emoji<'_, '_> = Emoji("⚠️  ", "! ");
 
-pub struct Runner {}
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+// the global `--format`, for commands that list profiles and repositories
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    pub fn is_structured(self) -> bool {
+        self != Format::Human
+    }
+
+    // `value` must serialize to a map, toml has no other top-level type
+    pub fn render<T: Serialize>(self, value: &T) -> Result<String, io::Error> {
+        match self {
+            Format::Human | Format::Json => {
+                serde_json::to_string_pretty(value).map_err(io::Error::other)
+            }
+            Format::Yaml => serde_yaml_ng::to_string(value).map_err(io::Error::other),
+            Format::Toml => toml::to_string_pretty(value).map_err(io::Error::other),
+        }
+    }
+}
+
+// `-v` logs the decisions octopush makes, `-vv` also every command it runs
+// and every file it reads or writes. Logs go to stderr so the data printed
+// on stdout stays parseable.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // dependencies log too, only octopush's own records are wanted
+        metadata.level() <= log::max_level() && metadata.target().starts_with("octopush")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let level = format!("[{}]", record.level().as_str().to_lowercase());
+            eprintln!("{} {}", level.dimmed(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+pub fn init_logging(verbosity: u8) {
+    let level = match verbosity {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Info,
+        _ => log::LevelFilter::Debug,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// What the attached terminal can render. Spinner and bar styles are picked
+// from this instead of assuming a unicode-capable interactive terminal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TerminalCaps {
+    pub unicode: bool,
+    pub interactive: bool,
+    // ansi colors, off when stdout is not a terminal
+    pub color: bool,
+    // emoji before messages, off when stdout is not a terminal, with
+    // NO_COLOR or `--no-color` and with the `disable_emoji` setting
+    pub emoji: bool,
+    pub width: u16,
+}
+
+impl TerminalCaps {
+    pub fn detect() -> Self {
+        let term = console::Term::stdout();
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|key| std::env::var(key).ok().filter(|v| !v.is_empty()));
+
+        let caps = Self::from_parts(
+            std::env::var("TERM").ok().as_deref(),
+            locale.as_deref(),
+            std::env::var_os("CI").is_some(),
+            term.is_term(),
+            term.size_checked().map(|(_, cols)| cols),
+        );
+
+        // https://no-color.org: any non-empty value turns color off
+        if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+            caps.without_color()
+        } else {
+            caps
+        }
+    }
+
+    pub fn from_parts(
+        term: Option<&str>,
+        locale: Option<&str>,
+        ci: bool,
+        is_tty: bool,
+        width: Option<u16>,
+    ) -> Self {
+        let dumb = term.is_none_or(|t| t == "dumb");
+        let utf8 = locale.is_some_and(|l| {
+            let l = l.to_ascii_lowercase();
+            l.contains("utf-8") || l.contains("utf8")
+        });
+
+        Self {
+            unicode: !dumb && (utf8 || cfg!(windows)),
+            interactive: is_tty && !dumb && !ci,
+            color: is_tty && !dumb,
+            emoji: is_tty && !dumb && (utf8 || cfg!(windows)),
+            width: width.unwrap_or(80),
+        }
+    }
+
+    pub fn without_color(self) -> Self {
+        Self {
+            color: false,
+            emoji: false,
+            ..self
+        }
+    }
+
+    pub fn without_emoji(self) -> Self {
+        Self {
+            emoji: false,
+            ..self
+        }
+    }
+
+    // `colored` and `console` keep their own global switches, both follow
+    // the caps so every styled string agrees with them
+    pub fn apply_color(&self) {
+        colored::control::set_override(self.color);
+        console::set_colors_enabled(self.color);
+        console::set_colors_enabled_stderr(self.color);
+    }
+
+    pub fn tick_strings(&self) -> &'static [&'static str] {
+        if self.unicode {
+            &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]
+        } else {
+            &["-", "\\", "|", "/", "-"]
+        }
+    }
+
+    pub fn spinner_style(&self) -> ProgressStyle {
+        ProgressStyle::default_spinner()
+            .tick_strings(self.tick_strings())
+            .template("{spinner:.cyan} {wide_msg}")
+            .unwrap()
+    }
+
+    pub fn bar_style(&self) -> ProgressStyle {
+        let (template, chars) = if self.unicode {
+            ("{msg} [{bar:BAR.cyan/blue}] {pos}/{len}", "█▉▊▋▌▍▎▏ ")
+        } else {
+            ("{msg} [{bar:BAR}] {pos}/{len}", "=> ")
+        };
+        let bar_width = (self.width / 3).clamp(10, 40).to_string();
+
+        ProgressStyle::default_bar()
+            .template(&template.replace("BAR", &bar_width))
+            .unwrap()
+            .progress_chars(chars)
+    }
+
+    // animating a spinner only makes sense when someone is watching it
+    pub fn steady_tick(&self) -> Option<Duration> {
+        self.interactive.then(|| Duration::from_millis(100))
+    }
+}
+
+pub struct Runner {
+    caps: TerminalCaps,
+    // `--quiet`: no spinners, progress bars, success or warning lines
+    quiet: bool,
+    // `--no-input`: a missing argument is an error instead of a prompt
+    no_input: bool,
+    // structured `--format`: errors are collected for one error document on
+    // stderr instead of printed as they happen
+    structured: bool,
+    // reported errors and warnings, for the exit status and `--result-fd`
+    errors: Mutex<Vec<Reported>>,
+    warnings: Mutex<Vec<String>>,
+    // how many of the warnings are already part of an OperationResult
+    attributed_warnings: AtomicUsize,
+    results: Mutex<Vec<OperationResult>>,
+    // how long a visible spinner stays up at least, so quick operations do
+    // not just flash by; zero unless asked for, since it only adds time
+    min_spinner: Duration,
+}
 
 impl Default for Runner {
     fn default() -> Self {
@@ -18,114 +229,781 @@ impl Default for Runner {
 
 impl Runner {
     pub fn new() -> Self {
-        Self {}
+        Self::with_caps(TerminalCaps::detect())
+    }
+
+    pub fn with_caps(caps: TerminalCaps) -> Self {
+        Self {
+            caps,
+            quiet: false,
+            no_input: false,
+            structured: false,
+            errors: Mutex::new(Vec::new()),
+            warnings: Mutex::new(Vec::new()),
+            attributed_warnings: AtomicUsize::new(0),
+            results: Mutex::new(Vec::new()),
+            min_spinner: Duration::ZERO,
+        }
+    }
+
+    pub fn min_spinner_duration(mut self, duration: Duration) -> Self {
+        self.min_spinner = duration;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn no_input(mut self, no_input: bool) -> Self {
+        self.no_input = no_input;
+        self
+    }
+
+    // prompts need someone at a terminal who has not opted out of them
+    pub fn can_prompt(&self) -> bool {
+        !self.no_input && console::user_attended()
+    }
+
+    pub fn structured(mut self, structured: bool) -> Self {
+        self.structured = structured;
+        self
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors.lock().unwrap().len()
+    }
+
+    pub fn errors(&self) -> Vec<Reported> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.warnings.lock().unwrap().len()
+    }
+
+    // what every operation run so far did, in order
+    pub fn results(&self) -> Vec<OperationResult> {
+        self.results.lock().unwrap().clone()
     }
 
+    // an operation owns the warnings raised since the one before it, which
+    // covers the preflight checks commands run ahead of applying
+    fn record(&self, mut result: OperationResult) {
+        let warnings = self.warnings.lock().unwrap();
+        let attributed = self
+            .attributed_warnings
+            .swap(warnings.len(), Ordering::Relaxed);
+        result.warnings = warnings[attributed.min(warnings.len())..].to_vec();
+        self.results.lock().unwrap().push(result);
+    }
+
+    pub fn caps(&self) -> TerminalCaps {
+        self.caps
+    }
+
+    fn icon(&self, emoji: Emoji<'static, 'static>) -> String {
+        match emoji.1 {
+            _ if self.caps.emoji => emoji.0.to_string(),
+            // terminals without a utf-8 locale get ascii markers
+            "✓ " if !self.caps.unicode => "+ ".to_string(),
+            "✗ " if !self.caps.unicode => "x ".to_string(),
+            fallback => fallback.to_string(),
+        }
+    }
+
+    // data and success lines go to stdout, warnings and errors to stderr so
+    // pipes and `2>` redirection each get what they expect
     pub fn message(&self, message: &str) {
         let mut out = std::io::stdout().lock();
         let _ = writeln!(out, "{}", message);
     }
 
+    // structured formats get the OperationResults instead
     pub fn success(&self, message: &str) {
+        if self.quiet || self.structured {
+            return;
+        }
         let mut out = std::io::stdout().lock();
         let _ = writeln!(
             out,
             "{}{} {}",
-            CHECK,
+            self.icon(CHECK),
             "SUCCESS".bold().bright_green(),
             message.green()
         );
     }
 
+    pub fn warning(&self, message: &str) {
+        self.warnings.lock().unwrap().push(message.to_string());
+        if self.quiet {
+            return;
+        }
+        let mut out = std::io::stderr().lock();
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            self.icon(WARN),
+            "WARNING".bold().bright_yellow(),
+            message.yellow()
+        );
+    }
+
     pub fn error(&self, message: &str) {
-        let mut out = std::io::stdout().lock();
+        self.report(exit::FAILURE, message);
+    }
+
+    fn report(&self, code: i32, message: &str) {
+        self.errors.lock().unwrap().push(Reported {
+            code,
+            message: message.to_string(),
+        });
+        if self.structured {
+            return;
+        }
+        let mut out = std::io::stderr().lock();
         let _ = writeln!(
             out,
             "{}{} {}",
-            CROSS,
+            self.icon(CROSS),
             "ERROR".bold().bright_red(),
             message.red()
         );
     }
 
+    // piped output gets the success or error line alone, no spinner frames
     pub fn spinner(&self, message: &str) -> ProgressBar {
+        if self.quiet || !self.caps.interactive {
+            return ProgressBar::hidden();
+        }
         let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
-                .template("{spinner:.cyan} {msg}")
-                .unwrap(),
-        );
-        pb.set_message(format!("{} {}", GEAR, message));
+        pb.set_style(self.caps.spinner_style());
+        pb.set_message(format!("{} {}", self.icon(GEAR), message));
+        pb
+    }
+
+    pub fn progress_bar(&self, len: u64, message: &str) -> ProgressBar {
+        if self.quiet {
+            return ProgressBar::hidden();
+        }
+        let pb = ProgressBar::new(len);
+        pb.set_style(self.caps.bar_style());
+        pb.set_message(message.to_string());
         pb
     }
 
+    // an overall bar over `len` items, each shown on its own line above it
+    // while it is worked on; nothing is drawn under `--quiet` or off a
+    // terminal
+    pub fn multi_progress(&self, len: u64, message: &str) -> Progress<'_> {
+        let multi = if self.quiet || !self.caps.interactive {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+        let overall = multi.add(self.progress_bar(len, message));
+
+        Progress {
+            runner: self,
+            multi,
+            overall,
+            changed: RefCell::default(),
+        }
+    }
+
     pub fn run<F, R>(
         &self,
         operation: F,
-        operation_type: OperationType,
-    ) -> Result<R, Box<dyn std::error::Error>>
+        operation_type: impl Operation,
+    ) -> Result<R, Box<dyn Error>>
     where
-        F: FnOnce() -> Result<R, Box<dyn std::error::Error>>,
+        F: FnOnce() -> Result<R, Box<dyn Error>>,
+    {
+        self.run_steps(|_| operation(), operation_type)
+    }
+
+    // run, for operations made of discrete steps the spinner names as they
+    // begin
+    pub fn run_steps<F, R>(
+        &self,
+        operation: F,
+        operation_type: impl Operation,
+    ) -> Result<R, Box<dyn Error>>
+    where
+        F: FnOnce(&Steps) -> Result<R, Box<dyn Error>>,
     {
         let (initial_prompt, success_prompt, error_prompt) = operation_type.get_spinner_prompt();
+        // quiet and piped runs get a hidden spinner, which is never ticked
+        // and never held on screen, so scripts and hooks pay nothing for it
         let spinner = self.spinner(&initial_prompt);
-        spinner.enable_steady_tick(Duration::from_millis(100));
+        let visible = !self.quiet && self.caps.interactive;
+        if visible && let Some(interval) = self.caps.steady_tick() {
+            spinner.enable_steady_tick(interval);
+        }
+        let steps = Steps {
+            bar: spinner.clone(),
+            icon: self.icon(GEAR),
+            message: initial_prompt.clone(),
+            changed: RefCell::default(),
+        };
+
+        let (result, report) = execute_with(steps, &operation_type, operation);
+        let elapsed = Duration::from_millis(report.duration_ms);
+        self.record(report);
+        let linger = || {
+            if visible && elapsed < self.min_spinner {
+                std::thread::sleep(self.min_spinner - elapsed);
+            }
+        };
+
+        match result {
+            Ok(result) => {
+                linger();
+                spinner.with_message(format!("{} {}", self.icon(CHECK), success_prompt));
+                self.success(&success_prompt);
+                Ok(result)
+            }
+            Err(e) => {
+                linger();
+                spinner.with_message(format!("{} {}", self.icon(CROSS), error_prompt));
+                self.report(exit::code(e.as_ref()), &format!("{}: {}", error_prompt, e));
+                Err(e)
+            }
+        }
+    }
+
+    // run, for operations over many repositories that draw a
+    // multi_progress instead of a spinner
+    pub fn run_with_progress<F, R>(
+        &self,
+        message: &str,
+        operation: F,
+        operation_type: impl Operation,
+    ) -> Result<R, Box<dyn Error>>
+    where
+        F: FnOnce(&Progress) -> Result<R, Box<dyn Error>>,
+    {
+        let (_, success_prompt, error_prompt) = operation_type.get_spinner_prompt();
+        let progress = self.multi_progress(0, message);
         let started_at = Instant::now();
+        let result = operation(&progress);
+        self.record(OperationResult {
+            operation: operation_type.name(),
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            changed: progress.changed.take(),
+            warnings: Vec::new(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        progress.clear();
 
-        match operation() {
+        match result {
             Ok(result) => {
-                // Ensure spinner is visible for a minimal duration
-                let min_duration = Duration::from_millis(600);
-                let elapsed = started_at.elapsed();
-                if elapsed < min_duration {
-                    std::thread::sleep(min_duration - elapsed);
-                }
-                spinner.with_message(format!("{} {}", CHECK, success_prompt));
                 self.success(&success_prompt);
                 Ok(result)
             }
             Err(e) => {
-                let min_duration = Duration::from_millis(600);
-                let elapsed = started_at.elapsed();
-                if elapsed < min_duration {
-                    std::thread::sleep(min_duration - elapsed);
-                }
-                spinner.with_message(format!("{} {}", CROSS, error_prompt));
-                self.error(&format!("{}: {}", error_prompt, e));
+                self.report(exit::code(e.as_ref()), &format!("{}: {}", error_prompt, e));
                 Err(e)
             }
         }
     }
 }
 
+// What an operation did, for library callers and structured `--format`:
+// how long it took, what it changed and the warnings raised on the way.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OperationResult {
+    // the OperationType in snake_case, `use_profile`
+    pub operation: String,
+    pub duration_ms: u64,
+    // what was written, named as plans name it: `git config user.email`,
+    // `remote origin`
+    pub changed: Vec<String>,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+// runs `operation` without printing anything, for library callers who want
+// the OperationResult rather than spinners and success lines
+pub fn execute<F, R>(
+    operation_type: &dyn Operation,
+    operation: F,
+) -> (Result<R, Box<dyn Error>>, OperationResult)
+where
+    F: FnOnce(&Steps) -> Result<R, Box<dyn Error>>,
+{
+    let steps = Steps {
+        bar: ProgressBar::hidden(),
+        icon: String::new(),
+        message: String::new(),
+        changed: RefCell::default(),
+    };
+    execute_with(steps, operation_type, operation)
+}
+
+fn execute_with<F, R>(
+    steps: Steps,
+    operation_type: &dyn Operation,
+    operation: F,
+) -> (Result<R, Box<dyn Error>>, OperationResult)
+where
+    F: FnOnce(&Steps) -> Result<R, Box<dyn Error>>,
+{
+    let started_at = Instant::now();
+    let result = operation(&steps);
+    let report = OperationResult {
+        operation: operation_type.name(),
+        duration_ms: started_at.elapsed().as_millis() as u64,
+        changed: steps.changed.take(),
+        warnings: Vec::new(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    };
+
+    (result, report)
+}
+
+// A spinner line that names the step its operation is on, e.g.
+// `Applying profile... (auth)`, and collects what the operation changed.
+pub struct Steps {
+    bar: ProgressBar,
+    icon: String,
+    message: String,
+    changed: RefCell<Vec<String>>,
+}
+
+impl Steps {
+    pub fn step(&self, step: &str) {
+        self.bar
+            .set_message(format!("{} {} ({})", self.icon, self.message, step));
+    }
+
+    pub fn changed<I: IntoIterator<Item = String>>(&self, targets: I) {
+        self.changed.borrow_mut().extend(targets);
+    }
+}
+
+// What Runner::multi_progress draws: a line per item in progress above an
+// overall bar. Finished items leave the screen, failed ones stay marked
+// until the progress is cleared.
+pub struct Progress<'a> {
+    runner: &'a Runner,
+    multi: MultiProgress,
+    overall: ProgressBar,
+    // what the finished items changed
+    changed: RefCell<Vec<String>>,
+}
+
+impl Progress<'_> {
+    // for counts that are only known once the work has started
+    pub fn set_length(&self, len: u64) {
+        self.overall.set_length(len);
+    }
+
+    pub fn item(&self, message: &str) -> Steps {
+        let bar = self
+            .multi
+            .insert_before(&self.overall, self.runner.spinner(message));
+        if !self.runner.quiet
+            && let Some(interval) = self.runner.caps.steady_tick()
+        {
+            bar.enable_steady_tick(interval);
+        }
+
+        Steps {
+            bar,
+            icon: self.runner.icon(GEAR),
+            message: message.to_string(),
+            changed: RefCell::default(),
+        }
+    }
+
+    pub fn finish_item(&self, item: Steps, failure: Option<&str>) {
+        self.changed.borrow_mut().extend(item.changed.take());
+        match failure {
+            Some(reason) => item.bar.finish_with_message(format!(
+                "{} {}: {}",
+                self.runner.icon(CROSS),
+                item.message,
+                reason
+            )),
+            None => item.bar.finish_and_clear(),
+        }
+        self.overall.inc(1);
+    }
+
+    // counts an item that got no line of its own
+    pub fn inc(&self) {
+        self.overall.inc(1);
+    }
+
+    // hides the bars while `f` prompts or prints
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.multi.suspend(f)
+    }
+
+    pub fn clear(self) {
+        self.overall.finish_and_clear();
+        let _ = self.multi.clear();
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PromptFormat {
+    Ps1,
+    Zsh,
+    Fish,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PromptColor {
+    Green,
+    Yellow,
+    Red,
+}
+
+// Builds a colored prompt segment with the escaping each shell expects.
+// The text is reduced to a conservative character set so nothing in a
+// profile name can be interpreted by prompt expansion.
+pub fn prompt_segment(text: &str, color: PromptColor, format: PromptFormat) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.@:()!".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let (ansi, zsh) = match color {
+        PromptColor::Green => ("32", "green"),
+        PromptColor::Yellow => ("33", "yellow"),
+        PromptColor::Red => ("31", "red"),
+    };
+
+    match format {
+        // bash only turns `\[ \]` into these when they are literal in PS1;
+        // readline's own markers also work from `$(...)` and PROMPT_COMMAND
+        PromptFormat::Ps1 => format!("\x01\x1b[{}m\x02{}\x01\x1b[0m\x02", ansi, text),
+        PromptFormat::Zsh => format!("%F{{{}}}{}%f", zsh, text),
+        PromptFormat::Fish => format!("\x1b[{}m{}\x1b[0m", ansi, text),
+    }
+}
+
+// renders a unix timestamp as a coarse relative age, e.g. "3 days ago"
+pub fn format_age(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let secs = now.saturating_sub(timestamp);
+
+    let (value, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        86_400..2_592_000 => (secs / 86_400, "day"),
+        2_592_000..31_536_000 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+
+    format!(
+        "{} {}{} ago",
+        value,
+        unit,
+        if value == 1 { "" } else { "s" }
+    )
+}
+
+// `90` -> `1 minute`, rounded down to the largest whole unit
+pub fn format_duration(secs: u64) -> String {
+    let (value, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+
+    format!("{} {}{}", value, unit, if value == 1 { "" } else { "s" })
+}
+
+// left-aligned columns padded to their widest cell, measured in terminal
+// columns so colored headers and non-ascii names line up
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> Vec<String> {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| console::measure_text_width(&row[i]))
+                .fold(header.len(), usize::max)
+        })
+        .collect();
+
+    let line = |cells: Vec<String>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let pad = widths[i].saturating_sub(console::measure_text_width(cell));
+                format!("{}{}", cell, " ".repeat(pad))
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![
+        line(
+            headers
+                .iter()
+                .map(|header| header.bold().cyan().to_string())
+                .collect(),
+        ),
+        line(widths.iter().map(|width| "-".repeat(*width)).collect()),
+    ];
+    lines.extend(rows.iter().map(|row| line(row.clone())));
+    lines
+}
+
+// What Runner::run needs to know about an operation. OperationType covers
+// octopush's own commands; new commands and plugins can implement it for
+// their own types or describe themselves with a CustomOperation.
+pub trait Operation {
+    // identifies the operation in an OperationResult, `use_profile`
+    fn name(&self) -> String;
+
+    // the spinner's line while it runs, then the success and error lines
+    fn get_spinner_prompt(&self) -> (String, String, String);
+}
+
+// an operation described by its messages alone, for commands that have no
+// OperationType of their own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomOperation {
+    pub name: String,
+    pub running: String,
+    pub success: String,
+    pub error: String,
+}
+
+impl CustomOperation {
+    // generic messages built from the name, `rotate_keys` shows
+    // `Running rotate_keys...`
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            running: format!("Running {}...", name),
+            success: format!("{} finished", name),
+            error: format!("{} failed", name),
+        }
+    }
+
+    pub fn running(mut self, message: &str) -> Self {
+        self.running = message.to_string();
+        self
+    }
+
+    pub fn success(mut self, message: &str) -> Self {
+        self.success = message.to_string();
+        self
+    }
+
+    pub fn error(mut self, message: &str) -> Self {
+        self.error = message.to_string();
+        self
+    }
+}
+
+impl Operation for CustomOperation {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_spinner_prompt(&self) -> (String, String, String) {
+        (
+            self.running.clone(),
+            self.success.clone(),
+            self.error.clone(),
+        )
+    }
+}
+
+impl<T: Operation + ?Sized> Operation for &T {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn get_spinner_prompt(&self) -> (String, String, String) {
+        (**self).get_spinner_prompt()
+    }
+}
+
+#[derive(Debug)]
 pub enum OperationType {
-    AddProfile { profile_name: String },
-    DeleteProfile { profile_name: String },
+    AddProfile {
+        profile_name: String,
+    },
+    AddProfiles {
+        path: String,
+    },
+    EditProfile {
+        profile_name: String,
+    },
+    ArchiveProfile {
+        profile_name: String,
+        archived: bool,
+    },
+    SetToken {
+        profile_name: String,
+    },
+    DeleteProfile {
+        profile_name: String,
+    },
+    MergeProfiles {
+        into: String,
+        from: String,
+    },
     ListProfiles,
-    UseProfile { profile_name: String },
+    ListProjects,
+    SearchProfiles {
+        query: String,
+    },
+    DiffProfiles {
+        left: String,
+        right: String,
+    },
+    UseProfile {
+        profile_name: String,
+    },
+    UseGlobalProfile {
+        profile_name: String,
+    },
+    ExecutePlan {
+        plan_path: String,
+    },
+    Sync {
+        repo: String,
+    },
+    SyncAll,
+    ExportGitconfig,
+    SetupAutoApply,
+    TestConnection,
+    InstallHook {
+        repo: String,
+    },
     GetProfile,
     ResetProfile,
+    InitRepo {
+        profile_name: String,
+        path: String,
+    },
+    Clone {
+        profile_name: String,
+        url: String,
+    },
+    MoveRepo {
+        from: String,
+        to: String,
+    },
+    Scan {
+        path: String,
+    },
+    Open {
+        url: String,
+    },
 }
 
-impl OperationType {
-    pub fn get_spinner_prompt(&self) -> (String, String, String) {
+impl Operation for OperationType {
+    // the variant name in snake_case, `UseProfile` is `use_profile`
+    fn name(&self) -> String {
+        let debug = format!("{:?}", self);
+        let variant = debug
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default();
+
+        let mut name = String::new();
+        for (i, c) in variant.char_indices() {
+            if c.is_uppercase() && i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+
+    fn get_spinner_prompt(&self) -> (String, String, String) {
         match &self {
             OperationType::AddProfile { profile_name } => (
                 format!("Adding new profile '{}'", profile_name),
                 format!("Profile '{}' was successfully added", profile_name),
                 format!("Failed to add profile '{}'", profile_name),
             ),
+            OperationType::AddProfiles { path } => (
+                format!("Adding profiles from '{}'", path),
+                format!("Profiles from '{}' were processed", path),
+                format!("Failed to add profiles from '{}'", path),
+            ),
+            OperationType::EditProfile { profile_name } => (
+                format!("Updating profile '{}'", profile_name),
+                format!("Profile '{}' was successfully updated", profile_name),
+                format!("Failed to update profile '{}'", profile_name),
+            ),
+            OperationType::SetToken { profile_name } => (
+                format!("Storing the token of '{}'", profile_name),
+                format!("Token of '{}' stored in the keychain", profile_name),
+                format!("Failed to store the token of '{}'", profile_name),
+            ),
+            OperationType::ArchiveProfile {
+                profile_name,
+                archived: true,
+            } => (
+                format!("Archiving profile '{}'", profile_name),
+                format!("Profile '{}' was successfully archived", profile_name),
+                format!("Failed to archive profile '{}'", profile_name),
+            ),
+            OperationType::ArchiveProfile {
+                profile_name,
+                archived: false,
+            } => (
+                format!("Restoring profile '{}'", profile_name),
+                format!("Profile '{}' was successfully restored", profile_name),
+                format!("Failed to restore profile '{}'", profile_name),
+            ),
             OperationType::DeleteProfile { profile_name } => (
                 format!("Deleting profile '{}'", profile_name),
                 format!("Profile '{}' was successfully deleted", profile_name),
                 format!("Failed to delete profile '{}'", profile_name),
             ),
+            OperationType::MergeProfiles { into, from } => (
+                format!("Merging profile '{}' into '{}'", from, into),
+                format!("Profile '{}' was merged into '{}'", from, into),
+                format!("Failed to merge profile '{}' into '{}'", from, into),
+            ),
             OperationType::ListProfiles => (
                 "Fetching all profiles".to_string(),
                 "Profiles successfully fetched".to_string(),
                 "Failed to fetch profiles".to_string(),
             ),
+            OperationType::ListProjects => (
+                "Fetching mapped repositories".to_string(),
+                "Mapped repositories successfully fetched".to_string(),
+                "Failed to fetch mapped repositories".to_string(),
+            ),
+            OperationType::SearchProfiles { query } => (
+                format!("Searching profiles for '{}'", query),
+                "Profiles successfully searched".to_string(),
+                format!("Failed to search profiles for '{}'", query),
+            ),
+            OperationType::DiffProfiles { left, right } => (
+                format!("Comparing profiles '{}' and '{}'", left, right),
+                format!("Profiles '{}' and '{}' were compared", left, right),
+                format!("Failed to compare profiles '{}' and '{}'", left, right),
+            ),
             OperationType::UseProfile { profile_name } => (
                 format!("Issuing profile '{}' for the repository", profile_name),
                 format!(
@@ -137,16 +1015,93 @@ impl OperationType {
                     profile_name
                 ),
             ),
+            OperationType::ExecutePlan { plan_path } => (
+                format!("Executing plan '{}'", plan_path),
+                format!("Plan '{}' was successfully executed", plan_path),
+                format!("Failed to execute plan '{}'", plan_path),
+            ),
+            OperationType::Sync { repo } => (
+                format!("Re-applying the mapped profile to '{}'", repo),
+                format!("'{}' is in sync with its profile", repo),
+                format!("Failed to sync '{}'", repo),
+            ),
+            OperationType::SyncAll => (
+                "Re-applying profiles to every mapped repository".to_string(),
+                "Finished syncing mapped repositories".to_string(),
+                "Failed to sync mapped repositories".to_string(),
+            ),
+            OperationType::InstallHook { repo } => (
+                format!("Installing the pre-commit hook in '{}'", repo),
+                format!("Pre-commit hook installed in '{}'", repo),
+                format!("Failed to install the pre-commit hook in '{}'", repo),
+            ),
+            OperationType::ExportGitconfig => (
+                "Exporting profiles as gitconfig".to_string(),
+                "Profiles exported as gitconfig".to_string(),
+                "Failed to export profiles as gitconfig".to_string(),
+            ),
+            OperationType::SetupAutoApply => (
+                "Installing the auto-apply hook".to_string(),
+                "Auto-apply hook installed".to_string(),
+                "Failed to install the auto-apply hook".to_string(),
+            ),
+            OperationType::TestConnection => (
+                "Testing the connection".to_string(),
+                "Connection succeeded".to_string(),
+                "Connection failed".to_string(),
+            ),
             OperationType::GetProfile => (
                 "Fetching current profile".to_string(),
                 "Profile successfully fetched".to_string(),
                 "Failed to fetch profile".to_string(),
             ),
+            OperationType::UseGlobalProfile { profile_name } => (
+                format!(
+                    "Writing profile '{}' to the global git config",
+                    profile_name
+                ),
+                format!("Profile '{}' now applies to every repository", profile_name),
+                format!(
+                    "Failed to write profile '{}' to the global git config",
+                    profile_name
+                ),
+            ),
             OperationType::ResetProfile => (
                 "Switching global profile".to_string(),
                 "Global profile successfully set for the repository".to_string(),
                 "Failed to reset global profile".to_string(),
             ),
+            OperationType::InitRepo { profile_name, path } => (
+                format!("Creating '{}' as profile '{}'", path, profile_name),
+                format!(
+                    "Repository '{}' was created and mapped to profile '{}'",
+                    path, profile_name
+                ),
+                format!("Failed to create '{}'", path),
+            ),
+            OperationType::Clone { profile_name, url } => (
+                format!("Cloning '{}' as profile '{}'", url, profile_name),
+                format!(
+                    "'{}' was cloned and mapped to profile '{}'",
+                    url, profile_name
+                ),
+                format!("Failed to clone '{}'", url),
+            ),
+            OperationType::MoveRepo { from, to } => (
+                format!("Moving mapping from '{}' to '{}'", from, to),
+                format!("Mapping was successfully moved to '{}'", to),
+                format!("Failed to move mapping from '{}'", from),
+            ),
+            OperationType::Scan { path } => (
+                format!("Scanning '{}' for repositories", path),
+                format!("Finished scanning '{}'", path),
+                format!("Failed to scan '{}'", path),
+            ),
+            OperationType::Open { url } => (
+                format!("Opening '{}'", url),
+                format!("Opened '{}' in the browser", url),
+                format!("Failed to open '{}'", url),
+            ),
         }
     }
 }
@@ -170,44 +1125,279 @@ mod tests {
 
         let message = "success message";
         let output = capture_stdout(|| runner.success(message));
-        let expected_output = format!("{}{} {}", CHECK, "SUCCESS", message) + "\n";
+        let expected_output = format!("{}{} {}", runner.icon(CHECK), "SUCCESS", message) + "\n";
 
         assert_eq!(output, expected_output);
 
         let message = "error message";
-        let output = capture_stdout(|| runner.error(message));
-        let expected_output = format!("{}{} {}", CROSS, "ERROR", message) + "\n";
+        assert_eq!(capture_stdout(|| runner.error(message)), "");
+        let output = capture_stderr(|| runner.error(message));
+        let expected_output = format!("{}{} {}", runner.icon(CROSS), "ERROR", message) + "\n";
 
         assert_eq!(output, expected_output);
 
+        let quiet = Runner::new().quiet(true);
+        let output = capture_stderr(|| {
+            quiet.success(message);
+            quiet.warning(message);
+            quiet.error(message);
+        });
+        assert_eq!(
+            output,
+            format!("{}{} {}", quiet.icon(CROSS), "ERROR", message) + "\n"
+        );
+        assert_eq!(quiet.warning_count(), 1);
+
         Ok(())
     }
 
+    #[test]
+    fn renders_structured_formats() {
+        let value = std::collections::BTreeMap::from([("work", vec!["a", "b"])]);
+
+        assert_eq!(
+            Format::Json.render(&value).unwrap(),
+            "{\n  \"work\": [\n    \"a\",\n    \"b\"\n  ]\n}"
+        );
+        assert_eq!(Format::Yaml.render(&value).unwrap(), "work:\n- a\n- b\n");
+        assert_eq!(
+            Format::Toml.render(&value).unwrap(),
+            "work = [\n    \"a\",\n    \"b\",\n]\n"
+        );
+        assert!(Format::Toml.render(&vec!["a"]).is_err());
+    }
+
+    #[test]
+    fn builds_escaped_prompt_segments() {
+        assert_eq!(
+            prompt_segment("work", PromptColor::Green, PromptFormat::Ps1),
+            "\x01\x1b[32m\x02work\x01\x1b[0m\x02"
+        );
+        assert_eq!(
+            prompt_segment("work", PromptColor::Red, PromptFormat::Zsh),
+            "%F{red}work%f"
+        );
+        assert_eq!(
+            prompt_segment("work", PromptColor::Yellow, PromptFormat::Fish),
+            "\x1b[33mwork\x1b[0m"
+        );
+        // prompt expansion characters never reach the shell
+        assert_eq!(
+            prompt_segment("$(rm) %n", PromptColor::Green, PromptFormat::Zsh),
+            "%F{green}_(rm)__n%f"
+        );
+    }
+
+    #[test]
+    fn aligns_table_columns() {
+        let rows = vec![
+            vec!["work".to_string(), "Jörg".to_string(), "ssh".to_string()],
+            vec!["personal".to_string(), "-".to_string(), "gh".to_string()],
+        ];
+
+        assert_eq!(
+            table(&["Profile", "Name", "Auth"], &rows)
+                .iter()
+                .map(|line| console::strip_ansi_codes(line))
+                .collect::<Vec<_>>(),
+            [
+                "Profile   Name  Auth",
+                "--------  ----  ----",
+                "work      Jörg  ssh",
+                "personal  -     gh",
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_relative_ages() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        assert_eq!(format_age(now), "just now");
+        assert_eq!(format_age(now - 3_600), "1 hour ago");
+        assert_eq!(format_age(now - 3 * 86_400), "3 days ago");
+        assert_eq!(format_duration(900), "15 minutes");
+        assert_eq!(format_duration(1), "1 second");
+        assert_eq!(format_duration(7_200), "2 hours");
+    }
+
+    #[test]
+    fn picks_styles_from_terminal_caps() {
+        let rich = TerminalCaps::from_parts(
+            Some("xterm-256color"),
+            Some("en_US.UTF-8"),
+            false,
+            true,
+            Some(120),
+        );
+        assert!(rich.unicode && rich.interactive);
+        assert_eq!(rich.tick_strings()[0], "⠋");
+        assert!(rich.steady_tick().is_some());
+
+        let dumb = TerminalCaps::from_parts(Some("dumb"), Some("en_US.UTF-8"), false, true, None);
+        assert!(!dumb.unicode && !dumb.interactive);
+        assert!(dumb.tick_strings().iter().all(|t| t.is_ascii()));
+        assert_eq!(dumb.width, 80);
+
+        let ci = TerminalCaps::from_parts(Some("xterm"), Some("C.UTF-8"), true, true, Some(80));
+        assert!(ci.unicode);
+        assert_eq!(ci.steady_tick(), None);
+
+        let piped = TerminalCaps::from_parts(Some("xterm"), Some("C.UTF-8"), false, false, None);
+        assert!(!piped.color && !piped.emoji);
+        assert!(Runner::with_caps(piped).spinner("working").is_hidden());
+        assert!(rich.color && !rich.without_color().color && !rich.without_color().emoji);
+        assert!(rich.emoji && rich.without_emoji().color && !rich.without_emoji().emoji);
+        assert_eq!(Runner::with_caps(rich).icon(CHECK), "✅ ");
+        assert_eq!(Runner::with_caps(dumb).icon(CROSS), "x ");
+
+        let plain = Runner::with_caps(rich.without_color());
+        assert_eq!(plain.icon(CHECK), "✓ ");
+        assert_eq!(plain.icon(GEAR), "");
+    }
+
+    #[test]
+    fn spinner_lingers_only_when_asked_to() {
+        let rich = TerminalCaps::from_parts(
+            Some("xterm-256color"),
+            Some("en_US.UTF-8"),
+            false,
+            true,
+            Some(120),
+        );
+        let piped = TerminalCaps::from_parts(Some("xterm"), Some("C.UTF-8"), false, false, None);
+        let timed = |runner: Runner| {
+            let started_at = Instant::now();
+            capture_stdout(|| {
+                runner.run(|| Ok(()), OperationType::ListProfiles).unwrap();
+            });
+            started_at.elapsed()
+        };
+        let linger = Duration::from_millis(300);
+
+        assert!(timed(Runner::with_caps(rich)) < linger);
+        assert!(timed(Runner::with_caps(rich).min_spinner_duration(linger)) >= linger);
+        assert!(timed(Runner::with_caps(piped).min_spinner_duration(linger)) < linger);
+        assert!(
+            timed(
+                Runner::with_caps(rich)
+                    .quiet(true)
+                    .min_spinner_duration(linger)
+            ) < linger
+        );
+    }
+
+    #[test]
+    fn multi_progress_counts_items_without_drawing_off_a_terminal() {
+        let piped = TerminalCaps::from_parts(Some("xterm"), Some("C.UTF-8"), false, false, None);
+        let runner = Runner::with_caps(piped);
+
+        let progress = runner.multi_progress(0, "Syncing");
+        progress.set_length(3);
+        let item = progress.item("/src/app");
+        assert!(item.bar.is_hidden());
+        item.step("identity");
+        progress.finish_item(item, None);
+        progress.finish_item(progress.item("/src/gone"), Some("no longer exists"));
+        progress.inc();
+        assert_eq!(progress.overall.position(), 3);
+        progress.clear();
+
+        let out = capture_stdout(|| {
+            runner
+                .run_with_progress("Syncing", |_| Ok(()), OperationType::SyncAll)
+                .unwrap();
+        });
+        assert!(out.contains("SUCCESS"));
+    }
+
+    #[test]
+    fn reports_what_operations_did() {
+        let operation = OperationType::UseProfile {
+            profile_name: "work".to_string(),
+        };
+        let (result, report) = execute(&operation, |steps| {
+            steps.changed(["git config user.email".to_string()]);
+            Ok(1)
+        });
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(report.operation, "use_profile");
+        assert_eq!(report.changed, vec!["git config user.email"]);
+        assert_eq!(report.error, None);
+
+        // warnings belong to the operation they lead up to, success lines
+        // give way to the results under a structured format
+        let runner = Runner::with_caps(TerminalCaps::from_parts(None, None, false, false, None))
+            .structured(true);
+        let out = capture_stdout(|| {
+            runner.warning("no ssh key loaded");
+            runner.run(|| Ok(()), operation).unwrap();
+            let _ = runner.run(
+                || Err::<(), _>("remote unreachable".into()),
+                OperationType::SyncAll,
+            );
+        });
+        assert!(out.is_empty());
+
+        let results = runner.results();
+        assert_eq!(results[0].warnings, vec!["no ssh key loaded"]);
+        assert!(results[1].warnings.is_empty());
+        assert_eq!(results[1].operation, "sync_all");
+        assert_eq!(results[1].error.as_deref(), Some("remote unreachable"));
+    }
+
+    #[test]
+    fn runs_operations_defined_outside_octopush() {
+        let rotate = CustomOperation::new("rotate_keys").success("Rotated 2 keys");
+        assert_eq!(rotate.running, "Running rotate_keys...");
+
+        let runner = Runner::with_caps(TerminalCaps::from_parts(None, None, false, false, None));
+        let out = capture_stdout(|| {
+            runner.run(|| Ok(()), &rotate).unwrap();
+            let _ = runner.run(|| Err::<(), _>("agent locked".into()), &rotate);
+        });
+        assert!(out.contains("Rotated 2 keys"));
+        assert_eq!(runner.results()[0].operation, "rotate_keys");
+        assert_eq!(
+            runner.errors()[0].message,
+            "rotate_keys failed: agent locked"
+        );
+    }
+
     fn build_runner() -> Result<Runner, std::io::Error> {
         let runner = Runner::new();
 
         Ok(runner)
     }
 
-    static STDOUT_LOCK: Mutex<()> = Mutex::new(());
+    static CAPTURE_LOCK: Mutex<()> = Mutex::new(());
 
     fn capture_stdout<F: FnOnce()>(f: F) -> String {
-        let _guard = STDOUT_LOCK.lock().unwrap();
+        capture(io::stdout().as_raw_fd(), f)
+    }
+
+    fn capture_stderr<F: FnOnce()>(f: F) -> String {
+        capture(io::stderr().as_raw_fd(), f)
+    }
 
-        let stdout_fd = io::stdout().as_raw_fd();
+    fn capture<F: FnOnce()>(fd: i32, f: F) -> String {
+        let _guard = CAPTURE_LOCK.lock().unwrap();
 
-        let saved_fd = unsafe { dup(stdout_fd) };
-        assert!(saved_fd >= 0, "dup(stdout) failed");
+        let saved_fd = unsafe { dup(fd) };
+        assert!(saved_fd >= 0, "dup failed");
 
         let (read_fd, write_fd) = pipe().expect("pipe failed");
 
-        let rc = unsafe { dup2(write_fd.as_raw_fd(), stdout_fd) };
-        assert!(rc >= 0, "dup2 to stdout failed");
+        let rc = unsafe { dup2(write_fd.as_raw_fd(), fd) };
+        assert!(rc >= 0, "dup2 failed");
 
         f();
         let _ = io::stdout().flush();
 
-        let rc = unsafe { dup2(saved_fd, stdout_fd) };
+        let rc = unsafe { dup2(saved_fd, fd) };
         assert!(rc >= 0, "dup2 restore failed");
 
         unsafe {
@@ -128,10 +128,14 @@ impl Completer for PathCompleter {
 }
 
 pub fn dialoguer_path_input(prompt: &str) -> String {
+    dialoguer_path_input_with_initial(prompt, "")
+}
+
+pub fn dialoguer_path_input_with_initial(prompt: &str, initial: &str) -> String {
     let mut rl: Editor<PathCompleter, DefaultHistory> = Editor::new().unwrap();
     rl.set_helper(Some(PathCompleter {}));
 
-    let line = match rl.readline(prompt) {
+    let line = match rl.readline_with_initial(prompt, (initial, "")) {
         Ok(line) => line,
         Err(ReadlineError::Interrupted) => {
             eprintln!("Aborted by user (Ctrl+C).");
@@ -0,0 +1,180 @@
+use crate::util::system::home_dir;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Host aliases for profiles using the `ssh_host_alias` strategy live in one
+// block of ~/.ssh/config delimited by these markers. Everything outside the
+// block belongs to the user and is never touched.
+const BEGIN_MARKER: &str = "# >>> octopush managed hosts >>>";
+const END_MARKER: &str = "# <<< octopush managed hosts <<<";
+
+pub fn config_path() -> Option<PathBuf> {
+    home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+// `github.com-work` for the `work` profile on github.com
+pub fn alias_for(host: &str, profile_name: &str) -> String {
+    format!("{}-{}", host, profile_name)
+}
+
+pub fn host_entry(alias: &str, host: &str, key_path: Option<&str>, agent: Option<&str>) -> String {
+    let mut entry = format!("Host {}\n    HostName {}\n    User git\n", alias, host);
+    if let Some(key_path) = key_path {
+        entry.push_str(&format!(
+            "    IdentityFile {}\n    IdentitiesOnly yes\n",
+            key_path
+        ));
+    }
+    if let Some(agent) = agent {
+        entry.push_str(&format!("    IdentityAgent \"{}\"\n", agent));
+    }
+    entry
+}
+
+// the managed entries, keyed by alias, in file order
+fn managed_entries(content: &str) -> Vec<(String, String)> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let mut in_block = false;
+
+    for line in content.lines() {
+        match line.trim() {
+            BEGIN_MARKER => in_block = true,
+            END_MARKER => in_block = false,
+            trimmed if in_block => {
+                if let Some(alias) = trimmed.strip_prefix("Host ") {
+                    entries.push((alias.trim().to_string(), String::new()));
+                }
+                if let Some((_, entry)) = entries.last_mut() {
+                    entry.push_str(line);
+                    entry.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+pub fn read_entry(path: &Path, alias: &str) -> Result<Option<String>, io::Error> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    Ok(managed_entries(&content)
+        .into_iter()
+        .find(|(name, _)| name == alias)
+        .map(|(_, entry)| entry))
+}
+
+// the real host behind a managed alias
+pub fn resolve_alias(path: &Path, alias: &str) -> Option<String> {
+    let entry = read_entry(path, alias).ok()??;
+    entry
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("HostName "))
+        .map(|host| host.trim().to_string())
+}
+
+// `host` itself unless it is one of the managed aliases
+pub fn real_host(host: &str) -> String {
+    config_path()
+        .and_then(|path| resolve_alias(&path, host))
+        .unwrap_or_else(|| host.to_string())
+}
+
+// sets (or removes, when `entry` is None) the managed entry for `alias`
+pub fn write_entry(path: &Path, alias: &str, entry: Option<&str>) -> Result<(), io::Error> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = managed_entries(&content);
+    match (entries.iter().position(|(name, _)| name == alias), entry) {
+        (Some(index), Some(entry)) => entries[index].1 = entry.to_string(),
+        (Some(index), None) => {
+            entries.remove(index);
+        }
+        (None, Some(entry)) => entries.push((alias.to_string(), entry.to_string())),
+        (None, None) => return Ok(()),
+    }
+
+    // the user's own config, without the old block
+    let mut out = String::new();
+    let mut in_block = false;
+    for line in content.lines() {
+        match line.trim() {
+            BEGIN_MARKER => in_block = true,
+            END_MARKER => in_block = false,
+            _ if !in_block => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    // ssh uses the first value it finds for each option, so the block goes
+    // first to win over broader `Host *` sections further down
+    let mut block = String::new();
+    if !entries.is_empty() {
+        block.push_str(BEGIN_MARKER);
+        block.push('\n');
+        for (_, entry) in &entries {
+            block.push_str(entry);
+        }
+        block.push_str(END_MARKER);
+        block.push('\n');
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, block + out.trim_start_matches('\n'))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn manages_only_its_own_block() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("ssh").join("config");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "Host *\n    AddKeysToAgent yes\n").unwrap();
+
+        let alias = alias_for("github.com", "work");
+        let entry = host_entry(&alias, "github.com", Some("~/.ssh/id_work"), None);
+        write_entry(&path, &alias, Some(&entry)).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(BEGIN_MARKER));
+        assert!(content.ends_with("Host *\n    AddKeysToAgent yes\n"));
+        assert_eq!(read_entry(&path, &alias).unwrap(), Some(entry));
+        assert_eq!(
+            resolve_alias(&path, "github.com-work").as_deref(),
+            Some("github.com")
+        );
+
+        write_entry(&path, &alias, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Host *\n    AddKeysToAgent yes\n"
+        );
+    }
+}
@@ -1,5 +1,170 @@
-use std::{env, io::Error};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Error, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
 
 pub fn cwd() -> Result<String, Error> {
     Ok(env::current_dir()?.to_string_lossy().into_owned())
 }
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+// expands a leading '~' the way a shell would
+pub fn expand_home(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix('~')
+        && let Some(home) = home_dir()
+    {
+        return home.join(rest.trim_start_matches(['/', '\\']));
+    }
+
+    PathBuf::from(path)
+}
+
+// hands `url` to the platform's default browser without waiting for it
+pub fn open_url(url: &str) -> Result<(), Error> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+
+    command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+// looks a bare program name up on PATH
+pub fn which(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| {
+            let candidate = dir.join(name);
+            [
+                candidate.with_extension(env::consts::EXE_EXTENSION),
+                candidate,
+            ]
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+// Resolves a configured tool (a path or a bare name looked up on PATH) to
+// an executable path. Lookups are cached for the life of the process since
+// the same tool is resolved once per repository in bulk commands.
+pub fn resolve_binary(configured: &str) -> Result<PathBuf, Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, PathBuf>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+
+    if let Some(path) = cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(configured)
+    {
+        return Ok(path.clone());
+    }
+
+    let resolved = if configured.contains(['/', '\\']) || configured.starts_with('~') {
+        Some(expand_home(configured)).filter(|path| path.is_file())
+    } else {
+        which(configured)
+    };
+    let resolved = resolved.ok_or_else(|| {
+        Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' was not found or is not a file", configured),
+        )
+    })?;
+
+    cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(configured.to_string(), resolved.clone());
+    Ok(resolved)
+}
+
+// Writes through a temporary file in the same directory that is then
+// renamed over `path`, so a crash leaves the old content or the new one but
+// never a truncated file. The file keeps its permissions, and a symlinked
+// file (dotfile managers) is replaced at its target.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name().ok_or_else(|| {
+        Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a file path", path.display()),
+        )
+    })?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempConfig;
+
+    #[test]
+    fn writes_atomically_keeping_permissions() {
+        let cfg = TempConfig::new().unwrap();
+        let path = cfg.base.join("profiles.toml");
+
+        write_atomic(&path, b"[work]\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[work]\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+            let link = cfg.base.join("link.toml");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+
+            write_atomic(&link, b"[home]\n").unwrap();
+            assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "[home]\n");
+            assert_eq!(
+                fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+
+        // nothing is left behind next to the file
+        assert_eq!(
+            fs::read_dir(&cfg.base)
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+                .count(),
+            0
+        );
+    }
+}
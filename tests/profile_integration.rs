@@ -1,7 +1,10 @@
 use nix::libc::{close, dup, dup2};
 use nix::unistd::pipe;
 use std::env;
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 use std::{
     fs::File,
     io::{Read, Write},
@@ -13,7 +16,7 @@ use clap::Parser;
 use octopush::{
     core::profile::Profile,
     test_helpers::{TempConfig, get_profiles},
-    util::cli,
+    util::{cli, exit},
 };
 
 fn build_add_profile_args(profile_name: String, profile: Profile) -> Vec<String> {
@@ -48,6 +51,7 @@ fn build_delete_profile_args(profile_name: String) -> Vec<String> {
         "delete-profile".into(),
         "--profile-name".into(),
         profile_name,
+        "--yes".into(),
     ];
 
     args
@@ -83,7 +87,7 @@ fn tests_add_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     let expected = r#"
 [profile_1]
 name = "profile_1_name"
-email = "profile_1_email"
+email = "profile_1@example.com"
 auth_type = "None"
     "#;
 
@@ -110,7 +114,7 @@ fn tests_delete_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     let expected = r#"
 [profile_1]
 name = "profile_1_name"
-email = "profile_1_email"
+email = "profile_1@example.com"
 auth_type = "None"
     "#;
 
@@ -131,6 +135,43 @@ auth_type = "None"
     Ok(())
 }
 
+#[test]
+fn tests_edit_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new().unwrap();
+
+    let (profile_names, profiles) = get_profiles();
+
+    let args = build_add_profile_args(profile_names[0].into(), profiles[0].clone());
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "edit-profile".into(),
+        profile_names[0].into(),
+        "--email".into(),
+        "edited_email".into(),
+        "--auth-type".into(),
+        "gh".into(),
+        "--hostname".into(),
+        "github.com".into(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let raw_profiles = read_raw_profiles(cfg.base.clone())?;
+
+    let expected = r#"
+[profile_1]
+name = "profile_1_name"
+email = "edited_email"
+auth_type = "GH"
+hostname = "github.com"
+    "#;
+
+    assert_eq!(normalize(&raw_profiles), normalize(expected));
+
+    Ok(())
+}
+
 #[test]
 fn tests_list_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     let _cfg = TempConfig::new().unwrap();
@@ -162,12 +203,56 @@ fn tests_list_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
         cli::run(parsed_cli).unwrap();
     });
 
-    // NOTE: test this output right after making the output pretty
-    let out = out.replace("\n✅ SUCCESS Profiles successfully fetched\n", "");
+    let lines: Vec<&str> = out
+        .lines()
+        .filter(|line| !line.contains("Profiles successfully fetched"))
+        .collect();
+
+    assert!(lines[0].starts_with("Profile "));
+    assert!(lines[1].starts_with("--------"));
+    // one row per profile, in name order
+    let mut expected: Vec<(&str, &Profile)> = profile_names.into_iter().zip(&profiles).collect();
+    expected.sort_by_key(|(profile_name, _)| *profile_name);
+    assert_eq!(lines.len(), 2 + expected.len());
+    for (line, (profile_name, profile)) in lines[2..].iter().zip(expected) {
+        assert!(line.starts_with(profile_name));
+        assert!(line.contains(&profile.email));
+    }
+
+    Ok(())
+}
 
-    // println!("------ profiles output: {} ------", out);
+#[test]
+fn tests_archive_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
+    let _cfg = TempConfig::new().unwrap();
 
-    assert!(!out.is_empty());
+    let (profile_names, profiles) = get_profiles();
+
+    for i in 0..2 {
+        let args = build_add_profile_args(profile_names[i].into(), profiles[i].clone());
+        cli::run(cli::Cli::try_parse_from(args)?)?;
+    }
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "archive-profile".into(),
+        profile_names[0].into(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let out = capture_stdout(|| {
+        let parsed_cli = cli::Cli::try_parse_from(build_list_profiles_args()).unwrap();
+        cli::run(parsed_cli).unwrap();
+    });
+    assert!(!out.contains(&format!("{} ", profile_names[0])));
+    assert!(out.contains(profile_names[1]));
+
+    let out = capture_stdout(|| {
+        let mut args = build_list_profiles_args();
+        args.push("--all".into());
+        cli::run(cli::Cli::try_parse_from(args).unwrap()).unwrap();
+    });
+    assert!(out.contains(&format!("{} (archived)", profile_names[0])));
 
     Ok(())
 }
@@ -204,6 +289,103 @@ fn tests_use_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn tests_repo_commands_with_path_flags() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+
+    let args = build_add_profile_args(profile_name.to_string(), profile.clone());
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let other = cfg.base.join("other");
+    TempConfig::init_repo_at(&other);
+    let repos = [cfg.repo.clone(), other];
+
+    let mut args: Vec<String> = vec![
+        "octopush".into(),
+        "use-profile".into(),
+        "-p".into(),
+        profile_name.to_string(),
+    ];
+    for repo in &repos {
+        args.push("--path".into());
+        args.push(repo.to_string_lossy().into_owned());
+    }
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    use octopush::core::app::App;
+    for repo in &repos {
+        let (applied, _, _) = App::get_project_profile(repo.to_string_lossy().into_owned())?;
+        assert_eq!(applied, profile_name);
+    }
+
+    let args = vec![
+        "octopush".to_string(),
+        "reset-profile".into(),
+        "--path".into(),
+        repos[1].to_string_lossy().into_owned(),
+        "--yes".into(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    assert!(App::get_project_profile(repos[0].to_string_lossy().into_owned()).is_ok());
+    assert!(App::get_project_profile(repos[1].to_string_lossy().into_owned()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn tests_use_profile_dry_run_and_execute_plan_cmd() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+
+    use octopush::core::app::App;
+    App::add_profile(profile_name.to_string(), profile.clone())?;
+
+    let prev_cwd = env::current_dir()?;
+    env::set_current_dir(&cfg.repo)?;
+
+    let out = capture_stdout(|| {
+        let args: Vec<String> = vec![
+            "octopush".into(),
+            "use-profile".into(),
+            "--profile-name".into(),
+            profile_name.to_string(),
+            "--dry-run".into(),
+            "--output".into(),
+            "json".into(),
+        ];
+        cli::run(cli::Cli::try_parse_from(args).unwrap()).unwrap();
+    });
+
+    // the dry run leaves the repository unmapped
+    assert!(App::get_project_profile(cfg.repo.to_string_lossy().into_owned()).is_err());
+
+    let plan: serde_json::Value = serde_json::from_str(&out)?;
+    assert_eq!(plan["profile_name"], profile_name);
+    assert!(!plan["operations"].as_array().unwrap().is_empty());
+
+    let plan_path = cfg.base.join("plan.json");
+    fs::write(&plan_path, out)?;
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "use-profile".into(),
+        "--execute-plan".into(),
+        plan_path.to_string_lossy().into_owned(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let (_, applied, _) = App::get_project_profile(cfg.repo.to_string_lossy().into_owned())?;
+    assert_eq!(applied, profile);
+
+    env::set_current_dir(prev_cwd)?;
+
+    Ok(())
+}
+
 #[test]
 fn tests_get_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     let cfg = TempConfig::new()?;
@@ -238,6 +420,239 @@ fn tests_get_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn tests_chdir_flag() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+
+    use octopush::core::app::App;
+    App::add_profile(profile_name.to_string(), profile.clone())?;
+
+    let prev_cwd = env::current_dir()?;
+    env::set_current_dir(&cfg.base)?;
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "-C".into(),
+        cfg.repo.to_string_lossy().into_owned(),
+        "use-profile".into(),
+        "--profile-name".into(),
+        profile_name.to_string(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    let (_, applied, _) = App::get_project_profile(cfg.repo.to_string_lossy().into_owned())?;
+    assert_eq!(applied, profile);
+
+    env::set_current_dir(prev_cwd)?;
+
+    Ok(())
+}
+
+#[test]
+fn tests_strict_mode_fails_on_warnings() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    use octopush::core::{app::App, auth::AuthType};
+    let profile = Profile::build(
+        "Strict User".to_string(),
+        "strict@example.com".to_string(),
+        AuthType::SSH,
+        None,
+        Some("/nonexistent/octopush/key".to_string()),
+    );
+    App::add_profile("strict".to_string(), profile)?;
+
+    let prev_cwd = env::current_dir()?;
+    env::set_current_dir(&cfg.repo)?;
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "--strict".into(),
+        "use-profile".into(),
+        "--profile-name".into(),
+        "strict".into(),
+    ];
+    let err = cli::run(cli::Cli::try_parse_from(args)?).unwrap_err();
+    assert!(cli::is_strict_failure(&err));
+
+    // nothing was applied
+    assert!(App::get_project_profile(cfg.repo.to_string_lossy().into_owned()).is_err());
+
+    // without strict mode the same warning does not stop the command
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "use-profile".into(),
+        "--profile-name".into(),
+        "strict".into(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+    assert!(App::get_project_profile(cfg.repo.to_string_lossy().into_owned()).is_ok());
+
+    env::set_current_dir(prev_cwd)?;
+
+    Ok(())
+}
+
+#[test]
+fn tests_result_fd_reports_outcome() -> Result<(), Box<dyn std::error::Error>> {
+    let _cfg = TempConfig::new()?;
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+
+    // the result line and the exit status
+    let run_with_result =
+        |mut args: Vec<String>| -> Result<(String, i32), Box<dyn std::error::Error>> {
+            let (read_end, write_end) = pipe()?;
+            args.insert(1, "--result-fd".into());
+            args.insert(2, write_end.as_raw_fd().to_string());
+            let code = match cli::run(cli::Cli::try_parse_from(args)?) {
+                Ok(()) => 0,
+                Err(e) => exit::code(&e),
+            };
+            drop(write_end);
+
+            let mut line = String::new();
+            File::from(read_end).read_to_string(&mut line)?;
+            Ok((line, code))
+        };
+
+    let args = build_add_profile_args(profile_name.to_string(), profile.clone());
+    assert_eq!(
+        run_with_result(args)?,
+        (
+            "OCTOPUSH_RESULT=ok OCTOPUSH_ERRORS=0 OCTOPUSH_WARNINGS=0\n".to_string(),
+            0
+        )
+    );
+
+    let args = build_delete_profile_args("missing".to_string());
+    assert_eq!(
+        run_with_result(args)?,
+        (
+            "OCTOPUSH_RESULT=error OCTOPUSH_ERRORS=1 OCTOPUSH_WARNINGS=0\n".to_string(),
+            exit::NOT_FOUND
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn tests_status_fix_reconciles_drift() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+    let args = build_add_profile_args(profile_name.to_string(), profile.clone());
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    use octopush::core::{app::App, status::RepoState};
+    use octopush::util::git;
+    let repo = cfg.repo.to_string_lossy().into_owned();
+    App::use_profile(profile_name.to_string(), repo.clone())?;
+
+    // a manual edit behind octopush's back
+    git::set_local_config(&cfg.repo, "user.email", "someone@else.com")?;
+    assert!(matches!(
+        App::repo_state(repo.clone())?,
+        RepoState::Drifted { .. }
+    ));
+
+    // strict mode turns the reported drift into a failure
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "--strict".into(),
+        "status".into(),
+        "--path".into(),
+        repo.clone(),
+    ];
+    let err = cli::run(cli::Cli::try_parse_from(args)?).unwrap_err();
+    assert!(cli::is_strict_failure(&err));
+
+    let args: Vec<String> = vec![
+        "octopush".into(),
+        "status".into(),
+        "--fix".into(),
+        "--path".into(),
+        repo.clone(),
+    ];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    assert!(matches!(App::repo_state(repo)?, RepoState::InSync { .. }));
+
+    Ok(())
+}
+
+#[test]
+fn tests_setup_auto_apply_configures_new_clones() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    // keep the user's global git config out of reach, and let the hook find
+    // the octopush binary under test
+    let prev_global = env::var_os("GIT_CONFIG_GLOBAL");
+    let prev_path = env::var_os("PATH").unwrap_or_default();
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_octopush")).parent().unwrap();
+    let mut paths = vec![bin_dir.to_path_buf()];
+    paths.extend(env::split_paths(&prev_path));
+    unsafe {
+        env::set_var("GIT_CONFIG_GLOBAL", cfg.base.join("gitconfig"));
+        env::set_var("PATH", env::join_paths(paths)?);
+    }
+
+    let ([profile_name, _, _], [profile, _, _]) = get_profiles();
+
+    use octopush::core::app::App;
+    use octopush::core::rules::{Rule, Rules};
+    use octopush::util::git;
+    App::add_profile(profile_name.to_string(), profile.clone())?;
+    App::save_rules(&Rules {
+        rules: vec![Rule {
+            gitdir: Some(format!("{}/clones/", cfg.base.canonicalize()?.display())),
+            remote: None,
+            profile: profile_name.to_string(),
+        }],
+    })?;
+
+    let args: Vec<String> = vec!["octopush".into(), "setup-auto-apply".into()];
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+    assert!(git::get_global_config("init.templateDir")?.is_some());
+
+    git::run_git(
+        &cfg.repo,
+        [
+            "-c",
+            "user.name=Init",
+            "-c",
+            "user.email=init@example.com",
+            "commit",
+            "--allow-empty",
+            "-m",
+            "init",
+        ],
+    )?;
+    let clone = cfg.base.join("clones").join("app");
+    let o = git::run_git(
+        &cfg.base,
+        ["clone".as_ref(), cfg.repo.as_os_str(), clone.as_os_str()],
+    )?;
+    assert!(o.status.success(), "git clone failed: {:?}", o);
+
+    let email = git::get_local_config(&clone, "user.email");
+
+    unsafe {
+        match prev_global {
+            Some(value) => env::set_var("GIT_CONFIG_GLOBAL", value),
+            None => env::remove_var("GIT_CONFIG_GLOBAL"),
+        }
+        env::set_var("PATH", prev_path);
+    }
+
+    assert_eq!(email?, Some(profile.email));
+
+    Ok(())
+}
+
 #[test]
 fn tests_reset_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     let cfg = TempConfig::new()?;
@@ -260,7 +675,13 @@ fn tests_reset_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
 
     assert_eq!(applied_name, profile_name);
 
+    // scripts have to confirm
     let args: Vec<String> = vec!["octopush".into(), "reset-profile".into()];
+    let err = cli::run(cli::Cli::try_parse_from(args)?).unwrap_err();
+    assert_eq!(exit::code(&err), exit::INVALID_INPUT);
+    assert!(App::get_project_profile(cfg.repo.to_string_lossy().into_owned()).is_ok());
+
+    let args: Vec<String> = vec!["octopush".into(), "reset-profile".into(), "-y".into()];
     let parsed_cli = cli::Cli::try_parse_from(args)?;
 
     cli::run(parsed_cli)?;
@@ -279,6 +700,27 @@ fn tests_reset_profile_cmd() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn tests_concurrent_add_profile_processes() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+    let (_, [profile, ..]) = get_profiles();
+
+    let outputs = cfg.spawn_concurrently(env!("CARGO_BIN_EXE_octopush").as_ref(), 6, |i| {
+        build_add_profile_args(format!("process_{}", i), profile.clone())
+            .into_iter()
+            .skip(1)
+            .collect()
+    })?;
+    assert!(outputs.iter().all(|output| output.status.success()));
+
+    let raw_profiles = read_raw_profiles(cfg.base.clone())?;
+    for i in 0..6 {
+        assert!(raw_profiles.contains(&format!("[process_{}]", i)));
+    }
+
+    Ok(())
+}
+
 fn normalize(s: &str) -> String {
     s.lines()
         .map(|line| line.trim())
@@ -315,3 +757,117 @@ fn capture_stdout<F: FnOnce()>(f: F) -> String {
     let _ = file.read_to_string(&mut output);
     output
 }
+
+#[test]
+fn tests_no_input_fails_instead_of_prompting() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+
+    let args = vec![
+        "octopush".to_string(),
+        "--no-input".into(),
+        "add-profile".into(),
+        "--profile-name".into(),
+        "work".into(),
+        "--name".into(),
+        "John".into(),
+        "--auth-type".into(),
+        "gh".into(),
+    ];
+    let err = cli::run(cli::Cli::try_parse_from(args)?).unwrap_err();
+    assert_eq!(exit::code(&err), exit::INVALID_INPUT);
+    assert!(err.to_string().contains("--email"));
+    assert!(!cfg.base.join("octopush").join("profiles.toml").exists());
+
+    Ok(())
+}
+
+#[test]
+fn tests_completions_cmd() -> Result<(), Box<dyn std::error::Error>> {
+    let _cfg = TempConfig::new()?;
+
+    let args = vec!["octopush", "completions", "zsh"];
+    let cli = cli::Cli::try_parse_from(args)?;
+    let output = capture_stdout(|| cli::run(cli).unwrap());
+    assert!(output.contains("#compdef octopush"));
+    assert!(output.contains("COMPLETE=\"zsh\""));
+
+    assert!(cli::Cli::try_parse_from(["octopush", "completions", "tcsh"]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn tests_config_dir_flag() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+    let dir = cfg.base.join("synced");
+
+    let (profile_names, profiles) = get_profiles();
+    let mut args = build_add_profile_args(profile_names[0].into(), profiles[0].clone());
+    args.insert(1, "--config-dir".into());
+    args.insert(2, dir.to_string_lossy().into_owned());
+    cli::run(cli::Cli::try_parse_from(args)?)?;
+
+    assert!(dir.join("profiles.toml").exists());
+    assert!(!cfg.base.join("octopush").join("profiles.toml").exists());
+
+    // the same directory through the environment, as scripts set it
+    let out = capture_stdout(|| {
+        cli::run(cli::Cli::try_parse_from(build_list_profiles_args()).unwrap()).unwrap();
+    });
+    assert!(out.contains(profile_names[0]));
+
+    Ok(())
+}
+
+#[test]
+fn tests_prompt_cmd() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+    let repo = cfg.repo.to_string_lossy().into_owned();
+    let prev_cwd = env::current_dir()?;
+
+    // the global --format and prompt's --shell must not clash
+    let args = vec!["octopush", "-C", &repo, "prompt", "--shell", "zsh"];
+    let cli = cli::Cli::try_parse_from(args)?;
+    let output = capture_stdout(|| cli::run(cli).unwrap());
+    assert_eq!(output.trim(), "%F{yellow}unmapped%f");
+
+    let args = vec!["octopush", "--format", "json", "-C", &repo, "prompt"];
+    let cli = cli::Cli::try_parse_from(args)?;
+    let output = capture_stdout(|| cli::run(cli).unwrap());
+    assert!(output.contains("unmapped"));
+
+    env::set_current_dir(prev_cwd)?;
+
+    Ok(())
+}
+
+#[test]
+fn tests_cli_definition_is_consistent() {
+    use clap::CommandFactory;
+    cli::Cli::command().debug_assert();
+}
+
+#[test]
+fn tests_output_flag_agrees_with_format() -> Result<(), Box<dyn std::error::Error>> {
+    let cfg = TempConfig::new()?;
+    let root = cfg.base.to_string_lossy().into_owned();
+
+    let args = vec![
+        "octopush", "--format", "yaml", "scan", &root, "--output", "json",
+    ];
+    let err = cli::run(cli::Cli::try_parse_from(args)?).unwrap_err();
+    assert_eq!(exit::code(&err), exit::INVALID_INPUT);
+
+    let args = vec!["octopush", "--format", "toml", "scan", &root];
+    let cli = cli::Cli::try_parse_from(args)?;
+    let output = capture_stdout(|| cli::run(cli).unwrap());
+    assert!(output.contains("[[repos]]"));
+
+    // the deprecated spelling still prints json
+    let args = vec!["octopush", "scan", &root, "--output", "json"];
+    let cli = cli::Cli::try_parse_from(args)?;
+    let output = capture_stdout(|| cli::run(cli).unwrap());
+    assert!(output.trim_start().starts_with('['));
+
+    Ok(())
+}